@@ -0,0 +1,17 @@
+%
+Z
+G90
+G10 L2 P1 X10
+G0 X0 A0
+G91
+M808 L2
+M808 L40
+G0 X0.25 A360
+M808
+M808 L40
+G0 X-0.25 A360
+M808
+M808
+G90
+G0 X0
+%
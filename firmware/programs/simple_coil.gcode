@@ -0,0 +1,10 @@
+%
+Z
+G90
+G0 X0 A0
+G91
+M808 L50
+G0 X0.25 A360
+M808
+G90
+%
@@ -0,0 +1,15 @@
+%
+Z
+G90
+G10 L2 P1 X10
+G0 X0 A0
+G91
+M808 L30
+G0 X0.2 A360
+M808
+G4 P5000
+M808 L30
+G0 X0.2 A360
+M808
+G90
+%
@@ -0,0 +1,79 @@
+use arduino_hal::{
+    port::{
+        mode::{Input, PullUp},
+        Pin, D12,
+    },
+    Peripherals, Pins,
+};
+use heapless::HistoryBuffer;
+
+use crate::{clock, machine::MoveObserver, settings::Settings};
+
+/// Number of recent captures kept.
+pub const CAPTURES: usize = 4;
+
+/// Position capture input.
+///
+/// An external trigger (eg. a camera trigger, or an index pulse) drives D12,
+/// which is pulled up. The input is checked after every step of a move, and
+/// the position is captured on the configured edge, so a capture is at most
+/// one step late. Edges between moves are not captured.
+pub struct CaptureInput {
+    pin: Pin<Input<PullUp>, D12>,
+    /// Level of the input when it was last checked.
+    high: bool,
+}
+impl CaptureInput {
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+        let pin = pins.d12.into_pull_up_input();
+        let high = pin.is_high();
+        Self { pin, high }
+    }
+}
+
+/// A captured position.
+#[derive(Clone, Copy)]
+pub struct Capture {
+    /// Uptime at the capture, in milliseconds.
+    pub time_ms: u32,
+    /// X and A positions, in steps.
+    pub x_pos: u32,
+    pub a_pos: i64,
+}
+
+/// Captures the position on each trigger edge during a move.
+pub struct CaptureLatch<'a> {
+    input: &'a mut CaptureInput,
+    captures: &'a mut HistoryBuffer<Capture, CAPTURES>,
+    rising: bool,
+}
+impl<'a> CaptureLatch<'a> {
+    pub fn new(
+        input: &'a mut CaptureInput,
+        captures: &'a mut HistoryBuffer<Capture, CAPTURES>,
+        settings: &Settings,
+    ) -> Self {
+        Self {
+            input,
+            captures,
+            rising: settings.capture_rising_edge,
+        }
+    }
+}
+impl MoveObserver for CaptureLatch<'_> {
+    fn after_step(&mut self, x_pos: u32, a_pos: i64) {
+        let high = self.input.pin.is_high();
+        if high != self.input.high {
+            self.input.high = high;
+            if high == self.rising {
+                self.captures.write(Capture {
+                    time_ms: clock::millis(),
+                    x_pos,
+                    a_pos,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use core::cell::Cell;
+
+use arduino_hal::pac::TC0;
+use avr_device::interrupt::{self, Mutex};
+
+/// Timer prescaler: the 16 MHz system clock is divided by this.
+const PRESCALER: u32 = 64;
+/// Timer counts per interrupt: 16 MHz / 64 / 250 = 1 kHz.
+const TIMER_COUNTS: u32 = 250;
+/// Milliseconds added on each timer interrupt.
+const MILLIS_INCREMENT: u32 = PRESCALER * TIMER_COUNTS / 16_000;
+
+/// Milliseconds since the clock was started.
+static MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Start the millisecond clock.
+///
+/// Timer0 is run in CTC mode, interrupting once per millisecond. Global
+/// interrupts are enabled, so this must only be called once all other
+/// hardware has been set up.
+///
+/// # Parameters
+///
+/// - `tc0`: Timer0, which is dedicated to the clock.
+pub fn init(tc0: TC0) {
+    tc0.tccr0a().write(|w| w.wgm0().ctc());
+    tc0.ocr0a().write(|w| w.set((TIMER_COUNTS - 1) as u8));
+    tc0.tccr0b().write(|w| w.cs0().prescale_64());
+    tc0.timsk0().write(|w| w.ocie0a().set_bit());
+
+    interrupt::free(|cs| MILLIS.borrow(cs).set(0));
+    unsafe { interrupt::enable() };
+}
+
+/// Return the number of milliseconds since the clock was started.
+///
+/// This wraps around after roughly 49 days.
+pub fn millis() -> u32 {
+    interrupt::free(|cs| MILLIS.borrow(cs).get())
+}
+
+/// Return the number of microseconds since the clock was started, to the
+/// resolution of the timer (4 microseconds).
+///
+/// This wraps around after roughly 71 minutes, so it is only suitable for
+/// measuring short intervals.
+#[cfg(any(
+    feature = "instrument",
+    feature = "trace",
+    not(feature = "timer-steps")
+))]
+pub fn micros() -> u32 {
+    interrupt::free(|cs| {
+        let tc0 = unsafe { &*TC0::ptr() };
+        let mut millis = MILLIS.borrow(cs).get();
+        let mut counts = tc0.tcnt0().read().bits() as u32;
+        // With interrupts disabled, a timer interrupt that is due has not
+        // yet counted its millisecond.
+        if tc0.tifr0().read().ocf0a().bit_is_set() {
+            millis = millis.wrapping_add(MILLIS_INCREMENT);
+            counts = tc0.tcnt0().read().bits() as u32;
+        }
+        millis
+            .wrapping_mul(1000)
+            .wrapping_add(counts * PRESCALER / 16)
+    })
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn TIMER0_COMPA() {
+    interrupt::free(|cs| {
+        let millis = MILLIS.borrow(cs);
+        millis.set(millis.get().wrapping_add(MILLIS_INCREMENT));
+    })
+}
@@ -1,119 +1,425 @@
 use ufmt_macros::uDebug;
+use winderbot_lib::{
+    gcode::{parse_angle_millis, parse_digits, parse_millis_i64},
+    planner::ArcCenter,
+};
 use winnow::{
-    ascii::{digit1, space1},
-    combinator::{alt, opt},
-    token::literal,
+    ascii::{space0, space1},
+    combinator::{alt, eof, opt},
+    error::ContextError,
+    stream::AsChar,
+    token::{literal, one_of, rest, take_till},
     Parser, Result,
 };
 
-#[derive(Debug, uDebug)]
-pub enum Command {
-    Zero,
-    AbsolutePositioning,
-    RelativePositioning,
-    Move(Move),
+#[cfg(feature = "instrument")]
+use crate::instrument::{Probe, Section};
+#[cfg(feature = "a-enable")]
+use crate::machine::AIdleMode;
+use crate::machine::{Axis, Overflow, OverflowStage};
+
+/// Parse the command word at the start of a line.
+///
+/// The command word is everything up to the first space (eg. `G0` in
+/// `G0 X1`), except for settings commands and spindle speed words, where it
+/// is just the leading `$` or `S`. The rest of the line is left in `input`,
+/// for the handler of the command to parse its arguments from.
+pub fn parse_word<'a>(
+    input: &mut &'a str,
+) -> core::result::Result<&'a str, Error> {
+    run(
+        input,
+        alt((literal("$"), literal("S"), take_till(1.., ' '))),
+    )
 }
-impl Command {
-    pub fn parse<'a>(
+
+/// A settings command: everything after the leading `$`.
+pub enum SettingsCommand<'a> {
+    /// `$$` (or a bare `$`): list all settings.
+    List,
+    /// `$<id>=<value>`: change a setting.
+    Set { id: u8, value: u32 },
+    /// `$N`: list the startup lines.
+    StartupLines,
+    /// `$N<index>=<line>`: store a startup line. An empty line clears it.
+    SetStartupLine { index: u8, line: &'a str },
+    /// `$M`: list the macros.
+    Macros,
+    /// `$M<index>=<commands>`: store a macro, with its commands separated
+    /// by `|`. An empty macro clears it.
+    SetMacro { index: u8, commands: &'a str },
+    /// `$X`: unlock motion after a hard limit alarm.
+    Unlock,
+    /// `$I`: report the firmware and protocol versions, and the settings
+    /// checksum.
+    Info,
+}
+impl<'a> SettingsCommand<'a> {
+    /// Parse the arguments of a settings command.
+    pub fn parse(
         input: &mut &'a str,
-    ) -> core::result::Result<Command, Error> {
-        let result = alt((
-            Self::parse_zero,
-            Self::parse_absolute_positioning,
-            Self::parse_relative_positioning,
-            Self::parse_move,
-        ))
-        .parse(input);
-
-        match result {
-            Ok(cmd) => Ok(cmd),
-            Err(_) => Err(Error::InvalidGCode),
-        }
+    ) -> core::result::Result<SettingsCommand<'a>, Error> {
+        let set = (parse_digits, literal("="), parse_digits, eof)
+            .map(|(id, _, value, _)| SettingsCommand::Set { id, value });
+        let list = alt((literal("$"), eof)).map(|_| SettingsCommand::List);
+        let set_startup_line = (literal("N"), parse_digits, literal("="), rest)
+            .map(|(_, index, _, line)| SettingsCommand::SetStartupLine {
+                index,
+                line,
+            });
+        let startup_lines =
+            (literal("N"), eof).map(|_| SettingsCommand::StartupLines);
+        let set_macro = (literal("M"), parse_digits, literal("="), rest).map(
+            |(_, index, _, commands)| SettingsCommand::SetMacro {
+                index,
+                commands,
+            },
+        );
+        let macros = (literal("M"), eof).map(|_| SettingsCommand::Macros);
+        let info = (literal("I"), eof).map(|_| SettingsCommand::Info);
+        let unlock = (literal("X"), eof).map(|_| SettingsCommand::Unlock);
+        run(
+            input,
+            alt((
+                set,
+                list,
+                set_startup_line,
+                startup_lines,
+                set_macro,
+                macros,
+                info,
+                unlock,
+            )),
+        )
     }
+}
 
-    fn parse_zero<'a>(input: &mut &'a str) -> Result<Command> {
-        literal("Z").parse_next(input).map(|_| Command::Zero)
-    }
+/// Parse the arguments of a move in machine coordinates: `G53 G0 ...`.
+///
+/// `G53` only applies to the move on the same line.
+pub fn parse_machine_move<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<Move, Error> {
+    run(input, (space1, literal("G0")))?;
+    Move::parse(input, permissive)
+}
 
-    fn parse_absolute_positioning<'a>(input: &mut &'a str) -> Result<Command> {
-        literal("G90")
-            .parse_next(input)
-            .map(|_| Command::AbsolutePositioning)
+/// Parse the end of a command's arguments.
+///
+/// Only spaces may follow, unless `permissive` is set. Then the rest of the
+/// line is left in `input`, and skipped with a warning once the command
+/// has run.
+pub fn parse_end<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<(), Error> {
+    if !permissive {
+        run(input, (space0, eof))?;
     }
+    Ok(())
+}
 
-    fn parse_relative_positioning<'a>(input: &mut &'a str) -> Result<Command> {
-        literal("G91")
-            .parse_next(input)
-            .map(|_| Command::RelativePositioning)
-    }
+/// Longest dwell that `G4` accepts: one hour, in milliseconds.
+pub const MAX_DWELL_MS: u32 = 3_600_000;
 
-    fn parse_move<'a>(input: &mut &'a str) -> Result<Command> {
-        literal("G0").parse_next(input)?;
-        let x_microns = opt((space1, Self::parse_x))
-            .map(|t| t.map(|(_, x)| x))
-            .parse_next(input)?;
-        let a_millidegrees = opt((space1, Self::parse_a))
-            .map(|t| t.map(|(_, a)| a))
-            .parse_next(input)?;
-        Ok(Command::Move(Move {
-            x_microns,
-            a_millidegrees,
-        }))
+/// Parse the arguments of a dwell: `G4 P<milliseconds>`, with P at most
+/// [`MAX_DWELL_MS`].
+pub fn parse_dwell<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<u32, Error> {
+    let p = run(
+        input,
+        (space1, literal("P"), parse_digits).map(|(_, _, p)| p),
+    )?;
+    parse_end(input, permissive)?;
+    if p > MAX_DWELL_MS {
+        return Err(Error::InvalidGCode);
     }
+    Ok(p)
+}
 
-    fn parse_x<'a>(input: &mut &'a str) -> Result<i32> {
-        literal("X").parse_next(input)?;
-        Self::parse_decimal_millis(input)
+/// Parse the arguments of an A indexing command: `M19 P<index>`.
+pub fn parse_index<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<u16, Error> {
+    let p = run(
+        input,
+        (space1, literal("P"), parse_digits).map(|(_, _, p)| p),
+    )?;
+    parse_end(input, permissive)?;
+    Ok(p)
+}
+
+/// A burn-in command: `M980 X<mm> L<cycles> [R<steps/s>]`, or with
+/// `A<degrees>` in place of `X`.
+///
+/// The axis moves out by the distance from where it is and back again, for
+/// each cycle.
+pub struct BurnIn {
+    pub axis: Axis,
+    /// Distance out, in thousandths of the axis unit.
+    pub distance: i32,
+    pub cycles: u16,
+    /// Step rate, in steps per second, or `None` for the maximum step rate
+    /// of the axis.
+    pub step_rate: Option<u16>,
+}
+impl BurnIn {
+    pub fn parse<'a>(
+        input: &mut &'a str,
+        permissive: bool,
+    ) -> core::result::Result<BurnIn, Error> {
+        let distance =
+            alt((parse_x.map(|x| (Axis::X, x)), parse_a.map(|a| (Axis::A, a))));
+        let step_rate = opt((space1, literal("R"), parse_digits))
+            .map(|r| r.map(|(_, _, rate)| rate));
+        let (_, (axis, distance), _, _, cycles, step_rate) = run(
+            input,
+            (
+                space1,
+                distance,
+                space1,
+                literal("L"),
+                parse_digits,
+                step_rate,
+            ),
+        )?;
+        parse_end(input, permissive)?;
+        Ok(BurnIn {
+            axis,
+            distance: fit_word(axis, distance)?,
+            cycles,
+            step_rate,
+        })
     }
+}
 
-    fn parse_a<'a>(input: &mut &'a str) -> Result<i32> {
-        literal("A").parse_next(input)?;
-        Self::parse_decimal_millis(input)
+/// A work offset command: `G10 L2 P1 [X<mm>] [A<degrees>]` or
+/// `G10 L20 P1 [X<mm>] [A<degrees>]`, with at least one of `X` and `A`.
+///
+/// Bobbin coordinates are the only work coordinate system, so `P` must be
+/// `1` (or `0`, for the active system). An axis without a word keeps its
+/// origin.
+pub struct SetOffset {
+    /// `L20`: the current position is at the words in bobbin coordinates.
+    /// Otherwise (`L2`), the origin of bobbin coordinates is at the words
+    /// in machine coordinates.
+    pub current: bool,
+    pub x_microns: Option<i32>,
+    pub a_millidegrees: Option<i32>,
+}
+impl SetOffset {
+    pub fn parse<'a>(
+        input: &mut &'a str,
+        permissive: bool,
+    ) -> core::result::Result<SetOffset, Error> {
+        let (l, x, a) = run(
+            input,
+            (
+                space1,
+                alt((literal("L20"), literal("L2"))),
+                space1,
+                literal("P"),
+                alt((literal("0"), literal("1"))),
+                opt((space1, parse_x)),
+                opt((space1, parse_a)),
+            )
+                .map(|(_, l, _, _, _, x, a)| {
+                    (l, x.map(|(_, x)| x), a.map(|(_, a)| a))
+                }),
+        )?;
+        parse_end(input, permissive)?;
+        if x.is_none() && a.is_none() {
+            return Err(Error::InvalidGCode);
+        }
+        Ok(SetOffset {
+            current: l == "L20",
+            x_microns: x.map(|x| fit_word(Axis::X, x)).transpose()?,
+            a_millidegrees: a.map(|a| fit_word(Axis::A, a)).transpose()?,
+        })
     }
+}
 
-    /// Parse a decmial value with thousandths precision.
-    ///
-    /// eg.
-    ///   - 3      -> 3000
-    ///   - 3.14   -> 3140
-    ///   - 3.142  -> 3142
-    ///   - 3.1428 -> 3142
-    fn parse_decimal_millis<'a>(input: &mut &'a str) -> Result<i32> {
-        let sign: i32 = opt(alt((literal("-"), literal("+"))))
-            .parse_next(input)?
-            .map(|s| if s == "-" { -1 } else { 1 })
-            .unwrap_or(1);
-
-        let before_decimal: i32 =
-            digit1.try_map(str::parse).parse_next(input)?;
-
-        let opt_decimal = opt(literal(".")).parse_next(input)?;
-        let after_decimal: i32 = match opt_decimal {
-            None => 0,
-            Some(_) => {
-                let mut s: &str = digit1(input)?;
-                s = &s[..3.min(s.len())];
-                let factor = 10_i32.pow(3 - s.len() as u32);
-                str::parse::<i32>(s).unwrap() * factor
-            }
-        };
+/// Parse the arguments of a telemetry command: `M990 S<steps>`.
+///
+/// A position sample is emitted every `S` steps during moves. `S0` (or
+/// omitting `S`) turns telemetry off.
+#[cfg(feature = "telemetry")]
+pub fn parse_telemetry<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<u16, Error> {
+    let s = run(
+        input,
+        opt((space1, literal("S"), parse_digits))
+            .map(|t| t.map(|(_, _, s)| s).unwrap_or(0)),
+    )?;
+    parse_end(input, permissive)?;
+    Ok(s)
+}
+
+/// Parse the arguments of a spindle mode command: `M3 S<rpm>`.
+pub fn parse_spindle_on<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<u16, Error> {
+    let rpm = run(
+        input,
+        (space1, literal("S"), parse_digits).map(|(_, _, rpm)| rpm),
+    )?;
+    parse_end(input, permissive)?;
+    Ok(rpm)
+}
+
+/// Parse the speed of a spindle speed word, `S<rpm>`, after the `S`.
+pub fn parse_spindle_speed<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<u16, Error> {
+    let rpm = run(input, parse_digits)?;
+    parse_end(input, permissive)?;
+    Ok(rpm)
+}
+
+/// Parse the arguments of a second spindle command: `M992 S<0|1>`.
+///
+/// `S1` enables the second spindle and `S0` disables it.
+#[cfg(feature = "spindle2")]
+pub fn parse_spindle2<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<bool, Error> {
+    let enabled = run(
+        input,
+        (space1, literal("S"), alt((literal("0"), literal("1"))))
+            .map(|(_, _, s)| s == "1"),
+    )?;
+    parse_end(input, permissive)?;
+    Ok(enabled)
+}
+
+/// Parse the arguments of an A idle mode command: `M993 S<0|1>`.
+///
+/// `S1` holds A during moves that only turn X, and `S0` releases it.
+#[cfg(feature = "a-enable")]
+pub fn parse_a_idle_mode<'a>(
+    input: &mut &'a str,
+    permissive: bool,
+) -> core::result::Result<AIdleMode, Error> {
+    let mode = run(
+        input,
+        (space1, literal("S"), alt((literal("0"), literal("1")))).map(
+            |(_, _, s)| match s {
+                "1" => AIdleMode::Hold,
+                _ => AIdleMode::Free,
+            },
+        ),
+    )?;
+    parse_end(input, permissive)?;
+    Ok(mode)
+}
 
-        let value = sign * (before_decimal * 1000 + after_decimal);
+/// Run a parser on the input, mapping any failure to `InvalidGCode`.
+fn run<'a, O>(
+    input: &mut &'a str,
+    mut parser: impl Parser<&'a str, O, ContextError>,
+) -> core::result::Result<O, Error> {
+    parser.parse_next(input).map_err(|_| Error::InvalidGCode)
+}
+
+fn parse_x<'a>(input: &mut &'a str) -> Result<i64> {
+    literal("X").parse_next(input)?;
+    parse_millis_i64(input)
+}
+
+fn parse_a<'a>(input: &mut &'a str) -> Result<i64> {
+    literal("A").parse_next(input)?;
+    parse_angle_millis(input)
+}
 
-        Ok(value)
+/// Parse an arc word with a value in thousandths, eg. `I<mm>`.
+fn parse_arc_word<'a>(
+    letter: &'static str,
+) -> impl Parser<&'a str, i64, ContextError> {
+    move |input: &mut &'a str| {
+        literal(letter).parse_next(input)?;
+        parse_millis_i64(input)
     }
 }
 
+/// Narrow the value of an axis word to an `i32`, or report that it
+/// overflowed.
+fn fit_word(axis: Axis, value: i64) -> core::result::Result<i32, Error> {
+    i32::try_from(value).map_err(|_| {
+        Error::Overflow(Overflow {
+            axis,
+            stage: OverflowStage::Word,
+            value,
+        })
+    })
+}
+
 pub enum Error {
     InvalidGCode,
+    /// The value of a word does not fit.
+    Overflow(Overflow),
 }
 
 #[derive(Debug, uDebug)]
 pub struct Move {
     x_microns: Option<i32>,
     a_millidegrees: Option<i32>,
+    skipped_words: u8,
 }
 impl Move {
+    /// Parse the arguments of a move: `G0 [X<mm>] [A<degrees>]`. A may
+    /// also be given in turns, as `A<turns>r`.
+    ///
+    /// Any other word is rejected, unless `permissive` is set. Then other
+    /// words (eg. the `E` words of 3D printer G-code) are skipped, and
+    /// counted in [`Move::skipped_words`].
+    pub fn parse<'a>(
+        input: &mut &'a str,
+        permissive: bool,
+    ) -> core::result::Result<Move, Error> {
+        #[cfg(feature = "instrument")]
+        let _probe = Probe::start(Section::Parse);
+        let mut mv = Move {
+            x_microns: None,
+            a_millidegrees: None,
+            skipped_words: 0,
+        };
+        loop {
+            run(input, space0)?;
+            if run(input, opt(eof))?.is_some() {
+                return Ok(mv);
+            }
+            if let Some(x) = run(input, opt(parse_x))? {
+                if mv.x_microns.replace(fit_word(Axis::X, x)?).is_some() {
+                    return Err(Error::InvalidGCode);
+                }
+            } else if let Some(a) = run(input, opt(parse_a))? {
+                let a = fit_word(Axis::A, a)?;
+                if mv.a_millidegrees.replace(a).is_some() {
+                    return Err(Error::InvalidGCode);
+                }
+            } else if permissive {
+                run(input, (one_of(AsChar::is_alpha), take_till(0.., ' ')))?;
+                mv.skipped_words = mv.skipped_words.saturating_add(1);
+            } else {
+                return Err(Error::InvalidGCode);
+            }
+        }
+    }
+
+    /// Number of unknown words skipped when parsing permissively.
+    pub fn skipped_words(&self) -> u8 {
+        self.skipped_words
+    }
+
     pub fn x_microns(&self) -> i32 {
         self.x_microns.unwrap_or(0)
     }
@@ -121,3 +427,83 @@ impl Move {
         self.a_millidegrees.unwrap_or(0)
     }
 }
+
+/// An arc move: `G2`/`G3 [X<mm>] [A<degrees>] [I<mm>] [J<degrees>]`, or
+/// with `R<mm>` in place of `I` and `J`.
+///
+/// The arc is in the plane of X and A, where a millimetre of X is as long
+/// as a degree of A. `I` and `J` are the offset of the centre from the
+/// start, along X and A; at least one of them must be given. `R` is the
+/// radius instead, which is negative for the longer of the two arcs.
+#[derive(Debug, uDebug)]
+pub struct ArcMove {
+    /// The end, as for a [`Move`].
+    end: Move,
+    /// `I` and `J`, in thousandths of their units.
+    offset: [Option<i32>; 2],
+    /// `R`, in thousandths of the X units.
+    radius: Option<i32>,
+}
+impl ArcMove {
+    /// Parse the arguments of an arc move.
+    ///
+    /// Other words are treated as by [`Move::parse`].
+    pub fn parse<'a>(
+        input: &mut &'a str,
+        permissive: bool,
+    ) -> core::result::Result<ArcMove, Error> {
+        let mut arc = ArcMove {
+            end: Move {
+                x_microns: None,
+                a_millidegrees: None,
+                skipped_words: 0,
+            },
+            offset: [None, None],
+            radius: None,
+        };
+        loop {
+            run(input, space0)?;
+            if run(input, opt(eof))?.is_some() {
+                return Ok(arc);
+            }
+            let (word, value) = if let Some(x) = run(input, opt(parse_x))? {
+                (&mut arc.end.x_microns, fit_word(Axis::X, x)?)
+            } else if let Some(a) = run(input, opt(parse_a))? {
+                (&mut arc.end.a_millidegrees, fit_word(Axis::A, a)?)
+            } else if let Some(i) = run(input, opt(parse_arc_word("I")))? {
+                (&mut arc.offset[0], fit_word(Axis::X, i)?)
+            } else if let Some(j) = run(input, opt(parse_arc_word("J")))? {
+                (&mut arc.offset[1], fit_word(Axis::A, j)?)
+            } else if let Some(r) = run(input, opt(parse_arc_word("R")))? {
+                (&mut arc.radius, fit_word(Axis::X, r)?)
+            } else if permissive {
+                run(input, (one_of(AsChar::is_alpha), take_till(0.., ' ')))?;
+                arc.end.skipped_words = arc.end.skipped_words.saturating_add(1);
+                continue;
+            } else {
+                return Err(Error::InvalidGCode);
+            };
+            if word.replace(value).is_some() {
+                return Err(Error::InvalidGCode);
+            }
+        }
+    }
+
+    /// The end, as for a [`Move`].
+    pub fn end(&self) -> &Move {
+        &self.end
+    }
+
+    /// `I` and `J`, with a missing word as zero, or `R`, in thousandths of
+    /// their units.
+    pub fn center(&self) -> core::result::Result<ArcCenter, Error> {
+        match (self.offset, self.radius) {
+            ([None, None], Some(radius)) => Ok(ArcCenter::Radius(radius)),
+            ([Some(_), _] | [_, Some(_)], None) => {
+                let [i, j] = self.offset.map(|word| word.unwrap_or(0));
+                Ok(ArcCenter::Offset([i, j]))
+            }
+            _ => Err(Error::InvalidGCode),
+        }
+    }
+}
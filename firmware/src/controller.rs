@@ -1,19 +1,83 @@
-use core::fmt::{self, Display, Formatter, Write};
+use core::{
+    cell::RefCell,
+    convert::Infallible,
+    fmt::{self, Display, Formatter},
+};
 
 use arduino_hal::{
-    default_serial, delay_ms, pins,
+    default_serial, delay_us, pins,
     prelude::_unwrap_infallible_UnwrapInfallible, Peripherals, Pins,
 };
-use heapless::String;
+use heapless::{HistoryBuffer, String};
 use nb::block;
-use ufmt::{uWrite, uwriteln};
+use ufmt::uWrite;
 use ufmt_macros::uwrite;
+use winderbot_lib::{
+    convert::Converter,
+    diagnostics::Diagnostics,
+    line::{self, LineBuffer},
+    planner::{ArcCenter, ArcDir, ArcError},
+    protocol::{
+        during_motion, is_emergency_stop, is_realtime, DuringMotion, Response,
+        ABORT, BANNER, PROTOCOL_VERSION, SOFT_RESET, SPINDLE_STOP,
+    },
+    ramp::{step_period_us, Ramp},
+    repeat::{self, Feed, Player, Recorder},
+    serial::{self, SerialIo},
+    source::{CommandSource, SerialSource, TextSource},
+    truncate::format_truncated,
+    units::Thousandths,
+    validate::{self, MachineState, ValidationError},
+};
 
 use crate::{
-    command::{self, Command, Move},
-    machine::{Machine, MoveMode},
-    readln,
-    uno::UnoSerial,
+    clock,
+    command::{self, ArcMove, BurnIn, Move, SetOffset, SettingsCommand},
+    log::{log_debug, log_error, log_info, log_warn},
+    machine::{
+        Axis, Coordinates, Machine, MoveError, MoveMode, MoveObserver,
+        Overflow, OverflowStage, ZeroingError,
+    },
+    realtime::Realtime,
+    settings::{
+        self, BaudRate, ReportUnits, Setting, Settings, BAUD_RATE_ID, SETTINGS,
+    },
+    storage::{
+        self, Storage, MACROS, MACRO_SZ, STARTUP_LINES, STARTUP_LINE_SZ,
+    },
+    uno::{UnoSerial, UnoSerialIo},
+};
+
+#[cfg(feature = "capture")]
+use crate::capture::{Capture, CaptureInput, CaptureLatch, CAPTURES};
+#[cfg(feature = "done-out")]
+use crate::done_out::DoneOutput;
+#[cfg(feature = "door")]
+use crate::door::{Door, DoorInterlock};
+#[cfg(feature = "hard-limits")]
+use crate::hard_limit::{HardLimitGuard, HardLimits};
+#[cfg(feature = "instrument")]
+use crate::instrument::{self, Section};
+#[cfg(feature = "a-enable")]
+use crate::machine::AIdleMode;
+#[cfg(feature = "payout")]
+use crate::payout::{Payout, PayoutFollower};
+#[cfg(feature = "spindle2")]
+use crate::spindle2::{Spindle2, Spindle2Follower};
+#[cfg(feature = "trace")]
+use crate::step_trace::{TraceRecorder, TRACE_STEPS};
+#[cfg(feature = "sync-out")]
+use crate::sync_out::SyncOutput;
+#[cfg(feature = "telemetry")]
+use crate::telemetry::{DroReport, Telemetry};
+#[cfg(feature = "tension")]
+use crate::tension::{Tension, TensionLoop};
+#[cfg(feature = "a-index")]
+use crate::{index::IndexSensor, machine::HomingError};
+#[cfg(feature = "trace")]
+use winderbot_lib::{
+    interpolate::{Dir, Step},
+    trace::StepTrace,
 };
 
 /// Size of the buffer used to read from the UART.
@@ -24,162 +88,1723 @@ const READ_BUFFER_SZ: usize = 256;
 /// This is necessary for formatting strings.
 const WRITE_BUFFER_SZ: usize = 256;
 
-/// Write an error message, expanding its arguments.
-macro_rules! error {
-    ($self:expr, $($arg:tt)*) => {{
-        $self.output_buffer.clear();
-        let result = write!($self.output_buffer, "ERROR: {}", format_args!($($arg)*));
-        if result.is_err() {
-            $self.writeln("ERROR: Buffer overflow when formatting output!");
-        } else {
-            $self.writeln_buffer();
-        }
-    }};
-}
+/// Capabilities reported by `M115`. Features are reported as `1` when they
+/// are compiled in, and `0` otherwise.
+const CAPABILITIES: &[(&str, u32)] = &[
+    ("LINE_BUFFER_SIZE", READ_BUFFER_SZ as u32),
+    ("REPEAT_BUFFER_SIZE", REPEAT_BUFFER_SZ as u32),
+    ("EEPROM", 1),
+    ("EMERGENCY_PARSER", 1),
+    ("MACROS", MACROS as u32),
+    ("TELEMETRY", cfg!(feature = "telemetry") as u32),
+    ("TENSION", cfg!(feature = "tension") as u32),
+    ("PAYOUT", cfg!(feature = "payout") as u32),
+    ("DOOR", cfg!(feature = "door") as u32),
+    ("A_INDEX", cfg!(feature = "a-index") as u32),
+    ("SPINDLE2", cfg!(feature = "spindle2") as u32),
+    ("A_ENABLE", cfg!(feature = "a-enable") as u32),
+    ("INSTRUMENT", cfg!(feature = "instrument") as u32),
+    ("HARD_LIMITS", cfg!(feature = "hard-limits") as u32),
+    ("POSITION_CAPTURE", cfg!(feature = "capture") as u32),
+    ("SYNC_OUTPUT", cfg!(feature = "sync-out") as u32),
+    ("DONE_OUTPUT", cfg!(feature = "done-out") as u32),
+    ("STEP_TRACE", cfg!(feature = "trace") as u32),
+    ("TIMER_STEPS", cfg!(feature = "timer-steps") as u32),
+];
 
-/// Write an info message, expanding its arguments.
-macro_rules! info {
-    ($self:expr, $($arg:tt)*) => {{
-        $self.output_buffer.clear();
-        let result = write!($self.output_buffer, "INFO: {}", format_args!($($arg)*));
-        if result.is_err() {
-            $self.writeln("ERROR: Buffer overflow when formatting output!");
-        } else {
-            $self.writeln_buffer();
-        }
-    }};
-}
+/// Conversion from microns to thousandths of an inch, for positions
+/// reported in inches.
+const MICRONS_TO_MILS: Converter = Converter::new(1000, 25_400);
+
+/// Conversion from thousandths of an inch to microns, for X words in
+/// inches (`G20`).
+const MILS_TO_MICRONS: Converter = Converter::new(25_400, 1000);
+
+/// Size of the buffer that records repeated blocks.
+const REPEAT_BUFFER_SZ: usize = 256;
+
+/// Number of recent alarms kept in the alarm history.
+const ALARM_HISTORY_SZ: usize = 8;
+
+/// Interval at which the UART is polled between spindle mode steps, in
+/// microseconds. The UART only holds two received bytes, so this must be
+/// well under the time of a byte at the fastest baud rate.
+const SPIN_POLL_US: u32 = 20;
 
 pub struct Controller {
     serial: UnoSerial,
     machine: Option<Machine>,
-    input_buffer: String<READ_BUFFER_SZ>,
+    settings: Settings,
+    storage: Storage,
+    alarms: HistoryBuffer<Alarm, ALARM_HISTORY_SZ>,
+    #[cfg(feature = "telemetry")]
+    telemetry_interval: u16,
+    /// Rate of DRO position lines during moves, in lines per second, or
+    /// zero for none.
+    #[cfg(feature = "telemetry")]
+    dro_rate_hz: u16,
+    #[cfg(feature = "tension")]
+    tension: Tension,
+    #[cfg(feature = "payout")]
+    payout: Payout,
+    #[cfg(feature = "door")]
+    door: Door,
+    #[cfg(feature = "a-index")]
+    index: IndexSensor,
+    #[cfg(feature = "spindle2")]
+    spindle2: Spindle2,
+    #[cfg(feature = "hard-limits")]
+    hard_limits: HardLimits,
+    /// Whether a hard limit has tripped since motion was last unlocked
+    /// with `$X`. Neither a soft reset nor zeroing clears this.
+    #[cfg(feature = "hard-limits")]
+    hard_limit_alarm: bool,
+    #[cfg(feature = "capture")]
+    capture_input: CaptureInput,
+    #[cfg(feature = "capture")]
+    captures: HistoryBuffer<Capture, CAPTURES>,
+    #[cfg(feature = "sync-out")]
+    sync_output: SyncOutput,
+    #[cfg(feature = "done-out")]
+    done_output: DoneOutput,
+    /// The last steps taken, kept across soft resets so that they can be
+    /// listed after a fault.
+    #[cfg(feature = "trace")]
+    trace: StepTrace<TRACE_STEPS>,
+    input_buffer: LineBuffer<READ_BUFFER_SZ>,
     output_buffer: String<WRITE_BUFFER_SZ>,
+    repeat: Recorder<REPEAT_BUFFER_SZ>,
+    /// How the line that arrived during the last move ended, if one did.
+    /// The line is in the input buffer until it is handled.
+    held_line: Option<Result<(), line::Error>>,
+    /// Whether a macro is running.
+    in_macro: bool,
+    /// Whether a repeated block is running.
+    in_repeat: bool,
+    /// Totals of the running job, or of the last one.
+    job: Job,
+    /// Whether moves are queued for lookahead, rather than run at once, as
+    /// while a stored source runs.
+    queue_moves: bool,
+    /// Whether a feed hold was requested, and not yet released by a cycle
+    /// start. It lasts until the line that it held has finished.
+    feed_hold: bool,
+    /// Whether X words are in inches (`G20`) rather than millimetres.
+    inches: bool,
+    /// Speed of A in spindle mode (`M3`), in milli-RPM.
+    spindle: Ramp,
+    /// Time of the last update of the spindle speed, in milliseconds.
+    spindle_ms: u32,
+    diagnostics: Diagnostics,
 }
 impl Controller {
-    const BAUD_RATE: u32 = 57600;
-
     pub fn new() -> Self {
+        let mut storage = Storage::new();
+        let settings_reset = Self::settings_reset_requested();
+        if settings_reset {
+            storage.clear_baud_rate();
+        }
+        let mut settings = Settings::default();
+        settings.baud_rate =
+            storage.read_baud_rate().unwrap_or(BaudRate::DEFAULT);
+        let inches = settings.default_inches;
+
         let peripherals: Peripherals = unsafe { Peripherals::steal() };
         let pins: Pins = pins!(peripherals);
-
-        let serial = default_serial!(peripherals, pins, Self::BAUD_RATE);
+        let baud_rate = settings.baud_rate.bits_per_second();
+        let serial = default_serial!(peripherals, pins, baud_rate);
         let machine = None;
-        let input_buffer = String::new();
+        let alarms = HistoryBuffer::new();
+        #[cfg(feature = "telemetry")]
+        let telemetry_interval = 0;
+        #[cfg(feature = "tension")]
+        let tension = Tension::new();
+        #[cfg(feature = "payout")]
+        let payout = Payout::new();
+        #[cfg(feature = "door")]
+        let door = Door::new();
+        #[cfg(feature = "a-index")]
+        let index = IndexSensor::new();
+        #[cfg(feature = "spindle2")]
+        let spindle2 = Spindle2::new();
+        #[cfg(feature = "hard-limits")]
+        let hard_limits = HardLimits::new();
+        #[cfg(feature = "capture")]
+        let capture_input = CaptureInput::new();
+        #[cfg(feature = "sync-out")]
+        let sync_output = SyncOutput::new();
+        #[cfg(feature = "done-out")]
+        let done_output = DoneOutput::new();
+        let input_buffer = LineBuffer::new();
         let output_buffer = String::new();
+        let repeat = Recorder::new();
 
         let mut controller = Self {
             serial,
             machine,
+            settings,
+            storage,
+            alarms,
+            #[cfg(feature = "telemetry")]
+            telemetry_interval,
+            #[cfg(feature = "telemetry")]
+            dro_rate_hz: 0,
+            #[cfg(feature = "tension")]
+            tension,
+            #[cfg(feature = "payout")]
+            payout,
+            #[cfg(feature = "door")]
+            door,
+            #[cfg(feature = "a-index")]
+            index,
+            #[cfg(feature = "spindle2")]
+            spindle2,
+            #[cfg(feature = "hard-limits")]
+            hard_limits,
+            #[cfg(feature = "hard-limits")]
+            hard_limit_alarm: false,
+            #[cfg(feature = "capture")]
+            capture_input,
+            #[cfg(feature = "capture")]
+            captures: HistoryBuffer::new(),
+            #[cfg(feature = "sync-out")]
+            sync_output,
+            #[cfg(feature = "done-out")]
+            done_output,
+            #[cfg(feature = "trace")]
+            trace: StepTrace::new(),
             input_buffer,
             output_buffer,
+            repeat,
+            held_line: None,
+            in_macro: false,
+            in_repeat: false,
+            job: Job::default(),
+            queue_moves: false,
+            feed_hold: false,
+            inches,
+            spindle: Ramp::new(),
+            spindle_ms: 0,
+            diagnostics: Diagnostics::new(),
         };
-        controller.writeln("WINDERBOT!");
+        clock::init(peripherals.TC0);
+        #[cfg(feature = "instrument")]
+        instrument::init();
+        controller.write_banner();
+        if settings_reset {
+            log_warn!(controller, "Settings reset: default baud rate.");
+        }
+        controller.run_startup_lines();
         controller
     }
 
+    /// Write the banner, with the protocol version.
+    fn write_banner(&mut self) {
+        format_truncated(
+            &mut self.output_buffer,
+            format_args!("{} {}", BANNER, PROTOCOL_VERSION),
+        );
+        self.writeln_buffer();
+    }
+
+    /// Return `true` if the settings reset input is active: A5 is held low
+    /// (eg. by a jumper to ground) at power-up.
+    ///
+    /// This recovers a board whose stored baud rate the host cannot use.
+    fn settings_reset_requested() -> bool {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = pins!(peripherals);
+        let pin = pins.a5.into_pull_up_input();
+        // Let the pull-up charge the input.
+        delay_us(10);
+        pin.is_low()
+    }
+
+    /// Reset to the startup state without a power cycle: the machine must
+    /// be zeroed again and modal state is cleared. Settings are kept.
+    fn soft_reset(&mut self) {
+        self.machine = None;
+        self.spindle.stop();
+        self.inches = self.settings.default_inches;
+        #[cfg(feature = "telemetry")]
+        {
+            self.telemetry_interval = 0;
+            self.dro_rate_hz = 0;
+        }
+        self.input_buffer.clear();
+        self.held_line = None;
+        self.repeat.clear();
+        self.write_banner();
+        self.run_startup_lines();
+    }
+
+    /// Stop at once (`M112`, or the abort byte), and forget the machine, so
+    /// that it must be zeroed again. A repeated block being recorded is
+    /// dropped, but unlike a soft reset, the modal state is kept.
+    fn emergency_stop(&mut self) {
+        self.machine = None;
+        self.spindle.stop();
+        self.repeat.clear();
+    }
+
+    /// Execute the startup lines stored in EEPROM, echoing each one first
+    /// as `><line>` unless the quiet setting is on.
+    fn run_startup_lines(&mut self) {
+        let mut line: String<STARTUP_LINE_SZ> = String::new();
+        for index in 0..STARTUP_LINES {
+            self.storage.read_startup_line(index, &mut line);
+            if !line.is_empty() {
+                if !self.settings.quiet {
+                    uwrite!(self.serial, ">{}", line.as_str())
+                        .unwrap_infallible();
+                    self.end_line();
+                }
+                self.execute(line.as_str());
+            }
+        }
+    }
+
+    /// Read a line from the UART and execute it.
+    ///
+    /// A line that arrived during the last move is handled first. In
+    /// spindle mode, A takes a step instead, and a line is executed if one
+    /// arrives before the next step.
     pub fn command_step(&mut self) {
-        let result = match self.read_command() {
-            Command::Zero => self.zero(),
-            Command::AbsolutePositioning => self.absolute_positioning(),
-            Command::RelativePositioning => self.relative_positioning(),
-            Command::Move(mv) => self.do_move(mv),
-        };
-        /*
-        let result = match self.read_command() {
-            Command::Zero => self.zero(),
-            Command::AbsolutePositioning => self.absolute_positioning(),
-            Command::RelativePositioning => self.relative_positioning(),
-            Command::Move(mv) => self.do_move(mv),
-        };
-        */
+        if let Some(held) = self.held_line.take() {
+            self.run_held_line(held);
+            return;
+        }
+        if !self.spindle.is_stopped() {
+            if let Err(error) = self.spin_step(true) {
+                self.report_error(error, "");
+            }
+            return;
+        }
+        let serial = UnoSerialIo(&mut self.serial);
+        let mut source = SerialSource::new(serial, &mut self.input_buffer);
+        match source.next_line() {
+            Some(Ok(_)) => {
+                let line = self.input_buffer.take_line();
+                self.execute(line.as_str());
+            }
+            Some(Err(serial::Error::BufferOverflow)) => {
+                self.report_error(Error::LineTooLong, "")
+            }
+            Some(Err(serial::Error::SoftReset)) => self.soft_reset(),
+            Some(Err(serial::Error::Abort)) => {
+                self.emergency_stop();
+                self.report_error(Error::Aborted, "")
+            }
+            None => {}
+        }
+    }
+
+    /// Handle a line that arrived during a move, now that the move has
+    /// finished. See [`during_motion`] for which lines are rejected.
+    fn run_held_line(&mut self, held: Result<(), line::Error>) {
+        if let Err(line::Error::Overflow) = held {
+            self.report_error(Error::LineTooLong, "");
+            return;
+        }
+        let line = self.input_buffer.take_line();
+        match during_motion(line.as_str()) {
+            DuringMotion::Queue => self.execute(line.as_str()),
+            DuringMotion::Reject => {
+                self.report_error(Error::SentDuringMove, line.as_str())
+            }
+        }
+    }
 
+    /// Dispatch a line to its command handler and report the result.
+    fn execute(&mut self, line: &str) {
+        let start_ms = clock::millis();
+        let result = self.dispatch(line);
+        self.feed_hold = false;
         match result {
-            Ok(()) => self.writeln("Ok."),
-            Err(error) => error!(self, "{}", error),
+            Ok(()) if self.settings.report_command_time => {
+                let elapsed_ms = clock::millis().wrapping_sub(start_ms);
+                self.respond(Response::OkTimed(elapsed_ms))
+            }
+            Ok(()) => self.respond(Response::Ok),
+            Err(error) => self.report_error(error, line),
+        }
+    }
+
+    /// Report an error that ended a line, and record it in the alarm
+    /// history.
+    fn report_error(&mut self, error: Error, line: &str) {
+        self.record_alarm(error);
+        match error {
+            Error::InvalidGCode => {
+                log_error!(self, "Invalid GCode \"{}\"", line)
+            }
+            Error::LineTooLong => log_error!(
+                self,
+                "{} {} lines dropped since startup.",
+                error,
+                self.input_buffer.dropped_lines()
+            ),
+            error => log_error!(self, "{}", error),
+        }
+        if let Some(alarm) = error.alarm() {
+            self.respond(Response::Alarm(alarm));
+        }
+        self.respond(Response::Error(error.code()));
+        if let Error::Reset = error {
+            self.soft_reset();
+        }
+    }
+
+    /// Add an error to the alarm history, along with the machine position
+    /// when it happened.
+    fn record_alarm(&mut self, error: Error) {
+        let time_ms = clock::millis();
+        let position = self.machine.as_ref().map(Machine::position);
+        self.alarms.write(Alarm {
+            error,
+            time_ms,
+            position,
+        });
+    }
+
+    /// Write the alarm history, oldest first.
+    fn alarm_history(&mut self) -> Result<(), Error> {
+        let alarms = self.alarms.clone();
+        for (i, alarm) in alarms.oldest_ordered().enumerate() {
+            match alarm.position {
+                Some((x, a)) => format_truncated(
+                    &mut self.output_buffer,
+                    format_args!(
+                        "ALARM {} at {} ms: {} (X={}, A={})",
+                        i, alarm.time_ms, alarm.error, x, a
+                    ),
+                ),
+                None => format_truncated(
+                    &mut self.output_buffer,
+                    format_args!(
+                        "ALARM {} at {} ms: {} (not zeroed)",
+                        i, alarm.time_ms, alarm.error
+                    ),
+                ),
+            };
+            self.writeln_buffer();
+        }
+        Ok(())
+    }
+
+    /// Run a line.
+    ///
+    /// Blank lines and `%` program delimiters are ignored. A line starting
+    /// with `/` is skipped when block delete is on, and otherwise runs
+    /// without the `/`. Lines of a repeated block (`M808`) are recorded,
+    /// and run when the block is complete.
+    fn dispatch(&mut self, line: &str) -> Result<(), Error> {
+        let mut line = line.trim();
+        if line.is_empty() || line.starts_with('%') {
+            return Ok(());
+        }
+        if let Some(rest) = line.strip_prefix('/') {
+            if self.settings.block_delete {
+                return Ok(());
+            }
+            line = rest.trim_start();
+        }
+        // An emergency stop is not recorded into a repeated block.
+        if is_emergency_stop(line) {
+            return self.run_command(line);
+        }
+        match self.repeat.feed(line)? {
+            Feed::Pass => self.run_command(line),
+            Feed::Recorded => Ok(()),
+            Feed::Complete => self.run_repeat(),
+        }
+    }
+
+    /// Run the lines of a completed repeated block, stopping at the first
+    /// error.
+    fn run_repeat(&mut self) -> Result<(), Error> {
+        log_info!(self, "Running repeated block.");
+        let program = self.repeat.take();
+        let job = self.start_job();
+        self.in_repeat = true;
+        let result = self.run_source(&mut Player::new(&program));
+        self.in_repeat = false;
+        result?;
+        log_info!(self, "Completed repeated block.");
+        if job {
+            self.end_job()?;
         }
+        Ok(())
+    }
+
+    /// Run the commands of a stored macro, stopping at the first error.
+    ///
+    /// A macro cannot call another macro.
+    fn run_macro(&mut self, index: u8) -> Result<(), Error> {
+        if self.in_macro {
+            return Err(Error::InvalidGCode);
+        }
+        let mut commands: String<MACRO_SZ> = String::new();
+        self.storage.read_macro(index, &mut commands);
+        log_info!(self, "Running macro {}.", index);
+        let job = self.start_job();
+        self.in_macro = true;
+        let result = self.run_source(&mut TextSource::new(&commands, '|'));
+        self.in_macro = false;
+        result?;
+        log_info!(self, "Completed macro {}.", index);
+        if job {
+            self.end_job()?;
+        }
+        Ok(())
+    }
+
+    /// Start the totals of a job, unless one is already running (eg. a
+    /// macro called from a repeated block), and return `true` if this
+    /// starts one.
+    fn start_job(&mut self) -> bool {
+        if self.in_macro || self.in_repeat {
+            return false;
+        }
+        self.job = Job {
+            start_ms: clock::millis(),
+            lines: 0,
+            moves: self.diagnostics.moves,
+            slow_moves: self.diagnostics.slow_moves,
+            a_steps: self.machine.as_ref().map_or(0, |m| m.position().1),
+        };
+        true
+    }
+
+    /// Finish a job that has completed: run the end of job steps in the
+    /// settings (park X, turn A to an index, release A, and pulse the job
+    /// done output, in that order), and then write the job's totals as
+    /// `JOB-DONE Lines:<n> Moves:<n> SlowMoves:<n> Turns:<n> Time:<ms>`.
+    ///
+    /// The totals are up to the end of the job, before these steps. Turns
+    /// are whole turns of A, negative for turns backwards. A job that fails
+    /// skips all of this.
+    fn end_job(&mut self) -> Result<(), Error> {
+        let job = self.job;
+        let time_ms = clock::millis().wrapping_sub(job.start_ms);
+        let a_steps = self.machine.as_ref().map_or(0, |m| m.position().1);
+        let turns = (a_steps - job.a_steps) / Machine::A_STEPS_PER_REV as i64;
+        if self.settings.end_park_x {
+            let x_microns = self.settings.end_park_x_microns;
+            self.check(validate::Command::Move)?;
+            log_info!(self, "Parking X at {} microns.", x_microns);
+            self.run_motion(Motion::ParkX { x_microns })?;
+        }
+        if self.settings.end_index_a {
+            self.index_a(self.settings.end_a_index)?;
+        }
+        #[cfg(feature = "a-enable")]
+        if self.settings.end_release_a {
+            self.machine()?.release_a();
+            log_info!(self, "Released A.");
+        }
+        #[cfg(feature = "done-out")]
+        if self.settings.end_pulse_ms != 0 {
+            self.done_output.pulse(self.settings.end_pulse_ms);
+        }
+        uwrite!(
+            self.serial,
+            "JOB-DONE Lines:{} Moves:{} SlowMoves:{} Turns:{} Time:{}",
+            job.lines,
+            self.diagnostics.moves.wrapping_sub(job.moves),
+            self.diagnostics.slow_moves.wrapping_sub(job.slow_moves),
+            turns,
+            time_ms
+        )
+        .unwrap_infallible();
+        self.end_line();
+        Ok(())
+    }
+
+    /// Run every line from a stored source, stopping at the first error.
+    ///
+    /// The lines of a stored source are all known before they run, so its
+    /// moves are queued for lookahead, if the settings allow, and run on
+    /// from one to the next. The queue is run before any other command,
+    /// and at the end.
+    fn run_source(
+        &mut self,
+        source: &mut impl CommandSource<Error = Infallible>,
+    ) -> Result<(), Error> {
+        let queue_moves = self.queue_moves;
+        self.queue_moves = self.settings.lookahead;
+        let mut result = Ok(());
+        while let Some(Ok(line)) = source.next_line() {
+            self.job.lines = self.job.lines.saturating_add(1);
+            result = self.run_command(line);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.queue_moves = queue_moves;
+        // After a reset, the machine (and its queue) is forgotten.
+        let queued = self.run_queue();
+        result.and(queued)
+    }
+
+    /// Run the moves queued for lookahead, if any.
+    fn run_queue(&mut self) -> Result<(), Error> {
+        match &self.machine {
+            Some(machine) if machine.has_queue() => {
+                self.run_motion(Motion::RunQueue).map(|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run the handler registered for the command word of a line.
+    fn run_command(&mut self, line: &str) -> Result<(), Error> {
+        let mut input = line;
+        let word = command::parse_word(&mut input)?;
+        let handler = HANDLERS
+            .iter()
+            .flat_map(|group| group.iter())
+            .find(|handler| handler.word == word);
+        match handler {
+            Some(handler) => {
+                // Only moves follow on from the queue, and an emergency
+                // stop drops it.
+                if !matches!(handler.word, "G0" | "G53" | "M112") {
+                    self.run_queue()?;
+                }
+                (handler.run)(self, &mut input)?;
+                // Only left over when parsing permissively.
+                let skipped = input.trim();
+                if !skipped.is_empty() {
+                    log_warn!(self, "Skipped extra words \"{}\".", skipped);
+                }
+                Ok(())
+            }
+            None if self.settings.permissive_gcode => {
+                log_warn!(self, "Skipped unknown command \"{}\".", word);
+                Ok(())
+            }
+            None => Err(Error::InvalidGCode),
+        }
+    }
+
+    /// Parse the end of the arguments of a command: see
+    /// [`command::parse_end`].
+    fn parse_end(&self, input: &mut &str) -> Result<(), Error> {
+        let permissive = self.settings.permissive_gcode;
+        Ok(command::parse_end(input, permissive)?)
     }
 
     fn zero(&mut self) -> Result<(), Error> {
-        info!(self, "Starting to zero the machine.");
-        self.machine = Some(Machine::new());
-        info!(self, "Completed zeroing the machine.");
+        self.check(validate::Command::Zero)?;
+        log_info!(self, "Starting to zero the machine.");
+        self.machine = None;
+        let machine = Machine::new(&self.settings)?;
+        #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+        if machine.limit_switches_swapped()
+            != self.settings.x_limit_switches_swapped
+        {
+            log_warn!(self, "Swapped the crossed X limit switches.");
+            self.settings.x_limit_switches_swapped =
+                machine.limit_switches_swapped();
+        }
+        self.machine = Some(machine);
+        log_info!(self, "Completed zeroing the machine.");
+        Ok(())
+    }
+
+    #[cfg(feature = "a-index")]
+    fn home_a(&mut self) -> Result<(), Error> {
+        self.check(validate::Command::HomeA)?;
+        log_info!(self, "Starting to home A.");
+        let machine = self.machine.as_mut().ok_or(Error::NotZeroed)?;
+        let index = &self.index;
+        machine.home_a(|| index.is_active(), &self.settings)?;
+        log_info!(self, "Completed homing A.");
         Ok(())
     }
 
     fn absolute_positioning(&mut self) -> Result<(), Error> {
         self.machine()?.set_move_mode(MoveMode::Absolute);
-        info!(self, "Set absolute positioning mode.");
+        log_info!(self, "Set absolute positioning mode.");
         Ok(())
     }
 
     fn relative_positioning(&mut self) -> Result<(), Error> {
         self.machine()?.set_move_mode(MoveMode::Relative);
-        info!(self, "Set relative positioning mode.");
+        log_info!(self, "Set relative positioning mode.");
+        Ok(())
+    }
+
+    fn set_inches(&mut self, inches: bool) -> Result<(), Error> {
+        self.inches = inches;
+        if inches {
+            log_info!(self, "Set X units to inches.");
+        } else {
+            log_info!(self, "Set X units to millimetres.");
+        }
         Ok(())
     }
 
-    fn do_move(&mut self, mv: Move) -> Result<(), Error> {
-        let x = mv.x_microns();
+    /// Convert an X word, in thousandths of the current X units, to
+    /// microns.
+    fn x_word_microns(&self, x: i32) -> Result<i32, Error> {
+        if !self.inches {
+            return Ok(x);
+        }
+        MILS_TO_MICRONS
+            .checked_to_steps(x)
+            .ok_or(Error::Overflow(Overflow {
+                axis: Axis::X,
+                stage: OverflowStage::Conversion,
+                value: x.into(),
+            }))
+    }
+
+    fn do_move(
+        &mut self,
+        mv: Move,
+        coordinates: Coordinates,
+    ) -> Result<(), Error> {
+        let x = self.x_word_microns(mv.x_microns())?;
         let a = mv.a_millidegrees();
-        /*
-        info!(
+        if mv.skipped_words() > 0 {
+            log_warn!(self, "Skipped {} unknown words.", mv.skipped_words());
+        }
+        log_debug!(
             self,
-            "Starting move: X={} microns, A={} millidegrees.", x, a
+            "Starting move: X={} microns, A={} millidegrees.",
+            x,
+            a
         );
-        */
-        info!(self, "Starting move.");
-        self.machine()?
-            .move_millis(mv.x_microns(), mv.a_millidegrees());
-        info!(self, "Completed move.");
+        self.check(validate::Command::Move)?;
+        if self.queue_moves {
+            self.run_motion(Motion::Queue {
+                x_microns: x,
+                a_millidegrees: a,
+                coordinates,
+            })?;
+            log_debug!(self, "Queued move.");
+            return Ok(());
+        }
+        log_info!(self, "Starting move.");
+        self.run_motion(Motion::Move {
+            x_microns: x,
+            a_millidegrees: a,
+            coordinates,
+        })?;
+        log_info!(self, "Completed move.");
         Ok(())
     }
 
-    /// Return the zeroed machine, otherwise return an error indicating that
-    /// the machine must still be zeroed.
-    fn machine(&mut self) -> Result<&mut Machine, Error> {
-        match &mut self.machine {
-            None => Err(Error::NotZeroed),
-            Some(m) => Ok(m),
+    /// Make an arc move (`G2`/`G3`) in the plane of X and A. See
+    /// [`Machine::arc_millis`].
+    fn do_arc(&mut self, arc: ArcMove, dir: ArcDir) -> Result<(), Error> {
+        let x = self.x_word_microns(arc.end().x_microns())?;
+        let a = arc.end().a_millidegrees();
+        let center = match arc.center()? {
+            ArcCenter::Offset([i, j]) => {
+                ArcCenter::Offset([self.x_word_microns(i)?, j])
+            }
+            ArcCenter::Radius(r) => ArcCenter::Radius(self.x_word_microns(r)?),
+        };
+        if arc.end().skipped_words() > 0 {
+            log_warn!(
+                self,
+                "Skipped {} unknown words.",
+                arc.end().skipped_words()
+            );
         }
+        log_debug!(
+            self,
+            "Starting arc: X={} microns, A={} millidegrees.",
+            x,
+            a
+        );
+        self.check(validate::Command::Move)?;
+        log_info!(self, "Starting arc.");
+        self.run_motion(Motion::Arc {
+            x_microns: x,
+            a_millidegrees: a,
+            center,
+            dir,
+        })?;
+        log_info!(self, "Completed arc.");
+        Ok(())
+    }
+
+    /// Turn A to an index angle (`M19 P<index>`), eg. for access to the
+    /// chuck when loading a bobbin. See [`Machine::index_a`].
+    ///
+    /// This is refused while a job (a macro or a repeated block) runs.
+    fn index_a(&mut self, index: u16) -> Result<(), Error> {
+        self.check(validate::Command::IndexA { index })?;
+        log_info!(self, "Turning A to index {}.", index);
+        self.run_motion(Motion::IndexA { index })?;
+        log_info!(self, "Completed indexing A.");
+        Ok(())
     }
 
-    /// Block trying to read commands from the UART, until reading a command
-    /// succeeds.
-    fn read_command(&mut self) -> Command {
-        self.read_line();
-        loop {
-            match Command::parse(&mut self.input_buffer.as_str()) {
-                Err(command::Error::InvalidGCode) => {
-                    error!(
+    /// Run a motion, watching for real-time commands and the interlocks.
+    /// The caller checks that the motion is allowed first, with
+    /// [`Self::check`].
+    ///
+    /// Returns the planned and the elapsed time of the motion, in
+    /// milliseconds.
+    fn run_motion(&mut self, motion: Motion) -> Result<(u32, u32), Error> {
+        let machine = self.machine.as_mut().ok_or(Error::NotZeroed)?;
+        let serial = RefCell::new(&mut self.serial);
+        let realtime =
+            Realtime::new(&serial, &mut self.input_buffer, &mut self.feed_hold);
+        #[cfg(feature = "telemetry")]
+        let telemetry = Telemetry::new(
+            &serial,
+            self.telemetry_interval,
+            self.settings.line_ending(),
+        );
+        #[cfg(not(feature = "telemetry"))]
+        let telemetry = ();
+        #[cfg(feature = "telemetry")]
+        let dro = DroReport::new(&serial, self.dro_rate_hz, &self.settings);
+        #[cfg(not(feature = "telemetry"))]
+        let dro = ();
+        #[cfg(feature = "tension")]
+        let tension = TensionLoop::new(&mut self.tension, &self.settings);
+        #[cfg(not(feature = "tension"))]
+        let tension = ();
+        #[cfg(feature = "payout")]
+        let payout = PayoutFollower::new(&mut self.payout, &self.settings);
+        #[cfg(not(feature = "payout"))]
+        let payout = ();
+        #[cfg(feature = "door")]
+        let door = DoorInterlock::new(&self.door, &self.settings);
+        #[cfg(not(feature = "door"))]
+        let door = ();
+        #[cfg(feature = "spindle2")]
+        let spindle2 =
+            Spindle2Follower::new(&mut self.spindle2, &self.settings);
+        #[cfg(not(feature = "spindle2"))]
+        let spindle2 = ();
+        #[cfg(feature = "hard-limits")]
+        let hard_limit = HardLimitGuard::new(&self.hard_limits);
+        #[cfg(not(feature = "hard-limits"))]
+        let hard_limit = ();
+        #[cfg(feature = "capture")]
+        let capture = CaptureLatch::new(
+            &mut self.capture_input,
+            &mut self.captures,
+            &self.settings,
+        );
+        #[cfg(not(feature = "capture"))]
+        let capture = ();
+        #[cfg(feature = "sync-out")]
+        let sync_output = &mut self.sync_output;
+        #[cfg(not(feature = "sync-out"))]
+        let sync_output = ();
+        #[cfg(feature = "trace")]
+        let trace = TraceRecorder::new(&mut self.trace, machine.position());
+        #[cfg(not(feature = "trace"))]
+        let trace = ();
+        let mut observer = (
+            realtime,
+            door,
+            telemetry,
+            tension,
+            payout,
+            spindle2,
+            hard_limit,
+            capture,
+            sync_output,
+            trace,
+            dro,
+        );
+        let start_ms = clock::millis();
+        let queued = matches!(motion, Motion::Queue { .. });
+        let result = match motion {
+            Motion::Move {
+                x_microns,
+                a_millidegrees,
+                coordinates,
+            } => machine.move_millis(
+                x_microns,
+                a_millidegrees,
+                coordinates,
+                &self.settings,
+                &mut observer,
+            ),
+            Motion::Arc {
+                x_microns,
+                a_millidegrees,
+                center,
+                dir,
+            } => machine.arc_millis(
+                x_microns,
+                a_millidegrees,
+                center,
+                dir,
+                Coordinates::Bobbin,
+                &self.settings,
+                &mut observer,
+            ),
+            Motion::Queue {
+                x_microns,
+                a_millidegrees,
+                coordinates,
+            } => machine.queue_millis(
+                x_microns,
+                a_millidegrees,
+                coordinates,
+                &self.settings,
+                &mut observer,
+            ),
+            Motion::RunQueue => {
+                Ok(machine.run_queue(&self.settings, &mut observer))
+            }
+            Motion::ParkX { x_microns } => {
+                machine.park_x(x_microns, &self.settings, &mut observer)
+            }
+            Motion::IndexA { index } => {
+                Ok(machine.index_a(index, &self.settings, &mut observer))
+            }
+            Motion::Axis {
+                axis,
+                distance,
+                step_rate,
+            } => machine.move_axis(
+                axis,
+                distance,
+                step_rate,
+                &self.settings,
+                &mut observer,
+            ),
+        };
+        self.held_line = observer.0.held_line();
+        #[cfg(feature = "telemetry")]
+        observer.10.finish();
+        let report = result?;
+        let elapsed_ms = clock::millis()
+            .wrapping_sub(start_ms)
+            .saturating_sub(report.held_ms);
+        if observer.0.abort_requested() {
+            self.machine = None;
+            return Err(Error::Aborted);
+        }
+        if observer.0.reset_requested() {
+            return Err(Error::Reset);
+        }
+        if observer.0.a_stop_requested() {
+            return Err(Error::AStopped);
+        }
+        #[cfg(feature = "door")]
+        if observer.1.opened() {
+            return Err(Error::DoorOpen);
+        }
+        #[cfg(feature = "hard-limits")]
+        if observer.6.tripped() {
+            self.hard_limit_alarm = true;
+            self.machine = None;
+            return Err(Error::HardLimit);
+        }
+        let planned_ms = (report.planned_us / 1000) as u32;
+        if report.x_clipped_steps != 0 {
+            log_warn!(
+                self,
+                "X clipped by {} steps at the soft limits.",
+                report.x_clipped_steps
+            );
+        }
+        if report.rate_limited {
+            log_warn!(self, "Move slowed to the maximum step rate.");
+        }
+        if report.held_ms != 0 {
+            log_info!(self, "Move held for {} ms.", report.held_ms);
+        }
+        // A queued move that did not have to run anything yet is not timed.
+        if queued && report.planned_us == 0 {
+            return Ok((0, 0));
+        }
+        let slow = self.diagnostics.record_move(
+            planned_ms,
+            elapsed_ms,
+            self.settings.slow_move_percent,
+        );
+        if slow {
+            log_warn!(
+                self,
+                "Move took {} ms, planned {} ms: step rate not reached.",
+                elapsed_ms,
+                planned_ms
+            );
+        } else {
+            log_debug!(
+                self,
+                "Move took {} ms, planned {} ms.",
+                elapsed_ms,
+                planned_ms
+            );
+        }
+        Ok((planned_ms, elapsed_ms))
+    }
+
+    /// Cycle an axis out and back (`M980`), for mechanical burn-in and for
+    /// checking belt and coupler tension, then write a summary as
+    /// `BURNIN Cycles:<n> SlowLegs:<n> WorstOverrun:<ms> LimitHits:<n>`.
+    ///
+    /// Each leg is timed like a move, and a leg that is slow by the slow
+    /// move setting is reported. So is an X limit switch that is pressed
+    /// after a leg, which means that X has lost steps. A line sent during
+    /// the burn-in stops it after the current leg.
+    fn burn_in(&mut self, burn_in: BurnIn) -> Result<(), Error> {
+        let axis = burn_in.axis;
+        let distance = match axis {
+            Axis::X => self.x_word_microns(burn_in.distance)?,
+            Axis::A => burn_in.distance,
+        };
+        let back = distance.checked_neg().ok_or(Error::Overflow(Overflow {
+            axis,
+            stage: OverflowStage::Word,
+            value: distance.into(),
+        }))?;
+        let step_rate = burn_in.step_rate.unwrap_or(u16::MAX);
+        log_info!(self, "Starting burn-in of {} cycles.", burn_in.cycles);
+        let mut legs = Diagnostics::new();
+        let mut cycles = 0;
+        let mut limit_hits = 0;
+        'cycles: while cycles < burn_in.cycles {
+            for distance in [distance, back] {
+                self.check(validate::Command::Move)?;
+                let (planned_ms, elapsed_ms) =
+                    self.run_motion(Motion::Axis {
+                        axis,
+                        distance,
+                        step_rate,
+                    })?;
+                let limit = self.settings.slow_move_percent;
+                legs.record_move(planned_ms, elapsed_ms, limit);
+                if self.machine()?.limit_switch_down() {
+                    limit_hits += 1;
+                    log_warn!(
+                        self,
+                        "Limit switch pressed in cycle {}.",
+                        cycles
+                    );
+                }
+                if self.held_line.is_some() {
+                    log_warn!(
                         self,
-                        "Invalid GCode \"{}\"",
-                        self.input_buffer.as_str()
+                        "Burn-in stopped by a line sent during it."
                     );
+                    break 'cycles;
+                }
+            }
+            cycles += 1;
+        }
+        uwrite!(
+            self.serial,
+            "BURNIN Cycles:{} SlowLegs:{} WorstOverrun:{} LimitHits:{}",
+            cycles,
+            legs.slow_moves,
+            legs.worst_overrun_ms,
+            limit_hits
+        )
+        .unwrap_infallible();
+        self.end_line();
+        Ok(())
+    }
+
+    /// Move the origin of bobbin coordinates, by changing the bobbin flange
+    /// setting and the A origin setting.
+    fn set_offset(&mut self, offset: SetOffset) -> Result<(), Error> {
+        let x_origin = match offset.x_microns {
+            Some(x_microns) => Some(self.x_origin(x_microns, offset.current)?),
+            None => None,
+        };
+        let a_origin = match offset.a_millidegrees {
+            Some(a) => Some(self.a_origin(a, offset.current)?),
+            None => None,
+        };
+        if let Some(origin) = x_origin {
+            // $2: bobbin left flange X.
+            self.settings.set(2, origin)?;
+            log_info!(
+                self,
+                "Bobbin origin at machine X={} mm.",
+                Thousandths(origin.into())
+            );
+        }
+        if let Some(origin) = a_origin {
+            // $43: bobbin A origin.
+            self.settings.set(43, origin)?;
+            log_info!(
+                self,
+                "Bobbin origin at machine A={} degrees.",
+                Thousandths(origin.into())
+            );
+        }
+        Ok(())
+    }
+
+    /// Return the X origin of bobbin coordinates for a `G10` X word, in
+    /// machine microns. The word is the origin in machine coordinates, or
+    /// if `current` is set, the current position in bobbin coordinates.
+    fn x_origin(&self, x_microns: i32, current: bool) -> Result<u32, Error> {
+        let x_microns = self.x_word_microns(x_microns)?;
+        let origin = if current {
+            let machine = self.machine.as_ref().ok_or(Error::NotZeroed)?;
+            let (x, _) =
+                machine.position_millis(Coordinates::Machine, &self.settings);
+            let origin = x as i64 - x_microns as i64;
+            i32::try_from(origin).map_err(|_| {
+                Error::Overflow(Overflow {
+                    axis: Axis::X,
+                    stage: OverflowStage::Offset,
+                    value: origin,
+                })
+            })?
+        } else {
+            x_microns
+        };
+        u32::try_from(origin).map_err(|_| Error::SettingOutOfRange)
+    }
+
+    /// Return the A origin of bobbin coordinates for a `G10` A word, as a
+    /// machine angle within a turn, in millidegrees. The word is taken as
+    /// for [`Self::x_origin`]. Only the angle of the origin is kept, so
+    /// that the count of whole turns stays the same.
+    fn a_origin(
+        &self,
+        a_millidegrees: i32,
+        current: bool,
+    ) -> Result<u32, Error> {
+        let origin = if current {
+            let machine = self.machine.as_ref().ok_or(Error::NotZeroed)?;
+            let (_, a) =
+                machine.position_millis(Coordinates::Machine, &self.settings);
+            a.angle as i64 - a_millidegrees as i64
+        } else {
+            a_millidegrees as i64
+        };
+        Ok(origin.rem_euclid(360_000) as u32)
+    }
+
+    /// Start turning A continuously at a speed (`M3 S<rpm>`), or change
+    /// the speed if it is already turning.
+    ///
+    /// A ramps to the new speed at the spindle acceleration in the
+    /// settings, without stopping. The speed is limited by the maximum
+    /// step rate of A. Moves are refused until the spindle stops.
+    fn spindle_on(&mut self, rpm: u16) -> Result<(), Error> {
+        self.check(validate::Command::SpindleOn)?;
+        self.set_spindle_speed(rpm);
+        Ok(())
+    }
+
+    /// Change the speed of the spindle (a bare `S<rpm>` word). Unlike
+    /// `M3`, this does not start a spindle that is stopped.
+    fn spindle_speed(&mut self, rpm: u16) -> Result<(), Error> {
+        self.check(validate::Command::SpindleSpeed)?;
+        self.set_spindle_speed(rpm);
+        Ok(())
+    }
+
+    /// Set the target speed of the spindle, which must be allowed to turn.
+    fn set_spindle_speed(&mut self, rpm: u16) {
+        let max_milli_rpm = self.settings.a_max_step_rate as u32 * 60_000
+            / Machine::A_STEPS_PER_REV;
+        let mut milli_rpm = rpm as u32 * 1000;
+        if milli_rpm > max_milli_rpm {
+            log_warn!(self, "Spindle slowed to the maximum step rate.");
+            milli_rpm = max_milli_rpm;
+        }
+        if self.spindle.is_stopped() {
+            self.spindle_ms = clock::millis();
+        }
+        let accel = self.settings.a_spindle_accel as u32;
+        self.spindle.set_target(milli_rpm, accel);
+        log_info!(self, "Spindle to {} RPM.", rpm);
+    }
+
+    /// Stop the spindle (`M5`), ramping down at the spindle acceleration.
+    /// This completes once A has stopped.
+    fn spindle_off(&mut self) -> Result<(), Error> {
+        let accel = self.settings.a_spindle_accel as u32;
+        self.spindle.set_target(0, accel);
+        while !self.spindle.is_stopped() {
+            self.spin_step(false)?;
+        }
+        log_info!(self, "Spindle stopped.");
+        Ok(())
+    }
+
+    /// Take a step of A in spindle mode at the current speed, ramping it
+    /// towards the target, and watch the UART until the next step is due.
+    ///
+    /// A line that arrives is executed at once if `run_lines` is set,
+    /// which delays the next step by as long as the line takes. Otherwise
+    /// it is held, as during a move, unless it is `M112`. The spindle stops
+    /// at once on a soft reset, a spindle stop or an emergency stop, or if
+    /// the interlocks forbid motion.
+    fn spin_step(&mut self, run_lines: bool) -> Result<(), Error> {
+        if let Err(error) = self.check_interlocks() {
+            self.spindle.stop();
+            return Err(error);
+        }
+        let now_ms = clock::millis();
+        let elapsed_ms = now_ms.wrapping_sub(self.spindle_ms);
+        self.spindle_ms = now_ms;
+        let milli_rpm = self.spindle.update(elapsed_ms);
+        let period_us =
+            match step_period_us(milli_rpm, Machine::A_STEPS_PER_REV) {
+                Some(period_us) => {
+                    self.machine()?.spin_a();
+                    #[cfg(feature = "trace")]
+                    self.trace.record(Step::A(Dir::Pos), clock::micros());
+                    period_us
+                }
+                None => SPIN_POLL_US,
+            };
+        let mut waited_us = 0;
+        while waited_us < period_us {
+            match UnoSerialIo(&mut self.serial).try_read() {
+                Some(SOFT_RESET) => {
+                    self.spindle.stop();
+                    return Err(Error::Reset);
+                }
+                Some(SPINDLE_STOP) => {
+                    self.spindle.stop();
+                    return Err(Error::AStopped);
                 }
-                Ok(cmd) => return cmd,
+                Some(ABORT) => {
+                    self.emergency_stop();
+                    return Err(Error::Aborted);
+                }
+                // There is no move to hold or resume.
+                Some(byte) if is_realtime(byte) => {}
+                Some(byte) if self.held_line.is_none() => {
+                    match self.input_buffer.push(byte) {
+                        Some(Ok(())) if run_lines => {
+                            let line = self.input_buffer.take_line();
+                            self.execute(line.as_str());
+                            return Ok(());
+                        }
+                        Some(Err(_)) if run_lines => {
+                            return Err(Error::LineTooLong)
+                        }
+                        Some(Ok(()))
+                            if is_emergency_stop(self.input_buffer.line()) =>
+                        {
+                            self.input_buffer.clear();
+                            self.emergency_stop();
+                            return Err(Error::Aborted);
+                        }
+                        held => self.held_line = held,
+                    }
+                }
+                _ => {}
             }
+            delay_us(SPIN_POLL_US);
+            waited_us += SPIN_POLL_US;
+        }
+        Ok(())
+    }
+
+    /// Pause for a number of milliseconds, eg. to let a lead be anchored
+    /// at a tie-off position.
+    ///
+    /// The UART is watched as during a move: a soft reset or an emergency
+    /// stop ends the dwell at once, a feed hold stops the clock until a
+    /// cycle start, and a line that arrives is held.
+    fn dwell(&mut self, ms: u32) -> Result<(), Error> {
+        self.check(validate::Command::Dwell)?;
+        log_info!(self, "Dwell for {} ms.", ms);
+        let serial = RefCell::new(&mut self.serial);
+        let mut realtime =
+            Realtime::new(&serial, &mut self.input_buffer, &mut self.feed_hold);
+        let mut waited_ms = 0;
+        let mut last_ms = clock::millis();
+        while waited_ms < ms && !realtime.stop_requested() {
+            realtime.while_waiting();
+            delay_us(SPIN_POLL_US);
+            let now_ms = clock::millis();
+            if !realtime.hold_requested() {
+                waited_ms += now_ms.wrapping_sub(last_ms);
+            }
+            last_ms = now_ms;
+        }
+        self.held_line = realtime.held_line();
+        if realtime.abort_requested() {
+            self.emergency_stop();
+            return Err(Error::Aborted);
         }
+        if realtime.reset_requested() {
+            return Err(Error::Reset);
+        }
+        Ok(())
+    }
+
+    /// Write the position as
+    /// `X:<x> A<turns>:<angle> Count X:<steps> A:<steps>`, with X in bobbin
+    /// coordinates (after Marlin's `M114` format).
+    ///
+    /// A is given as whole turns and the angle within the turn, so that it
+    /// stays exact however long a job runs. `X` and the angle are given in
+    /// the report units from the settings: mm and degrees, steps, or inches
+    /// and degrees. The counts are always machine steps.
+    fn report_position(&mut self) -> Result<(), Error> {
+        let machine = self.machine.as_ref().ok_or(Error::NotZeroed)?;
+        let (x, a) =
+            machine.position_millis(Coordinates::Bobbin, &self.settings);
+        let (x_steps, a_steps) = machine.position();
+        match self.settings.report_units {
+            ReportUnits::Millimetres => format_truncated(
+                &mut self.output_buffer,
+                format_args!(
+                    "X:{} A{}:{} Count X:{} A:{}",
+                    Thousandths(x.into()),
+                    a.turns,
+                    Thousandths(a.angle.into()),
+                    x_steps,
+                    a_steps
+                ),
+            ),
+            ReportUnits::Steps => {
+                let (x, a) =
+                    machine.position_steps(Coordinates::Bobbin, &self.settings);
+                format_truncated(
+                    &mut self.output_buffer,
+                    format_args!(
+                        "X:{} A{}:{} Count X:{} A:{}",
+                        x, a.turns, a.angle, x_steps, a_steps
+                    ),
+                )
+            }
+            ReportUnits::Inches => format_truncated(
+                &mut self.output_buffer,
+                format_args!(
+                    "X:{} A{}:{} Count X:{} A:{}",
+                    Thousandths(MICRONS_TO_MILS.to_steps(x).into()),
+                    a.turns,
+                    Thousandths(a.angle.into()),
+                    x_steps,
+                    a_steps
+                ),
+            ),
+        };
+        self.writeln_buffer();
+        Ok(())
+    }
+
+    /// Write the machine state on a single line of `key:value` fields, for
+    /// scripts to poll (`M996`). Positions and soft limits are machine
+    /// coordinates in steps, whatever the report units; A is the whole step
+    /// count, which is negative left of zero. Moves always run at their
+    /// programmed feed, so the feed override is always 100%. Moves queued
+    /// for lookahead (in a macro or a repeated block) are run before this
+    /// report, as before any command other than a move, so the queue is
+    /// empty by then. Both are reported so that the line keeps one format.
+    /// Fields for position are left out until the machine is zeroed.
+    fn report_state(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "hard-limits")]
+        let locked = self.hard_limit_alarm;
+        #[cfg(not(feature = "hard-limits"))]
+        let locked = false;
+        let state = match (&self.machine, locked) {
+            (_, true) => "Alarm",
+            (None, false) => "NotZeroed",
+            (Some(_), false) => "Idle",
+        };
+        let units = match self.settings.report_units {
+            ReportUnits::Millimetres => "mm",
+            ReportUnits::Steps => "steps",
+            ReportUnits::Inches => "in",
+        };
+        match &self.machine {
+            None => format_truncated(
+                &mut self.output_buffer,
+                format_args!(
+                    "State:{} Units:{} Feed:100 Queue:0",
+                    state, units
+                ),
+            ),
+            Some(machine) => {
+                let (x, a) = machine.position();
+                let x_range = machine.x_range();
+                let mode = match machine.move_mode() {
+                    MoveMode::Absolute => "G90",
+                    MoveMode::Relative => "G91",
+                };
+                format_truncated(
+                    &mut self.output_buffer,
+                    format_args!(
+                        "State:{} X:{} A:{} XMin:{} XMax:{} Units:{} \
+                         Mode:{} Feed:100 Queue:0",
+                        state,
+                        x,
+                        a,
+                        x_range.min(),
+                        x_range.max(),
+                        units,
+                        mode
+                    ),
+                )
+            }
+        };
+        self.writeln_buffer();
+        Ok(())
+    }
+
+    /// Wait for all moves to finish (`M400`).
+    ///
+    /// A move sent on its own has finished before its line is answered.
+    /// Inside a macro or a repeated block, moves are queued for lookahead,
+    /// and run on from one to the next; this runs the rest of the queue,
+    /// eg. before a command that must wait for the winder to be still.
+    fn finish_moves(&mut self) -> Result<(), Error> {
+        self.run_queue()
     }
 
-    /// Keep trying to read a line of input from the UART, until it succeeds.
+    /// Write the diagnostics counters since startup (`M997`), as
+    /// `Moves:<n> SlowMoves:<n> WorstOverrun:<ms> DroppedLines:<n>`.
     ///
-    /// The line that was reqd is stored in `self.serial_buffer`.
-    fn read_line(&mut self) {
-        loop {
-            match readln::readln(&mut self.serial, &mut self.input_buffer) {
-                Ok(()) => break,
-                Err(readln::Error::BufferOverflow) => {
-                    error!(self, "Buffer overflow.")
+    /// Slow moves are those that took longer than the slow move setting
+    /// allows: the step rate was not reached, and steps may have been
+    /// skipped.
+    fn report_diagnostics(&mut self) -> Result<(), Error> {
+        let diagnostics = self.diagnostics;
+        uwrite!(
+            self.serial,
+            "Moves:{} SlowMoves:{} WorstOverrun:{} DroppedLines:{}",
+            diagnostics.moves,
+            diagnostics.slow_moves,
+            diagnostics.worst_overrun_ms,
+            self.input_buffer.dropped_lines()
+        )
+        .unwrap_infallible();
+        self.end_line();
+        Ok(())
+    }
+
+    /// Answer a Marlin temperature query. There are no heaters, so this
+    /// reports a cold hotend without a target.
+    fn report_temperature(&mut self) -> Result<(), Error> {
+        self.writeln("T:0.0 /0.0");
+        Ok(())
+    }
+
+    /// Write the firmware identification line, followed by a `Cap:<name>:<n>`
+    /// line for each capability (Marlin's `M115` format).
+    fn report_firmware(&mut self) -> Result<(), Error> {
+        format_truncated(
+            &mut self.output_buffer,
+            format_args!(
+                "FIRMWARE_NAME:WinderBot {} PROTOCOL_VERSION:{} \
+                 MACHINE_TYPE:Coil winder EXTRUDER_COUNT:0",
+                env!("CARGO_PKG_VERSION"),
+                PROTOCOL_VERSION
+            ),
+        );
+        self.writeln_buffer();
+        self.writeln("Cap:AXES:XA");
+        for (name, value) in CAPABILITIES {
+            format_truncated(
+                &mut self.output_buffer,
+                format_args!("Cap:{}:{}", name, value),
+            );
+            self.writeln_buffer();
+        }
+        Ok(())
+    }
+
+    /// Write the time since the firmware started.
+    fn uptime(&mut self) -> Result<(), Error> {
+        uwrite!(self.serial, "Uptime: {} ms", clock::millis())
+            .unwrap_infallible();
+        self.end_line();
+        Ok(())
+    }
+
+    #[cfg(feature = "a-enable")]
+    fn a_idle_mode(&mut self, a_idle_mode: AIdleMode) -> Result<(), Error> {
+        self.machine()?.set_a_idle_mode(a_idle_mode);
+        match a_idle_mode {
+            AIdleMode::Hold => log_info!(self, "A held during X-only moves."),
+            AIdleMode::Free => {
+                log_info!(self, "A released during X-only moves.")
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the captured positions, oldest first, in steps.
+    #[cfg(feature = "capture")]
+    fn report_captures(&mut self) -> Result<(), Error> {
+        let captures = self.captures.clone();
+        for (i, capture) in captures.oldest_ordered().enumerate() {
+            uwrite!(
+                self.serial,
+                "CAPTURE {} at {} ms: X={} A={}",
+                i,
+                capture.time_ms,
+                capture.x_pos,
+                capture.a_pos
+            )
+            .unwrap_infallible();
+            self.end_line();
+        }
+        Ok(())
+    }
+
+    /// Write the step trace, oldest step first, with the time of each step
+    /// in microseconds since startup (wrapping every 71 minutes).
+    #[cfg(feature = "trace")]
+    fn report_trace(&mut self) -> Result<(), Error> {
+        // The trace is too big to copy on the stack, so the lines are ended
+        // here rather than with `end_line`, which borrows all of `self`.
+        let line_ending = self.settings.line_ending();
+        for (i, event) in self.trace.events().enumerate() {
+            let (axis, dir) = match event.step {
+                Step::X(dir) => ('X', dir),
+                Step::A(dir) => ('A', dir),
+            };
+            let sign = match dir {
+                Dir::Pos => '+',
+                Dir::Neg => '-',
+            };
+            uwrite!(
+                self.serial,
+                "TRACE {} at {} us: {}{}{}",
+                i,
+                event.time_us,
+                axis,
+                sign,
+                line_ending
+            )
+            .unwrap_infallible();
+            self.serial.flush();
+        }
+        Ok(())
+    }
+
+    /// Write the worst-case durations of the timed sections, then clear
+    /// them.
+    #[cfg(feature = "instrument")]
+    fn timing(&mut self) -> Result<(), Error> {
+        uwrite!(
+            self.serial,
+            "Worst case: step {} us, parse {} us",
+            instrument::worst_us(Section::Step),
+            instrument::worst_us(Section::Parse)
+        )
+        .unwrap_infallible();
+        self.end_line();
+        instrument::clear();
+        Ok(())
+    }
+
+    #[cfg(feature = "spindle2")]
+    fn spindle2(&mut self, enabled: bool) -> Result<(), Error> {
+        self.spindle2.set_enabled(enabled);
+        if enabled {
+            log_info!(self, "Second spindle on.");
+        } else {
+            log_info!(self, "Second spindle off.");
+        }
+        Ok(())
+    }
+
+    /// Set the rate of DRO position lines during moves (`M989 S<hz>`).
+    /// `S0`, or no `S`, turns them off.
+    #[cfg(feature = "telemetry")]
+    fn dro(&mut self, rate_hz: u16) -> Result<(), Error> {
+        self.dro_rate_hz = rate_hz;
+        if rate_hz == 0 {
+            log_info!(self, "DRO reports off.");
+        } else {
+            log_info!(self, "DRO reports at {} Hz during moves.", rate_hz);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn telemetry(&mut self, interval: u16) -> Result<(), Error> {
+        self.telemetry_interval = interval;
+        if interval == 0 {
+            log_info!(self, "Telemetry off.");
+        } else {
+            log_info!(self, "Telemetry every {} steps.", interval);
+        }
+        Ok(())
+    }
+
+    fn settings(&mut self, command: SettingsCommand) -> Result<(), Error> {
+        match command {
+            SettingsCommand::List => {
+                for setting in SETTINGS {
+                    self.write_setting(setting);
+                }
+            }
+            SettingsCommand::Set { id, value } => {
+                self.settings.set(id, value)?;
+                log_info!(self, "Set ${}={}.", id, value);
+                if id == BAUD_RATE_ID {
+                    self.storage.write_baud_rate(self.settings.baud_rate);
+                    log_info!(self, "New baud rate applies at restart.");
+                }
+            }
+            SettingsCommand::StartupLines => {
+                let mut line: String<STARTUP_LINE_SZ> = String::new();
+                for index in 0..STARTUP_LINES {
+                    self.storage.read_startup_line(index, &mut line);
+                    uwrite!(self.serial, "$N{}={}", index, line.as_str())
+                        .unwrap_infallible();
+                    self.end_line();
+                }
+            }
+            SettingsCommand::SetStartupLine { index, line } => {
+                if index >= STARTUP_LINES {
+                    return Err(Error::UnknownSetting);
+                }
+                self.storage.write_startup_line(index, line)?;
+                log_info!(self, "Set startup line {}.", index);
+            }
+            SettingsCommand::Macros => {
+                let mut commands: String<MACRO_SZ> = String::new();
+                for index in 0..MACROS {
+                    self.storage.read_macro(index, &mut commands);
+                    uwrite!(self.serial, "$M{}={}", index, commands.as_str())
+                        .unwrap_infallible();
+                    self.end_line();
+                }
+            }
+            SettingsCommand::SetMacro { index, commands } => {
+                if index >= MACROS {
+                    return Err(Error::UnknownSetting);
                 }
+                self.storage.write_macro(index, commands)?;
+                log_info!(self, "Set macro {}.", index);
+            }
+            SettingsCommand::Unlock => self.unlock()?,
+            SettingsCommand::Info => {
+                self.writeln(concat!(
+                    "[VER:",
+                    env!("CARGO_PKG_VERSION"),
+                    ":WinderBot]"
+                ));
+                format_truncated(
+                    &mut self.output_buffer,
+                    format_args!("[PROTOCOL:{}]", PROTOCOL_VERSION),
+                );
+                self.writeln_buffer();
+                let checksum = self.settings.checksum();
+                uwrite!(self.serial, "[SETTINGS:{}]", checksum)
+                    .unwrap_infallible();
+                self.end_line();
             }
         }
+        Ok(())
+    }
+
+    /// Write a setting as a line `$<id>=<value> (<description>)`.
+    fn write_setting(&mut self, setting: &Setting) {
+        uwrite!(
+            self.serial,
+            "${}={} ({})",
+            setting.id,
+            (setting.get)(&self.settings),
+            setting.description
+        )
+        .unwrap_infallible();
+        self.end_line();
+    }
+
+    /// Return an error if the door interlock or the hard limits forbid
+    /// motion.
+    ///
+    /// A hard limit that is found open raises an alarm, and motion stays
+    /// locked until it is unlocked with `$X`, even once the switch closes.
+    fn check_interlocks(&mut self) -> Result<(), Error> {
+        let result = validate::check_interlocks(&self.machine_state());
+        self.raise_validation_error(result)
+    }
+
+    /// Return an error if the machine may not run a command in its current
+    /// state. See [`validate::validate`], which host tools share.
+    fn check(&mut self, command: validate::Command) -> Result<(), Error> {
+        let result = validate::validate(command, &self.machine_state());
+        self.raise_validation_error(result)
+    }
+
+    /// Convert the result of a validation, raising an alarm if it found a
+    /// hard limit open.
+    fn raise_validation_error(
+        &mut self,
+        result: Result<(), ValidationError>,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "hard-limits")]
+        if result == Err(ValidationError::HardLimit) {
+            self.hard_limit_alarm = true;
+            self.machine = None;
+        }
+        result.map_err(Error::from)
+    }
+
+    /// Return the state that decides whether commands may run, from the
+    /// machine, the spindle and the interlock inputs.
+    fn machine_state(&self) -> MachineState {
+        #[allow(unused_mut)]
+        let mut state = MachineState {
+            zeroed: self.machine.is_some(),
+            spindle_running: !self.spindle.is_stopped(),
+            job_active: self.in_macro || self.in_repeat,
+            a_index_positions: self.settings.a_index_positions,
+            ..MachineState::default()
+        };
+        #[cfg(feature = "door")]
+        {
+            state.door_open = self.door.blocks_motion(&self.settings);
+        }
+        #[cfg(feature = "hard-limits")]
+        {
+            state.hard_limit_open = self.hard_limits.tripped();
+            state.locked = self.hard_limit_alarm;
+        }
+        state
+    }
+
+    /// Unlock motion after a hard limit alarm (`$X`). The hard limit
+    /// switches must be closed again.
+    fn unlock(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "hard-limits")]
+        {
+            if self.hard_limits.tripped() {
+                return Err(Error::HardLimit);
+            }
+            if self.hard_limit_alarm {
+                self.hard_limit_alarm = false;
+                log_warn!(self, "Unlocked. Zero the machine before moving.");
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the zeroed machine, otherwise return an error indicating that
+    /// the machine must still be zeroed.
+    fn machine(&mut self) -> Result<&mut Machine, Error> {
+        match &mut self.machine {
+            None => Err(Error::NotZeroed),
+            Some(m) => Ok(m),
+        }
+    }
+
+    /// Write a protocol response line.
+    fn respond(&mut self, response: Response) {
+        format_truncated(&mut self.output_buffer, format_args!("{}", response));
+        self.writeln_buffer();
     }
 
     /// Write a line to the UART.
     fn writeln(&mut self, s: &str) {
-        self.output_buffer.clear();
-        self.output_buffer.write_str(s).unwrap(); // TODO
+        format_truncated(&mut self.output_buffer, format_args!("{}", s));
         self.writeln_buffer();
     }
 
@@ -188,18 +1813,737 @@ impl Controller {
         self.serial
             .write_str(self.output_buffer.as_str())
             .unwrap_infallible();
-        self.serial.write_char('\n').unwrap_infallible();
+        self.end_line();
+    }
+
+    /// End a line on the UART, with the configured line ending.
+    fn end_line(&mut self) {
+        self.serial
+            .write_str(self.settings.line_ending())
+            .unwrap_infallible();
         self.serial.flush();
     }
 }
 
+/// A motion for [`Controller::run_motion`].
+enum Motion {
+    /// A move, to a target or by a distance depending on the move mode.
+    Move {
+        x_microns: i32,
+        a_millidegrees: i32,
+        coordinates: Coordinates,
+    },
+    /// An arc, in bobbin coordinates, to a target or by a distance
+    /// depending on the move mode.
+    Arc {
+        x_microns: i32,
+        a_millidegrees: i32,
+        center: ArcCenter,
+        dir: ArcDir,
+    },
+    /// A move queued for lookahead, which runs the first queued move if
+    /// the queue is full.
+    Queue {
+        x_microns: i32,
+        a_millidegrees: i32,
+        coordinates: Coordinates,
+    },
+    /// The moves queued for lookahead.
+    RunQueue,
+    /// A move of X alone to a position in machine coordinates, in microns,
+    /// whatever the move mode.
+    ParkX { x_microns: u32 },
+    /// A turn of A to an index angle.
+    IndexA { index: u16 },
+    /// A move of one axis by a distance, at no more than a step rate.
+    Axis {
+        axis: Axis,
+        distance: i32,
+        step_rate: u16,
+    },
+}
+
+/// Totals of a job (a macro or a repeated block), for its `JOB-DONE`
+/// report.
+#[derive(Clone, Copy, Default)]
+struct Job {
+    /// Time the job started, in milliseconds.
+    start_ms: u32,
+    /// Number of lines run, including those of macros that it called.
+    lines: u32,
+    /// Moves and slow moves recorded in the diagnostics before the job.
+    moves: u32,
+    slow_moves: u32,
+    /// A position when the job started, in steps.
+    a_steps: i64,
+}
+
+/// A command handler.
+struct Handler {
+    /// Command word that selects this handler (eg. `G0`).
+    word: &'static str,
+    /// Executes the command. The input holds the rest of the line after the
+    /// command word, for the handler to parse its arguments from.
+    run: fn(&mut Controller, &mut &str) -> Result<(), Error>,
+}
+
+/// Command handlers, grouped by the subsystem that provides them.
+///
+/// Optional subsystems register stubs in place of their handlers when their
+/// feature is not enabled, which refuse their commands with
+/// [`Error::FeatureDisabled`].
+const HANDLERS: &[&[Handler]] = &[
+    STOP_HANDLERS,
+    MOTION_HANDLERS,
+    SPINDLE_HANDLERS,
+    A_INDEXING_HANDLERS,
+    INDEX_HANDLERS,
+    SPINDLE2_HANDLERS,
+    A_ENABLE_HANDLERS,
+    SETTINGS_HANDLERS,
+    MACRO_HANDLERS,
+    STATUS_HANDLERS,
+    MARLIN_HANDLERS,
+    TELEMETRY_HANDLERS,
+    INSTRUMENT_HANDLERS,
+    CAPTURE_HANDLERS,
+    TRACE_HANDLERS,
+];
+
+/// Handlers for stopping the machine, which are allowed at any time.
+const STOP_HANDLERS: &[Handler] = &[Handler {
+    word: "M112",
+    run: |c, _| {
+        c.emergency_stop();
+        Err(Error::Aborted)
+    },
+}];
+
+/// Handlers for zeroing, units, positioning modes, moves, dwells and offsets.
+const MOTION_HANDLERS: &[Handler] = &[
+    Handler {
+        word: "Z",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.zero()
+        },
+    },
+    Handler {
+        word: "G20",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.set_inches(true)
+        },
+    },
+    Handler {
+        word: "G21",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.set_inches(false)
+        },
+    },
+    Handler {
+        word: "G90",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.absolute_positioning()
+        },
+    },
+    Handler {
+        word: "G91",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.relative_positioning()
+        },
+    },
+    Handler {
+        word: "G0",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.do_move(Move::parse(input, permissive)?, Coordinates::Bobbin)
+        },
+    },
+    Handler {
+        word: "G2",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.do_arc(ArcMove::parse(input, permissive)?, ArcDir::Clockwise)
+        },
+    },
+    Handler {
+        word: "G3",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            let arc = ArcMove::parse(input, permissive)?;
+            c.do_arc(arc, ArcDir::CounterClockwise)
+        },
+    },
+    Handler {
+        word: "M980",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.burn_in(BurnIn::parse(input, permissive)?)
+        },
+    },
+    Handler {
+        word: "G53",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            let mv = command::parse_machine_move(input, permissive)?;
+            c.do_move(mv, Coordinates::Machine)
+        },
+    },
+    Handler {
+        word: "G4",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.dwell(command::parse_dwell(input, permissive)?)
+        },
+    },
+    Handler {
+        word: "G10",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.set_offset(SetOffset::parse(input, permissive)?)
+        },
+    },
+];
+
+/// Handlers for turning A continuously, as a spindle.
+const SPINDLE_HANDLERS: &[Handler] = &[
+    Handler {
+        word: "M3",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.spindle_on(command::parse_spindle_on(input, permissive)?)
+        },
+    },
+    Handler {
+        word: "M5",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.spindle_off()
+        },
+    },
+    Handler {
+        word: "S",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.spindle_speed(command::parse_spindle_speed(input, permissive)?)
+        },
+    },
+];
+
+/// Handlers for turning A to index angles.
+const A_INDEXING_HANDLERS: &[Handler] = &[Handler {
+    word: "M19",
+    run: |c, input| {
+        let permissive = c.settings.permissive_gcode;
+        c.index_a(command::parse_index(input, permissive)?)
+    },
+}];
+
+/// Handlers for the spindle index sensor.
+#[cfg(feature = "a-index")]
+const INDEX_HANDLERS: &[Handler] = &[Handler {
+    word: "ZA",
+    run: |c, input| {
+        c.parse_end(input)?;
+        c.home_a()
+    },
+}];
+
+/// Stubs that refuse the commands of the a-index feature, which is not
+/// built in.
+#[cfg(not(feature = "a-index"))]
+const INDEX_HANDLERS: &[Handler] = &[Handler {
+    word: "ZA",
+    run: |_, _| Err(Error::FeatureDisabled("a-index")),
+}];
+
+/// Handlers for the second spindle.
+#[cfg(feature = "spindle2")]
+const SPINDLE2_HANDLERS: &[Handler] = &[Handler {
+    word: "M992",
+    run: |c, input| {
+        let permissive = c.settings.permissive_gcode;
+        c.spindle2(command::parse_spindle2(input, permissive)?)
+    },
+}];
+
+/// Stubs that refuse the commands of the spindle2 feature, which is not
+/// built in.
+#[cfg(not(feature = "spindle2"))]
+const SPINDLE2_HANDLERS: &[Handler] = &[Handler {
+    word: "M992",
+    run: |_, _| Err(Error::FeatureDisabled("spindle2")),
+}];
+
+/// Handlers for the A driver enable output.
+#[cfg(feature = "a-enable")]
+const A_ENABLE_HANDLERS: &[Handler] = &[Handler {
+    word: "M993",
+    run: |c, input| {
+        let permissive = c.settings.permissive_gcode;
+        c.a_idle_mode(command::parse_a_idle_mode(input, permissive)?)
+    },
+}];
+
+/// Stubs that refuse the commands of the a-enable feature, which is not
+/// built in.
+#[cfg(not(feature = "a-enable"))]
+const A_ENABLE_HANDLERS: &[Handler] = &[Handler {
+    word: "M993",
+    run: |_, _| Err(Error::FeatureDisabled("a-enable")),
+}];
+
+/// Handlers for stored macros. `M810` runs macro 0, `M811` macro 1, and so
+/// on; `$M` lists and changes them.
+const MACRO_HANDLERS: &[Handler] = &[
+    Handler {
+        word: "M810",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.run_macro(0)
+        },
+    },
+    Handler {
+        word: "M811",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.run_macro(1)
+        },
+    },
+    Handler {
+        word: "M812",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.run_macro(2)
+        },
+    },
+    Handler {
+        word: "M813",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.run_macro(3)
+        },
+    },
+];
+
+/// Handlers for reading and changing settings.
+const SETTINGS_HANDLERS: &[Handler] = &[Handler {
+    word: "$",
+    run: |c, input| c.settings(SettingsCommand::parse(input)?),
+}];
+
+/// Handlers for status reports.
+const STATUS_HANDLERS: &[Handler] = &[
+    Handler {
+        word: "M31",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.uptime()
+        },
+    },
+    Handler {
+        word: "M991",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.alarm_history()
+        },
+    },
+    Handler {
+        word: "M996",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.report_state()
+        },
+    },
+    Handler {
+        word: "M997",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.report_diagnostics()
+        },
+    },
+];
+
+/// Handlers for the queries that Marlin hosts (eg. Pronterface) send when
+/// they connect, so that they can drive the winder.
+const MARLIN_HANDLERS: &[Handler] = &[
+    Handler {
+        word: "M105",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.report_temperature()
+        },
+    },
+    Handler {
+        word: "M114",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.report_position()
+        },
+    },
+    Handler {
+        word: "M115",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.report_firmware()
+        },
+    },
+    Handler {
+        word: "M400",
+        run: |c, input| {
+            c.parse_end(input)?;
+            c.finish_moves()
+        },
+    },
+];
+
+/// Handlers for the telemetry subsystem.
+#[cfg(feature = "telemetry")]
+const TELEMETRY_HANDLERS: &[Handler] = &[
+    Handler {
+        word: "M990",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.telemetry(command::parse_telemetry(input, permissive)?)
+        },
+    },
+    Handler {
+        word: "M989",
+        run: |c, input| {
+            let permissive = c.settings.permissive_gcode;
+            c.dro(command::parse_telemetry(input, permissive)?)
+        },
+    },
+];
+
+/// Stubs that refuse the commands of the telemetry feature, which is not
+/// built in.
+#[cfg(not(feature = "telemetry"))]
+const TELEMETRY_HANDLERS: &[Handler] = &[
+    Handler {
+        word: "M990",
+        run: |_, _| Err(Error::FeatureDisabled("telemetry")),
+    },
+    Handler {
+        word: "M989",
+        run: |_, _| Err(Error::FeatureDisabled("telemetry")),
+    },
+];
+
+/// Handlers for the timing instrumentation.
+#[cfg(feature = "instrument")]
+const INSTRUMENT_HANDLERS: &[Handler] = &[Handler {
+    word: "M994",
+    run: |c, input| {
+        c.parse_end(input)?;
+        c.timing()
+    },
+}];
+
+/// Stubs that refuse the commands of the instrument feature, which is not
+/// built in.
+#[cfg(not(feature = "instrument"))]
+const INSTRUMENT_HANDLERS: &[Handler] = &[Handler {
+    word: "M994",
+    run: |_, _| Err(Error::FeatureDisabled("instrument")),
+}];
+
+/// Handlers for position capture.
+#[cfg(feature = "capture")]
+const CAPTURE_HANDLERS: &[Handler] = &[Handler {
+    word: "M995",
+    run: |c, input| {
+        c.parse_end(input)?;
+        c.report_captures()
+    },
+}];
+
+/// Stubs that refuse the commands of the capture feature, which is not
+/// built in.
+#[cfg(not(feature = "capture"))]
+const CAPTURE_HANDLERS: &[Handler] = &[Handler {
+    word: "M995",
+    run: |_, _| Err(Error::FeatureDisabled("capture")),
+}];
+
+/// Handlers for the step trace.
+#[cfg(feature = "trace")]
+const TRACE_HANDLERS: &[Handler] = &[Handler {
+    word: "M998",
+    run: |c, input| {
+        c.parse_end(input)?;
+        c.report_trace()
+    },
+}];
+
+/// Stubs that refuse the commands of the trace feature, which is not
+/// built in.
+#[cfg(not(feature = "trace"))]
+const TRACE_HANDLERS: &[Handler] = &[Handler {
+    word: "M998",
+    run: |_, _| Err(Error::FeatureDisabled("trace")),
+}];
+
+/// An entry in the alarm history.
+#[derive(Clone, Copy)]
+struct Alarm {
+    error: Error,
+    /// Uptime when the alarm was raised, in milliseconds.
+    time_ms: u32,
+    /// Machine position (X and A steps) when the alarm was raised, if the
+    /// machine had been zeroed.
+    position: Option<(u32, i64)>,
+}
+
+#[derive(Clone, Copy)]
 enum Error {
     NotZeroed,
+    InvalidGCode,
+    UnknownSetting,
+    SettingOutOfRange,
+    /// A soft reset was requested while the machine was moving.
+    Reset,
+    InvalidStartupLine,
+    /// A line was too long for the input buffer, and was discarded.
+    LineTooLong,
+    /// A repeated block was too long for its buffer, and was discarded.
+    RepeatTooLong,
+    InvalidMacro,
+    /// An absolute X target was beyond the soft limits.
+    OutOfRange,
+    /// The door is open, or was opened during a move.
+    #[cfg(feature = "door")]
+    DoorOpen,
+    /// A homing found no spindle index.
+    #[cfg(feature = "a-index")]
+    IndexNotFound,
+    /// A hard limit switch is open, or opened during a move.
+    #[cfg(feature = "hard-limits")]
+    HardLimit,
+    /// Motion is locked after a hard limit alarm.
+    #[cfg(feature = "hard-limits")]
+    Locked,
+    /// A line that changes the settings or the machine's reference arrived
+    /// during a move.
+    SentDuringMove,
+    /// A command that needs the machine to be still was sent while A was
+    /// turning in spindle mode.
+    SpindleRunning,
+    /// A value overflowed while a command was worked out.
+    Overflow(Overflow),
+    /// A command that is only for an idle machine was sent during a job.
+    JobActive,
+    /// Zeroing found the limit switches crossed.
+    SwitchesSwapped,
+    /// An arc's centre or radius does not fit its start and end.
+    InvalidArc(ArcError),
+    /// A was stopped by a spindle stop, eg. because the wire snapped.
+    AStopped,
+    /// An emergency stop was requested.
+    Aborted,
+    /// A command belongs to a feature that is not built in.
+    FeatureDisabled(&'static str),
+}
+impl From<command::Error> for Error {
+    fn from(error: command::Error) -> Self {
+        match error {
+            command::Error::InvalidGCode => Error::InvalidGCode,
+            command::Error::Overflow(overflow) => Error::Overflow(overflow),
+        }
+    }
+}
+impl From<ValidationError> for Error {
+    fn from(error: ValidationError) -> Self {
+        match error {
+            ValidationError::NotZeroed => Error::NotZeroed,
+            ValidationError::SpindleRunning => Error::SpindleRunning,
+            ValidationError::SpindleStopped | ValidationError::InvalidIndex => {
+                Error::InvalidGCode
+            }
+            ValidationError::JobActive => Error::JobActive,
+            #[cfg(feature = "door")]
+            ValidationError::DoorOpen => Error::DoorOpen,
+            #[cfg(feature = "hard-limits")]
+            ValidationError::HardLimit => Error::HardLimit,
+            #[cfg(feature = "hard-limits")]
+            ValidationError::Locked => Error::Locked,
+            // The state never has an interlock that was not built in, but
+            // the command is refused rather than risking a panic.
+            #[cfg(not(feature = "door"))]
+            ValidationError::DoorOpen => Error::InvalidGCode,
+            #[cfg(not(feature = "hard-limits"))]
+            ValidationError::HardLimit | ValidationError::Locked => {
+                Error::InvalidGCode
+            }
+        }
+    }
+}
+impl From<settings::Error> for Error {
+    fn from(error: settings::Error) -> Self {
+        match error {
+            settings::Error::UnknownSetting => Error::UnknownSetting,
+            settings::Error::ValueOutOfRange => Error::SettingOutOfRange,
+        }
+    }
+}
+impl From<storage::Error> for Error {
+    fn from(error: storage::Error) -> Self {
+        match error {
+            storage::Error::InvalidStartupLine => Error::InvalidStartupLine,
+            storage::Error::InvalidMacro => Error::InvalidMacro,
+        }
+    }
+}
+impl Error {
+    /// Code reported for the error in an `error:<code>` response.
+    fn code(&self) -> u8 {
+        match self {
+            Error::InvalidGCode => 1,
+            Error::NotZeroed => 2,
+            Error::UnknownSetting => 3,
+            Error::SettingOutOfRange => 4,
+            Error::InvalidStartupLine => 5,
+            #[cfg(feature = "door")]
+            Error::DoorOpen => 6,
+            Error::Reset => 7,
+            #[cfg(feature = "a-index")]
+            Error::IndexNotFound => 8,
+            Error::LineTooLong => 9,
+            Error::OutOfRange => 10,
+            Error::RepeatTooLong => 11,
+            Error::InvalidMacro => 12,
+            #[cfg(feature = "hard-limits")]
+            Error::HardLimit => 13,
+            #[cfg(feature = "hard-limits")]
+            Error::Locked => 14,
+            Error::SentDuringMove => 15,
+            Error::SpindleRunning => 16,
+            Error::Overflow(_) => 17,
+            Error::JobActive => 18,
+            Error::SwitchesSwapped => 19,
+            Error::InvalidArc(_) => 20,
+            Error::AStopped => 21,
+            Error::Aborted => 22,
+            Error::FeatureDisabled(_) => 23,
+        }
+    }
+
+    /// Code reported in an `ALARM:<code>` response, for errors that leave
+    /// the machine needing attention.
+    fn alarm(&self) -> Option<u8> {
+        match self {
+            // The machine has been forgotten, and must be zeroed again.
+            Error::Reset => Some(1),
+            // The A angle is unknown.
+            #[cfg(feature = "a-index")]
+            Error::IndexNotFound => Some(2),
+            // The machine has been forgotten, and motion is locked.
+            #[cfg(feature = "hard-limits")]
+            Error::HardLimit => Some(3),
+            // The machine has been forgotten, and must be zeroed again.
+            Error::Aborted => Some(4),
+            _ => None,
+        }
+    }
+}
+#[cfg(feature = "a-index")]
+impl From<HomingError> for Error {
+    fn from(error: HomingError) -> Self {
+        match error {
+            HomingError::IndexNotFound => Error::IndexNotFound,
+        }
+    }
+}
+impl From<ZeroingError> for Error {
+    fn from(error: ZeroingError) -> Self {
+        match error {
+            ZeroingError::SwitchesSwapped => Error::SwitchesSwapped,
+        }
+    }
+}
+impl From<repeat::Error> for Error {
+    fn from(error: repeat::Error) -> Self {
+        match error {
+            repeat::Error::Overflow => Error::RepeatTooLong,
+            repeat::Error::Invalid
+            | repeat::Error::UnmatchedEnd
+            | repeat::Error::TooDeep => Error::InvalidGCode,
+        }
+    }
+}
+impl From<MoveError> for Error {
+    fn from(error: MoveError) -> Self {
+        match error {
+            MoveError::OutOfRange => Error::OutOfRange,
+            MoveError::Overflow(overflow) => Error::Overflow(overflow),
+            MoveError::InvalidArc(error) => Error::InvalidArc(error),
+        }
+    }
 }
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             Error::NotZeroed => write!(f, "Machine not zeroed."),
+            Error::InvalidGCode => write!(f, "Invalid GCode."),
+            Error::UnknownSetting => write!(f, "Unknown setting."),
+            Error::SettingOutOfRange => {
+                write!(f, "Setting value out of range.")
+            }
+            Error::Reset => write!(f, "Reset while in motion."),
+            Error::LineTooLong => write!(f, "Line too long; discarded."),
+            Error::OutOfRange => write!(f, "Target beyond soft limits."),
+            Error::RepeatTooLong => {
+                write!(f, "Repeated block too long; discarded.")
+            }
+            #[cfg(feature = "door")]
+            Error::DoorOpen => write!(f, "Door open."),
+            #[cfg(feature = "a-index")]
+            Error::IndexNotFound => write!(f, "Spindle index not found."),
+            #[cfg(feature = "hard-limits")]
+            Error::HardLimit => write!(f, "Hard limit tripped."),
+            #[cfg(feature = "hard-limits")]
+            Error::Locked => {
+                write!(f, "Locked by a hard limit; unlock with $X.")
+            }
+            Error::SentDuringMove => {
+                write!(f, "Sent during a move; wait for ok first.")
+            }
+            Error::SpindleRunning => {
+                write!(f, "Spindle running; stop it with M5 first.")
+            }
+            Error::Overflow(overflow) => write!(f, "{}", overflow),
+            Error::JobActive => write!(f, "Not allowed while a job runs."),
+            Error::SwitchesSwapped => {
+                write!(f, "X limit switches crossed, or X reversed.")
+            }
+            Error::InvalidArc(ArcError::NoRadius) => {
+                write!(f, "Arc has no radius.")
+            }
+            Error::InvalidArc(ArcError::InvalidRadius) => {
+                write!(f, "Arc radius does not reach its end.")
+            }
+            Error::InvalidArc(ArcError::EndNotOnCircle) => {
+                write!(f, "Arc end is not on its circle.")
+            }
+            Error::AStopped => write!(f, "A stopped by a spindle stop."),
+            Error::Aborted => write!(f, "Emergency stop; zero again."),
+            Error::FeatureDisabled(feature) => {
+                write!(f, "Feature \"{}\" is not built in.", feature)
+            }
+            Error::InvalidStartupLine => write!(
+                f,
+                "Startup lines must be ASCII and at most {} characters.",
+                STARTUP_LINE_SZ
+            ),
+            Error::InvalidMacro => write!(
+                f,
+                "Macros must be ASCII and at most {} characters.",
+                MACRO_SZ
+            ),
         }
     }
 }
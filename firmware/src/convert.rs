@@ -0,0 +1,243 @@
+//! Conversions between physical units and motor steps.
+//!
+//! A conversion is a ratio of steps to units (eg. 6400 steps per 5000
+//! microns). Multiplying a value by the ratio directly can overflow 32-bit
+//! arithmetic long before the result does, and 64-bit arithmetic is slow
+//! on the AVR. [`Converter`] instead reduces the ratio once, when it is
+//! constructed, and converts with a quotient / remainder split that stays
+//! within 32 bits whenever the result fits.
+
+/// Converts between a physical unit and motor steps, at a fixed ratio.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Converter {
+    /// Steps in the reduced ratio.
+    steps: i32,
+    /// Units in the reduced ratio.
+    units: i32,
+}
+impl Converter {
+    /// Creates a converter for `steps` steps per `units` units.
+    ///
+    /// The ratio is reduced to lowest terms, so this is best evaluated in
+    /// a `const`.
+    pub const fn new(steps: u32, units: u32) -> Self {
+        let divisor = gcd(steps, units);
+        Self {
+            steps: (steps / divisor) as i32,
+            units: (units / divisor) as i32,
+        }
+    }
+
+    /// Converts a value in units to steps, rounding toward zero.
+    pub const fn to_steps(&self, value: i32) -> i32 {
+        scale(value, self.steps, self.units)
+    }
+
+    /// Converts a value in units to steps, rounding toward zero, or returns
+    /// `None` if the result does not fit in an `i32`.
+    pub const fn checked_to_steps(&self, value: i32) -> Option<i32> {
+        checked_scale(value, self.steps, self.units)
+    }
+
+    /// Converts steps to a value in units, rounding toward zero.
+    pub const fn to_units(&self, steps: i32) -> i32 {
+        scale(steps, self.units, self.steps)
+    }
+}
+
+/// Converts a sequence of relative moves to steps, carrying the fraction of
+/// a step left over from each move into the next.
+///
+/// Converting each move on its own rounds away part of a step every time,
+/// and moves shorter than a step are lost altogether, so a long program of
+/// small moves drifts. The accumulator instead rounds the running total:
+/// after any sequence of moves, the steps returned add up to the total
+/// distance converted to steps, rounded down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Accumulator {
+    converter: Converter,
+    /// Part of a step carried over, in units of `1 / converter.units` of a
+    /// step. Always in `[0, converter.units)`.
+    remainder: i32,
+}
+impl Accumulator {
+    /// Creates an accumulator with nothing carried over.
+    pub const fn new(converter: Converter) -> Self {
+        Self {
+            converter,
+            remainder: 0,
+        }
+    }
+
+    /// Converts a relative move in units to steps.
+    pub fn to_steps(&mut self, delta: i32) -> i32 {
+        let Converter { steps, units } = self.converter;
+        let q = delta.div_euclid(units);
+        let r = delta.rem_euclid(units) * steps + self.remainder;
+        self.remainder = r % units;
+        q * steps + r / units
+    }
+
+    /// Converts a relative move in units to steps, like
+    /// [`to_steps`](Self::to_steps), or returns `None` if the result does
+    /// not fit in an `i32`. Nothing is carried over from a move that
+    /// overflows.
+    pub fn checked_to_steps(&mut self, delta: i32) -> Option<i32> {
+        let Converter { steps, units } = self.converter;
+        let q = delta.div_euclid(units);
+        let r = delta.rem_euclid(units) * steps + self.remainder;
+        let result = q.checked_mul(steps)?.checked_add(r / units)?;
+        self.remainder = r % units;
+        Some(result)
+    }
+
+    /// Drops the fraction of a step carried over, eg. after an absolute
+    /// move has put the axis exactly on a step.
+    pub fn reset(&mut self) {
+        self.remainder = 0;
+    }
+}
+
+/// Computes `value * num / den`, rounding toward zero, without forming the
+/// full product.
+///
+/// With `value = q * den + r`, the result is `q * num + r * num / den`, and
+/// `|r * num| < den * num`, which is small for a reduced ratio.
+const fn scale(value: i32, num: i32, den: i32) -> i32 {
+    let q = value / den;
+    let r = value % den;
+    q * num + r * num / den
+}
+
+/// Computes `value * num / den` like [`scale`], or returns `None` if the
+/// result does not fit in an `i32`.
+const fn checked_scale(value: i32, num: i32, den: i32) -> Option<i32> {
+    let q = value / den;
+    let r = value % den;
+    match q.checked_mul(num) {
+        Some(whole) => whole.checked_add(r * num / den),
+        None => None,
+    }
+}
+
+/// Greatest common divisor.
+const fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 6400 steps per 5 mm, in microns.
+    const X: Converter = Converter::new(6400, 5000);
+    /// 6400 steps per revolution, in millidegrees.
+    const A: Converter = Converter::new(6400, 360_000);
+
+    /// Reference conversion in 64-bit arithmetic.
+    fn reference(value: i32, num: i32, den: i32) -> i32 {
+        (value as i64 * num as i64 / den as i64) as i32
+    }
+
+    #[test]
+    fn test_ratio_is_reduced() {
+        assert_eq!(
+            Converter {
+                steps: 32,
+                units: 25
+            },
+            X
+        );
+        assert_eq!(
+            Converter {
+                steps: 4,
+                units: 225
+            },
+            A
+        );
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        for value in (-2_000_000..2_000_000).step_by(997) {
+            assert_eq!(reference(value, 6400, 5000), X.to_steps(value));
+            assert_eq!(reference(value, 5000, 6400), X.to_units(value));
+            assert_eq!(reference(value, 6400, 360_000), A.to_steps(value));
+            assert_eq!(reference(value, 360_000, 6400), A.to_units(value));
+        }
+    }
+
+    #[test]
+    fn test_small_values_round_toward_zero() {
+        for value in -1000..1000 {
+            assert_eq!(reference(value, 4, 225), A.to_steps(value));
+            assert_eq!(reference(value, 225, 4), A.to_units(value));
+        }
+    }
+
+    #[test]
+    fn test_accumulator_carries_sub_step_moves() {
+        let mut acc = Accumulator::new(A);
+        // 1 step is 56.25 millidegrees.
+        let steps: i32 = (0..100).map(|_| acc.to_steps(10)).sum();
+        assert_eq!(17, steps);
+        assert_eq!(0, A.to_steps(10));
+    }
+
+    #[test]
+    fn test_accumulator_matches_total() {
+        let mut acc = Accumulator::new(X);
+        let mut total = 0i64;
+        let mut steps = 0i64;
+        for i in 0..10_000 {
+            let delta = (i * 7919) % 2001 - 1000;
+            total += delta as i64;
+            steps += acc.to_steps(delta) as i64;
+            assert_eq!((total * 32).div_euclid(25), steps);
+        }
+    }
+
+    #[test]
+    fn test_accumulator_reset() {
+        let mut acc = Accumulator::new(A);
+        assert_eq!(0, acc.to_steps(50));
+        acc.reset();
+        assert_eq!(0, acc.to_steps(50));
+        assert_eq!(1, acc.to_steps(50));
+    }
+
+    #[test]
+    fn test_large_values_do_not_overflow() {
+        // 1000 revolutions of A: the direct product overflows 32 bits.
+        assert_eq!(6_400_000, A.to_steps(360_000_000));
+        assert_eq!(-6_400_000, A.to_steps(-360_000_000));
+        assert_eq!(360_000_000, A.to_units(6_400_000));
+        assert_eq!(i32::MAX / 32 * 25, X.to_units(i32::MAX / 32 * 32));
+    }
+
+    #[test]
+    fn test_checked_conversion() {
+        assert_eq!(Some(6_400_000), A.checked_to_steps(360_000_000));
+        assert_eq!(
+            Some(i32::MAX / 32 * 32),
+            X.checked_to_steps(i32::MAX / 32 * 25)
+        );
+        assert_eq!(None, X.checked_to_steps(i32::MAX));
+        assert_eq!(None, X.checked_to_steps(i32::MIN));
+    }
+
+    #[test]
+    fn test_checked_accumulator() {
+        let mut acc = Accumulator::new(X);
+        assert_eq!(Some(1), acc.checked_to_steps(1));
+        assert_eq!(None, acc.checked_to_steps(i32::MAX));
+        // The overflowing move left the carried fraction alone, so 25
+        // microns in all still make 32 steps.
+        assert_eq!(31, acc.to_steps(24));
+    }
+}
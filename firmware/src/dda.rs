@@ -0,0 +1,108 @@
+//! Digital differential analyser (DDA): the incremental core of
+//! Bresenham's algorithm.
+//!
+//! A move is taken in iterations, one for each step of the axis with the
+//! most steps (the major axis). A [`Dda`] tells another axis on which of
+//! those iterations to step, so that it stays within half a step of the
+//! straight line. It only adds and subtracts `u32`s, with no multiplying
+//! or dividing, so it is cheap enough to run on every step.
+
+/// Spreads the steps of one axis over the iterations of a move.
+#[derive(Clone, Copy, Debug)]
+pub struct Dda {
+    /// Number of steps of this axis.
+    steps: u32,
+    /// Number of iterations, ie. steps of the major axis.
+    major: u32,
+    /// Error term, in `[0, major)`, or zero if `major` is zero.
+    error: u32,
+}
+impl Dda {
+    /// Creates a DDA for `steps` steps over `major` iterations.
+    ///
+    /// `steps` is limited to `major`, since an axis steps at most once an
+    /// iteration.
+    pub fn new(steps: u32, major: u32) -> Self {
+        Self {
+            steps: steps.min(major),
+            major,
+            error: major / 2,
+        }
+    }
+
+    /// Moves on to the next iteration, and returns whether the axis steps
+    /// in it.
+    ///
+    /// This must be called at most `major` times.
+    pub fn next_iteration(&mut self) -> bool {
+        let step = self.error < self.steps;
+        self.error = match step {
+            true => self.error + (self.major - self.steps),
+            false => self.error - self.steps,
+        };
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a DDA over all of its iterations, checking that the axis is
+    /// within half a step of the straight line after each one, and returns
+    /// the number of steps taken.
+    fn run(steps: u32, major: u32) -> u32 {
+        let mut dda = Dda::new(steps, major);
+        let mut taken = 0;
+        for iteration in 1..=major as u64 {
+            taken += dda.next_iteration() as u32;
+            // 2 * major * (distance from the line), in steps.
+            let deviation = (2 * major as u64 * taken as u64)
+                .abs_diff(2 * iteration * steps as u64);
+            assert!(
+                deviation <= major as u64,
+                "{} over {}: {} steps after {}",
+                steps,
+                major,
+                taken,
+                iteration
+            );
+        }
+        taken
+    }
+
+    #[test]
+    fn test_reaches_the_end() {
+        for major in 0..=64 {
+            for steps in 0..=major {
+                assert_eq!(steps, run(steps, major));
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_moves() {
+        for (steps, major) in [(1, 100_000), (99_999, 100_000), (3, 65_537)] {
+            assert_eq!(steps, run(steps, major));
+        }
+    }
+
+    #[test]
+    fn test_extreme_counts() {
+        let mut all = Dda::new(u32::MAX, u32::MAX);
+        let mut none = Dda::new(0, u32::MAX);
+        let mut half = Dda::new(u32::MAX / 2, u32::MAX);
+        for _ in 0..1000 {
+            assert!(all.next_iteration());
+            assert!(!none.next_iteration());
+        }
+        let taken = (0..1000).filter(|_| half.next_iteration()).count();
+        assert_eq!(500, taken);
+    }
+
+    #[test]
+    fn test_too_many_steps() {
+        let mut dda = Dda::new(5, 3);
+        assert_eq!(3, (0..3).filter(|_| dda.next_iteration()).count());
+    }
+}
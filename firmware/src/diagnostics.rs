@@ -0,0 +1,91 @@
+//! Counters of how well the firmware keeps up with what it is asked to do.
+//!
+//! A move that takes much longer than planned means that the step loop
+//! could not keep to the step rate: the settings ask for more than the
+//! controller can do, and the motors may have skipped steps.
+
+/// Move timing counters, since startup.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Diagnostics {
+    /// Number of moves completed.
+    pub moves: u32,
+    /// Number of moves that took longer than allowed.
+    pub slow_moves: u32,
+    /// Longest time that a move took beyond its plan, in milliseconds.
+    pub worst_overrun_ms: u32,
+}
+impl Diagnostics {
+    /// Time that every move is allowed beyond its plan, in milliseconds, so
+    /// that very short moves are not reported for clock resolution and
+    /// per-move overheads.
+    pub const OVERRUN_MARGIN_MS: u32 = 10;
+
+    /// Creates counters at zero.
+    pub const fn new() -> Self {
+        Self {
+            moves: 0,
+            slow_moves: 0,
+            worst_overrun_ms: 0,
+        }
+    }
+
+    /// Records the time that a move took, and returns `true` if it was
+    /// slow.
+    ///
+    /// A move is slow if it took longer than `limit_percent` of its planned
+    /// time, plus [`Self::OVERRUN_MARGIN_MS`]. A limit of zero never
+    /// reports a move as slow.
+    pub fn record_move(
+        &mut self,
+        planned_ms: u32,
+        elapsed_ms: u32,
+        limit_percent: u16,
+    ) -> bool {
+        self.moves = self.moves.saturating_add(1);
+        let overrun_ms = elapsed_ms.saturating_sub(planned_ms);
+        self.worst_overrun_ms = self.worst_overrun_ms.max(overrun_ms);
+        let limit_ms = (planned_ms as u64 * limit_percent as u64 / 100)
+            + Self::OVERRUN_MARGIN_MS as u64;
+        let slow = limit_percent != 0 && elapsed_ms as u64 > limit_ms;
+        if slow {
+            self.slow_moves = self.slow_moves.saturating_add(1);
+        }
+        slow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_move() {
+        let mut diagnostics = Diagnostics::new();
+        assert!(!diagnostics.record_move(100, 105, 200));
+        assert!(!diagnostics.record_move(100, 210, 200));
+        assert!(diagnostics.record_move(100, 211, 200));
+        assert_eq!(
+            Diagnostics {
+                moves: 3,
+                slow_moves: 1,
+                worst_overrun_ms: 111,
+            },
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn test_short_moves_have_a_margin() {
+        let mut diagnostics = Diagnostics::new();
+        assert!(!diagnostics.record_move(0, 10, 100));
+        assert!(diagnostics.record_move(0, 11, 100));
+    }
+
+    #[test]
+    fn test_zero_limit_is_off() {
+        let mut diagnostics = Diagnostics::new();
+        assert!(!diagnostics.record_move(1, 5000, 0));
+        assert_eq!(0, diagnostics.slow_moves);
+        assert_eq!(4999, diagnostics.worst_overrun_ms);
+    }
+}
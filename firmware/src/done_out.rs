@@ -0,0 +1,27 @@
+use arduino_hal::{
+    delay_ms,
+    port::{mode::Output, Pin, D12},
+    Peripherals, Pins,
+};
+
+/// Job done output, which pulses at the end of each job, eg. to light a
+/// lamp or to tell a PLC that the bobbin can be taken off.
+pub struct DoneOutput {
+    pin: Pin<Output, D12>,
+}
+impl DoneOutput {
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+        let mut pin = pins.d12.into_output();
+        pin.set_low();
+        Self { pin }
+    }
+
+    /// Pulse D12 high for a number of milliseconds.
+    pub fn pulse(&mut self, ms: u16) {
+        self.pin.set_high();
+        delay_ms(ms as u32);
+        self.pin.set_low();
+    }
+}
@@ -0,0 +1,66 @@
+use arduino_hal::{
+    port::{
+        mode::{Input, PullUp},
+        Pin, D2,
+    },
+    Peripherals, Pins,
+};
+
+use crate::{machine::MoveObserver, settings::Settings};
+
+/// Safety door (or cover) interlock.
+///
+/// The door switch is wired between D2 and ground, closed while the door is
+/// shut. D2 is pulled up, so an open door (or a broken wire) reads high.
+pub struct Door {
+    pin: Pin<Input<PullUp>, D2>,
+}
+impl Door {
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+        Self {
+            pin: pins.d2.into_pull_up_input(),
+        }
+    }
+
+    /// Return `true` if motion must not run because the door is open.
+    ///
+    /// This is always `false` while the interlock is overridden in the
+    /// settings.
+    pub fn blocks_motion(&self, settings: &Settings) -> bool {
+        settings.door_interlock && self.pin.is_high()
+    }
+}
+
+/// Stops a move as soon as the door is opened.
+pub struct DoorInterlock<'a> {
+    door: &'a Door,
+    settings: &'a Settings,
+    opened: bool,
+}
+impl<'a> DoorInterlock<'a> {
+    pub fn new(door: &'a Door, settings: &'a Settings) -> Self {
+        Self {
+            door,
+            settings,
+            opened: false,
+        }
+    }
+
+    /// Return `true` if the door was opened during the move.
+    pub fn opened(&self) -> bool {
+        self.opened
+    }
+}
+impl MoveObserver for DoorInterlock<'_> {
+    fn after_step(&mut self, _x_pos: u32, _a_pos: i64) {
+        if self.door.blocks_motion(self.settings) {
+            self.opened = true;
+        }
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.opened
+    }
+}
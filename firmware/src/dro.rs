@@ -0,0 +1,119 @@
+//! Position streaming for a digital readout (DRO) on the host.
+//!
+//! While the machine moves, a position line is due at a fixed rate, so
+//! that a host readout can follow the carriage smoothly. The step loop
+//! must not wait for the UART, so a line is sent a byte at a time, whenever
+//! the UART can take one, and no new line is started until the last one
+//! has gone.
+
+use heapless::String;
+
+/// A rate-limited stream of lines, sent a byte at a time.
+pub struct DroStream<const N: usize> {
+    /// Time between the starts of lines, in milliseconds, or zero if the
+    /// stream is off.
+    period_ms: u32,
+    /// Time that the last line was started, in milliseconds.
+    last_ms: Option<u32>,
+    line: String<N>,
+    /// Number of bytes of the line that have been sent.
+    sent: usize,
+}
+impl<const N: usize> DroStream<N> {
+    /// Creates a stream of `rate_hz` lines per second. A rate of zero
+    /// turns the stream off.
+    pub fn new(rate_hz: u16) -> Self {
+        Self {
+            period_ms: match rate_hz {
+                0 => 0,
+                rate_hz => 1000 / rate_hz.min(1000) as u32,
+            },
+            last_ms: None,
+            line: String::new(),
+            sent: 0,
+        }
+    }
+
+    /// Returns the line buffer, cleared, if a new line is due at `now_ms`.
+    ///
+    /// The first line is due at once. The caller writes the line into the
+    /// buffer, and it is sent by [`Self::next_byte`].
+    pub fn due(&mut self, now_ms: u32) -> Option<&mut String<N>> {
+        if self.period_ms == 0 || self.sent < self.line.len() {
+            return None;
+        }
+        if let Some(last_ms) = self.last_ms {
+            if now_ms.wrapping_sub(last_ms) < self.period_ms {
+                return None;
+            }
+        }
+        self.last_ms = Some(now_ms);
+        self.line.clear();
+        self.sent = 0;
+        Some(&mut self.line)
+    }
+
+    /// Returns the next byte of the line to send, if there is one.
+    pub fn next_byte(&self) -> Option<u8> {
+        self.line.as_bytes().get(self.sent).copied()
+    }
+
+    /// Records that the byte from [`Self::next_byte`] was sent.
+    pub fn byte_sent(&mut self) {
+        self.sent = (self.sent + 1).min(self.line.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use super::*;
+
+    fn send_all<const N: usize>(stream: &mut DroStream<N>) -> String<N> {
+        let mut sent = String::new();
+        while let Some(byte) = stream.next_byte() {
+            sent.push(byte as char).unwrap();
+            stream.byte_sent();
+        }
+        sent
+    }
+
+    #[test]
+    fn test_off() {
+        let mut stream: DroStream<8> = DroStream::new(0);
+        assert!(stream.due(0).is_none());
+        assert_eq!(None, stream.next_byte());
+    }
+
+    #[test]
+    fn test_rate() {
+        let mut stream: DroStream<8> = DroStream::new(10);
+        writeln!(stream.due(1000).unwrap(), "A").unwrap();
+        assert_eq!("A\n", send_all(&mut stream));
+        assert!(stream.due(1099).is_none());
+        writeln!(stream.due(1100).unwrap(), "B").unwrap();
+        assert_eq!("B\n", send_all(&mut stream));
+    }
+
+    #[test]
+    fn test_waits_for_the_last_line() {
+        let mut stream: DroStream<8> = DroStream::new(1000);
+        write!(stream.due(0).unwrap(), "AB").unwrap();
+        assert_eq!(Some(b'A'), stream.next_byte());
+        stream.byte_sent();
+        assert!(stream.due(5).is_none());
+        assert_eq!(Some(b'B'), stream.next_byte());
+        stream.byte_sent();
+        assert_eq!(None, stream.next_byte());
+        assert!(stream.due(5).is_some());
+    }
+
+    #[test]
+    fn test_wrapping_clock() {
+        let mut stream: DroStream<8> = DroStream::new(10);
+        assert!(stream.due(u32::MAX - 50).is_some());
+        assert!(stream.due(20).is_none());
+        assert!(stream.due(50).is_some());
+    }
+}
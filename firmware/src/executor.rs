@@ -0,0 +1,573 @@
+//! Resumable execution of two-axis moves.
+//!
+//! A [`MoveExecutor`] does not wait between steps itself. It is polled
+//! with the current time, and says whether a step is due, so that the
+//! caller can do other work (eg. service the UART) while it waits for the
+//! next one.
+//!
+//! A move can be held part way (a feed hold), and resumed. It slows down
+//! to rest along a ramp at its acceleration, rather than stopping dead,
+//! keeps the steps that it has left, and speeds up again from rest when it
+//! resumes. Either axis can also be stopped on its own, at once.
+
+use crate::{
+    interpolate::{Dir, Interpolator, Step},
+    trapezoid::Trapezoid,
+};
+
+/// Result of polling a [`MoveExecutor`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Poll {
+    /// Take this step now.
+    Step(Step),
+    /// The next step is not due yet.
+    Wait,
+    /// The move is held, and no step is due until it resumes.
+    Held,
+    /// The move is complete.
+    Done,
+}
+
+/// Feed hold state of a [`MoveExecutor`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hold {
+    /// Stepping as planned, or speeding up again after a hold.
+    Running,
+    /// Slowing down to rest for a hold.
+    Stopping,
+    /// At rest part way through the move.
+    Held,
+}
+
+/// Extra delay after the steps of an axis in each direction, for a drive
+/// train that binds more one way than the other.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Trim {
+    /// Extra delay after negative steps, in tenths of a percent.
+    pub neg: u16,
+    /// Extra delay after positive steps, in tenths of a percent.
+    pub pos: u16,
+}
+impl Trim {
+    /// Returns the delay after a step in direction `dir`, given the delay
+    /// before trimming, in microseconds.
+    pub fn apply(&self, dir: Dir, delay_us: u32) -> u32 {
+        let trim = match dir {
+            Dir::Neg => self.neg,
+            Dir::Pos => self.pos,
+        };
+        // Skip the division, which is slow on the AVR, for an untrimmed
+        // direction.
+        if trim == 0 {
+            return delay_us;
+        }
+        let extra_us = delay_us as u64 * trim as u64 / 1000;
+        delay_us.saturating_add(extra_us.min(u32::MAX as u64) as u32)
+    }
+}
+
+/// Schedules the steps of a two-axis move.
+///
+/// The first step is due as soon as the executor is polled. After each
+/// step, the next one is due after that step's delay: from the profile if
+/// it was a step of the major axis, or the axis' cruise delay otherwise.
+/// Steps are timed from when the last one was due, rather than when it was
+/// taken, so that work done between steps does not slow the move down. If
+/// a step is taken more than a whole delay late, though, the next one is
+/// timed from when it was taken, rather than being rushed to catch up.
+///
+/// Each delay is then lengthened by the [`Trim`] of the step's axis in the
+/// step's direction, if any.
+///
+/// Once a move has been held, its major axis is timed by a
+/// [`Trapezoid`] from then on, in place of the profile: down to rest, and
+/// then up again over the rest of the move when it resumes.
+pub struct MoveExecutor<P> {
+    steps: Interpolator,
+    /// Delays after the steps of the major axis, in microseconds.
+    profile: P,
+    /// Ramp that replaces the profile once the move has been held.
+    ramp: Option<Trapezoid>,
+    hold: Hold,
+    /// Steps of the major axis left in the ramp down to rest, while
+    /// stopping.
+    stop_steps: u32,
+    /// Steps of the major axis left in the move.
+    remaining: u32,
+    /// Acceleration of the major axis, in steps per second squared.
+    accel: u32,
+    /// Rate of the major axis at the end of the move, in steps per second.
+    exit_rate: u32,
+    /// Delay after the last step of the major axis, in microseconds, or
+    /// `None` at rest.
+    major_delay_us: Option<u32>,
+    /// Whether X and A have been stopped, with their remaining steps
+    /// dropped.
+    x_stopped: bool,
+    a_stopped: bool,
+    x_major: bool,
+    /// Delays after X and A steps at their cruise rates, in microseconds.
+    x_delay_us: u32,
+    a_delay_us: u32,
+    /// Trims of the delays after X and A steps.
+    x_trim: Trim,
+    a_trim: Trim,
+    /// Time the next step is due, in microseconds, or `None` before the
+    /// first step.
+    due_us: Option<u32>,
+    /// Sum of the delays after the steps taken, in microseconds.
+    planned_us: u64,
+}
+impl<P: Iterator<Item = u32>> MoveExecutor<P> {
+    /// Creates an executor.
+    ///
+    /// # Parameters
+    ///
+    /// - `dx`, `da`: Steps to move along X and A.
+    /// - `x_delay_us`, `a_delay_us`: Delays after X and A steps at their
+    ///   cruise rates, in microseconds.
+    /// - `profile`: Delays after each step of the axis with more steps, in
+    ///   microseconds, eg. a [`Trapezoid`](crate::trapezoid::Trapezoid).
+    pub fn new(
+        dx: i32,
+        da: i32,
+        (x_delay_us, a_delay_us): (u32, u32),
+        profile: P,
+    ) -> Self {
+        let x_major = dx.unsigned_abs() >= da.unsigned_abs();
+        let remaining = match x_major {
+            true => dx.unsigned_abs(),
+            false => da.unsigned_abs(),
+        };
+        Self {
+            steps: Interpolator::new(dx, da),
+            profile,
+            ramp: None,
+            hold: Hold::Running,
+            stop_steps: 0,
+            remaining: remaining.min(i32::MAX as u32),
+            accel: 0,
+            exit_rate: 0,
+            major_delay_us: None,
+            x_stopped: false,
+            a_stopped: false,
+            x_major,
+            x_delay_us,
+            a_delay_us,
+            x_trim: Trim::default(),
+            a_trim: Trim::default(),
+            due_us: None,
+            planned_us: 0,
+        }
+    }
+
+    /// Trims the delays after X and A steps in each direction.
+    pub fn with_trims(self, (x_trim, a_trim): (Trim, Trim)) -> Self {
+        Self {
+            x_trim,
+            a_trim,
+            ..self
+        }
+    }
+
+    /// Sets the acceleration of the major axis, in steps per second squared,
+    /// and its rate at the end of the move, in steps per second, for the
+    /// ramps of a hold. Without them, a hold stops after the current step,
+    /// and the move resumes at its cruise rate.
+    pub fn with_ramp(self, accel: u32, exit_rate: u32) -> Self {
+        Self {
+            accel,
+            exit_rate,
+            ..self
+        }
+    }
+
+    /// Returns the feed hold state.
+    pub fn hold_state(&self) -> Hold {
+        self.hold
+    }
+
+    /// Returns the number of steps of the major axis left in the move.
+    pub fn remaining_steps(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Starts to slow down to rest for a hold, if the move is running.
+    ///
+    /// If the move cannot come to rest before its end, it runs on as
+    /// planned, and this should be called again for the next move.
+    pub fn hold(&mut self) {
+        if self.hold != Hold::Running {
+            return;
+        }
+        let rate = self.rate();
+        let stop_steps = match self.accel {
+            0 => 0,
+            accel => (rate as u64 * rate as u64 / (2 * accel as u64)) as u32,
+        };
+        if rate == 0 || stop_steps == 0 {
+            self.hold = Hold::Held;
+            return;
+        }
+        if stop_steps >= self.remaining {
+            return;
+        }
+        let cruise_delay_us = self.cruise_delay_us();
+        self.ramp = Some(Trapezoid::between(
+            stop_steps,
+            cruise_delay_us,
+            self.accel,
+            (rate, 0),
+        ));
+        self.stop_steps = stop_steps;
+        self.hold = Hold::Stopping;
+    }
+
+    /// Resumes a held move: from rest, or from its current rate if it is
+    /// still slowing down, up to its cruise rate again, and then as
+    /// planned to its end.
+    pub fn resume(&mut self) {
+        if self.hold == Hold::Running {
+            return;
+        }
+        let rate = match self.hold {
+            Hold::Held => 0,
+            _ => self.rate(),
+        };
+        if self.hold == Hold::Held {
+            self.due_us = None;
+            self.major_delay_us = None;
+        }
+        self.ramp = Some(Trapezoid::between(
+            self.remaining,
+            self.cruise_delay_us(),
+            self.accel,
+            (rate, self.exit_rate),
+        ));
+        self.hold = Hold::Running;
+    }
+
+    /// Stops X at once, dropping its remaining steps. If X is the major
+    /// axis, this ends the move, and A stays where it is; otherwise A runs
+    /// on to its end.
+    pub fn stop_x(&mut self) {
+        self.x_stopped = true;
+        if self.x_major {
+            self.end();
+        }
+    }
+
+    /// Stops A at once, dropping its remaining steps. If A is the major
+    /// axis, this ends the move, and X stays where it is; otherwise X runs
+    /// on to its end.
+    pub fn stop_a(&mut self) {
+        self.a_stopped = true;
+        if !self.x_major {
+            self.end();
+        }
+    }
+
+    /// Ends the move after the steps taken, even if it is held.
+    fn end(&mut self) {
+        self.steps = Interpolator::new(0, 0);
+        self.remaining = 0;
+        self.hold = Hold::Running;
+    }
+
+    /// Returns the rate of the major axis, in steps per second, from the
+    /// delay after its last step.
+    fn rate(&self) -> u32 {
+        self.major_delay_us
+            .map_or(0, |delay_us| 1_000_000 / delay_us.max(1))
+    }
+
+    /// Returns the delay after steps of the major axis at its cruise rate.
+    fn cruise_delay_us(&self) -> u32 {
+        match self.x_major {
+            true => self.x_delay_us,
+            false => self.a_delay_us,
+        }
+    }
+
+    /// Returns the step to take at `now_us`, if one is due.
+    ///
+    /// # Parameters
+    ///
+    /// - `now_us`: Current time, in microseconds. This may wrap.
+    pub fn poll(&mut self, now_us: u32) -> Poll {
+        if self.hold == Hold::Held {
+            return Poll::Held;
+        }
+        let base_us = match self.due_us {
+            None => now_us,
+            Some(due_us) => {
+                let late_us = now_us.wrapping_sub(due_us);
+                if late_us > i32::MAX as u32 {
+                    return Poll::Wait;
+                }
+                due_us
+            }
+        };
+        let Some((step, delay_us)) = self.next_step() else {
+            return Poll::Done;
+        };
+        let base_us = match now_us.wrapping_sub(base_us) > delay_us {
+            true => now_us,
+            false => base_us,
+        };
+        self.due_us = Some(base_us.wrapping_add(delay_us));
+        Poll::Step(step)
+    }
+
+    /// Returns the next step and the delay after it, in microseconds,
+    /// whatever the time, for a caller that times the steps itself (eg.
+    /// with a hardware timer).
+    ///
+    /// This returns `None` while the move is held, as well as at its end.
+    pub fn next_step(&mut self) -> Option<(Step, u32)> {
+        if self.hold == Hold::Held {
+            return None;
+        }
+        let step = loop {
+            match self.steps.next()? {
+                Step::X(_) if self.x_stopped => {}
+                Step::A(_) if self.a_stopped => {}
+                step => break step,
+            }
+        };
+        let (major, delay_us, trim, dir) = match step {
+            Step::X(dir) => (self.x_major, self.x_delay_us, self.x_trim, dir),
+            Step::A(dir) => (!self.x_major, self.a_delay_us, self.a_trim, dir),
+        };
+        let delay_us = match major {
+            true => {
+                let delay_us = match &mut self.ramp {
+                    Some(ramp) => ramp.next(),
+                    None => self.profile.next(),
+                }
+                .unwrap_or(delay_us);
+                self.remaining = self.remaining.saturating_sub(1);
+                self.major_delay_us = Some(delay_us);
+                if self.hold == Hold::Stopping {
+                    self.stop_steps -= 1;
+                    if self.stop_steps == 0 {
+                        self.hold = Hold::Held;
+                    }
+                }
+                delay_us
+            }
+            false => delay_us,
+        };
+        let delay_us = trim.apply(dir, delay_us);
+        self.planned_us += delay_us as u64;
+        Some((step, delay_us))
+    }
+
+    /// Returns the sum of the delays after the steps taken so far, in
+    /// microseconds: the time that they should have taken.
+    pub fn planned_us(&self) -> u64 {
+        self.planned_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::iter;
+
+    use super::*;
+
+    #[test]
+    fn test_empty_move() {
+        let mut executor = MoveExecutor::new(0, 0, (10, 10), iter::empty());
+        assert_eq!(Poll::Done, executor.poll(0));
+    }
+
+    #[test]
+    fn test_steps_when_due() {
+        let mut executor =
+            MoveExecutor::new(2, 1, (100, 300), iter::repeat(100));
+        assert_eq!(Poll::Step(Step::X(Dir::Pos)), executor.poll(1000));
+        assert_eq!(Poll::Wait, executor.poll(1099));
+        assert_eq!(Poll::Step(Step::X(Dir::Pos)), executor.poll(1100));
+        assert_eq!(Poll::Step(Step::A(Dir::Pos)), executor.poll(1200));
+        assert_eq!(Poll::Wait, executor.poll(1499));
+        assert_eq!(Poll::Done, executor.poll(1500));
+        assert_eq!(500, executor.planned_us());
+    }
+
+    #[test]
+    fn test_profile_times_the_major_axis() {
+        let delays = [50, 20, 10].into_iter();
+        let mut executor = MoveExecutor::new(1, -3, (100, 100), delays);
+        assert_eq!(Poll::Step(Step::A(Dir::Neg)), executor.poll(0));
+        assert_eq!(Poll::Step(Step::X(Dir::Pos)), executor.poll(50));
+        assert_eq!(Poll::Step(Step::A(Dir::Neg)), executor.poll(150));
+        assert_eq!(Poll::Step(Step::A(Dir::Neg)), executor.poll(170));
+        assert_eq!(Poll::Done, executor.poll(180));
+    }
+
+    #[test]
+    fn test_next_step() {
+        let delays = [50, 20].into_iter();
+        let mut executor = MoveExecutor::new(2, 1, (100, 300), delays);
+        assert_eq!(Some((Step::X(Dir::Pos), 50)), executor.next_step());
+        assert_eq!(Some((Step::X(Dir::Pos), 20)), executor.next_step());
+        assert_eq!(Some((Step::A(Dir::Pos), 300)), executor.next_step());
+        assert_eq!(None, executor.next_step());
+        assert_eq!(370, executor.planned_us());
+    }
+
+    #[test]
+    fn test_trims() {
+        let trim = Trim { neg: 0, pos: 250 };
+        assert_eq!(1000, trim.apply(Dir::Neg, 1000));
+        assert_eq!(1250, trim.apply(Dir::Pos, 1000));
+        assert_eq!(u32::MAX, trim.apply(Dir::Pos, u32::MAX));
+
+        // X right is slowed, and so is A backwards, including the delays
+        // from the profile.
+        let trims = (trim, Trim { neg: 100, pos: 0 });
+        let delays = [50, 20].into_iter();
+        let mut executor =
+            MoveExecutor::new(2, -1, (100, 300), delays).with_trims(trims);
+        assert_eq!(Some((Step::X(Dir::Pos), 62)), executor.next_step());
+        assert_eq!(Some((Step::X(Dir::Pos), 25)), executor.next_step());
+        assert_eq!(Some((Step::A(Dir::Neg), 330)), executor.next_step());
+        assert_eq!(None, executor.next_step());
+        assert_eq!(417, executor.planned_us());
+    }
+
+    #[test]
+    fn test_late_steps() {
+        let mut executor = MoveExecutor::new(4, 0, (100, 100), iter::empty());
+        assert_eq!(Poll::Step(Step::X(Dir::Pos)), executor.poll(0));
+        // A little late: the next step is still due on time.
+        assert!(matches!(executor.poll(130), Poll::Step(_)));
+        assert_eq!(Poll::Wait, executor.poll(199));
+        // More than a whole delay late: the next step is not rushed.
+        assert!(matches!(executor.poll(500), Poll::Step(_)));
+        assert_eq!(Poll::Wait, executor.poll(599));
+        assert!(matches!(executor.poll(600), Poll::Step(_)));
+    }
+
+    /// Polls the executor in simulated time, from `now_us`, until it is
+    /// held or done, and returns the delays after the major axis' steps.
+    fn run(
+        executor: &mut MoveExecutor<Trapezoid>,
+        now_us: &mut u32,
+        limit: usize,
+    ) -> heapless::Vec<u32, 1024> {
+        let mut delays = heapless::Vec::new();
+        let mut last_us = None;
+        while delays.len() < limit {
+            match executor.poll(*now_us) {
+                Poll::Step(Step::X(_)) => {
+                    if let Some(last_us) = last_us {
+                        delays.push(*now_us - last_us).unwrap();
+                    }
+                    last_us = Some(*now_us);
+                }
+                Poll::Step(Step::A(_)) | Poll::Wait => {}
+                Poll::Held | Poll::Done => break,
+            }
+            *now_us += 1;
+        }
+        delays
+    }
+
+    #[test]
+    fn test_hold_and_resume() {
+        // 5000 steps/s, which takes 125 steps to reach at 100_000 steps/s^2.
+        let profile = Trapezoid::new(1000, 200, 100_000);
+        let mut executor = MoveExecutor::new(1000, 0, (200, 200), profile)
+            .with_ramp(100_000, 0);
+        let mut now_us = 0;
+        let cruise = run(&mut executor, &mut now_us, 300);
+        assert_eq!(200, cruise[cruise.len() - 1]);
+
+        // The move slows down to rest along the ramp, and keeps the rest of
+        // its steps.
+        executor.hold();
+        assert_eq!(Hold::Stopping, executor.hold_state());
+        let stopping = run(&mut executor, &mut now_us, 1024);
+        assert_eq!(Hold::Held, executor.hold_state());
+        assert_eq!(Poll::Held, executor.poll(now_us + 1_000_000));
+        assert_eq!(None, executor.next_step());
+        assert_eq!(1000 - 301 - 125, executor.remaining_steps());
+        assert!(stopping.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(stopping[0] < 220);
+        assert!(stopping[stopping.len() - 1] > 1000);
+
+        // It speeds up again from rest, rather than at once to cruise.
+        executor.resume();
+        now_us += 1_000_000;
+        let resumed = run(&mut executor, &mut now_us, 1024);
+        assert_eq!(Hold::Running, executor.hold_state());
+        assert_eq!(0, executor.remaining_steps());
+        assert!(resumed[0] > 2000);
+        assert!(resumed[..100].windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(200, resumed[300]);
+        assert_eq!(Poll::Done, executor.poll(now_us + 1_000_000));
+    }
+
+    #[test]
+    fn test_hold_edge_cases() {
+        // At rest, a move is held at once.
+        let profile = Trapezoid::new(100, 200, 100_000);
+        let mut executor = MoveExecutor::new(100, 0, (200, 200), profile)
+            .with_ramp(100_000, 0);
+        executor.hold();
+        assert_eq!(Poll::Held, executor.poll(0));
+        executor.resume();
+        assert!(matches!(executor.poll(0), Poll::Step(_)));
+
+        // Too close to its end to stop, it runs on.
+        let profile = Trapezoid::new(100, 200, 100_000);
+        let mut executor = MoveExecutor::new(100, 0, (200, 200), profile)
+            .with_ramp(100_000, 0);
+        let mut now_us = 0;
+        run(&mut executor, &mut now_us, 60);
+        executor.hold();
+        assert_eq!(Hold::Running, executor.hold_state());
+
+        // Without a ramp, it stops after the current step, and resumes at
+        // the cruise rate.
+        let mut executor =
+            MoveExecutor::new(3, 0, (200, 200), Trapezoid::new(3, 200, 0));
+        assert!(executor.next_step().is_some());
+        executor.hold();
+        assert_eq!(None, executor.next_step());
+        executor.resume();
+        assert_eq!(Some((Step::X(Dir::Pos), 200)), executor.next_step());
+    }
+
+    #[test]
+    fn test_stop_one_axis() {
+        // Stopping the minor axis drops its steps, and the major axis runs
+        // on.
+        let mut executor = MoveExecutor::new(4, 2, (100, 100), iter::empty());
+        assert_eq!(Some((Step::X(Dir::Pos), 100)), executor.next_step());
+        executor.stop_a();
+        for _ in 0..3 {
+            assert!(matches!(executor.next_step(), Some((Step::X(_), _))));
+        }
+        assert_eq!(None, executor.next_step());
+
+        // Stopping the major axis ends the move, even while it is held.
+        let mut executor = MoveExecutor::new(-1, -4, (100, 100), iter::empty());
+        assert!(executor.next_step().is_some());
+        executor.hold();
+        assert_eq!(Poll::Held, executor.poll(0));
+        executor.stop_a();
+        assert_eq!(Poll::Done, executor.poll(0));
+        assert_eq!(0, executor.remaining_steps());
+    }
+
+    #[test]
+    fn test_wrapping_clock() {
+        let mut executor = MoveExecutor::new(2, 0, (100, 100), iter::empty());
+        assert!(matches!(executor.poll(u32::MAX - 49), Poll::Step(_)));
+        assert_eq!(Poll::Wait, executor.poll(49));
+        assert!(matches!(executor.poll(50), Poll::Step(_)));
+    }
+}
@@ -0,0 +1,193 @@
+//! Q16.16 fixed-point arithmetic.
+//!
+//! Fractional factors (gains, ratios, rates) are carried as [`Q16`] values
+//! rather than floating point, which the AVR has to emulate in software.
+//! All operations round to nearest (halves away from zero) and saturate at
+//! the limits of the type, so results never wrap.
+
+/// A signed fixed-point number with 16 integer bits and 16 fraction bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q16(i32);
+impl Q16 {
+    /// Number of fraction bits.
+    pub const FRAC_BITS: u32 = 16;
+    pub const ZERO: Q16 = Q16(0);
+    pub const ONE: Q16 = Q16(1 << Self::FRAC_BITS);
+    pub const MIN: Q16 = Q16(i32::MIN);
+    pub const MAX: Q16 = Q16(i32::MAX);
+
+    /// Creates a value from its raw representation.
+    pub const fn from_bits(bits: i32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw representation.
+    pub const fn to_bits(self) -> i32 {
+        self.0
+    }
+
+    /// Creates a value from an integer, saturating if it is out of range.
+    pub const fn from_int(value: i32) -> Self {
+        Self(saturate((value as i64) << Self::FRAC_BITS))
+    }
+
+    /// Creates the value `num / den`, rounded and saturated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `den` is zero.
+    pub const fn from_ratio(num: i32, den: i32) -> Self {
+        Self(saturate(round_div(
+            (num as i64) << Self::FRAC_BITS,
+            den as i64,
+        )))
+    }
+
+    /// Rounds to the nearest integer.
+    pub const fn round(self) -> i32 {
+        round_shift(self.0 as i64, Self::FRAC_BITS) as i32
+    }
+
+    pub const fn saturating_add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
+    pub const fn saturating_sub(self, rhs: Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// Multiplies two values, rounding and saturating the result.
+    pub const fn saturating_mul(self, rhs: Self) -> Self {
+        let product = self.0 as i64 * rhs.0 as i64;
+        Self(saturate(round_shift(product, Self::FRAC_BITS)))
+    }
+
+    /// Divides two values, rounding and saturating the result.
+    ///
+    /// Returns `None` if `rhs` is zero.
+    pub const fn checked_div(self, rhs: Self) -> Option<Self> {
+        if rhs.0 == 0 {
+            return None;
+        }
+        let num = (self.0 as i64) << Self::FRAC_BITS;
+        Some(Self(saturate(round_div(num, rhs.0 as i64))))
+    }
+
+    /// Multiplies an integer by this value, returning the rounded and
+    /// saturated integer result.
+    pub const fn mul_int(self, value: i32) -> i32 {
+        let product = self.0 as i64 * value as i64;
+        saturate(round_shift(product, Self::FRAC_BITS))
+    }
+}
+
+/// Computes `value / 2^bits`, rounding halves away from zero.
+const fn round_shift(value: i64, bits: u32) -> i64 {
+    let half = 1 << (bits - 1);
+    if value < 0 {
+        -((-value + half) >> bits)
+    } else {
+        (value + half) >> bits
+    }
+}
+
+/// Computes `num / den`, rounding halves away from zero.
+const fn round_div(num: i64, den: i64) -> i64 {
+    let quotient = (num.abs() + den.abs() / 2) / den.abs();
+    if (num < 0) != (den < 0) {
+        -quotient
+    } else {
+        quotient
+    }
+}
+
+/// Clamps a 64-bit intermediate to the `i32` range.
+const fn saturate(value: i64) -> i32 {
+    if value > i32::MAX as i64 {
+        i32::MAX
+    } else if value < i32::MIN as i64 {
+        i32::MIN
+    } else {
+        value as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_int_and_round() {
+        assert_eq!(Q16::ONE, Q16::from_int(1));
+        assert_eq!(-3, Q16::from_int(-3).round());
+        assert_eq!(Q16::MAX, Q16::from_int(40_000));
+        assert_eq!(Q16::MIN, Q16::from_int(-40_000));
+    }
+
+    #[test]
+    fn test_from_ratio() {
+        assert_eq!(Q16::from_bits(0x8000), Q16::from_ratio(1, 2));
+        assert_eq!(Q16::from_bits(-0x8000), Q16::from_ratio(-1, 2));
+        assert_eq!(Q16::from_bits(-0x8000), Q16::from_ratio(1, -2));
+        // 1/3 = 0x5555.55..., which rounds down.
+        assert_eq!(Q16::from_bits(0x5555), Q16::from_ratio(1, 3));
+        // 2/3 = 0xaaaa.aa..., which rounds up.
+        assert_eq!(Q16::from_bits(0xaaab), Q16::from_ratio(2, 3));
+        assert_eq!(Q16::MAX, Q16::from_ratio(i32::MAX, 1));
+    }
+
+    #[test]
+    fn test_round_halves_away_from_zero() {
+        assert_eq!(2, Q16::from_ratio(3, 2).round());
+        assert_eq!(-2, Q16::from_ratio(-3, 2).round());
+        assert_eq!(1, Q16::from_ratio(5, 4).round());
+        assert_eq!(-1, Q16::from_ratio(-5, 4).round());
+    }
+
+    #[test]
+    fn test_add_sub_saturate() {
+        let half = Q16::from_ratio(1, 2);
+        assert_eq!(Q16::ONE, half.saturating_add(half));
+        assert_eq!(Q16::ZERO, half.saturating_sub(half));
+        assert_eq!(Q16::MAX, Q16::MAX.saturating_add(half));
+        assert_eq!(Q16::MIN, Q16::MIN.saturating_sub(half));
+    }
+
+    #[test]
+    fn test_mul() {
+        let a = Q16::from_ratio(3, 2);
+        let b = Q16::from_ratio(-5, 2);
+        assert_eq!(Q16::from_ratio(-15, 4), a.saturating_mul(b));
+        assert_eq!(
+            Q16::MAX,
+            Q16::from_int(300).saturating_mul(Q16::from_int(300))
+        );
+        assert_eq!(
+            Q16::MIN,
+            Q16::from_int(300).saturating_mul(Q16::from_int(-300))
+        );
+    }
+
+    #[test]
+    fn test_div() {
+        let a = Q16::from_int(3);
+        let b = Q16::from_int(4);
+        assert_eq!(Some(Q16::from_ratio(3, 4)), a.checked_div(b));
+        assert_eq!(
+            Some(Q16::from_ratio(-4, 3)),
+            b.checked_div(Q16::from_int(-3))
+        );
+        assert_eq!(None, a.checked_div(Q16::ZERO));
+        assert_eq!(
+            Some(Q16::MAX),
+            Q16::from_int(1000).checked_div(Q16::from_ratio(1, 1000))
+        );
+    }
+
+    #[test]
+    fn test_mul_int() {
+        assert_eq!(50, Q16::from_ratio(1, 2).mul_int(100));
+        assert_eq!(-51, Q16::from_ratio(1, 2).mul_int(-101));
+        assert_eq!(i32::MAX, Q16::from_int(2).mul_int(i32::MAX));
+    }
+}
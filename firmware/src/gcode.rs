@@ -0,0 +1,151 @@
+//! Parsers for the numbers in G-code words.
+//!
+//! The firmware parses values with these parsers, so host tools that use
+//! them read values exactly as the firmware does. Each parser takes the
+//! number at the start of the input, leaving the rest of the input for the
+//! next word.
+
+use core::str::FromStr;
+
+use winnow::{
+    ascii::digit1,
+    combinator::{alt, opt, preceded},
+    token::literal,
+    Parser, Result,
+};
+
+/// Parse a decimal value in thousandths of its unit (eg. mm as microns,
+/// or degrees as millidegrees).
+///
+/// Digits past the third decimal place are truncated. Values that do not
+/// fit in an `i32` fail to parse.
+///
+/// eg.
+///   - 3      -> 3000
+///   - 3.14   -> 3140
+///   - 3.142  -> 3142
+///   - 3.1428 -> 3142
+///   - -0.5   -> -500
+pub fn parse_millis(input: &mut &str) -> Result<i32> {
+    parse_millis_i64
+        .verify_map(|value| i32::try_from(value).ok())
+        .parse_next(input)
+}
+
+/// Parse a decimal value in thousandths of its unit, like
+/// [`parse_millis`], but with the range of an `i64`.
+pub fn parse_millis_i64(input: &mut &str) -> Result<i64> {
+    (
+        opt(alt((literal("-"), literal("+")))),
+        digit1,
+        opt(preceded(literal("."), digit1)),
+    )
+        .verify_map(|(sign, whole, fraction)| {
+            millis(sign == Some("-"), whole, fraction)
+        })
+        .parse_next(input)
+}
+
+/// Parse an angle in millidegrees: a decimal value in degrees, or in turns
+/// with an `r` suffix (eg. `A1.5r` is 540 degrees).
+///
+/// Turns are converted exactly: a thousandth of a turn is 360
+/// millidegrees. The value has the range of an `i64`, like
+/// [`parse_millis_i64`].
+pub fn parse_angle_millis(input: &mut &str) -> Result<i64> {
+    (parse_millis_i64, opt(literal("r")))
+        .verify_map(|(value, turns)| match turns {
+            Some(_) => value.checked_mul(360),
+            None => Some(value),
+        })
+        .parse_next(input)
+}
+
+/// Parse an unsigned integer, eg. a setting number or a parameter value.
+///
+/// Values that do not fit in `T` fail to parse.
+pub fn parse_digits<T: FromStr>(input: &mut &str) -> Result<T> {
+    digit1
+        .verify_map(|s: &str| s.parse().ok())
+        .parse_next(input)
+}
+
+/// Combine the parts of a decimal number into thousandths.
+fn millis(negative: bool, whole: &str, fraction: Option<&str>) -> Option<i64> {
+    let whole: i64 = whole.parse().ok()?;
+    let fraction = fraction.unwrap_or("");
+    let fraction = &fraction[..3.min(fraction.len())];
+    let mut thousandths = 0;
+    for i in 0..3 {
+        let digit = fraction.as_bytes().get(i).map_or(0, |d| d - b'0');
+        thousandths = thousandths * 10 + digit as i64;
+    }
+    let magnitude = whole.checked_mul(1000)?.checked_add(thousandths)?;
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(s: &str) -> Option<i32> {
+        parse_millis.parse(s).ok()
+    }
+
+    #[test]
+    fn test_millis() {
+        assert_eq!(Some(3000), millis("3"));
+        assert_eq!(Some(3140), millis("3.14"));
+        assert_eq!(Some(3142), millis("3.142"));
+        assert_eq!(Some(3142), millis("3.1428"));
+        assert_eq!(Some(-500), millis("-0.5"));
+        assert_eq!(Some(500), millis("+0.5"));
+    }
+
+    #[test]
+    fn test_millis_rejects_malformed() {
+        assert_eq!(None, millis(""));
+        assert_eq!(None, millis("-"));
+        assert_eq!(None, millis(".5"));
+        assert_eq!(None, millis("1."));
+    }
+
+    #[test]
+    fn test_millis_range() {
+        assert_eq!(Some(2_147_483_000), millis("2147483"));
+        assert_eq!(None, millis("2147484"));
+        assert_eq!(Ok(9_000_000_000_000), parse_millis_i64.parse("9000000000"));
+        assert!(parse_millis_i64.parse("9223372036854776").is_err());
+    }
+
+    #[test]
+    fn test_leaves_rest_of_input() {
+        let mut input = "12.5 A3";
+        assert_eq!(Ok(12_500), parse_millis(&mut input));
+        assert_eq!(" A3", input);
+    }
+
+    #[test]
+    fn test_angle() {
+        assert_eq!(Ok(360_000), parse_angle_millis.parse("360"));
+        assert_eq!(Ok(360_000), parse_angle_millis.parse("1r"));
+        assert_eq!(Ok(540_000), parse_angle_millis.parse("1.5r"));
+        assert_eq!(Ok(-360), parse_angle_millis.parse("-0.001r"));
+        assert_eq!(Ok(90_000), parse_angle_millis.parse("0.25r"));
+        assert!(parse_angle_millis.parse("9000000000000000r").is_err());
+    }
+
+    #[test]
+    fn test_angle_leaves_rest_of_input() {
+        let mut input = "2r X1";
+        assert_eq!(Ok(720_000), parse_angle_millis(&mut input));
+        assert_eq!(" X1", input);
+    }
+
+    #[test]
+    fn test_digits() {
+        assert_eq!(Ok(42u8), parse_digits.parse("42"));
+        assert!(parse_digits::<u8>.parse("256").is_err());
+        assert!(parse_digits::<u8>.parse("-1").is_err());
+    }
+}
@@ -1,48 +1,147 @@
 use arduino_hal::{
     delay_us,
-    port::{
-        mode::{Input, Output, PullUp},
-        Pin, D10, D11, D12, D13, D8, D9,
-    },
+    port::{mode::Output, Pin, D11, D9},
     Peripherals, Pins,
 };
 use embedded_hal::digital::{OutputPin, PinState};
 
+#[cfg(feature = "a-enable")]
+use arduino_hal::port::A2;
+#[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+use arduino_hal::port::D12;
+#[cfg(not(feature = "analog-limits"))]
+use arduino_hal::port::{
+    mode::{Input, PullUp},
+    D13,
+};
+#[cfg(not(feature = "timer-steps"))]
+use arduino_hal::port::{D10, D8};
+#[cfg(feature = "analog-limits")]
+use arduino_hal::{
+    port::{mode::Analog, A3},
+    Adc,
+};
+#[cfg(feature = "analog-limits")]
+use winderbot_lib::threshold::Threshold;
+
+use crate::machine::ZeroingError;
+#[cfg(feature = "timer-steps")]
+use crate::step_timer::{self, Pulse};
+
 /// `GhostInTheMachine`: Low-level (unsafe!) machine interface.
 pub struct GhostInTheMachine {
+    /// Step pulse outputs, unless they belong to the step timer.
+    #[cfg(not(feature = "timer-steps"))]
     pin_x_pulse: Pin<Output, D8>,
     pin_x_direc: Pin<Output, D9>,
+    #[cfg(not(feature = "timer-steps"))]
     pin_a_pulse: Pin<Output, D10>,
     pin_a_direc: Pin<Output, D11>,
+    /// Left limit switch, or both switches in series when they share an
+    /// input.
+    #[cfg(not(feature = "analog-limits"))]
     pin_limitswitch_l: Pin<Input<PullUp>, D13>,
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
     pin_limitswitch_r: Pin<Input<PullUp>, D12>,
+    /// Whether the left switch is read from the right switch's input, and
+    /// the right from the left, to make up for crossed wiring.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    limit_switches_swapped: bool,
+    /// With analog end-of-travel sensing, the ADC and the X position sensor
+    /// (eg. a slide potentiometer on the carriage).
+    #[cfg(feature = "analog-limits")]
+    adc: Adc,
+    #[cfg(feature = "analog-limits")]
+    pin_x_sensor: Pin<Analog, A3>,
+    /// With analog end-of-travel sensing, the ends of travel, as thresholds
+    /// on the X position sensor reading.
+    #[cfg(feature = "analog-limits")]
+    left_end: Threshold,
+    #[cfg(feature = "analog-limits")]
+    right_end: Threshold,
+    #[cfg(feature = "a-enable")]
+    pin_a_enable: Pin<Output, A2>,
     x_dir: PinState,
     a_dir: PinState,
+    #[cfg(feature = "a-enable")]
+    a_enabled: bool,
+    /// With shared limit switches, the X direction (as for `x_dir`) of the
+    /// switch that is engaged, if one is.
+    #[cfg(feature = "shared-limits")]
+    limit_end: Option<PinState>,
 }
 
 impl GhostInTheMachine {
     const DELAY_DIREC_US: u32 = 10;
+    #[cfg(not(feature = "timer-steps"))]
     const DELAY_PULSE_US: u32 = 5;
     const DELAY_MOVE_US: u32 = 40;
     const X_EDGE_SAFETY_STEPS: u32 = 3200;
+    #[cfg(feature = "a-enable")]
+    const DELAY_ENABLE_US: u32 = 2000;
 
     pub fn new() -> Self {
         let peripherals: Peripherals =
             unsafe { arduino_hal::Peripherals::steal() };
         let pins: Pins = arduino_hal::pins!(peripherals);
+        #[cfg(feature = "analog-limits")]
+        let mut adc = Adc::new(peripherals.ADC, Default::default());
+        #[cfg(feature = "analog-limits")]
+        let pin_x_sensor = pins.a3.into_analog_input(&mut adc);
+        #[cfg(feature = "timer-steps")]
+        step_timer::init(
+            peripherals.TC1,
+            pins.d8.into_output(),
+            pins.d10.into_output(),
+        );
 
         let mut gitm = GhostInTheMachine {
+            #[cfg(not(feature = "timer-steps"))]
             pin_x_pulse: pins.d8.into_output(),
             pin_x_direc: pins.d9.into_output(),
+            #[cfg(not(feature = "timer-steps"))]
             pin_a_pulse: pins.d10.into_output(),
             pin_a_direc: pins.d11.into_output(),
+            #[cfg(not(feature = "analog-limits"))]
             pin_limitswitch_l: pins.d13.into_pull_up_input(),
+            #[cfg(not(any(
+                feature = "shared-limits",
+                feature = "analog-limits"
+            )))]
             pin_limitswitch_r: pins.d12.into_pull_up_input(),
+            #[cfg(not(any(
+                feature = "shared-limits",
+                feature = "analog-limits"
+            )))]
+            limit_switches_swapped: false,
+            #[cfg(feature = "analog-limits")]
+            adc,
+            #[cfg(feature = "analog-limits")]
+            pin_x_sensor,
+            // Never active, until the thresholds are set.
+            #[cfg(feature = "analog-limits")]
+            left_end: Threshold::below(0, 0),
+            #[cfg(feature = "analog-limits")]
+            right_end: Threshold::above(u16::MAX, 0),
+            #[cfg(feature = "a-enable")]
+            pin_a_enable: pins.a2.into_output(),
             x_dir: PinState::Low,
             a_dir: PinState::Low,
+            #[cfg(feature = "a-enable")]
+            a_enabled: false,
+            #[cfg(feature = "shared-limits")]
+            limit_end: None,
         };
+        // A switch that is already engaged cannot be told apart from the
+        // other one, so it is taken to be the left one.
+        #[cfg(feature = "shared-limits")]
+        if gitm.pin_limitswitch_l.is_high() {
+            gitm.limit_end = Some(PinState::High);
+        }
         gitm.force_set_x_dir(PinState::Low);
         gitm.force_set_a_dir(PinState::Low);
+        #[cfg(feature = "a-enable")]
+        gitm.set_a_enabled(true);
 
         gitm
     }
@@ -55,15 +154,36 @@ impl GhostInTheMachine {
     /// 3. Moves to the middle (at half the number of steps).
     ///
     /// # Returns
-    /// The number of steps from the left limit switch to the right.
-    pub fn zero(&mut self) -> u32 {
-        let _ = self.move_to_left_limit_switch();
-        let count = self.move_to_right_limit_switch();
+    /// The number of steps from the left limit switch to the right, or an
+    /// error if the limit switches are swapped.
+    pub fn zero(&mut self) -> Result<u32, ZeroingError> {
+        self.move_to_left_limit_switch()?;
+        let count = self.move_to_right_limit_switch()?;
         for _ in 0..(count / 2) {
             self.step_x(PinState::High);
             delay_us(Self::DELAY_MOVE_US);
         }
-        count
+        Ok(count)
+    }
+
+    /// Zero the machine using only the left limit switch.
+    ///
+    /// This moves the machine to the left limit switch, and then to the
+    /// middle of the given travel. If a limit switch is reached on the way,
+    /// the travel is taken to be twice the distance moved.
+    ///
+    /// # Returns
+    /// The number of steps of travel, or an error if the limit switches are
+    /// swapped.
+    pub fn zero_from_left(&mut self, travel: u32) -> Result<u32, ZeroingError> {
+        self.move_to_left_limit_switch()?;
+        for count in 0..(travel / 2) {
+            if !self.step_x(PinState::Low) {
+                return Ok(2 * count);
+            }
+            delay_us(Self::DELAY_MOVE_US);
+        }
+        Ok(travel)
     }
 
     /// Move the carriage until the left limit switch is engaged.
@@ -72,8 +192,9 @@ impl GhostInTheMachine {
     ///       the left limit switch.
     ///
     /// # Returns
-    /// The number of steps.
-    pub fn move_to_left_limit_switch(&mut self) -> u32 {
+    /// The number of steps, or [`ZeroingError::SwitchesSwapped`] if the
+    /// right limit switch engaged on the way.
+    pub fn move_to_left_limit_switch(&mut self) -> Result<u32, ZeroingError> {
         let mut count: u32 = 0;
         // Move on to the limit switch.
         while !self.left_limit_switch_is_down()
@@ -83,15 +204,22 @@ impl GhostInTheMachine {
             count += 1;
             delay_us(Self::DELAY_MOVE_US);
         }
-        // In the unlikely case that the right limit switch is down; just do
-        // nothing at this point.
         if self.right_limit_switch_is_down() {
-            return 0;
+            // The right limit switch engaged while moving left, so the
+            // switches (or the X motor's direction) are crossed. If it was
+            // already down, it tells us nothing: just do nothing at this
+            // point.
+            return match count {
+                0 => Ok(0),
+                _ => Err(ZeroingError::SwitchesSwapped),
+            };
         }
-        // Move off the left limit switch.
+        // Move off the left limit switch. X may have started on it, eg.
+        // after zeroing found the switches swapped, and the count then
+        // stays at zero.
         while self.left_limit_switch_is_down() {
             self.step_x_unsafe(PinState::Low);
-            count -= 1;
+            count = count.saturating_sub(1);
             delay_us(Self::DELAY_MOVE_US);
         }
         // Take some extra steps to make sure we're really off it.
@@ -101,7 +229,7 @@ impl GhostInTheMachine {
             extra_steps -= 1;
             delay_us(Self::DELAY_MOVE_US);
         }
-        count
+        Ok(count)
     }
 
     /// Move the carriage until the right limit switch is engaged.
@@ -110,8 +238,9 @@ impl GhostInTheMachine {
     ///       the right limit switch.
     ///
     /// # Returns
-    /// The number of steps.
-    pub fn move_to_right_limit_switch(&mut self) -> u32 {
+    /// The number of steps, or [`ZeroingError::SwitchesSwapped`] if the
+    /// left limit switch engaged on the way.
+    pub fn move_to_right_limit_switch(&mut self) -> Result<u32, ZeroingError> {
         let mut count: u32 = 0;
         // Move on to the limit switch.
         while !self.left_limit_switch_is_down()
@@ -121,10 +250,12 @@ impl GhostInTheMachine {
             count += 1;
             delay_us(Self::DELAY_MOVE_US);
         }
-        // In the unlikely case that the right limit switch is down; just do
-        // nothing at this point.
+        // As when moving left, with the switches the other way round.
         if self.left_limit_switch_is_down() {
-            return 0;
+            return match count {
+                0 => Ok(0),
+                _ => Err(ZeroingError::SwitchesSwapped),
+            };
         }
         // Move off the left limit switch.
         while self.right_limit_switch_is_down() {
@@ -139,16 +270,85 @@ impl GhostInTheMachine {
             extra_steps -= 1;
             delay_us(Self::DELAY_MOVE_US);
         }
-        count
+        Ok(count)
+    }
+
+    /// Return whether the limit switch inputs are swapped.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    pub fn limit_switches_swapped(&self) -> bool {
+        self.limit_switches_swapped
+    }
+
+    /// Swap the limit switch inputs, or not, eg. to make up for the
+    /// switches being wired to each other's inputs.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    pub fn set_limit_switches_swapped(&mut self, swapped: bool) {
+        self.limit_switches_swapped = swapped;
+    }
+
+    /// Set the ends of travel for analog end-of-travel sensing. This must be
+    /// done before zeroing.
+    #[cfg(feature = "analog-limits")]
+    pub fn set_analog_limits(&mut self, left: Threshold, right: Threshold) {
+        self.left_end = left;
+        self.right_end = right;
+        self.update_analog_limits();
+    }
+
+    /// Energize or release the A driver.
+    ///
+    /// The enable input of the driver is active low. After the driver is
+    /// energized, this waits for it to be ready to take steps.
+    #[cfg(feature = "a-enable")]
+    pub fn set_a_enabled(&mut self, enabled: bool) {
+        if enabled == self.a_enabled {
+            return;
+        }
+        if enabled {
+            self.pin_a_enable.set_low();
+            delay_us(Self::DELAY_ENABLE_US);
+        } else {
+            self.pin_a_enable.set_high();
+        }
+        self.a_enabled = enabled;
     }
 
     /// Take a step along a.
     pub fn step_a(&mut self, dir: PinState) {
         self.set_a_dir(dir);
-        self.pin_a_pulse.set_high();
-        delay_us(Self::DELAY_PULSE_US);
-        self.pin_a_pulse.set_low();
-        delay_us(Self::DELAY_PULSE_US);
+        #[cfg(not(feature = "timer-steps"))]
+        {
+            self.pin_a_pulse.set_high();
+            delay_us(Self::DELAY_PULSE_US);
+            self.pin_a_pulse.set_low();
+            delay_us(Self::DELAY_PULSE_US);
+        }
+        #[cfg(feature = "timer-steps")]
+        step_timer::pulse_now(Pulse::A);
+    }
+
+    /// Set up a step along a, for the step timer to pulse.
+    #[cfg(feature = "timer-steps")]
+    pub fn prepare_step_a(&mut self, dir: PinState) {
+        self.set_a_dir(dir);
+    }
+
+    /// Set up a step along x, for the step timer to pulse, provided that
+    /// neither limit switch is triggered.
+    ///
+    /// # Returns
+    /// `true` if the step can be taken, `false` if a limit switch was
+    /// engaged.
+    #[cfg(feature = "timer-steps")]
+    pub fn prepare_step_x(&mut self, dir: PinState) -> bool {
+        if !self.left_limit_switch_is_down()
+            && !self.right_limit_switch_is_down()
+        {
+            self.set_x_dir(dir);
+            true
+        } else {
+            false
+        }
     }
 
     /// Take a step along x, provided that neither limit switch is triggered.
@@ -170,20 +370,92 @@ impl GhostInTheMachine {
     /// Take a step along x, ignoring limit switches.
     pub fn step_x_unsafe(&mut self, dir: PinState) {
         self.set_x_dir(dir);
-        self.pin_x_pulse.set_high();
-        delay_us(Self::DELAY_PULSE_US);
-        self.pin_x_pulse.set_low();
-        delay_us(Self::DELAY_PULSE_US);
+        #[cfg(not(feature = "timer-steps"))]
+        {
+            self.pin_x_pulse.set_high();
+            delay_us(Self::DELAY_PULSE_US);
+            self.pin_x_pulse.set_low();
+            delay_us(Self::DELAY_PULSE_US);
+        }
+        #[cfg(feature = "timer-steps")]
+        step_timer::pulse_now(Pulse::X);
+        self.after_step_x();
+    }
+
+    /// Update the limit switch state after a step along x.
+    pub fn after_step_x(&mut self) {
+        #[cfg(feature = "shared-limits")]
+        self.update_limit_end();
+        #[cfg(feature = "analog-limits")]
+        self.update_analog_limits();
     }
 
     /// Read the value of the left limit switch.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
     pub fn left_limit_switch_is_down(&self) -> bool {
-        self.pin_limitswitch_l.is_high()
+        match self.limit_switches_swapped {
+            false => self.pin_limitswitch_l.is_high(),
+            true => self.pin_limitswitch_r.is_high(),
+        }
     }
 
     /// Read the value of the right limit switch.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
     pub fn right_limit_switch_is_down(&self) -> bool {
-        self.pin_limitswitch_r.is_high()
+        match self.limit_switches_swapped {
+            false => self.pin_limitswitch_r.is_high(),
+            true => self.pin_limitswitch_l.is_high(),
+        }
+    }
+
+    /// Read the value of the left limit switch.
+    #[cfg(feature = "shared-limits")]
+    pub fn left_limit_switch_is_down(&self) -> bool {
+        self.limit_end == Some(PinState::High)
+    }
+
+    /// Read the value of the right limit switch.
+    #[cfg(feature = "shared-limits")]
+    pub fn right_limit_switch_is_down(&self) -> bool {
+        self.limit_end == Some(PinState::Low)
+    }
+
+    /// Read whether X is at the left end of travel.
+    #[cfg(feature = "analog-limits")]
+    pub fn left_limit_switch_is_down(&self) -> bool {
+        self.left_end.is_active()
+    }
+
+    /// Read whether X is at the right end of travel.
+    #[cfg(feature = "analog-limits")]
+    pub fn right_limit_switch_is_down(&self) -> bool {
+        self.right_end.is_active()
+    }
+
+    /// Read the X position sensor, and update the ends of travel.
+    ///
+    /// This takes one ADC conversion (about 0.1 ms), which slows down X
+    /// steps.
+    #[cfg(feature = "analog-limits")]
+    fn update_analog_limits(&mut self) {
+        let reading = self.pin_x_sensor.analog_read(&mut self.adc);
+        self.left_end.update(reading);
+        self.right_end.update(reading);
+    }
+
+    /// Work out which shared limit switch is engaged after an X step.
+    ///
+    /// Both switches are normally closed, and in series on one input, so
+    /// the input only shows that one of them is open. When it opens, the
+    /// open switch is the one that X was moving towards; it stays so until
+    /// the input closes again.
+    #[cfg(feature = "shared-limits")]
+    fn update_limit_end(&mut self) {
+        if self.pin_limitswitch_l.is_low() {
+            self.limit_end = None;
+        } else if self.limit_end.is_none() {
+            self.limit_end = Some(self.x_dir);
+        }
     }
 
     /// Set the x direction flag if necessary.
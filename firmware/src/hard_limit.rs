@@ -0,0 +1,65 @@
+use arduino_hal::{
+    port::{
+        mode::{Input, PullUp},
+        Pin, A4,
+    },
+    Peripherals, Pins,
+};
+
+use crate::machine::MoveObserver;
+
+/// Hard limit switches, mounted beyond the limit switches at each end of X
+/// as a last line of defence.
+///
+/// The switches are normally closed, and wired in series between A4 and
+/// ground. A4 is pulled up, so opening either switch (or a broken wire)
+/// reads high. Unlike the inner limit switches, which zeroing runs into as
+/// a matter of course, a hard limit only trips if the machine has lost its
+/// position.
+pub struct HardLimits {
+    pin: Pin<Input<PullUp>, A4>,
+}
+impl HardLimits {
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+        Self {
+            pin: pins.a4.into_pull_up_input(),
+        }
+    }
+
+    /// Return `true` if a hard limit switch is open.
+    pub fn tripped(&self) -> bool {
+        self.pin.is_high()
+    }
+}
+
+/// Stops a move as soon as a hard limit switch trips.
+pub struct HardLimitGuard<'a> {
+    limits: &'a HardLimits,
+    tripped: bool,
+}
+impl<'a> HardLimitGuard<'a> {
+    pub fn new(limits: &'a HardLimits) -> Self {
+        Self {
+            limits,
+            tripped: false,
+        }
+    }
+
+    /// Return `true` if a hard limit tripped during the move.
+    pub fn tripped(&self) -> bool {
+        self.tripped
+    }
+}
+impl MoveObserver for HardLimitGuard<'_> {
+    fn after_step(&mut self, _x_pos: u32, _a_pos: i64) {
+        if self.limits.tripped() {
+            self.tripped = true;
+        }
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.tripped
+    }
+}
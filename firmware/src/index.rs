@@ -0,0 +1,29 @@
+use arduino_hal::{
+    port::{
+        mode::{Input, PullUp},
+        Pin, D6,
+    },
+    Peripherals, Pins,
+};
+
+/// Spindle index sensor.
+///
+/// The sensor (eg. a hall effect sensor or slotted opto switch) pulls D6 low
+/// once per revolution of the spindle, at the angle that is taken as A zero.
+pub struct IndexSensor {
+    pin: Pin<Input<PullUp>, D6>,
+}
+impl IndexSensor {
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+        Self {
+            pin: pins.d6.into_pull_up_input(),
+        }
+    }
+
+    /// Return `true` while the index mark is at the sensor.
+    pub fn is_active(&self) -> bool {
+        self.pin.is_low()
+    }
+}
@@ -0,0 +1,98 @@
+//! Timing instrumentation.
+//!
+//! The probe output on A3 is high while a timed section runs, so that a
+//! logic analyzer on A3 shows the real on-target timing of each section.
+//! The worst-case duration of each section is also measured with the clock,
+//! to its 4 microsecond resolution, and reported by `M994`.
+
+use core::cell::{Cell, RefCell};
+
+use arduino_hal::{
+    port::{mode::Output, Pin, A3},
+    Peripherals, Pins,
+};
+use avr_device::interrupt::{self, Mutex};
+
+use crate::clock;
+
+/// A timed section of the firmware.
+#[derive(Clone, Copy)]
+pub enum Section {
+    /// A single step of a move, with the work done by its observers.
+    Step,
+    /// Parsing the arguments of a move command.
+    Parse,
+}
+
+/// Number of timed sections.
+const SECTIONS: usize = 2;
+
+/// Probe output, once it has been set up.
+static PROBE: Mutex<RefCell<Option<Pin<Output, A3>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Worst-case duration of each section, in microseconds.
+static WORST_US: Mutex<Cell<[u32; SECTIONS]>> =
+    Mutex::new(Cell::new([0; SECTIONS]));
+
+/// Set up the probe output. Sections that run earlier are not timed.
+pub fn init() {
+    let peripherals: Peripherals = unsafe { Peripherals::steal() };
+    let pins: Pins = arduino_hal::pins!(peripherals);
+    let pin = pins.a3.into_output();
+    interrupt::free(|cs| PROBE.borrow(cs).replace(Some(pin)));
+}
+
+/// Return the worst-case duration of a section since the last call to
+/// [`clear`], in microseconds.
+pub fn worst_us(section: Section) -> u32 {
+    interrupt::free(|cs| WORST_US.borrow(cs).get()[section as usize])
+}
+
+/// Clear the worst-case durations.
+pub fn clear() {
+    interrupt::free(|cs| WORST_US.borrow(cs).set([0; SECTIONS]));
+}
+
+/// Times a section, from when it is started until it is dropped.
+///
+/// Sections must not overlap: the probe output goes low when any section
+/// ends.
+pub struct Probe {
+    section: Section,
+    start_us: u32,
+}
+impl Probe {
+    pub fn start(section: Section) -> Self {
+        set_output(true);
+        Self {
+            section,
+            start_us: clock::micros(),
+        }
+    }
+}
+impl Drop for Probe {
+    fn drop(&mut self) {
+        let elapsed_us = clock::micros().wrapping_sub(self.start_us);
+        set_output(false);
+        interrupt::free(|cs| {
+            let worst = WORST_US.borrow(cs);
+            let mut worst_us = worst.get();
+            let index = self.section as usize;
+            worst_us[index] = worst_us[index].max(elapsed_us);
+            worst.set(worst_us);
+        });
+    }
+}
+
+fn set_output(high: bool) {
+    interrupt::free(|cs| {
+        if let Some(pin) = PROBE.borrow(cs).borrow_mut().as_mut() {
+            if high {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+        }
+    });
+}
@@ -0,0 +1,165 @@
+//! Interpolation of two-axis moves into single steps.
+//!
+//! A move of `dx` X steps and `da` A steps is broken into a sequence of
+//! single steps, with Bresenham's algorithm: the axis with more steps (the
+//! major axis) steps on every iteration, and the other axis steps on the
+//! iterations that keep it closest to the straight line between the start
+//! and the end of the move, as told by a [`Dda`].
+
+use crate::dda::Dda;
+
+/// Direction of a single step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dir {
+    Pos,
+    Neg,
+}
+impl Dir {
+    fn of(delta: i32) -> Self {
+        if delta >= 0 {
+            Dir::Pos
+        } else {
+            Dir::Neg
+        }
+    }
+}
+
+/// A single step of one axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    X(Dir),
+    A(Dir),
+}
+
+/// Yields the steps of a two-axis move.
+///
+/// Within an iteration where both axes step, X steps first.
+pub struct Interpolator {
+    x_dir: Dir,
+    a_dir: Dir,
+    x_major: bool,
+    /// Number of iterations left, each of which steps the major axis.
+    remaining: u32,
+    /// Steps of the minor axis.
+    minor: Dda,
+    /// A step to yield before starting the next iteration.
+    pending: Option<Step>,
+}
+impl Interpolator {
+    /// Creates an interpolator for a move of `dx` X steps and `da` A steps.
+    ///
+    /// Each axis moves at most `i32::MAX` steps, so a delta of `i32::MIN`
+    /// falls one step short.
+    pub fn new(dx: i32, da: i32) -> Self {
+        let x_steps = dx.unsigned_abs().min(i32::MAX as u32);
+        let a_steps = da.unsigned_abs().min(i32::MAX as u32);
+        let x_major = x_steps >= a_steps;
+        let (major, minor) = match x_major {
+            true => (x_steps, a_steps),
+            false => (a_steps, x_steps),
+        };
+        Self {
+            x_dir: Dir::of(dx),
+            a_dir: Dir::of(da),
+            x_major,
+            remaining: major,
+            minor: Dda::new(minor, major),
+            pending: None,
+        }
+    }
+}
+impl Iterator for Interpolator {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Step> {
+        if let Some(step) = self.pending.take() {
+            return Some(step);
+        }
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let minor_steps = self.minor.next_iteration();
+        let (step_x, step_a) = if self.x_major {
+            (true, minor_steps)
+        } else {
+            (minor_steps, true)
+        };
+        let x = Step::X(self.x_dir);
+        let a = Step::A(self.a_dir);
+        match (step_x, step_a) {
+            (true, true) => {
+                self.pending = Some(a);
+                Some(x)
+            }
+            (true, false) => Some(x),
+            (false, _) => Some(a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::String;
+
+    /// Records the steps of a move as a trace: `X` / `x` for X steps in the
+    /// positive / negative direction, and `A` / `a` for A steps.
+    fn trace(dx: i32, da: i32) -> String<64> {
+        Interpolator::new(dx, da)
+            .map(|step| match step {
+                Step::X(Dir::Pos) => 'X',
+                Step::X(Dir::Neg) => 'x',
+                Step::A(Dir::Pos) => 'A',
+                Step::A(Dir::Neg) => 'a',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_trace_single_axis() {
+        assert_eq!("", trace(0, 0));
+        assert_eq!("XXX", trace(3, 0));
+        assert_eq!("xxx", trace(-3, 0));
+        assert_eq!("AAA", trace(0, 3));
+        assert_eq!("aaa", trace(0, -3));
+    }
+
+    #[test]
+    fn test_trace_diagonal() {
+        assert_eq!("XAXAXA", trace(3, 3));
+        assert_eq!("xAxAxA", trace(-3, 3));
+        assert_eq!("XaXaXa", trace(3, -3));
+    }
+
+    #[test]
+    fn test_trace_x_major() {
+        assert_eq!("XXAXXA", trace(4, 2));
+        assert_eq!("XAXXAXXA", trace(5, 3));
+        assert_eq!("xxaxxa", trace(-4, -2));
+        assert_eq!("XXXXXAXXX", trace(8, 1));
+    }
+
+    #[test]
+    fn test_trace_a_major() {
+        assert_eq!("AXAAXA", trace(2, 4));
+        assert_eq!("AxAAxAA", trace(-2, 5));
+        assert_eq!("AAAAXAAAA", trace(1, 8));
+    }
+
+    #[test]
+    fn test_trace_reaches_target() {
+        for (dx, da) in [(7, 3), (-3, 7), (10, -1), (-1, -10), (6, 6)] {
+            let (mut x, mut a) = (0, 0);
+            for step in Interpolator::new(dx, da) {
+                match step {
+                    Step::X(Dir::Pos) => x += 1,
+                    Step::X(Dir::Neg) => x -= 1,
+                    Step::A(Dir::Pos) => a += 1,
+                    Step::A(Dir::Neg) => a -= 1,
+                }
+            }
+            assert_eq!((dx, da), (x, a));
+        }
+    }
+}
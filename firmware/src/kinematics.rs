@@ -27,7 +27,7 @@ impl Direction {
 ///
 /// The key feature of `Steps` is that it's careful to prevent overflows,
 /// so that axes will not get themselves into bad states.
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 struct Steps(i32);
 impl Steps {
     /// Create a new number of steps.
@@ -46,7 +46,7 @@ impl Steps {
     }
 
     /// Decrement the value if it's safe to do so without an overflow.
-    fn dec(&mut self) -> Option<Self> {
+    fn dec(&self) -> Option<Self> {
         self.0.checked_sub_unsigned(1).map(Steps)
     }
 }
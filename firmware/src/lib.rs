@@ -1,2 +1,30 @@
 #![no_std]
+pub mod convert;
+pub mod dda;
+pub mod diagnostics;
+pub mod dro;
+pub mod executor;
+pub mod fixed;
+pub mod gcode;
+pub mod interpolate;
 mod kinematics;
+pub mod line;
+pub mod lookahead;
+pub mod pid;
+pub mod planner;
+#[cfg(test)]
+mod programs;
+pub mod protocol;
+pub mod ramp;
+pub mod range;
+pub mod repeat;
+pub mod rotary;
+pub mod scurve;
+pub mod serial;
+pub mod source;
+pub mod threshold;
+pub mod trace;
+pub mod trapezoid;
+pub mod truncate;
+pub mod units;
+pub mod validate;
@@ -0,0 +1,207 @@
+//! Assembly of input lines from a stream of bytes.
+
+use core::mem;
+
+use heapless::String;
+
+/// Assembles bytes received from the host into lines.
+///
+/// Lines may end with LF, CR or CR LF. A line that does not fit in the
+/// buffer is discarded up to its newline, and reported once, when its
+/// newline arrives. Reading then resumes with the next line, so a flood of
+/// input costs one error per overlong line, rather than leaving the reader
+/// out of step with the host.
+pub struct LineBuffer<const N: usize> {
+    line: String<N>,
+    /// Whether the current line overflowed and is being discarded.
+    discarding: bool,
+    /// Whether the previous byte was a CR, so that a following LF belongs
+    /// to the same line ending.
+    after_cr: bool,
+    /// Number of lines discarded since startup.
+    dropped_lines: u32,
+}
+impl<const N: usize> LineBuffer<N> {
+    /// Creates an empty line buffer.
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            discarding: false,
+            after_cr: false,
+            dropped_lines: 0,
+        }
+    }
+
+    /// Adds a byte to the current line.
+    ///
+    /// # Returns
+    ///
+    /// - `None` if the line is not complete yet.
+    /// - `Some(Ok(()))` if the byte completed a line, which can be taken
+    ///   with [`take_line`](Self::take_line).
+    /// - `Some(Err(Error::Overflow))` if the byte ended a line that was too
+    ///   long and has been discarded.
+    pub fn push(&mut self, byte: u8) -> Option<Result<(), Error>> {
+        let after_cr = mem::replace(&mut self.after_cr, byte == b'\r');
+        if byte == b'\n' && after_cr {
+            return None;
+        }
+        if byte == b'\n' || byte == b'\r' {
+            if mem::take(&mut self.discarding) {
+                self.line.clear();
+                self.dropped_lines = self.dropped_lines.saturating_add(1);
+                return Some(Err(Error::Overflow));
+            }
+            return Some(Ok(()));
+        }
+        if !self.discarding && self.line.push(byte as char).is_err() {
+            self.discarding = true;
+        }
+        None
+    }
+
+    /// Returns the current line.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Takes the current line, leaving the buffer empty.
+    pub fn take_line(&mut self) -> String<N> {
+        mem::take(&mut self.line)
+    }
+
+    /// Discards the current line, including any overflow in progress.
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.discarding = false;
+        self.after_cr = false;
+    }
+
+    /// Returns the number of lines discarded since startup, because they
+    /// were too long.
+    pub fn dropped_lines(&self) -> u32 {
+        self.dropped_lines
+    }
+}
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Errors that might occur when assembling a line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The line was too long for the buffer, and has been discarded.
+    Overflow,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes all of `input`, returning the result for the last line ending
+    /// (or `None` if there was none).
+    fn push_all<const N: usize>(
+        buffer: &mut LineBuffer<N>,
+        input: &str,
+    ) -> Option<Result<(), Error>> {
+        let mut result = None;
+        for byte in input.bytes() {
+            if let Some(r) = buffer.push(byte) {
+                result = Some(r);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_line() {
+        let mut buffer: LineBuffer<8> = LineBuffer::new();
+        assert_eq!(None, push_all(&mut buffer, "G0 X1"));
+        assert_eq!(Some(Ok(())), buffer.push(b'\n'));
+        assert_eq!("G0 X1", buffer.take_line().as_str());
+        assert_eq!("", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        for input in ["G4\n", "G4\r", "G4\r\n"] {
+            let mut buffer: LineBuffer<8> = LineBuffer::new();
+            assert_eq!(Some(Ok(())), push_all(&mut buffer, input));
+            assert_eq!("G4", buffer.take_line().as_str());
+        }
+    }
+
+    #[test]
+    fn test_crlf_is_a_single_line_ending() {
+        let mut buffer: LineBuffer<8> = LineBuffer::new();
+        assert_eq!(Some(Ok(())), push_all(&mut buffer, "G4\r"));
+        buffer.take_line();
+        assert_eq!(None, buffer.push(b'\n'));
+        assert_eq!(Some(Ok(())), push_all(&mut buffer, "G0\r\n"));
+        assert_eq!("G0", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let mut buffer: LineBuffer<8> = LineBuffer::new();
+        assert_eq!(Some(Ok(())), buffer.push(b'\n'));
+        assert_eq!("", buffer.take_line().as_str());
+        assert_eq!(Some(Ok(())), push_all(&mut buffer, "\r\n"));
+        assert_eq!("", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_line_that_fills_buffer() {
+        let mut buffer: LineBuffer<4> = LineBuffer::new();
+        assert_eq!(Some(Ok(())), push_all(&mut buffer, "abcd\n"));
+        assert_eq!("abcd", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_overflow_is_reported_once_at_newline() {
+        let mut buffer: LineBuffer<4> = LineBuffer::new();
+        for byte in "abcdefgh".bytes() {
+            assert_eq!(None, buffer.push(byte));
+        }
+        assert_eq!(Some(Err(Error::Overflow)), buffer.push(b'\n'));
+        assert_eq!(1, buffer.dropped_lines());
+    }
+
+    #[test]
+    fn test_resynchronizes_after_overflow() {
+        let mut buffer: LineBuffer<4> = LineBuffer::new();
+        assert_eq!(
+            Some(Err(Error::Overflow)),
+            push_all(&mut buffer, "abcde\n")
+        );
+        assert_eq!(Some(Ok(())), push_all(&mut buffer, "G4\n"));
+        assert_eq!("G4", buffer.take_line().as_str());
+
+        assert_eq!(
+            Some(Err(Error::Overflow)),
+            push_all(&mut buffer, "abcde\r")
+        );
+        assert_eq!(None, buffer.push(b'\n'));
+        assert_eq!(Some(Ok(())), push_all(&mut buffer, "G0\r\n"));
+        assert_eq!("G0", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_counts_dropped_lines() {
+        let mut buffer: LineBuffer<2> = LineBuffer::new();
+        push_all(&mut buffer, "abc\ndef\nok\nghi\n");
+        assert_eq!(3, buffer.dropped_lines());
+    }
+
+    #[test]
+    fn test_clear_ends_overflow() {
+        let mut buffer: LineBuffer<2> = LineBuffer::new();
+        push_all(&mut buffer, "abc");
+        buffer.clear();
+        assert_eq!(Some(Ok(())), push_all(&mut buffer, "ab\n"));
+        assert_eq!("ab", buffer.take_line().as_str());
+        assert_eq!(0, buffer.dropped_lines());
+    }
+}
@@ -0,0 +1,85 @@
+//! Leveled log messages with compile-time filtering.
+//!
+//! Log messages are written to the UART, prefixed by the uptime in
+//! milliseconds and their level (eg. `[1234] INFO: ...`). Messages above
+//! [`MAX_LEVEL`] are compiled out entirely, so that verbose diagnostics cost
+//! no flash in normal builds.
+//!
+//! The maximum level is selected with cargo features. Without any features,
+//! the maximum level is `Info`. If more than one of the `log-level-*`
+//! features is enabled, the most restrictive one wins.
+
+/// Severity of a log message.
+#[derive(Clone, Copy)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+/// Most verbose level of log messages compiled into the firmware.
+pub const MAX_LEVEL: Level = if cfg!(feature = "log-level-error") {
+    Level::Error
+} else if cfg!(feature = "log-level-warn") {
+    Level::Warn
+} else if cfg!(feature = "log-level-debug") {
+    Level::Debug
+} else {
+    Level::Info
+};
+
+/// Write a log message at the given level, expanding its arguments.
+///
+/// This expects `$self` to have an `output_buffer`, `settings` and a
+/// `writeln_buffer` method (ie. to be the `Controller`). Messages that do
+/// not fit in the output buffer are truncated, and no messages are written
+/// while the `$53` quiet setting is on. Prefer the level-specific macros
+/// over using this directly.
+macro_rules! log {
+    ($self:expr, $level:expr, $prefix:literal, $($arg:tt)*) => {{
+        if ($level as u8) <= ($crate::log::MAX_LEVEL as u8)
+            && !$self.settings.quiet
+        {
+            winderbot_lib::truncate::format_truncated(
+                &mut $self.output_buffer,
+                format_args!(
+                    concat!("[{}] ", $prefix, ": {}"),
+                    $crate::clock::millis(),
+                    format_args!($($arg)*)
+                ),
+            );
+            $self.writeln_buffer();
+        }
+    }};
+}
+
+/// Write an error message, expanding its arguments.
+macro_rules! log_error {
+    ($self:expr, $($arg:tt)*) => {
+        $crate::log::log!($self, $crate::log::Level::Error, "ERROR", $($arg)*)
+    };
+}
+
+/// Write a warning message, expanding its arguments.
+macro_rules! log_warn {
+    ($self:expr, $($arg:tt)*) => {
+        $crate::log::log!($self, $crate::log::Level::Warn, "WARN", $($arg)*)
+    };
+}
+
+/// Write an info message, expanding its arguments.
+macro_rules! log_info {
+    ($self:expr, $($arg:tt)*) => {
+        $crate::log::log!($self, $crate::log::Level::Info, "INFO", $($arg)*)
+    };
+}
+
+/// Write a debug message, expanding its arguments.
+macro_rules! log_debug {
+    ($self:expr, $($arg:tt)*) => {
+        $crate::log::log!($self, $crate::log::Level::Debug, "DEBUG", $($arg)*)
+    };
+}
+
+pub(crate) use {log, log_debug, log_error, log_info, log_warn};
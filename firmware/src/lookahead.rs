@@ -0,0 +1,323 @@
+//! Lookahead across consecutive moves.
+//!
+//! A move on its own starts and ends at rest, so a run of short moves (eg.
+//! in scatter winding) spends most of its time speeding up and slowing
+//! down. A [`Lookahead`] holds the next few moves in a ring buffer, and
+//! plans the rate of each move's major axis at its ends, so that the
+//! machine passes from one move to the next without stopping wherever it
+//! can still stop by the end of the last move that it knows of.
+//!
+//! Rates are planned as their squares, as in Grbl: over `n` steps at
+//! acceleration `a`, the square of the rate changes by at most `2 * a * n`.
+
+use heapless::Deque;
+
+use crate::trapezoid::isqrt;
+
+/// A straight move of two axes, to be planned by a [`Lookahead`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    /// Steps along each axis.
+    pub steps: [i32; 2],
+    /// Cruise rate of the axis with more steps, in steps per second.
+    pub rate: u32,
+    /// Acceleration of the axis with more steps, in steps per second
+    /// squared. Zero starts and stops at the cruise rate.
+    pub accel: u32,
+    /// Whether the move may start without stopping after the one before
+    /// it. This is `false` eg. when X reverses, and dwells first.
+    pub blend: bool,
+}
+impl Segment {
+    /// Returns the index of the axis with more steps (the first, if they
+    /// have as many), and its number of steps.
+    fn major(&self) -> (usize, u32) {
+        let [first, second] = self.steps.map(i32::unsigned_abs);
+        match first >= second {
+            true => (0, first),
+            false => (1, second),
+        }
+    }
+
+    /// Returns the square of the highest rate that the move can reach from
+    /// a rate whose square is `from_sq`, over all of its steps.
+    fn reach_sq(&self, from_sq: u64) -> u64 {
+        match self.accel {
+            0 => u64::MAX,
+            accel => {
+                let gain = 2 * accel as u64 * self.major().1 as u64;
+                from_sq.saturating_add(gain)
+            }
+        }
+    }
+}
+
+/// A segment taken from a [`Lookahead`] to be run, with the planned rates
+/// of its major axis at its ends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Planned {
+    pub segment: Segment,
+    /// Rate at the start, in steps per second. Zero is rest.
+    pub entry_rate: u32,
+    /// Rate at the end, in steps per second. Zero is rest.
+    pub exit_rate: u32,
+}
+
+/// A segment waiting in a [`Lookahead`].
+struct Entry {
+    segment: Segment,
+    /// Square of the highest rate at the junction with the segment before.
+    max_entry_sq: u64,
+    /// Square of the planned rate at the start.
+    entry_sq: u64,
+}
+
+/// Plans the rates at the junctions of up to `N` consecutive moves.
+///
+/// At a junction, the rate is at most the lower of the two cruise rates,
+/// times the cosine of the angle between the moves. Moves stop at a
+/// junction where the major axis changes, the moves turn through a right
+/// angle or more, or the later move does not blend. The rates are then
+/// lowered so that each move can reach the next junction's rate at its
+/// acceleration, and the last move can stop at its end.
+pub struct Lookahead<const N: usize> {
+    entries: Deque<Entry, N>,
+    /// Whether the rate at the start of the first segment is fixed, as the
+    /// segment before it has already been taken to run up to that rate.
+    front_fixed: bool,
+}
+impl<const N: usize> Lookahead<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: Deque::new(),
+            front_fixed: false,
+        }
+    }
+
+    /// Returns the number of segments waiting.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no segments are waiting.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns `true` if no more segments can be added until one is taken.
+    pub fn is_full(&self) -> bool {
+        self.entries.is_full()
+    }
+
+    /// Returns the steps of all waiting segments, along each axis.
+    pub fn steps(&self) -> [i64; 2] {
+        self.entries.iter().fold([0, 0], |[first, second], entry| {
+            let [dfirst, dsecond] = entry.segment.steps;
+            [first + dfirst as i64, second + dsecond as i64]
+        })
+    }
+
+    /// Adds a segment after the others, and plans the rates again.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: If the segment was added.
+    /// - `Err(segment)`: If the lookahead is full.
+    pub fn push(&mut self, segment: Segment) -> Result<(), Segment> {
+        let max_entry_sq = match self.entries.back() {
+            Some(last) if segment.blend => junction_sq(&last.segment, &segment),
+            _ => 0,
+        };
+        let entry = Entry {
+            segment,
+            max_entry_sq,
+            entry_sq: max_entry_sq,
+        };
+        self.entries
+            .push_back(entry)
+            .map_err(|entry| entry.segment)?;
+        self.plan();
+        Ok(())
+    }
+
+    /// Takes the first segment to be run, with its planned rates.
+    ///
+    /// The rate at the start of the next segment is then fixed, since the
+    /// taken segment runs up to it.
+    pub fn pop(&mut self) -> Option<Planned> {
+        let entry = self.entries.pop_front()?;
+        let exit_sq = self.entries.front().map_or(0, |next| next.entry_sq);
+        self.front_fixed = !self.entries.is_empty();
+        Some(Planned {
+            segment: entry.segment,
+            entry_rate: rate(entry.entry_sq),
+            exit_rate: rate(exit_sq),
+        })
+    }
+
+    /// Drops all waiting segments, eg. after a move stops early.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.front_fixed = false;
+    }
+
+    /// Plans the rates at the junctions: backwards, so that each segment
+    /// can slow down to the rate at its end, and then forwards, so that it
+    /// can speed up to it.
+    fn plan(&mut self) {
+        let len = self.entries.len();
+        let mut exit_sq = 0;
+        for (i, entry) in self.entries.iter_mut().rev().enumerate() {
+            let fixed = self.front_fixed && i + 1 == len;
+            if !fixed {
+                let reach_sq = entry.segment.reach_sq(exit_sq);
+                entry.entry_sq = entry.max_entry_sq.min(reach_sq);
+            }
+            exit_sq = entry.entry_sq;
+        }
+        let mut reach_sq = u64::MAX;
+        for entry in self.entries.iter_mut() {
+            entry.entry_sq = entry.entry_sq.min(reach_sq);
+            reach_sq = entry.segment.reach_sq(entry.entry_sq);
+        }
+    }
+}
+impl<const N: usize> Default for Lookahead<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the square of the highest rate at the junction from segment
+/// `from` to segment `to`.
+fn junction_sq(from: &Segment, to: &Segment) -> u64 {
+    let ((from_major, from_steps), (to_major, to_steps)) =
+        (from.major(), to.major());
+    if from_steps == 0 || to_steps == 0 || from_major != to_major {
+        return 0;
+    }
+    let [a, b] = [from.steps, to.steps].map(|s| s.map(|c| c as i128));
+    let dot = a[0] * b[0] + a[1] * b[1];
+    if dot <= 0 {
+        return 0;
+    }
+    let rate = from.rate.min(to.rate) as u128;
+    let norms = (a[0] * a[0] + a[1] * a[1]) * (b[0] * b[0] + b[1] * b[1]);
+    // rate^2 * cos^2, where cos = dot / sqrt(norms).
+    let cos_sq_rate_sq = rate * rate * (dot * dot) as u128 / norms as u128;
+    cos_sq_rate_sq.min(u64::MAX as u128) as u64
+}
+
+/// Returns a rate from its square.
+fn rate(rate_sq: u64) -> u32 {
+    isqrt(rate_sq).min(u32::MAX as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+
+    fn segment(steps: [i32; 2]) -> Segment {
+        Segment {
+            steps,
+            rate: 10_000,
+            accel: 100_000,
+            blend: true,
+        }
+    }
+
+    /// Plans the segments together, and returns the entry and exit rates
+    /// of each.
+    fn plan(segments: &[Segment]) -> Vec<(u32, u32), 8> {
+        let mut lookahead: Lookahead<8> = Lookahead::new();
+        for segment in segments {
+            lookahead.push(*segment).unwrap();
+        }
+        let mut rates = Vec::new();
+        while let Some(planned) = lookahead.pop() {
+            rates.push((planned.entry_rate, planned.exit_rate)).unwrap();
+        }
+        rates
+    }
+
+    #[test]
+    fn test_single_segment() {
+        assert_eq!([(0, 0)], plan(&[segment([1000, 10])]).as_slice());
+    }
+
+    #[test]
+    fn test_collinear_segments() {
+        let long = segment([1000, 0]);
+        assert_eq!(
+            [(0, 10_000), (10_000, 10_000), (10_000, 0)],
+            plan(&[long, long, long]).as_slice()
+        );
+        // Over 10 steps at 100_000 steps/s^2, the rate changes by at most
+        // sqrt(2_000_000), and the last segment must still stop.
+        let short = segment([10, 0]);
+        assert_eq!(
+            [(0, 1414), (1414, 1414), (1414, 0)],
+            plan(&[short, short, short]).as_slice()
+        );
+    }
+
+    #[test]
+    fn test_corners() {
+        let x = segment([100, 0]);
+        let stop = [(0, 0), (0, 0)];
+        // A reversal, and a change of major axis.
+        assert_eq!(stop, plan(&[x, segment([-100, 0])]).as_slice());
+        assert_eq!(stop, plan(&[x, segment([10, 100])]).as_slice());
+        // Moves that do not blend.
+        let reversal = Segment { blend: false, ..x };
+        assert_eq!(stop, plan(&[x, reversal]).as_slice());
+        // cos(45 degrees) of the rate, which is also as fast as the second
+        // segment can stop from.
+        let rates = plan(&[x, segment([100, 100])]);
+        assert_eq!([(0, 4472), (4472, 0)], rates.as_slice());
+        let rates = plan(&[segment([1000, 0]), segment([1000, 1000])]);
+        assert_eq!([(0, 7071), (7071, 0)], rates.as_slice());
+    }
+
+    #[test]
+    fn test_rates() {
+        // A slower segment caps the junction, and one without acceleration
+        // does not limit the rates.
+        let slow = Segment {
+            rate: 5000,
+            ..segment([1000, 0])
+        };
+        let sudden = Segment {
+            accel: 0,
+            ..segment([10, 0])
+        };
+        assert_eq!(
+            [(0, 5000), (5000, 5000), (5000, 0)],
+            plan(&[segment([1000, 0]), slow, sudden]).as_slice()
+        );
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let short = segment([10, 0]);
+        let mut lookahead: Lookahead<3> = Lookahead::new();
+        for _ in 0..3 {
+            lookahead.push(short).unwrap();
+        }
+        assert!(lookahead.is_full());
+        assert_eq!(Err(short), lookahead.push(short));
+        assert_eq!([30, 0], lookahead.steps());
+        let first = lookahead.pop().unwrap();
+        assert_eq!((0, 1414), (first.entry_rate, first.exit_rate));
+        // A later segment lets the next one end faster, but not start any
+        // faster than the first ended.
+        lookahead.push(short).unwrap();
+        let second = lookahead.pop().unwrap();
+        assert_eq!((1414, 2000), (second.entry_rate, second.exit_rate));
+        lookahead.clear();
+        assert!(lookahead.is_empty());
+        assert_eq!(None, lookahead.pop());
+    }
+}
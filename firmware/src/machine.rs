@@ -1,15 +1,60 @@
+use core::fmt::{self, Display, Formatter};
+
+use arduino_hal::delay_ms;
+#[cfg(feature = "a-index")]
 use arduino_hal::delay_us;
 use embedded_hal::digital::PinState;
 
-use crate::gitm::GhostInTheMachine;
+use winderbot_lib::{
+    convert::{Accumulator, Converter},
+    executor::{Hold, MoveExecutor},
+    interpolate::{Dir, Step},
+    lookahead::{Lookahead, Segment},
+    planner::{Arc, ArcCenter, ArcDir, ArcError},
+    range::StepRange,
+    rotary::{index_steps, shortest_delta, Turns},
+    scurve::SCurve,
+    trapezoid::Trapezoid,
+    units::Thousandths,
+};
+
+use crate::{
+    gitm::GhostInTheMachine,
+    settings::{Profile, Settings},
+};
+
+use crate::clock;
+#[cfg(feature = "instrument")]
+use crate::instrument::{Probe, Section};
+#[cfg(feature = "timer-steps")]
+use crate::step_timer::{self, Pulse};
+#[cfg(not(feature = "timer-steps"))]
+use winderbot_lib::executor::Poll;
+
+/// Number of moves that can be queued for lookahead.
+const LOOKAHEAD_SEGMENTS: usize = 4;
 
 pub struct Machine {
     gitm: GhostInTheMachine,
     move_mode: MoveMode,
     move_delay_us: u32,
     x_pos: u32,
-    a_pos: u32,
-    x_limit: u32,
+    /// A position, in steps. A turns without limit, so this is kept wide
+    /// enough that it cannot overflow in any job.
+    a_pos: i64,
+    /// Soft limits of X.
+    x_range: StepRange,
+    /// Direction of the last X move, if there has been one.
+    last_x_dir: Option<XDir>,
+    /// Moves queued to run with lookahead, in steps.
+    lookahead: Lookahead<LOOKAHEAD_SEGMENTS>,
+    /// Direction of the last X move queued, or run if none are queued.
+    queued_x_dir: Option<XDir>,
+    /// Fractions of a step left over from relative moves.
+    x_carry: Accumulator,
+    a_carry: Accumulator,
+    #[cfg(feature = "a-enable")]
+    a_idle_mode: AIdleMode,
 }
 impl Machine {
     /// Number of steps to use as an "electronic addition" to the limit
@@ -20,107 +65,1034 @@ impl Machine {
     /// Steps per revolution for x-axis.
     const X_STEPS_PER_REV: u32 = 6400;
     /// Steps per revolution for a-axis.
-    const A_STEPS_PER_REV: u32 = 6400;
+    pub const A_STEPS_PER_REV: u32 = 6400;
+    /// Conversion between X microns and steps.
+    const X_CONVERTER: Converter =
+        Converter::new(Self::X_STEPS_PER_REV, Self::X_MM_PER_REV * 1000);
+    /// Conversion between A millidegrees and steps.
+    const A_CONVERTER: Converter =
+        Converter::new(Self::A_STEPS_PER_REV, 360_000);
+    /// Longest segment of an arc, in microns of X and millidegrees of A.
+    const ARC_SEGMENT_MILLIS: u32 = 250;
 
     /// Return a new machine.
     ///
     /// This zeroes the machine (on startup) so that we know where we are.
-    pub fn new() -> Machine {
+    /// If the settings give a maximum X travel, only the left limit switch
+    /// is used, and the travel sets the soft limit on the right.
+    ///
+    /// Zeroing fails if a limit switch engages while X moves away from it,
+    /// as when the switches are wired to each other's inputs. If the
+    /// settings allow, the switches are then swapped, and zeroing is tried
+    /// again.
+    pub fn new(settings: &Settings) -> Result<Machine, ZeroingError> {
         let mut gitm = GhostInTheMachine::new();
-        let move_mode = MoveMode::Absolute;
+        #[cfg(not(any(
+            feature = "shared-limits",
+            feature = "analog-limits"
+        )))]
+        gitm.set_limit_switches_swapped(settings.x_limit_switches_swapped);
+        #[cfg(feature = "analog-limits")]
+        {
+            let (left, right) = settings.analog_limits();
+            gitm.set_analog_limits(left, right);
+        }
+        let move_mode = if settings.default_relative {
+            MoveMode::Relative
+        } else {
+            MoveMode::Absolute
+        };
         let move_delay_us = 100;
-        let count = gitm.zero();
-        let x_pos = (count / 2) - Self::X_EDGE_SAFETY_STEPS;
+        let count = match Self::zero_x(&mut gitm, settings) {
+            #[cfg(not(any(
+                feature = "shared-limits",
+                feature = "analog-limits"
+            )))]
+            Err(ZeroingError::SwitchesSwapped)
+                if settings.x_limit_switches_auto_swap =>
+            {
+                let swapped = gitm.limit_switches_swapped();
+                gitm.set_limit_switches_swapped(!swapped);
+                Self::zero_x(&mut gitm, settings)?
+            }
+            result => result?,
+        };
+        let x_pos = (count / 2).saturating_sub(Self::X_EDGE_SAFETY_STEPS);
         let a_pos = 0;
-        let x_limit = count - 2 * Self::X_EDGE_SAFETY_STEPS;
+        let x_range = StepRange::from_count(
+            count.saturating_sub(2 * Self::X_EDGE_SAFETY_STEPS),
+        );
 
-        Machine {
+        Ok(Machine {
             gitm,
             move_mode,
             move_delay_us,
             x_pos,
             a_pos,
-            x_limit,
+            x_range,
+            last_x_dir: None,
+            lookahead: Lookahead::new(),
+            queued_x_dir: None,
+            x_carry: Accumulator::new(Self::X_CONVERTER),
+            a_carry: Accumulator::new(Self::A_CONVERTER),
+            #[cfg(feature = "a-enable")]
+            a_idle_mode: AIdleMode::Hold,
+        })
+    }
+
+    /// Move X to its limit switches, and return its travel in steps.
+    fn zero_x(
+        gitm: &mut GhostInTheMachine,
+        settings: &Settings,
+    ) -> Result<u32, ZeroingError> {
+        match settings.x_max_travel_microns {
+            0 => gitm.zero(),
+            travel => {
+                let travel = Self::X_CONVERTER.to_steps(travel as i32);
+                gitm.zero_from_left(travel as u32)
+            }
+        }
+    }
+
+    /// Return whether the limit switch inputs are swapped, which zeroing
+    /// may have changed from the settings.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    pub fn limit_switches_swapped(&self) -> bool {
+        self.gitm.limit_switches_swapped()
+    }
+
+    /// Return the current position, as (X, A) steps.
+    pub fn position(&self) -> (u32, i64) {
+        (self.x_pos, self.a_pos)
+    }
+
+    /// Return the current position, as X steps and A turns with the angle
+    /// in steps, in the given coordinate system.
+    pub fn position_steps(
+        &self,
+        coordinates: Coordinates,
+        settings: &Settings,
+    ) -> (i32, Turns) {
+        let x_offset = match coordinates {
+            Coordinates::Machine => 0,
+            Coordinates::Bobbin => Self::X_CONVERTER
+                .to_steps(settings.bobbin_flange_x_microns as i32),
+        };
+        let a_offset = Self::a_offset_steps(coordinates, settings);
+        (
+            self.x_pos as i32 - x_offset,
+            Self::a_turns(self.a_pos - a_offset),
+        )
+    }
+
+    /// Return the current position, as X in microns and A turns with the
+    /// angle in millidegrees.
+    ///
+    /// # Parameters
+    ///
+    /// - `coordinates`: Coordinate system.
+    /// - `settings`: Settings, for the origin of bobbin coordinates.
+    pub fn position_millis(
+        &self,
+        coordinates: Coordinates,
+        settings: &Settings,
+    ) -> (i32, Turns) {
+        Self::millis_of(self.x_pos, self.a_pos, coordinates, settings)
+    }
+
+    /// Return a position in steps as X in microns and A turns with the angle
+    /// in millidegrees, eg. for a position reported by a [`MoveObserver`].
+    ///
+    /// # Parameters
+    ///
+    /// - `x_pos`, `a_pos`: Position, in machine steps.
+    /// - `coordinates`: Coordinate system.
+    /// - `settings`: Settings, for the origin of bobbin coordinates.
+    pub fn millis_of(
+        x_pos: u32,
+        a_pos: i64,
+        coordinates: Coordinates,
+        settings: &Settings,
+    ) -> (i32, Turns) {
+        let x_offset = match coordinates {
+            Coordinates::Machine => 0,
+            Coordinates::Bobbin => settings.bobbin_flange_x_microns as i32,
+        };
+        let x_microns = Self::X_CONVERTER.to_units(x_pos as i32) - x_offset;
+        let a_offset = Self::a_offset_steps(coordinates, settings);
+        let a = Self::a_turns(a_pos - a_offset)
+            .map_angle(|angle| Self::A_CONVERTER.to_units(angle as i32) as u32);
+        (x_microns, a)
+    }
+
+    /// Return an A position in steps as whole turns and the angle within a
+    /// turn, in steps.
+    fn a_turns(a_pos: i64) -> Turns {
+        Turns::from_steps(a_pos, Self::A_STEPS_PER_REV)
+    }
+
+    /// Return the A position of the origin of a coordinate system, in
+    /// machine steps.
+    fn a_offset_steps(coordinates: Coordinates, settings: &Settings) -> i64 {
+        match coordinates {
+            Coordinates::Machine => 0,
+            Coordinates::Bobbin => Self::A_CONVERTER
+                .to_steps(settings.bobbin_origin_a_millidegrees as i32)
+                as i64,
         }
     }
 
+    /// Return the move mode.
+    pub fn move_mode(&self) -> MoveMode {
+        self.move_mode
+    }
+
+    /// Return the soft limits of X, in machine steps.
+    pub fn x_range(&self) -> StepRange {
+        self.x_range
+    }
+
     /// Set the move mode (absolute or relative moves).
     pub fn set_move_mode(&mut self, move_mode: MoveMode) {
         self.move_mode = move_mode;
     }
 
+    /// Set what the A driver does during moves that do not turn A.
+    ///
+    /// Zeroing the machine restores [`AIdleMode::Hold`].
+    #[cfg(feature = "a-enable")]
+    pub fn set_a_idle_mode(&mut self, a_idle_mode: AIdleMode) {
+        self.a_idle_mode = a_idle_mode;
+    }
+
+    /// Release the A driver, eg. so that the bobbin can be turned by hand
+    /// at the end of a job. The next move that turns A energizes it again.
+    #[cfg(feature = "a-enable")]
+    pub fn release_a(&mut self) {
+        self.gitm.set_a_enabled(false);
+    }
+
+    /// Take one step of A forwards, in spindle mode.
+    ///
+    /// A turns without limit, so its position wraps around.
+    pub fn spin_a(&mut self) {
+        #[cfg(feature = "a-enable")]
+        self.gitm.set_a_enabled(true);
+        self.step_a(ADir::Pos);
+    }
+
     /// Perform a move.
-    pub fn move_millis(&mut self, x_microns: i32, a_millidegrees: i32) {
+    ///
+    /// In absolute mode, X and A are taken in the given coordinate system. The
+    /// `observer` is notified after every step of the move, and the move
+    /// ends early if the observer requests a stop.
+    ///
+    /// An X target beyond the soft limits, in either mode, is clipped to
+    /// them, or the move is rejected without moving, depending on the
+    /// settings.
+    pub fn move_millis<O: MoveObserver>(
+        &mut self,
+        x_microns: i32,
+        a_millidegrees: i32,
+        coordinates: Coordinates,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> Result<MoveReport, MoveError> {
+        let (dx, da) =
+            self.move_steps(x_microns, a_millidegrees, coordinates, settings)?;
+        self.move_rel_steps_clipped(dx, da, settings, observer)
+    }
+
+    /// Queue a move (`G0`) to run with lookahead, so that it runs on from
+    /// the move before it without stopping where it can. See
+    /// [`Lookahead`] for where moves still stop, and note that a move
+    /// that reverses X only stops for it if there is a reversal dwell.
+    ///
+    /// The move is taken as by [`Self::move_millis`], but from the end of
+    /// the queued moves. If the queue is full, the first queued move runs
+    /// to make room for it. [`Self::run_queue`] runs the rest, and must be
+    /// called before any other motion.
+    ///
+    /// A move that is refused still runs the moves queued before it. A
+    /// stop requested by the observer, of the machine or of one axis,
+    /// drops the rest of the queue.
+    pub fn queue_millis<O: MoveObserver>(
+        &mut self,
+        x_microns: i32,
+        a_millidegrees: i32,
+        coordinates: Coordinates,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> Result<MoveReport, MoveError> {
+        let steps = self
+            .move_steps(x_microns, a_millidegrees, coordinates, settings)
+            .and_then(|(dx, da)| {
+                Ok((dx, da, self.x_clipped_steps(dx, settings)?))
+            });
+        let (dx, da, x_clipped_steps) = match steps {
+            Ok(steps) => steps,
+            Err(error) => {
+                self.run_queue(settings, observer);
+                return Err(error);
+            }
+        };
+        let mut report = MoveReport {
+            x_clipped_steps,
+            ..MoveReport::default()
+        };
+        let dx = dx - x_clipped_steps;
+        if dx == 0 && da == 0 {
+            return Ok(report);
+        }
+        let mut segment = self.segment(dx, da, settings);
+        while let Err(rejected) = self.lookahead.push(segment) {
+            segment = rejected;
+            if let Some(run) = self.run_queued(settings, observer) {
+                report.add(run);
+            }
+            if stop_requested(observer) {
+                break;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Run the moves queued by [`Self::queue_millis`].
+    pub fn run_queue<O: MoveObserver>(
+        &mut self,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> MoveReport {
+        let mut report = MoveReport::default();
+        while let Some(run) = self.run_queued(settings, observer) {
+            report.add(run);
+        }
+        report
+    }
+
+    /// Run the first queued move, if there is one, at the rates planned
+    /// for its ends.
+    fn run_queued<O: MoveObserver>(
+        &mut self,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> Option<MoveReport> {
+        let planned = self.lookahead.pop()?;
+        let [dx, da] = planned.segment.steps;
+        let report = self.move_rel_steps(
+            dx,
+            da,
+            (settings.x_max_step_rate, settings.a_max_step_rate),
+            (planned.entry_rate, planned.exit_rate),
+            settings,
+            observer,
+        );
+        if stop_requested(observer) {
+            self.lookahead.clear();
+            self.x_carry.reset();
+            self.a_carry.reset();
+        }
+        Some(report)
+    }
+
+    /// Return a move of `dx`, `da` steps as a segment for the lookahead.
+    fn segment(&mut self, dx: i32, da: i32, settings: &Settings) -> Segment {
+        let (max_step_rate, accel, profile) =
+            match dx.unsigned_abs() >= da.unsigned_abs() {
+                true => (
+                    settings.x_max_step_rate,
+                    settings.x_accel,
+                    settings.x_profile,
+                ),
+                false => (
+                    settings.a_max_step_rate,
+                    settings.a_accel,
+                    settings.a_profile,
+                ),
+            };
+        if self.lookahead.is_empty() {
+            self.queued_x_dir = self.last_x_dir;
+        }
+        let x_dir = match dx {
+            0 => None,
+            dx if dx > 0 => Some(XDir::Right),
+            _ => Some(XDir::Left),
+        };
+        let reverses = x_dir.is_some_and(|dir| {
+            self.queued_x_dir.is_some_and(|last| last != dir)
+        });
+        self.queued_x_dir = x_dir.or(self.queued_x_dir);
+        // A move that dwells before it reverses X starts from rest, and so
+        // does one that ramps with an S-curve, which only starts and ends
+        // at rest.
+        let dwells = reverses && settings.x_reversal_dwell_ms > 0;
+        Segment {
+            steps: [dx, da],
+            rate: 1_000_000 / self.step_delay_us(max_step_rate),
+            accel,
+            blend: profile == Profile::Trapezoid && !dwells,
+        }
+    }
+
+    /// Return `true` if moves are queued by [`Self::queue_millis`], to be
+    /// run by [`Self::run_queue`].
+    pub fn has_queue(&self) -> bool {
+        !self.lookahead.is_empty()
+    }
+
+    /// Return the position at the end of the queued moves, as (X, A) steps.
+    fn planned_position(&self) -> (i32, i64) {
+        let [dx, da] = self.lookahead.steps();
+        (self.x_pos as i32 + dx as i32, self.a_pos + da)
+    }
+
+    /// Return the steps of a move, taken as by [`Self::move_millis`], from
+    /// the end of the queued moves.
+    fn move_steps(
+        &mut self,
+        x_microns: i32,
+        a_millidegrees: i32,
+        coordinates: Coordinates,
+        settings: &Settings,
+    ) -> Result<(i32, i32), MoveError> {
         match self.move_mode {
+            // Fractions of a step are carried over to the next relative
+            // move, so that many short moves add up to the same distance
+            // as one long one.
             MoveMode::Relative => {
-                self.move_rel_millis(x_microns, a_millidegrees)
+                let dx = self.x_carry.checked_to_steps(x_microns).ok_or(
+                    overflow(Axis::X, OverflowStage::Conversion, x_microns),
+                )?;
+                let da = self.a_carry.checked_to_steps(a_millidegrees).ok_or(
+                    overflow(
+                        Axis::A,
+                        OverflowStage::Conversion,
+                        a_millidegrees,
+                    ),
+                )?;
+                Ok((dx, da))
             }
             MoveMode::Absolute => {
-                self.move_abs_millis(x_microns, a_millidegrees)
+                let x_target =
+                    Self::x_machine_microns(x_microns, coordinates, settings)?;
+                let a_target = Self::a_machine_millidegrees(
+                    a_millidegrees,
+                    coordinates,
+                    settings,
+                )?;
+                let steps = self.abs_steps(x_target, a_target)?;
+                self.x_carry.reset();
+                self.a_carry.reset();
+                Ok(steps)
             }
         }
     }
 
-    /// Move an absolute number of microns and milli-degrees along both X and
-    /// A at the same time.
-    fn move_abs_millis(&mut self, x_microns: i32, a_millidegrees: i32) {
-        let mut x_target = self.x_microns_to_steps(x_microns);
-        let a_target = self.a_millidegrees_to_steps(a_millidegrees);
+    /// Return an absolute X position in machine coordinates, in microns,
+    /// from one in the given coordinate system.
+    fn x_machine_microns(
+        x_microns: i32,
+        coordinates: Coordinates,
+        settings: &Settings,
+    ) -> Result<i32, MoveError> {
+        let x_offset = match coordinates {
+            Coordinates::Machine => 0,
+            Coordinates::Bobbin => settings.bobbin_flange_x_microns,
+        };
+        let x_target = x_offset as i64 + x_microns as i64;
+        i32::try_from(x_target)
+            .map_err(|_| overflow(Axis::X, OverflowStage::Offset, x_target))
+    }
+
+    /// Return an absolute A position in machine coordinates, in
+    /// millidegrees, from one in the given coordinate system.
+    fn a_machine_millidegrees(
+        a_millidegrees: i32,
+        coordinates: Coordinates,
+        settings: &Settings,
+    ) -> Result<i32, MoveError> {
+        let a_offset = match coordinates {
+            Coordinates::Machine => 0,
+            Coordinates::Bobbin => settings.bobbin_origin_a_millidegrees,
+        };
+        let a_target = a_offset as i64 + a_millidegrees as i64;
+        i32::try_from(a_target)
+            .map_err(|_| overflow(Axis::A, OverflowStage::Offset, a_target))
+    }
+
+    /// Return the steps from the end of the queued moves to an absolute
+    /// position in microns and milli-degrees, along X and A.
+    fn abs_steps(
+        &self,
+        x_microns: i32,
+        a_millidegrees: i32,
+    ) -> Result<(i32, i32), MoveError> {
+        let x_target = Self::X_CONVERTER
+            .checked_to_steps(x_microns)
+            .ok_or(overflow(Axis::X, OverflowStage::Conversion, x_microns))?;
+        let a_target =
+            Self::A_CONVERTER.checked_to_steps(a_millidegrees).ok_or(
+                overflow(Axis::A, OverflowStage::Conversion, a_millidegrees),
+            )?;
+
+        let (x_pos, a_pos) = self.planned_position();
+        let dx = x_target.saturating_sub(x_pos);
+        let da = a_target as i64 - a_pos;
+        let da = i32::try_from(da)
+            .map_err(|_| overflow(Axis::A, OverflowStage::Delta, da))?;
+        Ok((dx, da))
+    }
+
+    /// Perform an arc move (`G2`/`G3`), in the plane of X and A, as a
+    /// series of short straight moves.
+    ///
+    /// The plane is in microns of X and millidegrees of A, so that a
+    /// millimetre of X is as long as a degree of A. The end is taken as for
+    /// [`Self::move_millis`], and the centre is given relative to the
+    /// start. Each segment ramps up and down like any other move.
+    ///
+    /// An arc that would take X beyond the soft limits is clipped to them,
+    /// or rejected without moving, depending on the settings.
+    #[allow(clippy::too_many_arguments)]
+    pub fn arc_millis<O: MoveObserver>(
+        &mut self,
+        x_microns: i32,
+        a_millidegrees: i32,
+        center: ArcCenter,
+        dir: ArcDir,
+        coordinates: Coordinates,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> Result<MoveReport, MoveError> {
+        // The end, relative to the start, in microns and milli-degrees, and
+        // in steps.
+        let (end, end_steps) = match self.move_mode {
+            MoveMode::Relative => {
+                let dx = self.x_carry.checked_to_steps(x_microns).ok_or(
+                    overflow(Axis::X, OverflowStage::Conversion, x_microns),
+                )?;
+                let da = self.a_carry.checked_to_steps(a_millidegrees).ok_or(
+                    overflow(
+                        Axis::A,
+                        OverflowStage::Conversion,
+                        a_millidegrees,
+                    ),
+                )?;
+                ([x_microns, a_millidegrees], [dx, da])
+            }
+            MoveMode::Absolute => {
+                let x_target =
+                    Self::x_machine_microns(x_microns, coordinates, settings)?;
+                let a_target = Self::a_machine_millidegrees(
+                    a_millidegrees,
+                    coordinates,
+                    settings,
+                )?;
+                let (dx, da) = self.abs_steps(x_target, a_target)?;
+                self.x_carry.reset();
+                self.a_carry.reset();
+                let x_pos = Self::X_CONVERTER.to_units(self.x_pos as i32);
+                let a = self.position_millis(Coordinates::Machine, settings).1;
+                let a = a_target as i64 - (a.turns * 360_000 + a.angle as i64);
+                let a = i32::try_from(a)
+                    .map_err(|_| overflow(Axis::A, OverflowStage::Delta, a))?;
+                ([x_target.saturating_sub(x_pos), a], [dx, da])
+            }
+        };
+        let arc = Arc::new([0, 0], end, center, dir, Self::ARC_SEGMENT_MILLIS)
+            .map_err(MoveError::InvalidArc)?;
 
-        if x_target < 0 {
-            x_target = 0;
+        // Steps from the start to the end of each segment.
+        let segments = arc.segments();
+        let ends = arc.enumerate().map(|(i, end)| {
+            if i as u32 + 1 == segments {
+                return Ok(end_steps);
+            }
+            let [x, a] = end.map(|c| i32::try_from(c).ok());
+            let x = x.and_then(|x| Self::X_CONVERTER.checked_to_steps(x));
+            let a = a.and_then(|a| Self::A_CONVERTER.checked_to_steps(a));
+            match (x, a) {
+                (Some(x), Some(a)) => Ok([x, a]),
+                (None, _) => {
+                    Err(overflow(Axis::X, OverflowStage::Conversion, end[0]))
+                }
+                (_, None) => {
+                    Err(overflow(Axis::A, OverflowStage::Conversion, end[1]))
+                }
+            }
+        });
+        let start = (self.x_pos as i32, self.a_pos);
+        if !settings.x_clip_moves {
+            for end in ends.clone() {
+                let x_target = start.0.saturating_add(end?[0]);
+                if self.x_range.clamp(x_target) != x_target {
+                    return Err(MoveError::OutOfRange);
+                }
+            }
         }
-        if x_target > self.x_limit as i32 {
-            x_target = self.x_limit as i32;
+
+        // Each segment moves from where the last one actually ended, so
+        // that the arc picks up again after it is clipped.
+        let mut report = MoveReport::default();
+        for end in ends {
+            let [x, a] = end?;
+            let dx =
+                start.0.saturating_add(x).saturating_sub(self.x_pos as i32);
+            let da = start.1 + a as i64 - self.a_pos;
+            let da = i32::try_from(da)
+                .map_err(|_| overflow(Axis::A, OverflowStage::Delta, da))?;
+            report
+                .add(self.move_rel_steps_clipped(dx, da, settings, observer)?);
+            if stop_requested(observer) {
+                break;
+            }
         }
+        Ok(report)
+    }
 
-        let dx = x_target - self.x_pos as i32;
-        let da = a_target - self.a_pos as i32;
+    /// Move X alone to a position in machine coordinates, whatever the
+    /// move mode, eg. to park it at the end of a job. X stays within the
+    /// soft limits, as for any other move.
+    ///
+    /// # Parameters
+    ///
+    /// - `x_microns`: X position, in microns from the left limit.
+    pub fn park_x<O: MoveObserver>(
+        &mut self,
+        x_microns: u32,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> Result<MoveReport, MoveError> {
+        let x_target = i32::try_from(x_microns)
+            .ok()
+            .and_then(|x_microns| Self::X_CONVERTER.checked_to_steps(x_microns))
+            .ok_or(overflow(Axis::X, OverflowStage::Conversion, x_microns))?;
+        let dx = x_target.saturating_sub(self.planned_position().0);
+        self.x_carry.reset();
+        self.move_rel_steps_clipped(dx, 0, settings, observer)
+    }
 
-        self.move_rel_steps(dx, da);
+    /// Move a relative number of steps along both X and A, keeping X within
+    /// the soft limits.
+    ///
+    /// If the move would take X beyond the soft limits, X either moves as
+    /// far as the limit while A completes its move, or the move is rejected
+    /// without moving, depending on the settings. Either way, the fraction
+    /// of a step carried over from earlier relative X moves is dropped.
+    fn move_rel_steps_clipped<O: MoveObserver>(
+        &mut self,
+        dx: i32,
+        da: i32,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> Result<MoveReport, MoveError> {
+        let x_clipped_steps = self.x_clipped_steps(dx, settings)?;
+        let report = self.move_rel_steps(
+            dx - x_clipped_steps,
+            da,
+            (settings.x_max_step_rate, settings.a_max_step_rate),
+            (0, 0),
+            settings,
+            observer,
+        );
+        Ok(MoveReport {
+            x_clipped_steps,
+            ..report
+        })
     }
 
-    /// Move a relative number of microns and milli-degrees along both X and
-    /// A at the same time.
-    fn move_rel_millis(&mut self, dx_microns: i32, da_millidegrees: i32) {
-        let dx_steps = self.x_microns_to_steps(dx_microns);
-        let da_steps = self.a_millidegrees_to_steps(da_millidegrees);
-        self.move_rel_steps(dx_steps, da_steps);
+    /// Return the X steps that the soft limits cut off a relative move from
+    /// the end of the queued moves, or an error if the settings reject
+    /// such a move rather than clip it.
+    fn x_clipped_steps(
+        &mut self,
+        dx: i32,
+        settings: &Settings,
+    ) -> Result<i32, MoveError> {
+        let x_target = self.planned_position().0.saturating_add(dx);
+        let x_clipped_steps = x_target - self.x_range.clamp(x_target);
+        if x_clipped_steps != 0 {
+            self.x_carry.reset();
+            if !settings.x_clip_moves {
+                return Err(MoveError::OutOfRange);
+            }
+        }
+        Ok(x_clipped_steps)
     }
 
     /// Move a relative number of steps along both X and A at the same time.
-    fn move_rel_steps(&mut self, dx: i32, da: i32) {
-        if dx == 0 {
-            self.move_rel_a_only(da);
-        } else {
+    ///
+    /// If X reverses direction relative to its previous move, the machine
+    /// first dwells for the configured reversal time, so that the carriage
+    /// settles before it is driven the other way.
+    ///
+    /// Steps are taken at the machine's step rate, unless that is faster
+    /// than `max_step_rates` for X and A. The axis with more steps ramps up
+    /// to that rate and back down again at its acceleration and with its
+    /// ramp shape in the settings, and the other axis follows it. Ramps
+    /// with a trapezoid shape start and end at the rates in `ends`, in
+    /// steps per second of that axis, rather than at rest. The
+    /// delays after steps in each direction are then trimmed as in the
+    /// settings, and the planned time includes the trims.
+    ///
+    /// Steps are timed against the clock by a [`MoveExecutor`], so the
+    /// time that the observer takes after a step does not slow the move
+    /// down, and the observer gets the rest of the time until the next
+    /// step is due. The observer can also hold the move, which then slows
+    /// down to rest at the same acceleration, and speeds up again from rest
+    /// to run on to its end when the hold is released.
+    fn move_rel_steps<O: MoveObserver>(
+        &mut self,
+        dx: i32,
+        da: i32,
+        (x_max_step_rate, a_max_step_rate): (u16, u16),
+        ends: (u32, u32),
+        settings: &Settings,
+        observer: &mut O,
+    ) -> MoveReport {
+        #[cfg(feature = "a-enable")]
+        self.gitm
+            .set_a_enabled(da != 0 || self.a_idle_mode == AIdleMode::Hold);
+        let x_delay_us = self.step_delay_us(x_max_step_rate);
+        let a_delay_us = self.step_delay_us(a_max_step_rate);
+        let mut report = MoveReport {
+            rate_limited: (dx != 0 && x_delay_us > self.move_delay_us)
+                || (da != 0 && a_delay_us > self.move_delay_us),
+            ..MoveReport::default()
+        };
+
+        if dx != 0 {
             let x_dir = if dx >= 0 { XDir::Right } else { XDir::Left };
-            let a_dir = if da >= 0 { ADir::Pos } else { ADir::Neg };
-
-            // For Bresenham:
-            // - x is x
-            // - a is y
-            let mut d = 2 * da - dx;
-            for _ in 0..dx.abs() {
-                self.step_x(x_dir);
-                delay_us(self.move_delay_us);
-                if d > 0 {
-                    self.step_a(a_dir);
-                    delay_us(self.move_delay_us);
-                    d -= 2 * dx;
+            if self.last_x_dir.is_some_and(|last| last != x_dir) {
+                delay_ms(settings.x_reversal_dwell_ms as u32);
+                report.planned_us += settings.x_reversal_dwell_ms as u64 * 1000;
+            }
+            self.last_x_dir = Some(x_dir);
+        }
+
+        let x_major = dx.unsigned_abs() >= da.unsigned_abs();
+        let accel = match x_major {
+            true => settings.x_accel,
+            false => settings.a_accel,
+        };
+        let profile = if x_major {
+            StepProfile::new(
+                settings.x_profile,
+                dx.unsigned_abs(),
+                x_delay_us,
+                settings.x_accel,
+                ends,
+            )
+        } else {
+            StepProfile::new(
+                settings.a_profile,
+                da.unsigned_abs(),
+                a_delay_us,
+                settings.a_accel,
+                ends,
+            )
+        };
+
+        let mut executor =
+            MoveExecutor::new(dx, da, (x_delay_us, a_delay_us), profile)
+                .with_trims((settings.x_step_trim(), settings.a_step_trim()))
+                .with_ramp(accel, ends.1);
+        report.held_ms = self.run_steps(&mut executor, observer);
+        report.planned_us += executor.planned_us();
+        report
+    }
+
+    /// Take the steps of a move, polling the clock for when each one is
+    /// due, and return the time that the move was held, in milliseconds.
+    #[cfg(not(feature = "timer-steps"))]
+    fn run_steps<P, O>(
+        &mut self,
+        executor: &mut MoveExecutor<P>,
+        observer: &mut O,
+    ) -> u32
+    where
+        P: Iterator<Item = u32>,
+        O: MoveObserver,
+    {
+        let mut held = HeldTime::default();
+        loop {
+            follow_requests(executor, observer);
+            let step = match executor.poll(clock::micros()) {
+                Poll::Step(step) => step,
+                Poll::Wait => {
+                    observer.while_waiting();
+                    if observer.abort_requested() {
+                        break;
+                    }
+                    continue;
+                }
+                Poll::Held => {
+                    held.start();
+                    observer.while_waiting();
+                    if observer.stop_requested() {
+                        break;
+                    }
+                    continue;
+                }
+                Poll::Done => break,
+            };
+            held.end();
+            #[cfg(feature = "instrument")]
+            let probe = Probe::start(Section::Step);
+            match step {
+                Step::X(dir) => {
+                    self.step_x(x_dir(dir));
                 }
-                d += 2 * da;
+                Step::A(dir) => self.step_a(a_dir(dir)),
+            }
+            observer.after_step(self.x_pos, self.a_pos);
+            #[cfg(feature = "instrument")]
+            drop(probe);
+            if observer.stop_requested() {
+                break;
             }
         }
+        held.end()
+    }
+
+    /// Take the steps of a move, with the step timer giving each pulse when
+    /// it is due.
+    ///
+    /// Each step is set up (its direction, and the limits) and handed to
+    /// the step timer, and the observer is then called until the timer has
+    /// given the pulse. The pulse is timed from the one before it by the
+    /// timer, so the work done between steps does not change the step
+    /// rate, unless it runs past the time of the next step.
+    ///
+    /// Returns the time that the move was held, in milliseconds.
+    #[cfg(feature = "timer-steps")]
+    fn run_steps<P, O>(
+        &mut self,
+        executor: &mut MoveExecutor<P>,
+        observer: &mut O,
+    ) -> u32
+    where
+        P: Iterator<Item = u32>,
+        O: MoveObserver,
+    {
+        let mut held = HeldTime::default();
+        // Delay after the last step, or `None` before the first (or after a
+        // hold, which starts again from rest).
+        let mut delay_us = None;
+        loop {
+            follow_requests(executor, observer);
+            if executor.hold_state() == Hold::Held {
+                held.start();
+                delay_us = None;
+                observer.while_waiting();
+                if observer.stop_requested() {
+                    return held.end();
+                }
+                continue;
+            }
+            let Some((step, next_delay_us)) = executor.next_step() else {
+                break;
+            };
+            held.end();
+            // The pulse to give, and the position after it. A step that
+            // would leave the soft limits is skipped, but still waited for.
+            let (pulse, position) = match step {
+                Step::X(dir) => match self.x_step_target(x_dir(dir)) {
+                    Some((pin, x_pos)) => (
+                        self.gitm.prepare_step_x(pin).then_some(Pulse::X),
+                        Some((x_pos, self.a_pos)),
+                    ),
+                    None => (None, None),
+                },
+                Step::A(dir) => {
+                    let (pin, a_pos) = self.a_step_target(a_dir(dir));
+                    self.gitm.prepare_step_a(pin);
+                    (Some(Pulse::A), Some((self.x_pos, a_pos)))
+                }
+            };
+            step_timer::schedule(pulse, delay_us);
+            while !step_timer::fired() {
+                observer.while_waiting();
+                if observer.abort_requested() {
+                    // The pulse may have been given since the last check.
+                    if step_timer::cancel() {
+                        return held.end();
+                    }
+                }
+            }
+            #[cfg(feature = "instrument")]
+            let probe = Probe::start(Section::Step);
+            if let Some((x_pos, a_pos)) = position {
+                self.x_pos = x_pos;
+                self.a_pos = a_pos;
+            }
+            if pulse == Some(Pulse::X) {
+                self.gitm.after_step_x();
+            }
+            observer.after_step(self.x_pos, self.a_pos);
+            #[cfg(feature = "instrument")]
+            drop(probe);
+            if observer.stop_requested() {
+                return held.end();
+            }
+            delay_us = Some(next_delay_us);
+        }
+        // Wait out the delay after the last step, as a polled move does.
+        if delay_us.is_some() {
+            step_timer::schedule(None, delay_us);
+            while !step_timer::fired() {
+                observer.while_waiting();
+                if observer.abort_requested() {
+                    step_timer::cancel();
+                }
+            }
+        }
+        held.end()
+    }
+
+    /// Turn A by the shortest way to one of the evenly spaced index angles
+    /// in the settings, where index 0 is A zero, eg. to give access to the
+    /// chuck when loading a bobbin.
+    ///
+    /// A turns at the indexing step rate, or its maximum step rate if that
+    /// is slower.
+    pub fn index_a<O: MoveObserver>(
+        &mut self,
+        index: u16,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> MoveReport {
+        let positions = settings.a_index_positions;
+        let target = index_steps(index, positions, Self::A_STEPS_PER_REV);
+        let da = shortest_delta(self.a_pos, target, Self::A_STEPS_PER_REV);
+        self.a_carry.reset();
+        let step_rate =
+            settings.a_index_step_rate.min(settings.a_max_step_rate);
+        let step_rates = (settings.x_max_step_rate, step_rate);
+        self.move_rel_steps(0, da, step_rates, (0, 0), settings, observer)
     }
 
-    /// Move a relative number of steps along A only.
-    fn move_rel_a_only(&mut self, da: i32) {
-        let a_dir = if da >= 0 { ADir::Pos } else { ADir::Neg };
+    /// Move one axis by a distance, at no more than `step_rate`, eg. for a
+    /// leg of a burn-in cycle.
+    ///
+    /// X must stay within the soft limits: a move beyond them is rejected
+    /// without moving, whatever the settings.
+    ///
+    /// # Parameters
+    ///
+    /// - `axis`: Axis to move.
+    /// - `distance`: Distance, in microns for X or millidegrees for A.
+    /// - `step_rate`: Maximum step rate, in steps per second. The maximum
+    ///   step rate of the axis in the settings also applies.
+    pub fn move_axis<O: MoveObserver>(
+        &mut self,
+        axis: Axis,
+        distance: i32,
+        step_rate: u16,
+        settings: &Settings,
+        observer: &mut O,
+    ) -> Result<MoveReport, MoveError> {
+        let converter = match axis {
+            Axis::X => Self::X_CONVERTER,
+            Axis::A => Self::A_CONVERTER,
+        };
+        let steps = converter.checked_to_steps(distance).ok_or(overflow(
+            axis,
+            OverflowStage::Conversion,
+            distance,
+        ))?;
+        self.x_carry.reset();
+        self.a_carry.reset();
+        let (dx, da, step_rates) = match axis {
+            Axis::X => {
+                let x_target = (self.x_pos as i32).saturating_add(steps);
+                if !self.x_range.contains(x_target) {
+                    return Err(MoveError::OutOfRange);
+                }
+                let rate = step_rate.min(settings.x_max_step_rate);
+                (steps, 0, (rate, settings.a_max_step_rate))
+            }
+            Axis::A => {
+                let rate = step_rate.min(settings.a_max_step_rate);
+                (0, steps, (settings.x_max_step_rate, rate))
+            }
+        };
+        Ok(self.move_rel_steps(dx, da, step_rates, (0, 0), settings, observer))
+    }
+
+    /// Return `true` if either X limit switch is pressed.
+    ///
+    /// Moves stay a safety margin inside the switches, so a pressed switch
+    /// between moves means that X has lost steps.
+    pub fn limit_switch_down(&self) -> bool {
+        self.gitm.left_limit_switch_is_down()
+            || self.gitm.right_limit_switch_is_down()
+    }
+
+    /// Return the delay after each step of an axis, so that its steps are
+    /// no faster than its maximum step rate.
+    ///
+    /// # Parameters
+    ///
+    /// - `max_step_rate`: Maximum step rate of the axis, in steps per second.
+    fn step_delay_us(&self, max_step_rate: u16) -> u32 {
+        let min_delay_us = 1_000_000 / max_step_rate.max(1) as u32;
+        self.move_delay_us.max(min_delay_us)
+    }
+
+    /// Home the A axis against the spindle index sensor.
+    ///
+    /// The spindle first backs off the index, if it is already there, then
+    /// rotates forward at the normal step rate until the index is seen.
+    /// It backs off again, and approaches the index a second time at the
+    /// slow homing rate. The angle where the index is seen on the slow pass
+    /// becomes A zero.
+    ///
+    /// # Parameters
+    ///
+    /// - `index_active`: Returns `true` while the index is at the sensor.
+    /// - `settings`: Settings, for the slow pass step delay.
+    #[cfg(feature = "a-index")]
+    pub fn home_a(
+        &mut self,
+        index_active: impl Fn() -> bool,
+        settings: &Settings,
+    ) -> Result<(), HomingError> {
+        let fast_us = self.move_delay_us;
+        let slow_us = settings.a_home_slow_delay_us as u32;
+        #[cfg(feature = "a-enable")]
+        self.gitm.set_a_enabled(true);
+        self.seek_index(ADir::Neg, false, fast_us, &index_active)?;
+        self.seek_index(ADir::Pos, true, fast_us, &index_active)?;
+        self.seek_index(ADir::Neg, false, fast_us, &index_active)?;
+        self.seek_index(ADir::Pos, true, slow_us, &index_active)?;
+        self.a_pos = 0;
+        Ok(())
+    }
 
-        for _ in 0..da.abs() {
+    /// Step A until the index sensor reaches the given state.
+    ///
+    /// Fails if the state is not reached within a little over one
+    /// revolution.
+    #[cfg(feature = "a-index")]
+    fn seek_index(
+        &mut self,
+        a_dir: ADir,
+        active: bool,
+        step_delay_us: u32,
+        index_active: &impl Fn() -> bool,
+    ) -> Result<(), HomingError> {
+        let max_steps = Self::A_STEPS_PER_REV * 5 / 4;
+        let mut steps = 0;
+        while index_active() != active {
+            if steps == max_steps {
+                return Err(HomingError::IndexNotFound);
+            }
             self.step_a(a_dir);
-            delay_us(self.move_delay_us);
+            delay_us(step_delay_us);
+            steps += 1;
         }
+        Ok(())
     }
 
     /// Take a step along the A axis.
@@ -131,15 +1103,17 @@ impl Machine {
     ///
     /// - `a_dir`: Direction in which to take a step.
     fn step_a(&mut self, a_dir: ADir) {
+        let (pin, a_pos) = self.a_step_target(a_dir);
+        self.gitm.step_a(pin);
+        self.a_pos = a_pos;
+    }
+
+    /// Return the direction pin state and the position after a step along
+    /// the A axis.
+    fn a_step_target(&self, a_dir: ADir) -> (PinState, i64) {
         match a_dir {
-            ADir::Pos => {
-                self.gitm.step_a(PinState::High);
-                self.a_pos += 1;
-            }
-            ADir::Neg => {
-                self.gitm.step_a(PinState::Low);
-                self.a_pos -= 1;
-            }
+            ADir::Pos => (PinState::High, self.a_pos + 1),
+            ADir::Neg => (PinState::Low, self.a_pos - 1),
         }
     }
 
@@ -153,49 +1127,414 @@ impl Machine {
     ///
     /// # Returns
     /// `true` if the step could be taken; `false` otherwise.
+    #[cfg(not(feature = "timer-steps"))]
     fn step_x(&mut self, x_dir: XDir) -> bool {
-        match x_dir {
-            XDir::Left => {
-                if self.x_pos > 0 {
-                    self.gitm.step_x(PinState::High);
-                    self.x_pos -= 1;
-                    true
-                } else {
-                    false
-                }
+        match self.x_step_target(x_dir) {
+            Some((pin, x_pos)) => {
+                self.gitm.step_x(pin);
+                self.x_pos = x_pos;
+                true
             }
-            XDir::Right => {
-                if self.x_pos < self.x_limit - 1 {
-                    self.gitm.step_x(PinState::Low);
-                    self.x_pos += 1;
-                    true
-                } else {
-                    false
-                }
+            None => false,
+        }
+    }
+
+    /// Return the direction pin state and the position after a step along
+    /// the X axis, or `None` if the step would leave the soft limits.
+    fn x_step_target(&self, x_dir: XDir) -> Option<(PinState, u32)> {
+        let (pin, x_pos) = match x_dir {
+            XDir::Left => (PinState::High, self.x_pos as i32 - 1),
+            XDir::Right => (PinState::Low, self.x_pos as i32 + 1),
+        };
+        self.x_range.contains(x_pos).then_some((pin, x_pos as u32))
+    }
+}
+
+/// Return the X direction of a step from the interpolator.
+fn x_dir(dir: Dir) -> XDir {
+    match dir {
+        Dir::Pos => XDir::Right,
+        Dir::Neg => XDir::Left,
+    }
+}
+
+/// Return the A direction of a step from the interpolator.
+fn a_dir(dir: Dir) -> ADir {
+    match dir {
+        Dir::Pos => ADir::Pos,
+        Dir::Neg => ADir::Neg,
+    }
+}
+
+/// Delays after the steps of the axis with more steps in a move, with the
+/// ramp shape in the settings.
+enum StepProfile {
+    Trapezoid(Trapezoid),
+    SCurve(SCurve),
+}
+impl StepProfile {
+    /// Creates a profile. Only a trapezoid starts and ends at the rates in
+    /// `ends`; an S-curve always starts and ends at rest.
+    fn new(
+        profile: Profile,
+        steps: u32,
+        delay_us: u32,
+        accel: u32,
+        ends: (u32, u32),
+    ) -> Self {
+        match profile {
+            Profile::Trapezoid => StepProfile::Trapezoid(Trapezoid::between(
+                steps, delay_us, accel, ends,
+            )),
+            Profile::SCurve => {
+                StepProfile::SCurve(SCurve::new(steps, delay_us, accel))
+            }
+        }
+    }
+}
+impl Iterator for StepProfile {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        match self {
+            StepProfile::Trapezoid(trapezoid) => trapezoid.next(),
+            StepProfile::SCurve(scurve) => scurve.next(),
+        }
+    }
+}
+
+/// Stop an axis, or hold or resume a move, as the observer asks.
+fn follow_requests<P, O>(executor: &mut MoveExecutor<P>, observer: &O)
+where
+    P: Iterator<Item = u32>,
+    O: MoveObserver,
+{
+    if observer.axis_stop_requested(Axis::X) {
+        executor.stop_x();
+    }
+    if observer.axis_stop_requested(Axis::A) {
+        executor.stop_a();
+    }
+    match (observer.hold_requested(), executor.hold_state()) {
+        (true, Hold::Running) => executor.hold(),
+        (false, Hold::Stopping | Hold::Held) => executor.resume(),
+        _ => {}
+    }
+}
+
+/// Return `true` if the observer asks to stop the machine, or one of its
+/// axes.
+fn stop_requested<O: MoveObserver>(observer: &O) -> bool {
+    observer.stop_requested()
+        || observer.axis_stop_requested(Axis::X)
+        || observer.axis_stop_requested(Axis::A)
+}
+
+/// Time that a move has spent held.
+#[derive(Default)]
+struct HeldTime {
+    /// Time the current hold started, in milliseconds, if the move is held.
+    since_ms: Option<u32>,
+    /// Time of the holds that have ended, in milliseconds.
+    total_ms: u32,
+}
+impl HeldTime {
+    /// Starts a hold, unless one has already started.
+    fn start(&mut self) {
+        self.since_ms.get_or_insert_with(clock::millis);
+    }
+
+    /// Ends the current hold, if any, and returns the total time held.
+    fn end(&mut self) -> u32 {
+        if let Some(since_ms) = self.since_ms.take() {
+            let held_ms = clock::millis().wrapping_sub(since_ms);
+            self.total_ms = self.total_ms.saturating_add(held_ms);
+        }
+        self.total_ms
+    }
+}
+
+/// Observes the machine while a move is in progress.
+///
+/// The machine itself knows nothing about the UART. Anything that has to
+/// happen *during* a move (eg. reporting) is done by an observer, which is
+/// notified after every step.
+pub trait MoveObserver {
+    /// Called after each step of a move, before the post-step delay.
+    ///
+    /// # Parameters
+    ///
+    /// - `x_pos`: X position after the step, in steps.
+    /// - `a_pos`: A position after the step, in steps.
+    fn after_step(&mut self, x_pos: u32, a_pos: i64);
+
+    /// Called repeatedly while a move waits for its next step to be due,
+    /// eg. to service the UART. Each call must be short compared with the
+    /// time between steps.
+    fn while_waiting(&mut self) {}
+
+    /// Return `true` to stop the move after the current step.
+    fn stop_requested(&self) -> bool {
+        false
+    }
+
+    /// Return `true` to hold the move: it slows down to rest, and waits
+    /// until this returns `false` again.
+    fn hold_requested(&self) -> bool {
+        false
+    }
+
+    /// Return `true` to stop one axis at once, and leave the other where
+    /// it is, or let it run on if it leads the move. The rest of the queued
+    /// moves are dropped.
+    fn axis_stop_requested(&self, _axis: Axis) -> bool {
+        false
+    }
+
+    /// Return `true` to stop the move at once, without waiting for the next
+    /// step: a step that is due but not yet given is dropped. An observer
+    /// that returns `true` here should also request a stop.
+    fn abort_requested(&self) -> bool {
+        false
+    }
+}
+
+/// The unit observer ignores all motion.
+impl MoveObserver for () {
+    fn after_step(&mut self, _x_pos: u32, _a_pos: i64) {}
+}
+
+/// A tuple of observers notifies each of them, in order.
+macro_rules! impl_move_observer_for_tuple {
+    ($($o:ident: $i:tt),*) => {
+        impl<$($o: MoveObserver),*> MoveObserver for ($($o,)*) {
+            fn after_step(&mut self, x_pos: u32, a_pos: i64) {
+                $(self.$i.after_step(x_pos, a_pos);)*
+            }
+
+            fn while_waiting(&mut self) {
+                $(self.$i.while_waiting();)*
+            }
+
+            fn stop_requested(&self) -> bool {
+                $(self.$i.stop_requested())||*
+            }
+
+            fn hold_requested(&self) -> bool {
+                $(self.$i.hold_requested())||*
+            }
+
+            fn axis_stop_requested(&self, axis: Axis) -> bool {
+                $(self.$i.axis_stop_requested(axis))||*
+            }
+
+            fn abort_requested(&self) -> bool {
+                $(self.$i.abort_requested())||*
             }
         }
+    };
+}
+impl_move_observer_for_tuple!(A: 0, B: 1);
+impl_move_observer_for_tuple!(A: 0, B: 1, C: 2);
+impl_move_observer_for_tuple!(A: 0, B: 1, C: 2, D: 3);
+impl_move_observer_for_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_move_observer_for_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+impl_move_observer_for_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+impl_move_observer_for_tuple!(
+    A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7
+);
+impl_move_observer_for_tuple!(
+    A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8
+);
+impl_move_observer_for_tuple!(
+    A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9
+);
+impl_move_observer_for_tuple!(
+    A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10
+);
+
+/// An observer can be lent to a move, so that it keeps its state between
+/// moves.
+impl<O: MoveObserver> MoveObserver for &mut O {
+    fn after_step(&mut self, x_pos: u32, a_pos: i64) {
+        (**self).after_step(x_pos, a_pos);
+    }
+
+    fn while_waiting(&mut self) {
+        (**self).while_waiting();
+    }
+
+    fn stop_requested(&self) -> bool {
+        (**self).stop_requested()
+    }
+
+    fn hold_requested(&self) -> bool {
+        (**self).hold_requested()
     }
 
-    fn x_microns_to_steps(&self, x_microns: i32) -> i32 {
-        let dx = x_microns.abs() as u32;
-        let dsteps = dx * Self::X_STEPS_PER_REV / Self::X_MM_PER_REV / 1000;
-        (dsteps as i32) * x_microns.signum()
+    fn axis_stop_requested(&self, axis: Axis) -> bool {
+        (**self).axis_stop_requested(axis)
     }
 
-    fn a_millidegrees_to_steps(&self, a_millidegrees: i32) -> i32 {
-        let da = a_millidegrees.abs() as u32;
-        let dsteps = da * Self::A_STEPS_PER_REV / 360 / 1000;
-        (dsteps as i32) * a_millidegrees.signum()
+    fn abort_requested(&self) -> bool {
+        (**self).abort_requested()
     }
 }
 
+/// Summary of a completed move.
+#[derive(Default)]
+pub struct MoveReport {
+    /// Whether the move was slowed down to an axis' maximum step rate.
+    pub rate_limited: bool,
+    /// Time the move should have taken, in microseconds. A move that takes
+    /// much longer than this was slowed down by work done between steps.
+    pub planned_us: u64,
+    /// Number of X steps that were cut off the move at the soft limits:
+    /// positive beyond the right limit, and negative beyond the left.
+    pub x_clipped_steps: i32,
+    /// Time the move was held, in milliseconds.
+    pub held_ms: u32,
+}
+impl MoveReport {
+    /// Adds the report of a later part of the same move.
+    fn add(&mut self, other: MoveReport) {
+        self.rate_limited |= other.rate_limited;
+        self.planned_us += other.planned_us;
+        self.x_clipped_steps += other.x_clipped_steps;
+        self.held_ms = self.held_ms.saturating_add(other.held_ms);
+    }
+}
+
+/// Reasons that a move can be refused.
+pub enum MoveError {
+    /// The X target is beyond the soft limits.
+    OutOfRange,
+    /// A value overflowed while the move was worked out.
+    Overflow(Overflow),
+    /// An arc's centre or radius does not fit its start and end.
+    InvalidArc(ArcError),
+}
+
+/// Return a [`MoveError::Overflow`].
+fn overflow(
+    axis: Axis,
+    stage: OverflowStage,
+    value: impl Into<i64>,
+) -> MoveError {
+    MoveError::Overflow(Overflow {
+        axis,
+        stage,
+        value: value.into(),
+    })
+}
+
+/// An axis of the machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    X,
+    A,
+}
+impl Axis {
+    /// Letter of the axis in G-code words.
+    fn letter(&self) -> char {
+        match self {
+            Axis::X => 'X',
+            Axis::A => 'A',
+        }
+    }
+
+    /// Unit of the axis in G-code words.
+    fn unit(&self) -> &'static str {
+        match self {
+            Axis::X => "mm",
+            Axis::A => "deg",
+        }
+    }
+}
+
+/// A value that does not fit in the arithmetic of a command, and where it
+/// overflowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Overflow {
+    pub axis: Axis,
+    pub stage: OverflowStage,
+    /// The value that overflowed: in thousandths of the axis unit, or in
+    /// steps for [`OverflowStage::Delta`].
+    pub value: i64,
+}
+impl Display for Overflow {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (axis, unit) = (self.axis.letter(), self.axis.unit());
+        let value = Thousandths(self.value);
+        match self.stage {
+            OverflowStage::Word => {
+                write!(f, "{}{} {} is out of range.", axis, value, unit)
+            }
+            OverflowStage::Offset => write!(
+                f,
+                "{} target {} {} with the offset is out of range.",
+                axis, value, unit
+            ),
+            OverflowStage::Conversion => write!(
+                f,
+                "{} {} {} is too large to convert to steps.",
+                axis, value, unit
+            ),
+            OverflowStage::Delta => write!(
+                f,
+                "{} move of {} steps from the current position is out of \
+                 range.",
+                axis, self.value
+            ),
+        }
+    }
+}
+
+/// Where a value overflowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowStage {
+    /// Parsing the value of a G-code word.
+    Word,
+    /// Adding the origin of bobbin coordinates to an absolute target.
+    Offset,
+    /// Converting a target or a relative move to steps.
+    Conversion,
+    /// Working out the steps from the current position to a target.
+    Delta,
+}
+
+/// Coordinate system for absolute positions.
+#[derive(Copy, Clone)]
+pub enum Coordinates {
+    /// Relative to the left end of the soft limits, and to where A was
+    /// zeroed.
+    Machine,
+    /// Relative to the left flange of the bobbin (`$2`), and to the A
+    /// origin angle (`$43`).
+    Bobbin,
+}
+
+/// Errors that might occur when zeroing.
+pub enum ZeroingError {
+    /// A limit switch engaged while X moved away from it, so the switches
+    /// are wired to each other's inputs, or X's motor turns the wrong way.
+    SwitchesSwapped,
+}
+
+/// Errors that might occur when homing.
+#[cfg(feature = "a-index")]
+pub enum HomingError {
+    /// The index sensor did not change state within a revolution.
+    IndexNotFound,
+}
+
 #[derive(Copy, Clone)]
 pub enum ADir {
     Pos,
     Neg,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum XDir {
     Left,
     Right,
@@ -206,3 +1545,14 @@ pub enum MoveMode {
     Absolute,
     Relative,
 }
+
+/// What the A driver does during moves that only turn X.
+#[cfg(feature = "a-enable")]
+#[derive(Copy, Clone, PartialEq)]
+pub enum AIdleMode {
+    /// A stays energized and holds its position against the load.
+    Hold,
+    /// A is released, and the spindle turns freely. A is energized again
+    /// for the next move that turns it.
+    Free,
+}
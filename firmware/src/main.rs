@@ -1,11 +1,75 @@
 #![no_std]
 #![no_main]
+#![feature(abi_avr_interrupt)]
 
+#[cfg(all(feature = "shared-limits", feature = "analog-limits"))]
+compile_error!("`shared-limits` and `analog-limits` cannot both be enabled");
+#[cfg(all(feature = "instrument", feature = "analog-limits"))]
+compile_error!("`instrument` and `analog-limits` both use A3");
+#[cfg(all(
+    feature = "capture",
+    not(any(feature = "shared-limits", feature = "analog-limits"))
+))]
+compile_error!(
+    "`capture` needs `shared-limits` or `analog-limits` to free D12"
+);
+#[cfg(all(
+    feature = "sync-out",
+    not(any(feature = "shared-limits", feature = "analog-limits"))
+))]
+compile_error!(
+    "`sync-out` needs `shared-limits` or `analog-limits` to free D12"
+);
+#[cfg(all(feature = "sync-out", feature = "capture"))]
+compile_error!("`sync-out` and `capture` both use D12");
+#[cfg(all(
+    feature = "done-out",
+    not(any(feature = "shared-limits", feature = "analog-limits"))
+))]
+compile_error!(
+    "`done-out` needs `shared-limits` or `analog-limits` to free D12"
+);
+#[cfg(all(
+    feature = "done-out",
+    any(feature = "sync-out", feature = "capture")
+))]
+compile_error!("`done-out` uses D12, as do `sync-out` and `capture`");
+
+#[cfg(feature = "capture")]
+mod capture;
+mod clock;
 mod command;
 mod controller;
+#[cfg(feature = "done-out")]
+mod done_out;
+#[cfg(feature = "door")]
+mod door;
 mod gitm;
+#[cfg(feature = "hard-limits")]
+mod hard_limit;
+#[cfg(feature = "a-index")]
+mod index;
+#[cfg(feature = "instrument")]
+mod instrument;
+mod log;
 mod machine;
-mod readln;
+#[cfg(feature = "payout")]
+mod payout;
+mod realtime;
+mod settings;
+#[cfg(feature = "spindle2")]
+mod spindle2;
+#[cfg(feature = "timer-steps")]
+mod step_timer;
+#[cfg(feature = "trace")]
+mod step_trace;
+mod storage;
+#[cfg(feature = "sync-out")]
+mod sync_out;
+#[cfg(feature = "telemetry")]
+mod telemetry;
+#[cfg(feature = "tension")]
+mod tension;
 mod uno;
 
 use controller::Controller;
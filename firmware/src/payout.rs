@@ -0,0 +1,112 @@
+use arduino_hal::{
+    delay_us,
+    port::{mode::Output, Pin, D4, D5},
+    Peripherals, Pins,
+};
+
+use crate::{
+    machine::{Machine, MoveObserver},
+    settings::Settings,
+};
+
+/// Payout (de-reeler) motor for the supply spool.
+///
+/// The payout motor is not a G-code axis. It follows the spindle, paying
+/// out exactly as much wire as the coil takes up, so that a heavy supply
+/// spool does not have to be dragged around by the wire itself.
+///
+/// Pins: D4 is the pulse output and D5 the direction output.
+pub struct Payout {
+    pin_pulse: Pin<Output, D4>,
+    pin_direc: Pin<Output, D5>,
+    forward: bool,
+}
+impl Payout {
+    const DELAY_DIREC_US: u32 = 10;
+    const DELAY_PULSE_US: u32 = 5;
+
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+
+        let mut payout = Self {
+            pin_pulse: pins.d4.into_output(),
+            pin_direc: pins.d5.into_output(),
+            forward: true,
+        };
+        payout.pin_pulse.set_low();
+        payout.pin_direc.set_high();
+        payout
+    }
+
+    /// Take a single step, forward (paying out) or backward.
+    fn step(&mut self, forward: bool) {
+        if forward != self.forward {
+            delay_us(Self::DELAY_DIREC_US);
+            if forward {
+                self.pin_direc.set_high();
+            } else {
+                self.pin_direc.set_low();
+            }
+            self.forward = forward;
+            delay_us(Self::DELAY_DIREC_US);
+        }
+        self.pin_pulse.set_high();
+        delay_us(Self::DELAY_PULSE_US);
+        self.pin_pulse.set_low();
+        delay_us(Self::DELAY_PULSE_US);
+    }
+}
+
+/// Drives the payout motor from spindle motion.
+///
+/// One spindle revolution takes up `pi * coil_diameter` of wire, which the
+/// supply spool releases in `coil_diameter / spool_diameter` revolutions.
+/// Each A step therefore adds `coil_diameter * payout_steps_per_rev` to an
+/// accumulator, and the payout motor steps whenever the accumulator passes
+/// `spool_diameter * A_STEPS_PER_REV` (the same scheme as Bresenham line
+/// drawing, so no division is needed per step).
+///
+/// Payout is disabled while the coil diameter setting is zero.
+pub struct PayoutFollower<'a> {
+    payout: &'a mut Payout,
+    settings: &'a Settings,
+    last_a_pos: Option<i64>,
+    accumulator: i32,
+}
+impl<'a> PayoutFollower<'a> {
+    pub fn new(payout: &'a mut Payout, settings: &'a Settings) -> Self {
+        Self {
+            payout,
+            settings,
+            last_a_pos: None,
+            accumulator: 0,
+        }
+    }
+}
+impl MoveObserver for PayoutFollower<'_> {
+    fn after_step(&mut self, _x_pos: u32, a_pos: i64) {
+        let last_a_pos = self.last_a_pos.replace(a_pos).unwrap_or(a_pos);
+        let s = self.settings;
+        if a_pos == last_a_pos || s.coil_diameter == 0 {
+            return;
+        }
+
+        let num = s.coil_diameter as i32 * s.payout_steps_per_rev as i32;
+        let den =
+            s.supply_spool_diameter as i32 * Machine::A_STEPS_PER_REV as i32;
+        if a_pos > last_a_pos {
+            self.accumulator += num;
+            while self.accumulator >= den {
+                self.payout.step(true);
+                self.accumulator -= den;
+            }
+        } else {
+            self.accumulator -= num;
+            while self.accumulator <= -den {
+                self.payout.step(false);
+                self.accumulator += den;
+            }
+        }
+    }
+}
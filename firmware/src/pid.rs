@@ -0,0 +1,161 @@
+//! Integer PID controller.
+//!
+//! The controller works entirely in integer arithmetic, so that it is cheap
+//! to run on the AVR. Gains are given in thousandths (eg. a proportional
+//! gain of `1500` is 1.5).
+
+use crate::fixed::Q16;
+
+/// PID gains, in thousandths.
+#[derive(Clone, Copy)]
+pub struct Gains {
+    pub kp: i32,
+    pub ki: i32,
+    pub kd: i32,
+}
+
+/// State of a PID controller.
+///
+/// The output is clamped to `[out_min, out_max]`. The integral term is
+/// clamped to the same range (anti-windup), so that the controller recovers
+/// quickly after a long period of saturation.
+pub struct Pid {
+    out_min: i32,
+    out_max: i32,
+    /// Integral term, already scaled by `ki`, in output units.
+    integral: i32,
+    /// Error from the previous update, if there has been one.
+    last_error: Option<i32>,
+}
+impl Pid {
+    /// Creates a new PID controller with the given output range.
+    pub fn new(out_min: i32, out_max: i32) -> Self {
+        Self {
+            out_min,
+            out_max,
+            integral: 0,
+            last_error: None,
+        }
+    }
+
+    /// Resets the integral and derivative state.
+    pub fn reset(&mut self) {
+        self.integral = 0;
+        self.last_error = None;
+    }
+
+    /// Runs one update of the controller.
+    ///
+    /// The update is assumed to be called at a fixed period, so the period
+    /// is folded into the integral and derivative gains.
+    ///
+    /// # Parameters
+    ///
+    /// - `gains`: Gains to use for this update.
+    /// - `setpoint`: Desired value of the measurement.
+    /// - `measurement`: Current value of the measurement.
+    ///
+    /// # Returns
+    ///
+    /// The new output, clamped to the output range.
+    pub fn update(
+        &mut self,
+        gains: &Gains,
+        setpoint: i32,
+        measurement: i32,
+    ) -> i32 {
+        let error = setpoint.saturating_sub(measurement);
+
+        let p = scale(gains.kp, error);
+
+        let i = scale(gains.ki, error);
+        self.integral = self
+            .integral
+            .saturating_add(i)
+            .clamp(self.out_min, self.out_max);
+
+        let d = match self.last_error {
+            None => 0,
+            Some(last_error) => {
+                scale(gains.kd, error.saturating_sub(last_error))
+            }
+        };
+        self.last_error = Some(error);
+
+        p.saturating_add(self.integral)
+            .saturating_add(d)
+            .clamp(self.out_min, self.out_max)
+    }
+}
+
+/// Multiplies a value by a gain in thousandths, rounding and saturating.
+fn scale(gain: i32, value: i32) -> i32 {
+    Q16::from_ratio(gain, 1000).mul_int(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P_ONLY: Gains = Gains {
+        kp: 500,
+        ki: 0,
+        kd: 0,
+    };
+
+    #[test]
+    fn test_proportional() {
+        let mut pid = Pid::new(-1000, 1000);
+        assert_eq!(50, pid.update(&P_ONLY, 100, 0));
+        assert_eq!(-50, pid.update(&P_ONLY, 0, 100));
+    }
+
+    #[test]
+    fn test_output_is_clamped() {
+        let mut pid = Pid::new(0, 255);
+        assert_eq!(255, pid.update(&P_ONLY, 10_000, 0));
+        assert_eq!(0, pid.update(&P_ONLY, 0, 10_000));
+    }
+
+    #[test]
+    fn test_integral_accumulates() {
+        let gains = Gains {
+            kp: 0,
+            ki: 1000,
+            kd: 0,
+        };
+        let mut pid = Pid::new(-1000, 1000);
+        assert_eq!(10, pid.update(&gains, 10, 0));
+        assert_eq!(20, pid.update(&gains, 10, 0));
+        assert_eq!(30, pid.update(&gains, 10, 0));
+    }
+
+    #[test]
+    fn test_integral_does_not_wind_up() {
+        let gains = Gains {
+            kp: 0,
+            ki: 1000,
+            kd: 0,
+        };
+        let mut pid = Pid::new(0, 255);
+        for _ in 0..1000 {
+            pid.update(&gains, 100, 0);
+        }
+        // A single step of negative error must move the output straight away.
+        assert_eq!(155, pid.update(&gains, 0, 100));
+    }
+
+    #[test]
+    fn test_derivative_ignores_first_update() {
+        let gains = Gains {
+            kp: 0,
+            ki: 0,
+            kd: 1000,
+        };
+        let mut pid = Pid::new(-1000, 1000);
+        assert_eq!(0, pid.update(&gains, 10, 0));
+        assert_eq!(10, pid.update(&gains, 20, 0));
+        pid.reset();
+        assert_eq!(0, pid.update(&gains, 20, 0));
+    }
+}
@@ -0,0 +1,639 @@
+//! Planning of coordinated moves of any number of axes.
+//!
+//! A [`Line`] breaks a straight move between two positions into single
+//! steps, in the same way as the two-axis
+//! [`Interpolator`](crate::interpolate::Interpolator): the axis with the
+//! most steps (the major axis) steps on every iteration, and each other
+//! axis steps on the iterations that keep it closest to the straight line,
+//! as told by a [`Dda`].
+//!
+//! An [`Arc`] breaks a curved move of two axes into short straight
+//! segments, each of which can then be taken as a line. Its angles and
+//! rotations are worked out with CORDIC, which only shifts and adds, since
+//! there is no floating point trigonometry on the AVR.
+
+use crate::{dda::Dda, interpolate::Dir};
+
+/// A single step of one axis of a [`Line`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisStep {
+    /// Index of the axis.
+    pub axis: usize,
+    pub dir: Dir,
+}
+
+/// Yields the steps of a straight move of `N` axes.
+///
+/// Within an iteration where several axes step, they step in order of
+/// their index.
+pub struct Line<const N: usize> {
+    dirs: [Dir; N],
+    /// Number of steps of each axis.
+    steps: [u32; N],
+    /// Which iterations each axis steps on.
+    ddas: [Dda; N],
+    /// Number of iterations left.
+    remaining: u32,
+    /// Axes that step in the current iteration, and have yet to be yielded.
+    pending: [bool; N],
+    /// Next axis to look at in `pending`.
+    next_axis: usize,
+}
+impl<const N: usize> Line<N> {
+    /// Creates a line from `start` to `end`, in steps.
+    ///
+    /// Each axis moves at most `u32::MAX` steps, which no two `i32`
+    /// positions exceed.
+    pub fn new(start: [i32; N], end: [i32; N]) -> Self {
+        let delta = |i: usize| end[i] as i64 - start[i] as i64;
+        let dirs = core::array::from_fn(|i| match delta(i) >= 0 {
+            true => Dir::Pos,
+            false => Dir::Neg,
+        });
+        let steps: [u32; N] =
+            core::array::from_fn(|i| delta(i).unsigned_abs() as u32);
+        let major = steps.iter().copied().max().unwrap_or(0);
+        Self {
+            dirs,
+            steps,
+            ddas: steps.map(|steps| Dda::new(steps, major)),
+            remaining: major,
+            pending: [false; N],
+            next_axis: N,
+        }
+    }
+
+    /// Returns the number of steps that each axis takes.
+    pub fn steps(&self) -> [u32; N] {
+        self.steps
+    }
+
+    /// Works out which axes step in the next iteration.
+    fn start_iteration(&mut self) {
+        self.remaining -= 1;
+        for axis in 0..N {
+            self.pending[axis] = self.ddas[axis].next_iteration();
+        }
+        self.next_axis = 0;
+    }
+}
+impl<const N: usize> Iterator for Line<N> {
+    type Item = AxisStep;
+
+    fn next(&mut self) -> Option<AxisStep> {
+        loop {
+            while self.next_axis < N {
+                let axis = self.next_axis;
+                self.next_axis += 1;
+                if self.pending[axis] {
+                    return Some(AxisStep {
+                        axis,
+                        dir: self.dirs[axis],
+                    });
+                }
+            }
+            if self.remaining == 0 {
+                return None;
+            }
+            self.start_iteration();
+        }
+    }
+}
+
+/// Direction of an [`Arc`], looking at the plane of its two axes with the
+/// first to the right and the second upwards (as `G17` looks at the XY
+/// plane).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArcDir {
+    /// `G2`.
+    Clockwise,
+    /// `G3`.
+    CounterClockwise,
+}
+
+/// How the centre of an [`Arc`] is given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArcCenter {
+    /// Offset of the centre from the start (`I` and `J`).
+    Offset([i32; 2]),
+    /// Radius (`R`). Of the two arcs with this radius from the start to the
+    /// end, a positive radius takes the shorter one and a negative radius
+    /// the longer one.
+    Radius(i32),
+}
+
+/// Reasons that an [`Arc`] cannot be planned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArcError {
+    /// The centre is at the start.
+    NoRadius,
+    /// The radius is too short to reach from the start to the end, or it
+    /// was given for an arc that ends where it starts, whose centre it does
+    /// not fix.
+    InvalidRadius,
+    /// The end is further from the centre than the start is, or nearer,
+    /// by more than 5 (eg. microns) and 0.1% of the radius, as in Grbl.
+    EndNotOnCircle,
+}
+
+/// Yields the ends of the short straight segments of an arc of two axes.
+///
+/// Both axes are taken to be in the same unit: eg. for X in microns and A
+/// in millidegrees, a millimetre of X is as long as a degree of A. The
+/// last segment ends exactly at the end of the arc, even if that is not
+/// quite on the circle.
+#[derive(Clone, Debug)]
+pub struct Arc {
+    center: [i64; 2],
+    /// Start, relative to the centre.
+    radius: [i64; 2],
+    /// Angle from the start to the end, in `2^-32` turns: positive
+    /// counter-clockwise.
+    sweep: i64,
+    segments: u32,
+    /// Number of segments yielded so far.
+    segment: u32,
+    end: [i64; 2],
+}
+impl Arc {
+    /// Plans an arc from `start` to `end`. An arc given by the offset of
+    /// its centre that ends where it starts is a whole circle.
+    ///
+    /// The end may be a little off the circle, to allow for rounding; the
+    /// last segment then makes up the difference.
+    ///
+    /// # Parameters
+    ///
+    /// - `segment_len`: Longest segment, along the arc.
+    pub fn new(
+        start: [i32; 2],
+        end: [i32; 2],
+        center: ArcCenter,
+        dir: ArcDir,
+        segment_len: u32,
+    ) -> Result<Self, ArcError> {
+        let start = start.map(i64::from);
+        let end = end.map(i64::from);
+        let delta = [end[0] - start[0], end[1] - start[1]];
+        let offset = match center {
+            ArcCenter::Offset(offset) => offset.map(i64::from),
+            ArcCenter::Radius(radius) => radius_offset(delta, radius, dir)?,
+        };
+        if offset == [0, 0] {
+            return Err(ArcError::NoRadius);
+        }
+        let radius = offset.map(|c| -c);
+        let (start_angle, magnitude) = vectorize(radius);
+        let (end_angle, end_magnitude) =
+            vectorize([delta[0] - offset[0], delta[1] - offset[1]]);
+        let error = end_magnitude.abs_diff(magnitude);
+        if error > 5 && (error > 500 || error * 1000 > magnitude) {
+            return Err(ArcError::EndNotOnCircle);
+        }
+        let sweep = match dir {
+            ArcDir::Clockwise => -(start_angle.wrapping_sub(end_angle) as i64),
+            ArcDir::CounterClockwise => {
+                end_angle.wrapping_sub(start_angle) as i64
+            }
+        };
+        let sweep = match (sweep, delta, dir) {
+            (0, [0, 0], ArcDir::Clockwise) => -TURN,
+            (0, [0, 0], ArcDir::CounterClockwise) => TURN,
+            _ => sweep,
+        };
+        // 2π r, times the fraction of a turn.
+        let length =
+            magnitude as u128 * sweep.unsigned_abs() as u128 * 6_283_185
+                / (1_000_000 << 32);
+        let segments = length
+            .div_ceil(segment_len.max(1) as u128)
+            .clamp(1, u32::MAX as u128) as u32;
+        Ok(Self {
+            center: [start[0] + offset[0], start[1] + offset[1]],
+            radius,
+            sweep,
+            segments,
+            segment: 0,
+            end,
+        })
+    }
+
+    /// Returns the number of segments.
+    pub fn segments(&self) -> u32 {
+        self.segments
+    }
+}
+impl Iterator for Arc {
+    type Item = [i64; 2];
+
+    fn next(&mut self) -> Option<[i64; 2]> {
+        if self.segment == self.segments {
+            return None;
+        }
+        self.segment += 1;
+        if self.segment == self.segments {
+            return Some(self.end);
+        }
+        let angle =
+            self.sweep as i128 * self.segment as i128 / self.segments as i128;
+        let [x, y] = rotate(self.radius, angle as u32);
+        Some([self.center[0] + x, self.center[1] + y])
+    }
+}
+
+/// A whole turn, in the `2^-32` turns of CORDIC angles.
+const TURN: i64 = 1 << 32;
+
+/// `atan(2^-i)`, in `2^-32` turns.
+const ATAN: [u32; 30] = [
+    536_870_912,
+    316_933_406,
+    167_458_907,
+    85_004_756,
+    42_667_331,
+    21_354_465,
+    10_679_838,
+    5_340_245,
+    2_670_163,
+    1_335_087,
+    667_544,
+    333_772,
+    166_886,
+    83_443,
+    41_722,
+    20_861,
+    10_430,
+    5_215,
+    2_608,
+    1_304,
+    652,
+    326,
+    163,
+    81,
+    41,
+    20,
+    10,
+    5,
+    3,
+    1,
+];
+
+/// Inverse of the CORDIC gain (about 0.6073), in `2^-30`.
+const INVERSE_GAIN: i64 = 652_032_874;
+
+/// Bits that vectors are scaled to for CORDIC, to keep its rounding small.
+const CORDIC_BITS: i32 = 40;
+
+/// Returns the angle of a vector, in `2^-32` turns counter-clockwise from
+/// the first axis, and its length.
+fn vectorize(v: [i64; 2]) -> (u32, u64) {
+    let (shift, [mut x, mut y]) = scale_up(v);
+    let mut angle = 0u32;
+    if x < 0 {
+        (x, y) = (-x, -y);
+        angle = (TURN / 2) as u32;
+    }
+    for (i, &atan) in ATAN.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if y > 0 {
+            (x, y) = (x + dx, y - dy);
+            angle = angle.wrapping_add(atan);
+        } else {
+            (x, y) = (x - dx, y + dy);
+            angle = angle.wrapping_sub(atan);
+        }
+    }
+    (angle, scale_down(x, shift) as u64)
+}
+
+/// Rotates a vector counter-clockwise by `angle`, in `2^-32` turns.
+fn rotate(v: [i64; 2], angle: u32) -> [i64; 2] {
+    let (shift, [mut x, mut y]) = scale_up(v);
+    let mut angle = angle as i32;
+    if angle.unsigned_abs() > (TURN / 4) as u32 {
+        (x, y) = (-x, -y);
+        angle = angle.wrapping_add(i32::MIN);
+    }
+    for (i, &atan) in ATAN.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if angle >= 0 {
+            (x, y) = (x - dx, y + dy);
+            angle -= atan as i32;
+        } else {
+            (x, y) = (x + dx, y - dy);
+            angle += atan as i32;
+        }
+    }
+    [scale_down(x, shift), scale_down(y, shift)]
+}
+
+/// Scales a vector up (or down) to [`CORDIC_BITS`], and returns the shift
+/// along with it.
+fn scale_up(v: [i64; 2]) -> (i32, [i64; 2]) {
+    let bits = 64 - (v[0].unsigned_abs() | v[1].unsigned_abs()).leading_zeros();
+    let shift = CORDIC_BITS - bits as i32;
+    let v = v.map(|c| match shift >= 0 {
+        true => c << shift,
+        false => c >> -shift,
+    });
+    (shift, v)
+}
+
+/// Takes the CORDIC gain out of a component of a vector from
+/// [`scale_up`], and scales it back down, rounding to the nearest.
+fn scale_down(c: i64, shift: i32) -> i64 {
+    let c = ((c >> 10) * INVERSE_GAIN) >> 20;
+    match shift {
+        1.. => (c + (1 << (shift - 1))) >> shift,
+        _ => c << -shift,
+    }
+}
+
+/// Returns the offset of the centre of an arc from its start, given its
+/// radius, as [`ArcCenter::Radius`].
+///
+/// The centre is on the perpendicular bisector of the chord from the start
+/// to the end (`delta`), at `h / 2` from it, where `h^2 = 4 r^2 - d^2`.
+fn radius_offset(
+    delta: [i64; 2],
+    radius: i32,
+    dir: ArcDir,
+) -> Result<[i64; 2], ArcError> {
+    let [x, y] = delta.map(i128::from);
+    let d2 = x * x + y * y;
+    let h2 = 4 * (radius as i128).pow(2) - d2;
+    if d2 == 0 || h2 < 0 {
+        return Err(ArcError::InvalidRadius);
+    }
+    let d = isqrt(d2 as u128) as i128;
+    let h = isqrt(h2 as u128) as i128;
+    // For the shorter arc, the centre is on the right of the chord for a
+    // clockwise arc, and on the left for a counter-clockwise one.
+    let h = match (dir, radius < 0) {
+        (ArcDir::Clockwise, false) | (ArcDir::CounterClockwise, true) => -h,
+        _ => h,
+    };
+    Ok(
+        [(x * d - y * h) / (2 * d), (y * d + x * h) / (2 * d)]
+            .map(|c| c as i64),
+    )
+}
+
+/// Returns the square root of `n`, rounded down.
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method, from above.
+    let mut x = 1 << (128 - n.leading_zeros()).div_ceil(2);
+    loop {
+        let next = (x + n / x) / 2;
+        if next >= x {
+            return x;
+        }
+        x = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpolate::{Interpolator, Step};
+
+    /// Runs a line to its end, checking that after every iteration each
+    /// axis is within half a step of the straight line, and returns the
+    /// final position.
+    fn run<const N: usize>(start: [i32; N], end: [i32; N]) -> [i32; N] {
+        let line = Line::new(start, end);
+        let steps = line.steps();
+        let major = steps.iter().copied().max().unwrap_or(0) as i64;
+        let mut pos = start;
+        let mut iteration = 0;
+        let mut last_axis = None;
+        let check = |pos: &[i32; N], iteration: i64| {
+            for axis in 0..N {
+                // 2 * major * (distance from the line), in steps.
+                let moved = (pos[axis] as i64 - start[axis] as i64).abs();
+                let ideal = 2 * iteration * steps[axis] as i64;
+                let deviation = (2 * major * moved - ideal).abs();
+                assert!(
+                    deviation <= major,
+                    "{:?} -> {:?}: axis {} at {:?}",
+                    start,
+                    end,
+                    axis,
+                    pos
+                );
+            }
+        };
+        for step in line {
+            // Axes step in order within an iteration, so an axis that is no
+            // later than the last one starts the next iteration.
+            if last_axis.is_some_and(|last| step.axis <= last) {
+                iteration += 1;
+                check(&pos, iteration);
+            }
+            last_axis = Some(step.axis);
+            pos[step.axis] += match step.dir {
+                Dir::Pos => 1,
+                Dir::Neg => -1,
+            };
+        }
+        check(&pos, major);
+        pos
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(None, Line::new([3, -2, 0], [3, -2, 0]).next());
+        assert_eq!(None, Line::<0>::new([], []).next());
+    }
+
+    #[test]
+    fn test_single_axis() {
+        let steps: heapless::Vec<AxisStep, 4> =
+            Line::new([0, 5, 0], [0, 2, 0]).collect();
+        assert_eq!(3, steps.len());
+        assert!(steps.iter().all(|s| *s
+            == AxisStep {
+                axis: 1,
+                dir: Dir::Neg
+            }));
+    }
+
+    #[test]
+    fn test_reaches_the_end() {
+        for a in -6..=6 {
+            for b in -6..=6 {
+                for c in -6..=6 {
+                    let start = [10, -20, 0];
+                    let end = [10 + a, -20 + b, c];
+                    assert_eq!(end, run(start, end));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_lines() {
+        for end in [[1000, 3, -999], [-7, 4096, 4095], [1, 1, 1]] {
+            assert_eq!(end, run([0, 0, 0], end));
+        }
+        let end = [i32::MAX, 0];
+        let mut line = Line::new([i32::MIN, 0], end);
+        assert_eq!([u32::MAX, 0], line.steps());
+        let first = AxisStep {
+            axis: 0,
+            dir: Dir::Pos,
+        };
+        assert_eq!(Some(first), line.next());
+    }
+
+    #[test]
+    fn test_two_axes_match_the_interpolator() {
+        for dx in -12..=12 {
+            for da in -12..=12 {
+                let line = Line::new([0, 0], [dx, da]).map(|s| match s.axis {
+                    0 => Step::X(s.dir),
+                    _ => Step::A(s.dir),
+                });
+                assert!(line.eq(Interpolator::new(dx, da)), "{} {}", dx, da);
+            }
+        }
+    }
+
+    /// Runs an arc from the origin, checking that each segment ends within
+    /// `tolerance` of the circle around `center` with `radius`, and is no
+    /// longer than `segment_len` (plus the tolerance), and returns the ends.
+    fn run_arc(
+        arc: Arc,
+        center: [i64; 2],
+        radius: i64,
+        segment_len: i64,
+        tolerance: i64,
+    ) -> heapless::Vec<[i64; 2], 128> {
+        let length = |[x, y]: [i64; 2]| {
+            isqrt((x as i128).pow(2) as u128 + (y as i128).pow(2) as u128)
+                as i64
+        };
+        let mut last = [0, 0];
+        let mut ends = heapless::Vec::new();
+        for end in arc {
+            let from_center = length([end[0] - center[0], end[1] - center[1]]);
+            assert!((from_center - radius).abs() <= tolerance, "{:?}", end);
+            let chord = length([end[0] - last[0], end[1] - last[1]]);
+            assert!(chord <= segment_len + tolerance, "{:?}", end);
+            last = end;
+            ends.push(end).unwrap();
+        }
+        ends
+    }
+
+    #[test]
+    fn test_quarter_circle() {
+        let center = ArcCenter::Offset([10_000, 0]);
+        let end = [10_000, -10_000];
+        let arc = Arc::new([0, 0], end, center, ArcDir::CounterClockwise, 1000)
+            .unwrap();
+        assert_eq!(16, arc.segments());
+        let ends = run_arc(arc, [10_000, 0], 10_000, 1000, 2);
+        assert_eq!(Some(&[10_000, -10_000]), ends.last());
+        assert!(ends.iter().all(|&[x, y]| x >= 0 && y <= 0));
+
+        // The other way round, the arc is three quarters of a turn.
+        let arc =
+            Arc::new([0, 0], end, center, ArcDir::Clockwise, 1000).unwrap();
+        assert_eq!(48, arc.segments());
+        let ends = run_arc(arc, [10_000, 0], 10_000, 1000, 2);
+        assert_eq!(Some(&[10_000, -10_000]), ends.last());
+        assert_eq!(Some(10_000), ends.iter().map(|end| end[1]).max());
+    }
+
+    #[test]
+    fn test_whole_circle() {
+        let center = ArcCenter::Offset([0, 5000]);
+        let arc =
+            Arc::new([0, 0], [0, 0], center, ArcDir::Clockwise, 1000).unwrap();
+        assert_eq!(32, arc.segments());
+        let ends = run_arc(arc, [0, 5000], 5000, 1000, 2);
+        assert_eq!(Some(&[0, 0]), ends.last());
+        // Clockwise from the bottom of the circle, the arc first heads left.
+        assert!(ends[0][0] < 0);
+        assert_eq!(Some(10_000), ends.iter().map(|end| end[1]).max());
+    }
+
+    #[test]
+    fn test_radius() {
+        let end = [10_000, 0];
+        let arc = |radius, dir| {
+            let arc =
+                Arc::new([0, 0], end, ArcCenter::Radius(radius), dir, 500)
+                    .unwrap();
+            run_arc(arc.clone(), arc.center, radius.abs() as i64, 500, 2);
+            (arc.center, arc.segments())
+        };
+        // The shorter arcs turn through 60 degrees, around a centre below
+        // the chord clockwise, and above it counter-clockwise. The longer
+        // arcs turn through 300 degrees, around the other centre.
+        let (below, above) = ([5000, -8660], [5000, 8660]);
+        assert_eq!((below, 21), arc(10_000, ArcDir::Clockwise));
+        assert_eq!((above, 21), arc(10_000, ArcDir::CounterClockwise));
+        assert_eq!((above, 105), arc(-10_000, ArcDir::Clockwise));
+        assert_eq!((below, 105), arc(-10_000, ArcDir::CounterClockwise));
+        // A half circle.
+        assert_eq!(([5000, 0], 32), arc(5000, ArcDir::Clockwise));
+    }
+
+    #[test]
+    fn test_invalid_arcs() {
+        let arc = |end, center| {
+            Arc::new([0, 0], end, center, ArcDir::Clockwise, 1000).err()
+        };
+        let no_radius = Some(ArcError::NoRadius);
+        let invalid_radius = Some(ArcError::InvalidRadius);
+        assert_eq!(no_radius, arc([10, 0], ArcCenter::Offset([0, 0])));
+        assert_eq!(invalid_radius, arc([10_000, 0], ArcCenter::Radius(4999)));
+        assert_eq!(invalid_radius, arc([0, 0], ArcCenter::Radius(5000)));
+        // A centre that is not as far from the end as from the start.
+        let center = ArcCenter::Offset([10_000, 0]);
+        assert_eq!(
+            Some(ArcError::EndNotOnCircle),
+            arc([10_000, -10_100], center)
+        );
+        assert_eq!(None, arc([10_000, -10_004], center));
+    }
+
+    #[test]
+    fn test_cordic() {
+        let quarter = (TURN / 4) as u32;
+        assert_eq!([0, 1_000_000], rotate([1_000_000, 0], quarter));
+        assert_eq!([-1_000_000, 0], rotate([0, 1_000_000], quarter));
+        assert_eq!([707_107, 707_107], rotate([1_000_000, 0], quarter / 2));
+        for k in 0..64u32 {
+            let angle = k << 26;
+            let v = rotate([3_000_000, -4_000_000], angle);
+            let (back, length) = vectorize(v);
+            assert!(length.abs_diff(5_000_000) <= 2, "{}", k);
+            let expected =
+                vectorize([3_000_000, -4_000_000]).0.wrapping_add(angle);
+            assert!(
+                (back.wrapping_sub(expected) as i32).abs() < 1 << 12,
+                "{}",
+                k
+            );
+        }
+    }
+
+    #[test]
+    fn test_long_arcs() {
+        // The largest offsets, far beyond any machine's travel.
+        let center = ArcCenter::Offset([i32::MAX, i32::MIN]);
+        let arc =
+            Arc::new([0, 0], [0, 0], center, ArcDir::CounterClockwise, 1 << 30)
+                .unwrap();
+        assert_eq!(18, arc.segments());
+        let radius = isqrt(2 * (1u128 << 62)) as i64;
+        let center = [i32::MAX as i64, i32::MIN as i64];
+        run_arc(arc, center, radius, 1 << 30, 1 << 12);
+    }
+}
@@ -0,0 +1,242 @@
+//! Runs the example programs in `programs/` against a simulated machine,
+//! and checks where each one ends up.
+//!
+//! The simulator interprets the motion commands that the programs use the
+//! way the controller does, with the firmware's own parsers, repeated
+//! blocks, step conversions and interpolation. It does not model the
+//! hardware: zeroing puts X at the left limit at once, and the soft limits
+//! are a fixed travel.
+
+use crate::{
+    convert::{Accumulator, Converter},
+    gcode::{parse_angle_millis, parse_digits, parse_millis},
+    interpolate::{Dir, Interpolator, Step},
+    repeat::{self, Feed, Player, Recorder},
+};
+
+/// Conversions of X microns and A millidegrees to steps, as in the firmware.
+const X_CONVERTER: Converter = Converter::new(6400, 5000);
+const A_CONVERTER: Converter = Converter::new(6400, 360_000);
+const A_STEPS_PER_REV: i32 = 6400;
+/// Travel of X between its limit switches, in steps.
+const X_TRAVEL_STEPS: i32 = X_CONVERTER.to_steps(100_000);
+
+/// Errors from running a program on the simulator.
+#[derive(Debug, PartialEq)]
+enum Error {
+    /// A command that the simulator does not know, or invalid arguments.
+    InvalidGCode,
+    NotZeroed,
+    OutOfRange,
+    Repeat(repeat::Error),
+}
+
+/// A simulated machine, and what it did while running a program.
+struct Simulator {
+    zeroed: bool,
+    absolute: bool,
+    /// Origin of bobbin coordinates, in machine microns.
+    origin_microns: i32,
+    x_pos: i32,
+    a_pos: i32,
+    x_carry: Accumulator,
+    a_carry: Accumulator,
+    last_x_dir: Option<Dir>,
+    /// Number of times that X changed direction.
+    x_reversals: u32,
+    /// Total time spent in dwells, in milliseconds.
+    dwell_ms: u32,
+    repeat: Recorder<256>,
+}
+impl Simulator {
+    fn new() -> Self {
+        Self {
+            zeroed: false,
+            absolute: true,
+            origin_microns: 0,
+            x_pos: 0,
+            a_pos: 0,
+            x_carry: Accumulator::new(X_CONVERTER),
+            a_carry: Accumulator::new(A_CONVERTER),
+            last_x_dir: None,
+            x_reversals: 0,
+            dwell_ms: 0,
+            repeat: Recorder::new(),
+        }
+    }
+
+    /// Number of whole turns of A.
+    fn turns(&self) -> i32 {
+        assert_eq!(0, self.a_pos % A_STEPS_PER_REV, "partial turn");
+        self.a_pos / A_STEPS_PER_REV
+    }
+
+    /// Run every line of a program, as if each was sent by the host.
+    fn run(&mut self, program: &str) -> Result<(), Error> {
+        for line in program.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('%') {
+                continue;
+            }
+            match self.repeat.feed(line).map_err(Error::Repeat)? {
+                Feed::Pass => self.command(line)?,
+                Feed::Recorded => {}
+                Feed::Complete => {
+                    let block = self.repeat.take();
+                    for line in Player::new(&block) {
+                        self.command(line)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, line: &str) -> Result<(), Error> {
+        let (word, args) = line.split_once(' ').unwrap_or((line, ""));
+        match word {
+            "Z" => {
+                self.zeroed = true;
+                self.absolute = true;
+                self.x_pos = 0;
+                self.a_pos = 0;
+                self.x_carry.reset();
+                self.a_carry.reset();
+                self.last_x_dir = None;
+            }
+            "G90" => self.absolute = true,
+            "G91" => self.absolute = false,
+            "G0" => self.move_to(args, self.origin_microns)?,
+            "G53" => {
+                let args =
+                    args.strip_prefix("G0").ok_or(Error::InvalidGCode)?;
+                self.move_to(args, 0)?;
+            }
+            "G4" => {
+                let mut ms =
+                    args.strip_prefix('P').ok_or(Error::InvalidGCode)?;
+                self.dwell_ms += parse_digits::<u32>(&mut ms)
+                    .map_err(|_| Error::InvalidGCode)?;
+            }
+            "G10" => {
+                let x =
+                    args.strip_prefix("L2 P1 X").ok_or(Error::InvalidGCode)?;
+                self.origin_microns = millis(x)?;
+            }
+            _ => return Err(Error::InvalidGCode),
+        }
+        Ok(())
+    }
+
+    /// Perform a move, with X in absolute moves relative to `x_origin`.
+    fn move_to(&mut self, args: &str, x_origin: i32) -> Result<(), Error> {
+        if !self.zeroed {
+            return Err(Error::NotZeroed);
+        }
+        let (mut x, mut a) = (None, None);
+        for arg in args.split_whitespace() {
+            let (axis, value) = arg.split_at(1);
+            match axis {
+                "X" => x = Some(millis(value)?),
+                "A" => a = Some(angle_millis(value)?),
+                _ => return Err(Error::InvalidGCode),
+            }
+        }
+        let (dx, da) = if self.absolute {
+            self.x_carry.reset();
+            self.a_carry.reset();
+            let x_target = x.map(|x| X_CONVERTER.to_steps(x + x_origin));
+            let a_target = a.map(|a| A_CONVERTER.to_steps(a));
+            (
+                x_target.map_or(0, |x| x - self.x_pos),
+                a_target.map_or(0, |a| a - self.a_pos),
+            )
+        } else {
+            (
+                self.x_carry.to_steps(x.unwrap_or(0)),
+                self.a_carry.to_steps(a.unwrap_or(0)),
+            )
+        };
+        if !(0..=X_TRAVEL_STEPS).contains(&(self.x_pos + dx)) {
+            return Err(Error::OutOfRange);
+        }
+        for step in Interpolator::new(dx, da) {
+            match step {
+                Step::X(dir) => {
+                    if self.last_x_dir.is_some_and(|last| last != dir) {
+                        self.x_reversals += 1;
+                    }
+                    self.last_x_dir = Some(dir);
+                    self.x_pos += if dir == Dir::Pos { 1 } else { -1 };
+                }
+                Step::A(dir) => {
+                    self.a_pos += if dir == Dir::Pos { 1 } else { -1 };
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse a whole word value in thousandths, as the firmware does.
+fn millis(mut value: &str) -> Result<i32, Error> {
+    parse_millis(&mut value)
+        .ok()
+        .filter(|_| value.is_empty())
+        .ok_or(Error::InvalidGCode)
+}
+
+/// Parse a whole A word value in millidegrees, as the firmware does.
+fn angle_millis(mut value: &str) -> Result<i32, Error> {
+    parse_angle_millis(&mut value)
+        .ok()
+        .filter(|_| value.is_empty())
+        .and_then(|value| i32::try_from(value).ok())
+        .ok_or(Error::InvalidGCode)
+}
+
+fn run(program: &str) -> Simulator {
+    let mut simulator = Simulator::new();
+    simulator.run(program).unwrap();
+    simulator
+}
+
+#[test]
+fn test_simple_coil() {
+    let simulator = run(include_str!("../programs/simple_coil.gcode"));
+    assert_eq!(50, simulator.turns());
+    assert_eq!(X_CONVERTER.to_steps(12_500), simulator.x_pos);
+    assert_eq!(0, simulator.x_reversals);
+    assert!(simulator.absolute);
+}
+
+#[test]
+fn test_layered_coil() {
+    let simulator = run(include_str!("../programs/layered_coil.gcode"));
+    assert_eq!(160, simulator.turns());
+    assert_eq!(X_CONVERTER.to_steps(10_000), simulator.x_pos);
+    assert_eq!(3, simulator.x_reversals);
+}
+
+#[test]
+fn test_tapped_coil() {
+    let simulator = run(include_str!("../programs/tapped_coil.gcode"));
+    assert_eq!(60, simulator.turns());
+    assert_eq!(X_CONVERTER.to_steps(22_000), simulator.x_pos);
+    assert_eq!(5000, simulator.dwell_ms);
+}
+
+#[test]
+fn test_moves_need_zeroing() {
+    let mut simulator = Simulator::new();
+    assert_eq!(Err(Error::NotZeroed), simulator.run("G0 X1"));
+    assert_eq!(Ok(()), simulator.run("Z\nG0 X1"));
+    assert_eq!(Err(Error::OutOfRange), simulator.run("G0 X101"));
+}
+
+#[test]
+fn test_turns_and_degrees_mix() {
+    let mut simulator = Simulator::new();
+    simulator.run("Z\nG91\nG0 A2.5r\nG0 A180\nG0 A-1r").unwrap();
+    assert_eq!(2, simulator.turns());
+}
@@ -0,0 +1,338 @@
+//! Response framing of the serial protocol.
+//!
+//! The host sends one line at a time and waits for it to be answered. Each
+//! line is answered by exactly one terminating response line:
+//!
+//! - `ok`: the line was executed successfully. If `$51` is set, this
+//!   reports how long the line took to execute (eg. `ok t=1234ms`).
+//! - `error:<code>`: the line failed; the code identifies the error.
+//!
+//! Before the terminating line, the firmware may write any number of other
+//! lines, eg. reports, log messages (`[<ms>] INFO: ...`), and `ALARM:<code>`
+//! when a failure has left the machine in a state that needs attention
+//! (eg. it must be zeroed again). The line that runs a job (a macro or a
+//! repeated block) writes `JOB-DONE` with the job's totals when the job
+//! completes. For senders that expect the response to
+//! come first, `$53=1` leaves out the log messages; `$52=1` ends every
+//! line with CR LF instead of LF. [`BANNER`] is written at startup and
+//! after a soft reset, followed by the [`PROTOCOL_VERSION`] (eg.
+//! `WINDERBOT! 2.9`).
+//!
+//! The host should wait for each line to be answered before it sends the
+//! next. A line that arrives during a move anyway is held until the move
+//! finishes (only one line is held; further bytes are discarded), and then
+//! handled as [`during_motion`] says. Real-time bytes (see
+//! [`RealtimeCommand`]) act at once, and are never part of a line. So does
+//! an `M112` line that arrives during a move (see [`is_emergency_stop`]).
+//!
+//! Host tools should check the protocol version, from the banner or from
+//! `$I`, and refuse to drive firmware that does not support the version
+//! that they were written for (see [`Version::supports`]). `$I` also
+//! reports a checksum of the settings (see [`settings_checksum`]), so that
+//! a host can check that the machine is configured as it expects.
+
+use core::fmt::{self, Display, Formatter};
+
+/// Line written at startup and after a soft reset.
+pub const BANNER: &str = "WINDERBOT!";
+
+/// Version of the serial protocol spoken by this firmware.
+///
+/// The minor version is incremented when commands or reports are added, and
+/// the major version when existing ones change. Changes since 1.2:
+///
+/// - 1.3: `$52` ends lines with CR LF, and `$53` leaves out log messages.
+/// - 1.4: `M400`.
+/// - 1.5: Lines sent during a move are held, and unsafe ones rejected (error
+///   15).
+/// - 1.6: Spindle mode: `M3`, `M5` and `S` (error 16).
+/// - 1.7: `M997`.
+/// - 1.8: Errors name the value that overflowed (error 17).
+/// - 1.9: `M19` (error 18).
+/// - 1.10: A in turns, as `A<turns>r`.
+/// - 1.11: `M998`.
+/// - 1.12: `M980`.
+/// - 1.13: `M989` and the DRO lines.
+/// - 2.0: `M114` and the DRO lines give A as `A<turns>:<angle>`, rather than as
+///   a single value, `A:<a>`.
+/// - 2.1: Zeroing reports crossed limit switches (error 19).
+/// - 2.2: `G2` and `G3` (error 20).
+/// - 2.3: [`FEED_HOLD`] and [`CYCLE_START`].
+/// - 2.4: [`SPINDLE_STOP`] (error 21).
+/// - 2.5: `JOB-DONE`.
+/// - 2.6: `M112` and [`ABORT`] (error 22, `ALARM:4`).
+/// - 2.7: The `M996` state line gives A as its whole, signed step count, rather
+///   than the low 32 bits of it.
+/// - 2.8: Commands of features that are not built in are refused (error 23),
+///   rather than being unknown.
+/// - 2.9: `G10` takes an `A` word, and `$43` holds the A origin of bobbin
+///   coordinates.
+pub const PROTOCOL_VERSION: Version = Version { major: 2, minor: 9 };
+
+/// Real-time command byte that requests a soft reset (Ctrl-X): a move stops
+/// after its current step, and the machine must be zeroed again.
+pub const SOFT_RESET: u8 = 0x18;
+
+/// Real-time command byte for an emergency stop, as `M112`: step pulses
+/// stop at once, even part way through the wait for a step, and the
+/// machine must be zeroed again. Unlike a soft reset, the modal state is
+/// kept, and an alarm is raised.
+pub const ABORT: u8 = 0x9F;
+
+/// Real-time command byte that holds the current move: both axes slow down
+/// to rest, and wait part way through the move for [`CYCLE_START`].
+pub const FEED_HOLD: u8 = b'!';
+
+/// Real-time command byte that resumes a held move.
+pub const CYCLE_START: u8 = b'~';
+
+/// Real-time command byte that stops A at once, eg. when the wire snaps,
+/// and leaves X where it is (as in Grbl, where it stops the spindle).
+pub const SPINDLE_STOP: u8 = 0x9E;
+
+/// A real-time command, which acts at once rather than being part of a
+/// line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RealtimeCommand {
+    /// [`SOFT_RESET`].
+    SoftReset,
+    /// [`FEED_HOLD`].
+    FeedHold,
+    /// [`CYCLE_START`].
+    CycleStart,
+    /// [`SPINDLE_STOP`].
+    SpindleStop,
+    /// [`ABORT`].
+    Abort,
+}
+impl RealtimeCommand {
+    /// Returns the real-time command sent as `byte`, if it is one.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            SOFT_RESET => Some(RealtimeCommand::SoftReset),
+            FEED_HOLD => Some(RealtimeCommand::FeedHold),
+            CYCLE_START => Some(RealtimeCommand::CycleStart),
+            SPINDLE_STOP => Some(RealtimeCommand::SpindleStop),
+            ABORT => Some(RealtimeCommand::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `true` if `byte` is a real-time command byte.
+pub fn is_realtime(byte: u8) -> bool {
+    RealtimeCommand::from_byte(byte).is_some()
+}
+
+/// Returns `true` if `line` is an emergency stop (`M112`).
+///
+/// `M112` acts as the [`ABORT`] byte when it arrives during a move, rather
+/// than being held until the move has finished, for hosts that cannot
+/// send real-time bytes.
+pub fn is_emergency_stop(line: &str) -> bool {
+    line.trim() == "M112"
+}
+
+/// A response line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response {
+    /// The line was executed successfully.
+    Ok,
+    /// The line was executed successfully, taking the given number of
+    /// milliseconds.
+    OkTimed(u32),
+    /// The line failed with the given error code.
+    Error(u8),
+    /// An alarm was raised with the given alarm code.
+    Alarm(u8),
+}
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Response::Ok => write!(f, "ok"),
+            Response::OkTimed(ms) => write!(f, "ok t={}ms", ms),
+            Response::Error(code) => write!(f, "error:{}", code),
+            Response::Alarm(code) => write!(f, "ALARM:{}", code),
+        }
+    }
+}
+
+/// What happens to a line that arrives during a move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuringMotion {
+    /// The line runs once the move has finished.
+    Queue,
+    /// The line is rejected with an error once the move has finished.
+    Reject,
+}
+
+/// Returns what happens to a line that arrives during a move.
+///
+/// Moves and reports are queued behind the move. Lines that change the
+/// settings, the stored lines, or the machine's reference (zeroing, homing,
+/// unlocking and `G10` offsets) are rejected: a host that streams them
+/// behind a move without waiting for it is out of step with the machine,
+/// and should wait for the move's `ok` (or send `M400`) first.
+///
+/// Reports are not answered during the move either. Every line gets
+/// exactly one `ok` or `error`, in the order that the lines were sent, and
+/// a report answered early would put its `ok` where the host expects the
+/// move's. A host that wants the position during a move should turn on the
+/// DRO lines (`M989`) or telemetry (`M990`), which are not responses, and
+/// send `M114`, `M996` or `$$` once the move's `ok` has arrived.
+pub fn during_motion(line: &str) -> DuringMotion {
+    let line = line.trim();
+    let word = line.split(' ').next().unwrap_or("");
+    let changes_setting = word.starts_with('$') && word.contains('=');
+    if changes_setting || matches!(word, "Z" | "ZA" | "$X" | "G10") {
+        DuringMotion::Reject
+    } else {
+        DuringMotion::Queue
+    }
+}
+
+/// A protocol version, written as `<major>.<minor>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+impl Version {
+    /// Returns `true` if a host written for the `required` version can use
+    /// firmware that speaks this version: the major versions must match,
+    /// and this minor version must be at least the required one.
+    pub fn supports(&self, required: Version) -> bool {
+        self.major == required.major && self.minor >= required.minor
+    }
+}
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Computes the checksum of a set of settings, given as `(id, value)`
+/// pairs in the order that `$$` lists them.
+///
+/// The checksum is a Fletcher-16 checksum of the id byte and the four
+/// little-endian value bytes of each setting.
+pub fn settings_checksum(settings: impl Iterator<Item = (u8, u32)>) -> u16 {
+    let (mut sum1, mut sum2) = (0u16, 0u16);
+    for (id, value) in settings {
+        for byte in [id].into_iter().chain(value.to_le_bytes()) {
+            sum1 = (sum1 + byte as u16) % 255;
+            sum2 = (sum2 + sum1) % 255;
+        }
+    }
+    (sum2 << 8) | sum1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use heapless::String;
+
+    fn format(response: Response) -> String<16> {
+        let mut s = String::new();
+        write!(s, "{}", response).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_ok() {
+        assert_eq!("ok", format(Response::Ok).as_str());
+    }
+
+    #[test]
+    fn test_ok_timed() {
+        assert_eq!("ok t=1234ms", format(Response::OkTimed(1234)).as_str());
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!("error:3", format(Response::Error(3)).as_str());
+    }
+
+    #[test]
+    fn test_alarm() {
+        assert_eq!("ALARM:12", format(Response::Alarm(12)).as_str());
+    }
+
+    #[test]
+    fn test_during_motion() {
+        assert_eq!(DuringMotion::Queue, during_motion("G0 X1 A360"));
+        assert_eq!(DuringMotion::Queue, during_motion("M996"));
+        assert_eq!(DuringMotion::Queue, during_motion("$$"));
+        assert_eq!(DuringMotion::Queue, during_motion("$N"));
+        assert_eq!(DuringMotion::Reject, during_motion("$6=0"));
+        assert_eq!(DuringMotion::Reject, during_motion("$N0=G90"));
+        assert_eq!(DuringMotion::Reject, during_motion(" Z "));
+        assert_eq!(DuringMotion::Reject, during_motion("ZA"));
+        assert_eq!(DuringMotion::Reject, during_motion("$X"));
+        assert_eq!(DuringMotion::Reject, during_motion("G10 L20 P1 X0"));
+    }
+
+    #[test]
+    fn test_realtime_commands() {
+        assert_eq!(
+            Some(RealtimeCommand::SoftReset),
+            RealtimeCommand::from_byte(0x18)
+        );
+        assert_eq!(
+            Some(RealtimeCommand::SpindleStop),
+            RealtimeCommand::from_byte(0x9E)
+        );
+        assert_eq!(
+            Some(RealtimeCommand::Abort),
+            RealtimeCommand::from_byte(0x9F)
+        );
+        assert!(is_realtime(b'!') && is_realtime(b'~'));
+        assert!(!is_realtime(b'G') && !is_realtime(b'\n'));
+    }
+
+    #[test]
+    fn test_emergency_stop() {
+        assert!(is_emergency_stop("M112"));
+        assert!(is_emergency_stop(" M112 "));
+        assert!(!is_emergency_stop("M11"));
+        assert!(!is_emergency_stop("M1120"));
+        assert!(!is_emergency_stop("G0 X1"));
+    }
+
+    #[test]
+    fn test_version() {
+        let mut s: String<8> = String::new();
+        write!(
+            s,
+            "{}",
+            Version {
+                major: 1,
+                minor: 12
+            }
+        )
+        .unwrap();
+        assert_eq!("1.12", s.as_str());
+    }
+
+    #[test]
+    fn test_version_supports() {
+        let version = Version { major: 1, minor: 1 };
+        assert!(version.supports(Version { major: 1, minor: 0 }));
+        assert!(version.supports(Version { major: 1, minor: 1 }));
+        assert!(!version.supports(Version { major: 1, minor: 2 }));
+        assert!(!version.supports(Version { major: 0, minor: 1 }));
+        assert!(!version.supports(Version { major: 2, minor: 0 }));
+    }
+
+    #[test]
+    fn test_settings_checksum() {
+        assert_eq!(0, settings_checksum([].into_iter()));
+        // Bytes 01 02 00 00 00 and 02 01 00 00 00.
+        assert_eq!(0x2A06, settings_checksum([(1, 2), (2, 1)].into_iter()));
+        assert_ne!(
+            settings_checksum([(1, 2), (2, 1)].into_iter()),
+            settings_checksum([(1, 1), (2, 2)].into_iter())
+        );
+    }
+}
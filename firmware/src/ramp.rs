@@ -0,0 +1,139 @@
+//! Ramped speed changes, for turning an axis continuously.
+//!
+//! A stepper that is told to change speed at once loses steps, or stalls.
+//! A [`Ramp`] moves the speed towards its target at a limited rate instead,
+//! so that the target can be changed at any time, even in the middle of an
+//! earlier change.
+
+/// A speed that follows its target at a limited rate of change.
+///
+/// Speeds are in milli-RPM, and the rate of change is in RPM per second
+/// (which is milli-RPM per millisecond).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ramp {
+    current: u32,
+    target: u32,
+    rate: u32,
+}
+impl Ramp {
+    /// Creates a stopped ramp.
+    pub const fn new() -> Self {
+        Self {
+            current: 0,
+            target: 0,
+            rate: 0,
+        }
+    }
+
+    /// Returns the current speed.
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Returns the target speed.
+    pub fn target(&self) -> u32 {
+        self.target
+    }
+
+    /// Returns `true` if the speed is zero, and will stay there.
+    pub fn is_stopped(&self) -> bool {
+        self.current == 0 && self.target == 0
+    }
+
+    /// Sets the target speed, and the rate at which to reach it. A `rate`
+    /// of zero changes speed at once.
+    pub fn set_target(&mut self, target: u32, rate: u32) {
+        self.target = target;
+        self.rate = rate;
+    }
+
+    /// Stops at once, without ramping down.
+    pub fn stop(&mut self) {
+        self.current = 0;
+        self.target = 0;
+    }
+
+    /// Moves the speed towards the target for the time since the last
+    /// update, and returns the new speed.
+    pub fn update(&mut self, elapsed_ms: u32) -> u32 {
+        let change = match self.rate {
+            0 => u32::MAX,
+            rate => rate.saturating_mul(elapsed_ms),
+        };
+        self.current = if self.current < self.target {
+            self.current.saturating_add(change).min(self.target)
+        } else {
+            self.current.saturating_sub(change).max(self.target)
+        };
+        self.current
+    }
+}
+impl Default for Ramp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the time between steps at a speed, in microseconds, or `None`
+/// if the speed is zero.
+///
+/// # Parameters
+///
+/// - `milli_rpm`: Speed, in milli-RPM.
+/// - `steps_per_rev`: Steps per revolution of the axis.
+pub fn step_period_us(milli_rpm: u32, steps_per_rev: u32) -> Option<u32> {
+    let steps_per_minute_x1000 = milli_rpm as u64 * steps_per_rev as u64;
+    if steps_per_minute_x1000 == 0 {
+        return None;
+    }
+    let period = 60_000_000_000 / steps_per_minute_x1000;
+    Some(period.min(u32::MAX as u64) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp_up_and_down() {
+        let mut ramp = Ramp::new();
+        assert!(ramp.is_stopped());
+        ramp.set_target(60_000, 30);
+        assert!(!ramp.is_stopped());
+        assert_eq!(30_000, ramp.update(1000));
+        assert_eq!(60_000, ramp.update(2000));
+        ramp.set_target(0, 30);
+        assert_eq!(57_000, ramp.update(100));
+        assert_eq!(0, ramp.update(10_000));
+        assert!(ramp.is_stopped());
+    }
+
+    #[test]
+    fn test_retarget_during_ramp() {
+        let mut ramp = Ramp::new();
+        ramp.set_target(60_000, 10);
+        assert_eq!(10_000, ramp.update(1000));
+        ramp.set_target(5_000, 10);
+        assert_eq!(5_000, ramp.update(1000));
+        assert_eq!(5_000, ramp.update(1000));
+        assert_eq!(5_000, ramp.target());
+    }
+
+    #[test]
+    fn test_zero_rate_is_immediate() {
+        let mut ramp = Ramp::new();
+        ramp.set_target(120_000, 0);
+        assert_eq!(120_000, ramp.update(0));
+        ramp.stop();
+        assert_eq!(0, ramp.current());
+    }
+
+    #[test]
+    fn test_step_period() {
+        assert_eq!(None, step_period_us(0, 6400));
+        // 60 RPM at 6400 steps per revolution is 6400 steps per second.
+        assert_eq!(Some(156), step_period_us(60_000, 6400));
+        assert_eq!(Some(9375), step_period_us(1_000, 6400));
+        assert_eq!(Some(u32::MAX), step_period_us(1, 1));
+    }
+}
@@ -0,0 +1,148 @@
+//! Ranges of axis positions.
+
+/// An inclusive range of axis positions, in steps (eg. the soft limits of an
+/// axis). A range always contains at least one position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepRange {
+    min: i32,
+    max: i32,
+}
+impl StepRange {
+    /// Number of bytes in the serialized form of a range.
+    pub const SERIALIZED_SZ: usize = 8;
+
+    /// Creates the range `min..=max`, or returns `None` if `min > max`.
+    pub const fn new(min: i32, max: i32) -> Option<Self> {
+        if min <= max {
+            Some(Self { min, max })
+        } else {
+            None
+        }
+    }
+
+    /// Creates the range of the first `count` positions from zero, ie.
+    /// `0..count`. A count of zero gives the range of position zero alone.
+    pub const fn from_count(count: u32) -> Self {
+        let max = if count > i32::MAX as u32 {
+            i32::MAX
+        } else {
+            count as i32
+        };
+        Self {
+            min: 0,
+            max: if max > 0 { max - 1 } else { 0 },
+        }
+    }
+
+    /// Lowest position in the range.
+    pub const fn min(&self) -> i32 {
+        self.min
+    }
+
+    /// Highest position in the range.
+    pub const fn max(&self) -> i32 {
+        self.max
+    }
+
+    /// Number of steps from one end of the range to the other.
+    pub const fn span(&self) -> u32 {
+        self.max.abs_diff(self.min)
+    }
+
+    /// Returns `true` if the range contains a position.
+    pub const fn contains(&self, pos: i32) -> bool {
+        self.min <= pos && pos <= self.max
+    }
+
+    /// Returns the position in the range that is closest to `pos`.
+    pub const fn clamp(&self, pos: i32) -> i32 {
+        if pos < self.min {
+            self.min
+        } else if pos > self.max {
+            self.max
+        } else {
+            pos
+        }
+    }
+
+    /// Serializes the range, as the little-endian bytes of its minimum and
+    /// then its maximum.
+    pub fn to_bytes(&self) -> [u8; Self::SERIALIZED_SZ] {
+        let mut bytes = [0; Self::SERIALIZED_SZ];
+        bytes[..4].copy_from_slice(&self.min.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.max.to_le_bytes());
+        bytes
+    }
+
+    /// Deserializes a range written by [`StepRange::to_bytes`].
+    ///
+    /// Returns `None` if the bytes do not hold a valid range. Note that
+    /// erased EEPROM (all `0xFF`) holds the valid range `-1..=-1`.
+    pub fn from_bytes(bytes: [u8; Self::SERIALIZED_SZ]) -> Option<Self> {
+        let [a, b, c, d, e, f, g, h] = bytes;
+        Self::new(
+            i32::from_le_bytes([a, b, c, d]),
+            i32::from_le_bytes([e, f, g, h]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        assert!(StepRange::new(-5, 5).is_some());
+        assert!(StepRange::new(3, 3).is_some());
+        assert_eq!(None, StepRange::new(4, 3));
+    }
+
+    #[test]
+    fn test_from_count() {
+        assert_eq!(StepRange::new(0, 99), Some(StepRange::from_count(100)));
+        assert_eq!(StepRange::new(0, 0), Some(StepRange::from_count(1)));
+        assert_eq!(StepRange::new(0, 0), Some(StepRange::from_count(0)));
+        assert_eq!(i32::MAX - 1, StepRange::from_count(u32::MAX).max());
+    }
+
+    #[test]
+    fn test_span() {
+        assert_eq!(99, StepRange::from_count(100).span());
+        assert_eq!(0, StepRange::from_count(0).span());
+        let full = StepRange::new(i32::MIN, i32::MAX).unwrap();
+        assert_eq!(u32::MAX, full.span());
+    }
+
+    #[test]
+    fn test_contains() {
+        let range = StepRange::new(-2, 3).unwrap();
+        assert!(!range.contains(-3));
+        assert!(range.contains(-2));
+        assert!(range.contains(3));
+        assert!(!range.contains(4));
+    }
+
+    #[test]
+    fn test_clamp() {
+        let range = StepRange::new(-2, 3).unwrap();
+        assert_eq!(-2, range.clamp(i32::MIN));
+        assert_eq!(-2, range.clamp(-3));
+        assert_eq!(0, range.clamp(0));
+        assert_eq!(3, range.clamp(4));
+        assert_eq!(3, range.clamp(i32::MAX));
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let range = StepRange::new(-70_000, 123_456).unwrap();
+        assert_eq!(Some(range), StepRange::from_bytes(range.to_bytes()));
+        let bytes = StepRange::new(1, 258).unwrap().to_bytes();
+        assert_eq!([1, 0, 0, 0, 2, 1, 0, 0], bytes);
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        assert_eq!(None, StepRange::from_bytes([1, 0, 0, 0, 0, 0, 0, 0]));
+    }
+}
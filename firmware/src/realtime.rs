@@ -0,0 +1,120 @@
+use arduino_hal::prelude::*;
+
+use winderbot_lib::{
+    line::{self, LineBuffer},
+    protocol::{is_emergency_stop, RealtimeCommand},
+};
+
+use crate::{
+    machine::{Axis, MoveObserver},
+    uno::SharedSerial,
+};
+
+/// Watches the UART for real-time commands while the machine is moving.
+///
+/// The host should wait for each command to be acknowledged before it
+/// sends the next one, so the only input expected during a move is a
+/// real-time command. A line that arrives anyway is held in the input
+/// buffer, for the controller to handle once the move has finished. Only
+/// one line is held: bytes after it are discarded. An `M112` line is not
+/// held, but stops the move at once, as the abort byte does.
+///
+/// A feed hold is kept by the controller, rather than here, so that it
+/// lasts over all of the moves of a line until a cycle start releases it.
+pub struct Realtime<'a, 's, const N: usize> {
+    serial: &'a SharedSerial<'s>,
+    input_buffer: &'a mut LineBuffer<N>,
+    feed_hold: &'a mut bool,
+    held: Option<Result<(), line::Error>>,
+    reset: bool,
+    a_stop: bool,
+    abort: bool,
+}
+impl<'a, 's, const N: usize> Realtime<'a, 's, N> {
+    pub fn new(
+        serial: &'a SharedSerial<'s>,
+        input_buffer: &'a mut LineBuffer<N>,
+        feed_hold: &'a mut bool,
+    ) -> Self {
+        Self {
+            serial,
+            input_buffer,
+            feed_hold,
+            held: None,
+            reset: false,
+            a_stop: false,
+            abort: false,
+        }
+    }
+
+    /// Return `true` if a soft reset was requested during the move.
+    pub fn reset_requested(&self) -> bool {
+        self.reset
+    }
+
+    /// Return `true` if an emergency stop was requested during the move.
+    pub fn abort_requested(&self) -> bool {
+        self.abort
+    }
+
+    /// Return `true` if A was stopped during the move.
+    pub fn a_stop_requested(&self) -> bool {
+        self.a_stop
+    }
+
+    /// Return how the line held during the move ended, or `None` if no
+    /// complete line arrived.
+    pub fn held_line(&self) -> Option<Result<(), line::Error>> {
+        self.held
+    }
+
+    /// Handle a byte from the UART, if one has arrived.
+    fn poll_serial(&mut self) {
+        let Ok(byte) = self.serial.borrow_mut().read() else {
+            return;
+        };
+        match RealtimeCommand::from_byte(byte) {
+            Some(RealtimeCommand::SoftReset) => self.reset = true,
+            Some(RealtimeCommand::FeedHold) => *self.feed_hold = true,
+            Some(RealtimeCommand::CycleStart) => *self.feed_hold = false,
+            Some(RealtimeCommand::SpindleStop) => self.a_stop = true,
+            Some(RealtimeCommand::Abort) => self.abort = true,
+            None if self.held.is_none() => {
+                self.held = self.input_buffer.push(byte);
+                if self.held == Some(Ok(()))
+                    && is_emergency_stop(self.input_buffer.line())
+                {
+                    self.input_buffer.clear();
+                    self.held = None;
+                    self.abort = true;
+                }
+            }
+            None => {}
+        }
+    }
+}
+impl<const N: usize> MoveObserver for Realtime<'_, '_, N> {
+    fn after_step(&mut self, _x_pos: u32, _a_pos: i64) {
+        self.poll_serial();
+    }
+
+    fn while_waiting(&mut self) {
+        self.poll_serial();
+    }
+
+    fn stop_requested(&self) -> bool {
+        self.reset || self.abort
+    }
+
+    fn hold_requested(&self) -> bool {
+        *self.feed_hold
+    }
+
+    fn axis_stop_requested(&self, axis: Axis) -> bool {
+        axis == Axis::A && self.a_stop
+    }
+
+    fn abort_requested(&self) -> bool {
+        self.abort
+    }
+}
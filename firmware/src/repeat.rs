@@ -0,0 +1,310 @@
+//! Repeated blocks of commands: `M808 L<count>` ... `M808`.
+//!
+//! The lines of a repeated block arrive from the host once, and are
+//! recorded by a [`Recorder`] until the block is complete. A [`Player`]
+//! then yields the lines of the block as many times as requested. Blocks
+//! may be nested, up to [`MAX_DEPTH`] deep; an outer block is recorded
+//! whole, with its inner blocks, and the inner blocks are expanded as it
+//! plays.
+
+use core::mem;
+
+use heapless::{String, Vec};
+use winnow::{combinator::eof, Parser};
+
+use crate::gcode::parse_digits;
+
+/// Maximum nesting depth of repeated blocks.
+pub const MAX_DEPTH: usize = 4;
+
+/// A repeat marker line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Marker {
+    /// `M808 L<count>`: start of a block that runs `count` times.
+    Start(u16),
+    /// `M808`: end of the innermost block.
+    End,
+}
+impl Marker {
+    /// Parse a repeat marker.
+    ///
+    /// # Returns
+    ///
+    /// - `None`: If the line is not an `M808` command.
+    /// - `Some(Ok(marker))`: If the line is a valid marker.
+    /// - `Some(Err(Error::Invalid))`: If the line is an `M808` command with
+    ///   invalid arguments. The count must be at least one.
+    pub fn parse(line: &str) -> Option<Result<Marker, Error>> {
+        let args = line.trim().strip_prefix("M808")?;
+        if !args.is_empty() && !args.starts_with(' ') {
+            return None;
+        }
+        let args = args.trim_start();
+        if args.is_empty() {
+            return Some(Ok(Marker::End));
+        }
+        let count = args
+            .strip_prefix('L')
+            .and_then(|count| (parse_digits, eof).parse(count).ok())
+            .map(|(count, _)| count)
+            .filter(|&count| count > 0);
+        Some(count.map(Marker::Start).ok_or(Error::Invalid))
+    }
+}
+
+/// Outcome of feeding a line to a [`Recorder`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Feed {
+    /// The line is not part of a repeated block, and should be run as
+    /// usual.
+    Pass,
+    /// The line was recorded.
+    Recorded,
+    /// The line completed a block, which is ready to be played.
+    Complete,
+}
+
+/// Records the lines of a repeated block as they arrive.
+pub struct Recorder<const N: usize> {
+    program: String<N>,
+    /// Nesting depth of the block being recorded, or zero if none is.
+    depth: u8,
+    /// Whether the block being recorded overflowed the buffer, and is
+    /// being discarded.
+    overflowed: bool,
+}
+impl<const N: usize> Recorder<N> {
+    pub const fn new() -> Self {
+        Self {
+            program: String::new(),
+            depth: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Returns `true` if a block is being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.depth > 0
+    }
+
+    /// Feed a line to the recorder.
+    ///
+    /// A block that does not fit in the buffer is discarded up to its end,
+    /// and reported once, when its end arrives.
+    pub fn feed(&mut self, line: &str) -> Result<Feed, Error> {
+        let marker = Marker::parse(line).transpose()?;
+        match marker {
+            None if self.depth == 0 => return Ok(Feed::Pass),
+            None => {}
+            Some(Marker::End) if self.depth == 0 => {
+                return Err(Error::UnmatchedEnd)
+            }
+            Some(Marker::End) => self.depth -= 1,
+            Some(Marker::Start(_)) if self.depth as usize == MAX_DEPTH => {
+                self.clear();
+                return Err(Error::TooDeep);
+            }
+            Some(Marker::Start(_)) => self.depth += 1,
+        }
+        if !self.overflowed
+            && (self.program.push_str(line).is_err()
+                || self.program.push('\n').is_err())
+        {
+            self.overflowed = true;
+            self.program.clear();
+        }
+        if self.depth > 0 {
+            Ok(Feed::Recorded)
+        } else if self.overflowed {
+            self.clear();
+            Err(Error::Overflow)
+        } else {
+            Ok(Feed::Complete)
+        }
+    }
+
+    /// Take the completed block, leaving the recorder empty.
+    pub fn take(&mut self) -> String<N> {
+        self.depth = 0;
+        self.overflowed = false;
+        mem::take(&mut self.program)
+    }
+
+    /// Discard any block being recorded.
+    pub fn clear(&mut self) {
+        self.program.clear();
+        self.depth = 0;
+        self.overflowed = false;
+    }
+}
+impl<const N: usize> Default for Recorder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Yields the lines of a recorded block, repeating blocks as many times as
+/// their markers say. The markers themselves are not yielded.
+pub struct Player<'a> {
+    program: &'a str,
+    /// Byte offset of the next line.
+    pos: usize,
+    /// Blocks being played, innermost last.
+    stack: Vec<Frame, MAX_DEPTH>,
+}
+impl<'a> Player<'a> {
+    pub fn new(program: &'a str) -> Self {
+        Self {
+            program,
+            pos: 0,
+            stack: Vec::new(),
+        }
+    }
+}
+impl<'a> Iterator for Player<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            let rest = &self.program[self.pos..];
+            if rest.is_empty() {
+                return None;
+            }
+            let (line, len) = match rest.find('\n') {
+                Some(end) => (&rest[..end], end + 1),
+                None => (rest, rest.len()),
+            };
+            self.pos += len;
+            match Marker::parse(line) {
+                Some(Ok(Marker::Start(count))) => {
+                    // The recorder has already limited the depth.
+                    let _ = self.stack.push(Frame {
+                        start: self.pos,
+                        remaining: count,
+                    });
+                }
+                Some(Ok(Marker::End)) => {
+                    if let Some(frame) = self.stack.last_mut() {
+                        frame.remaining -= 1;
+                        if frame.remaining > 0 {
+                            self.pos = frame.start;
+                        } else {
+                            self.stack.pop();
+                        }
+                    }
+                }
+                _ => return Some(line),
+            }
+        }
+    }
+}
+
+/// A block being played.
+struct Frame {
+    /// Byte offset of the first line of the block.
+    start: usize,
+    /// Number of times that the block still has to run, including the
+    /// current one.
+    remaining: u16,
+}
+
+/// Errors from recording a repeated block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// An `M808` command had invalid arguments.
+    Invalid,
+    /// An `M808` end marker had no block to end.
+    UnmatchedEnd,
+    /// Blocks were nested too deeply. The block being recorded was
+    /// discarded.
+    TooDeep,
+    /// A block did not fit in the buffer, and was discarded.
+    Overflow,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed lines to a recorder, returning the outcome of the last line.
+    fn record<const N: usize>(
+        recorder: &mut Recorder<N>,
+        lines: &[&str],
+    ) -> Result<Feed, Error> {
+        let mut feed = Ok(Feed::Pass);
+        for line in lines {
+            feed = recorder.feed(line);
+        }
+        feed
+    }
+
+    fn play(program: &str) -> Vec<&str, 16> {
+        Player::new(program).collect()
+    }
+
+    #[test]
+    fn test_parse_marker() {
+        assert_eq!(Some(Ok(Marker::End)), Marker::parse("M808"));
+        assert_eq!(Some(Ok(Marker::Start(3))), Marker::parse("M808 L3"));
+        assert_eq!(None, Marker::parse("G0 X1"));
+        assert_eq!(None, Marker::parse("M8080"));
+        assert_eq!(Some(Err(Error::Invalid)), Marker::parse("M808 L0"));
+        assert_eq!(Some(Err(Error::Invalid)), Marker::parse("M808 L"));
+        assert_eq!(Some(Err(Error::Invalid)), Marker::parse("M808 X1"));
+    }
+
+    #[test]
+    fn test_pass_outside_block() {
+        let mut recorder: Recorder<64> = Recorder::new();
+        assert_eq!(Ok(Feed::Pass), recorder.feed("G0 X1"));
+        assert_eq!(Err(Error::UnmatchedEnd), recorder.feed("M808"));
+    }
+
+    #[test]
+    fn test_repeat() {
+        let mut recorder: Recorder<64> = Recorder::new();
+        assert_eq!(Ok(Feed::Recorded), recorder.feed("M808 L3"));
+        assert!(recorder.is_recording());
+        assert_eq!(Ok(Feed::Recorded), recorder.feed("G0 X1"));
+        assert_eq!(Ok(Feed::Recorded), recorder.feed("G0 X0"));
+        assert_eq!(Ok(Feed::Complete), recorder.feed("M808"));
+        let program = recorder.take();
+        assert!(!recorder.is_recording());
+        assert_eq!(
+            ["G0 X1", "G0 X0", "G0 X1", "G0 X0", "G0 X1", "G0 X0"],
+            play(&program)[..]
+        );
+    }
+
+    #[test]
+    fn test_nested() {
+        let mut recorder: Recorder<128> = Recorder::new();
+        let lines = ["M808 L2", "A", "M808 L3", "B", "M808", "C", "M808"];
+        assert_eq!(Ok(Feed::Complete), record(&mut recorder, &lines));
+        assert_eq!(
+            ["A", "B", "B", "B", "C", "A", "B", "B", "B", "C"],
+            play(&recorder.take())[..]
+        );
+    }
+
+    #[test]
+    fn test_too_deep() {
+        let mut recorder: Recorder<128> = Recorder::new();
+        let lines = ["M808 L2"; MAX_DEPTH + 1];
+        assert_eq!(Err(Error::TooDeep), record(&mut recorder, &lines));
+        assert!(!recorder.is_recording());
+    }
+
+    #[test]
+    fn test_overflow_discards_block() {
+        let mut recorder: Recorder<24> = Recorder::new();
+        let lines = ["M808 L2", "G0 X100 A3600", "G0 X0 A0"];
+        assert_eq!(Ok(Feed::Recorded), record(&mut recorder, &lines));
+        assert_eq!(Err(Error::Overflow), recorder.feed("M808"));
+        assert!(!recorder.is_recording());
+        // Recording works again afterwards.
+        let lines = ["M808 L2", "G0 X1", "M808"];
+        assert_eq!(Ok(Feed::Complete), record(&mut recorder, &lines));
+        assert_eq!(["G0 X1", "G0 X1"], play(&recorder.take())[..]);
+    }
+}
@@ -0,0 +1,143 @@
+//! Angles of the rotary (A) axis, in steps.
+//!
+//! A turns without limit, so its position counts whole turns as well as
+//! the angle within a turn. These helpers work on the angle within a turn.
+//!
+//! A long job turns A far enough that its position no longer fits as a
+//! single angle: `i32` millidegrees overflow after about 5965 turns, and
+//! even an `i32` step count wraps after about 335,000 turns (a few days of
+//! winding). So the position is only ever kept as an `i64` count of
+//! steps, which is exact for any job, and it is reported as whole turns
+//! and the angle within a turn ([`Turns`]). Only the angle is converted to
+//! degrees, so the conversion cannot overflow, however many turns there
+//! are.
+
+/// A position of A, as whole turns and the angle within a turn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Turns {
+    /// Whole turns from zero. A position a little below zero is in turn
+    /// `-1`, so that the angle is never negative.
+    pub turns: i64,
+    /// Angle within the turn, from zero up to one turn.
+    pub angle: u32,
+}
+impl Turns {
+    /// Splits a position in steps into whole turns and the angle within a
+    /// turn, in steps.
+    pub fn from_steps(position: i64, steps_per_rev: u32) -> Self {
+        let steps_per_rev = steps_per_rev.max(1) as i64;
+        Self {
+            turns: position.div_euclid(steps_per_rev),
+            angle: position.rem_euclid(steps_per_rev) as u32,
+        }
+    }
+
+    /// Returns the same position with the angle converted by `f`, eg. from
+    /// steps to millidegrees.
+    pub fn map_angle(self, f: impl FnOnce(u32) -> u32) -> Self {
+        Self {
+            turns: self.turns,
+            angle: f(self.angle),
+        }
+    }
+}
+
+/// Returns the step position within a turn of one of `positions` evenly
+/// spaced angles, where index 0 is the zero angle.
+///
+/// The index is taken modulo the number of positions.
+pub fn index_steps(index: u16, positions: u16, steps_per_rev: u32) -> u32 {
+    let positions = positions.max(1) as u64;
+    let index = index as u64 % positions;
+    (index * steps_per_rev as u64 / positions) as u32
+}
+
+/// Returns the steps to turn by the shortest way from `position` to the
+/// angle `target` within a turn. Half a turn is taken forwards.
+///
+/// # Parameters
+///
+/// - `position`: Current position, in steps. Only its angle within a turn
+///   matters, so it may be any number of turns either side of zero.
+/// - `target`: Target angle within a turn, in steps.
+/// - `steps_per_rev`: Steps per turn.
+pub fn shortest_delta(position: i64, target: u32, steps_per_rev: u32) -> i32 {
+    let angle = position.rem_euclid(steps_per_rev as i64) as u32;
+    let forward =
+        (target % steps_per_rev + steps_per_rev - angle) % steps_per_rev;
+    if forward <= steps_per_rev / 2 {
+        forward as i32
+    } else {
+        forward as i32 - steps_per_rev as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turns() {
+        assert_eq!(Turns { turns: 0, angle: 0 }, Turns::from_steps(0, 6400));
+        assert_eq!(
+            Turns {
+                turns: 2,
+                angle: 100
+            },
+            Turns::from_steps(12_900, 6400)
+        );
+        assert_eq!(
+            Turns {
+                turns: -1,
+                angle: 6300
+            },
+            Turns::from_steps(-100, 6400)
+        );
+    }
+
+    #[test]
+    fn test_turns_of_a_long_job() {
+        // Far more turns than fit as i32 millidegrees.
+        let turns = Turns::from_steps(1_000_000 * 6400 + 3200, 6400);
+        assert_eq!(1_000_000, turns.turns);
+        let degrees = turns.map_angle(|angle| angle * 360_000 / 6400);
+        assert_eq!(180_000, degrees.angle);
+    }
+
+    #[test]
+    fn test_index_steps() {
+        assert_eq!(0, index_steps(0, 4, 6400));
+        assert_eq!(1600, index_steps(1, 4, 6400));
+        assert_eq!(4800, index_steps(3, 4, 6400));
+        assert_eq!(0, index_steps(4, 4, 6400));
+        assert_eq!(2133, index_steps(1, 3, 6400));
+        assert_eq!(0, index_steps(5, 0, 6400));
+    }
+
+    #[test]
+    fn test_shortest_delta() {
+        assert_eq!(1600, shortest_delta(0, 1600, 6400));
+        assert_eq!(-1600, shortest_delta(0, 4800, 6400));
+        assert_eq!(3200, shortest_delta(0, 3200, 6400));
+        assert_eq!(3199, shortest_delta(3201, 0, 6400));
+        assert_eq!(-3199, shortest_delta(3199, 0, 6400));
+        assert_eq!(0, shortest_delta(12_800, 0, 6400));
+        assert_eq!(-100, shortest_delta(6500, 0, 6400));
+    }
+
+    #[test]
+    fn test_shortest_delta_from_negative_positions() {
+        assert_eq!(100, shortest_delta(-100, 0, 6400));
+        assert_eq!(-1500, shortest_delta(-12_900, 4800, 6400));
+        assert_eq!(1, shortest_delta(6399, 0, 6400));
+    }
+
+    #[test]
+    fn test_shortest_delta_after_many_turns() {
+        // Past where an `i32` step count would wrap.
+        let turns = 1_000_000_i64;
+        assert_eq!(100, shortest_delta(turns * 6400 - 100, 0, 6400));
+        assert_eq!(-100, shortest_delta(-turns * 6400 + 100, 0, 6400));
+        assert_eq!(1600, shortest_delta(turns * 6400, 1600, 6400));
+    }
+}
@@ -0,0 +1,148 @@
+//! S-curve (jerk-limited) speed profiles for moves.
+//!
+//! A [`Trapezoid`](crate::trapezoid::Trapezoid) changes its acceleration
+//! at once at the ends of its ramps, which can snap fine wire. An
+//! [`SCurve`] eases into and out of each ramp instead: its speed follows a
+//! smoothstep curve over the ramp's steps, so that the acceleration rises
+//! from zero and falls back to zero. Its ramps are twice as long as a
+//! trapezoid's for the same peak acceleration.
+
+use crate::trapezoid::start_delay_us;
+
+/// Number of fractional bits in ramp positions.
+const POS_BITS: u32 = 16;
+
+/// Yields the delay after each step of a move, in microseconds.
+pub struct SCurve {
+    /// Number of steps in the move.
+    steps: u32,
+    /// Number of steps taken.
+    taken: u32,
+    /// Delay at cruise rate, in microseconds.
+    cruise_delay_us: u32,
+    /// Speeds at the start and the end of a ramp, in steps per second with
+    /// 8 fractional bits.
+    start_speed: u64,
+    cruise_speed: u64,
+    /// Number of steps of a full ramp.
+    ramp_steps: u32,
+}
+impl SCurve {
+    /// Creates a profile.
+    ///
+    /// # Parameters
+    ///
+    /// - `steps`: Number of steps in the move.
+    /// - `cruise_delay_us`: Delay after each step at the cruise rate, in
+    ///   microseconds.
+    /// - `accel`: Peak acceleration, in steps per second squared. Zero
+    ///   turns the ramps off, so that every step is at the cruise rate.
+    pub fn new(steps: u32, cruise_delay_us: u32, accel: u32) -> Self {
+        let cruise_delay_us = cruise_delay_us.max(1);
+        let start_delay_us = match accel {
+            0 => cruise_delay_us as u64,
+            accel => start_delay_us(accel).max(cruise_delay_us as u64),
+        };
+        let cruise_speed = 256_000_000 / cruise_delay_us as u64;
+        let start_speed = 256_000_000 / start_delay_us.max(1);
+        // The peak acceleration of a smoothstep ramp in distance is very
+        // nearly speed^2 / length.
+        let ramp_steps = match accel {
+            0 => 0,
+            accel => {
+                let speed = 1_000_000 / cruise_delay_us as u64;
+                (speed * speed / accel as u64).min(u32::MAX as u64) as u32
+            }
+        };
+        Self {
+            steps,
+            taken: 0,
+            cruise_delay_us,
+            start_speed,
+            cruise_speed,
+            ramp_steps,
+        }
+    }
+}
+impl Iterator for SCurve {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.taken == self.steps {
+            return None;
+        }
+        // Steps from the nearer end of the move.
+        let from_end = self.taken.min(self.steps - self.taken - 1);
+        self.taken += 1;
+        if from_end >= self.ramp_steps {
+            return Some(self.cruise_delay_us);
+        }
+        let x = ((from_end as u64) << POS_BITS) / self.ramp_steps as u64;
+        let x2 = (x * x) >> POS_BITS;
+        let x3 = (x2 * x) >> POS_BITS;
+        let smooth = 3 * x2 - 2 * x3;
+        let speed = self.start_speed
+            + (((self.cruise_speed - self.start_speed) * smooth) >> POS_BITS);
+        let delay_us = 256_000_000 / speed.max(1);
+        Some((delay_us as u32).max(self.cruise_delay_us))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::trapezoid::Trapezoid;
+
+    #[test]
+    fn test_no_acceleration() {
+        let delays: Vec<u32, 3> = SCurve::new(3, 100, 0).collect();
+        assert_eq!([100, 100, 100], delays.as_slice());
+    }
+
+    #[test]
+    fn test_starts_like_a_trapezoid() {
+        assert_eq!(
+            Trapezoid::new(10, 100, 10_000).next(),
+            SCurve::new(10, 100, 10_000).next()
+        );
+    }
+
+    #[test]
+    fn test_s_curve() {
+        let delays: Vec<u32, 3000> = SCurve::new(3000, 100, 100_000).collect();
+        assert_eq!(3000, delays.len());
+        // 10_000 steps/s at 100_000 steps/s^2 ramps over 1000 steps, the
+        // last of which round to the cruise delay.
+        let ramp = delays.iter().take_while(|d| **d > 100).count();
+        assert!((900..=1000).contains(&ramp), "{}", ramp);
+        assert!(delays[..ramp].windows(2).all(|d| d[0] >= d[1]));
+        for (up, down) in delays.iter().zip(delays.iter().rev()) {
+            assert_eq!(up, down);
+        }
+        assert_eq!(100, delays[1500]);
+    }
+
+    #[test]
+    fn test_acceleration_eases_in_and_out() {
+        let delays: Vec<u32, 3000> = SCurve::new(3000, 100, 100_000).collect();
+        let speed = |i: usize| 1_000_000 / delays[i];
+        // Speed gained over 50 steps near the ends of the ramp, and in its
+        // middle.
+        let start = speed(100) - speed(50);
+        let middle = speed(525) - speed(475);
+        let end = speed(975) - speed(925);
+        assert!(start * 3 < middle, "{} {}", start, middle);
+        assert!(end * 3 < middle, "{} {}", end, middle);
+    }
+
+    #[test]
+    fn test_short_move() {
+        // A full ramp would take 10 steps.
+        let delays: Vec<u32, 11> = SCurve::new(11, 100, 10_000_000).collect();
+        assert_eq!(delays[4], delays[6]);
+        assert!(delays[5] < delays[4]);
+        assert!(delays[5] > 100);
+    }
+}
@@ -0,0 +1,152 @@
+//! Reading lines from a serial port.
+
+use crate::{
+    line::{self, LineBuffer},
+    protocol::{is_realtime, ABORT, SOFT_RESET},
+};
+
+/// A serial port that bytes can be read from.
+///
+/// The firmware implements this for the UART; tests implement it with
+/// canned input.
+pub trait SerialIo {
+    /// Returns the next received byte, or `None` if no byte is waiting.
+    fn try_read(&mut self) -> Option<u8>;
+}
+
+/// Reads a line from a serial port into `buffer`, blocking until the line
+/// is complete.
+///
+/// Reading stops early, discarding the partial line, if a soft reset or an
+/// abort byte is received. Other real-time bytes (eg. a feed hold, with no
+/// move to hold) are dropped.
+pub fn readln<S: SerialIo, const N: usize>(
+    serial: &mut S,
+    buffer: &mut LineBuffer<N>,
+) -> Result<(), Error> {
+    loop {
+        let Some(byte) = serial.try_read() else {
+            continue;
+        };
+        if byte == SOFT_RESET {
+            buffer.clear();
+            return Err(Error::SoftReset);
+        }
+        if byte == ABORT {
+            buffer.clear();
+            return Err(Error::Abort);
+        }
+        if is_realtime(byte) {
+            continue;
+        }
+        match buffer.push(byte) {
+            None => {}
+            Some(Ok(())) => return Ok(()),
+            Some(Err(line::Error::Overflow)) => {
+                return Err(Error::BufferOverflow)
+            }
+        }
+    }
+}
+
+/// Errors that might occur when reading.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The line was too long for the buffer, and was discarded.
+    BufferOverflow,
+    /// A soft reset was requested.
+    SoftReset,
+    /// An emergency stop was requested.
+    Abort,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serial port that returns canned input, with no byte waiting between
+    /// each received byte.
+    struct MockSerial<'a> {
+        input: &'a [u8],
+        waiting: bool,
+    }
+    impl<'a> MockSerial<'a> {
+        fn new(input: &'a [u8]) -> Self {
+            Self {
+                input,
+                waiting: false,
+            }
+        }
+    }
+    impl SerialIo for MockSerial<'_> {
+        fn try_read(&mut self) -> Option<u8> {
+            self.waiting = !self.waiting;
+            if self.waiting {
+                return None;
+            }
+            let (byte, rest) = self.input.split_first().expect("out of input");
+            self.input = rest;
+            Some(*byte)
+        }
+    }
+
+    #[test]
+    fn test_reads_lines() {
+        let mut serial = MockSerial::new(b"G90\r\nG0 X1\rG4 P1\n");
+        let mut buffer: LineBuffer<16> = LineBuffer::new();
+        for expected in ["G90", "G0 X1", "G4 P1"] {
+            assert_eq!(Ok(()), readln(&mut serial, &mut buffer));
+            assert_eq!(expected, buffer.take_line().as_str());
+        }
+        assert!(serial.input.is_empty());
+    }
+
+    #[test]
+    fn test_overflow_then_resynchronizes() {
+        let mut serial = MockSerial::new(b"G0 X123456\r\nG4\r\n");
+        let mut buffer: LineBuffer<4> = LineBuffer::new();
+        assert_eq!(
+            Err(Error::BufferOverflow),
+            readln(&mut serial, &mut buffer)
+        );
+        assert_eq!(Ok(()), readln(&mut serial, &mut buffer));
+        assert_eq!("G4", buffer.take_line().as_str());
+        assert_eq!(1, buffer.dropped_lines());
+    }
+
+    #[test]
+    fn test_soft_reset_discards_partial_line() {
+        let mut serial = MockSerial::new(b"G0 X\x18G4\n");
+        let mut buffer: LineBuffer<16> = LineBuffer::new();
+        assert_eq!(Err(Error::SoftReset), readln(&mut serial, &mut buffer));
+        assert_eq!(Ok(()), readln(&mut serial, &mut buffer));
+        assert_eq!("G4", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_abort_discards_partial_line() {
+        let mut serial = MockSerial::new(b"G0 X\x9fG4\n");
+        let mut buffer: LineBuffer<16> = LineBuffer::new();
+        assert_eq!(Err(Error::Abort), readln(&mut serial, &mut buffer));
+        assert_eq!(Ok(()), readln(&mut serial, &mut buffer));
+        assert_eq!("G4", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_drops_realtime_bytes() {
+        let mut serial = MockSerial::new(b"G0~ X1!\n");
+        let mut buffer: LineBuffer<16> = LineBuffer::new();
+        assert_eq!(Ok(()), readln(&mut serial, &mut buffer));
+        assert_eq!("G0 X1", buffer.take_line().as_str());
+    }
+
+    #[test]
+    fn test_soft_reset_ends_overflow() {
+        let mut serial = MockSerial::new(b"G0 X123456\x18G4\n");
+        let mut buffer: LineBuffer<4> = LineBuffer::new();
+        assert_eq!(Err(Error::SoftReset), readln(&mut serial, &mut buffer));
+        assert_eq!(Ok(()), readln(&mut serial, &mut buffer));
+        assert_eq!("G4", buffer.take_line().as_str());
+        assert_eq!(0, buffer.dropped_lines());
+    }
+}
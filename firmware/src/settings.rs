@@ -0,0 +1,817 @@
+//! User-adjustable machine settings.
+//!
+//! Settings are addressed by number, Grbl-style: `$$` lists them and
+//! `$<id>=<value>` changes one. All values are unsigned integers in the unit
+//! given by their description.
+
+#[cfg(feature = "tension")]
+use winderbot_lib::pid::Gains;
+#[cfg(feature = "analog-limits")]
+use winderbot_lib::threshold::Threshold;
+use winderbot_lib::{executor::Trim, protocol::settings_checksum};
+
+/// User-adjustable machine settings.
+pub struct Settings {
+    /// Pause before X starts moving in the opposite direction to its
+    /// previous move, in milliseconds.
+    pub x_reversal_dwell_ms: u16,
+    /// X position of the bobbin's left flange in machine coordinates, in
+    /// microns. This is the origin of bobbin coordinates.
+    pub bobbin_flange_x_microns: u32,
+    /// A angle of the origin of bobbin coordinates, in machine
+    /// millidegrees. This is an angle within a turn, so that moving it
+    /// keeps the count of whole turns.
+    pub bobbin_origin_a_millidegrees: u32,
+    /// Travel of X between its limit switches, in microns, for machines
+    /// with only a left limit switch. Zero means that there are two
+    /// switches, and the travel is measured when zeroing.
+    pub x_max_travel_microns: u32,
+    /// Whether X moves beyond the soft limits are clipped to them (with a
+    /// warning), rather than rejected.
+    pub x_clip_moves: bool,
+    /// Whether the left and right limit switches are read from each
+    /// other's inputs, to make up for crossed wiring.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    pub x_limit_switches_swapped: bool,
+    /// Whether zeroing swaps the limit switches when it finds them crossed,
+    /// rather than failing.
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    pub x_limit_switches_auto_swap: bool,
+    /// Units of positions in status reports.
+    pub report_units: ReportUnits,
+    /// Whether unknown G-code words and commands, and words left over after
+    /// a command's arguments, are skipped with a warning, rather than
+    /// rejected.
+    pub permissive_gcode: bool,
+    /// Whether lines starting with `/` are skipped.
+    pub block_delete: bool,
+    /// Baud rate of the serial port. Unlike the other settings, this is
+    /// stored in EEPROM, and it only takes effect when the board restarts.
+    pub baud_rate: BaudRate,
+    /// Whether `ok` responses report how long the line took to execute.
+    pub report_command_time: bool,
+    /// Whether lines end with CR LF, rather than LF alone.
+    pub crlf_line_endings: bool,
+    /// Whether log messages, and the echo of startup lines, are left out,
+    /// for senders that expect a command's response to be its first line.
+    pub quiet: bool,
+    /// Maximum step rate of X, in steps per second.
+    pub x_max_step_rate: u16,
+    /// Maximum step rate of A, in steps per second.
+    pub a_max_step_rate: u16,
+    /// Acceleration of A in spindle mode (`M3`), in RPM per second. Zero
+    /// changes speed at once.
+    pub a_spindle_accel: u16,
+    /// Time that a move may take before it is reported as slow, as a
+    /// percentage of its planned time. Zero turns the check off.
+    pub slow_move_percent: u16,
+    /// Number of evenly spaced A angles that `M19` turns to.
+    pub a_index_positions: u16,
+    /// Step rate of A when it turns to an index angle, in steps per second.
+    pub a_index_step_rate: u16,
+    /// Whether positioning is relative (`G91`) rather than absolute
+    /// (`G90`) after zeroing.
+    pub default_relative: bool,
+    /// Whether X words are in inches (`G20`) rather than millimetres
+    /// (`G21`) at startup and after a soft reset.
+    pub default_inches: bool,
+    /// Acceleration of X at the start and end of moves, in steps per
+    /// second squared. Zero starts and stops at full rate.
+    pub x_accel: u32,
+    /// Acceleration of A at the start and end of moves, in steps per
+    /// second squared. Zero starts and stops at full rate.
+    pub a_accel: u32,
+    /// Shape of the X ramps at the start and end of moves.
+    pub x_profile: Profile,
+    /// Shape of the A ramps at the start and end of moves.
+    pub a_profile: Profile,
+    /// Whether the moves of repeated blocks and macros run on from one to
+    /// the next with lookahead, rather than each stopping at its end.
+    pub lookahead: bool,
+    /// Extra delay after X steps to the left, in tenths of a percent.
+    pub x_left_step_trim: u16,
+    /// Extra delay after X steps to the right, in tenths of a percent.
+    pub x_right_step_trim: u16,
+    /// Extra delay after negative A steps, in tenths of a percent.
+    pub a_neg_step_trim: u16,
+    /// Extra delay after positive A steps, in tenths of a percent.
+    pub a_pos_step_trim: u16,
+    /// Whether X parks at the end of a job.
+    pub end_park_x: bool,
+    /// X position to park at the end of a job, in machine coordinates, in
+    /// microns.
+    pub end_park_x_microns: u32,
+    /// Whether A turns to an index angle at the end of a job.
+    pub end_index_a: bool,
+    /// Index angle that A turns to at the end of a job, as for `M19`.
+    pub end_a_index: u16,
+    /// Whether the A driver is released at the end of a job, until the
+    /// next move.
+    #[cfg(feature = "a-enable")]
+    pub end_release_a: bool,
+    /// Length of the pulse of the job done output at the end of a job, in
+    /// milliseconds. Zero gives no pulse.
+    #[cfg(feature = "done-out")]
+    pub end_pulse_ms: u16,
+    /// Tension setpoint, in ADC counts.
+    #[cfg(feature = "tension")]
+    pub tension_setpoint: u16,
+    /// Tension PID proportional gain, in thousandths.
+    #[cfg(feature = "tension")]
+    pub tension_kp: u16,
+    /// Tension PID integral gain, in thousandths.
+    #[cfg(feature = "tension")]
+    pub tension_ki: u16,
+    /// Tension PID derivative gain, in thousandths.
+    #[cfg(feature = "tension")]
+    pub tension_kd: u16,
+    /// Number of steps between updates of the tension control loop.
+    #[cfg(feature = "tension")]
+    pub tension_period_steps: u16,
+    /// Diameter of the coil being wound, in tenths of a millimetre.
+    #[cfg(feature = "payout")]
+    pub coil_diameter: u16,
+    /// Diameter of the wire on the supply spool, in tenths of a millimetre.
+    #[cfg(feature = "payout")]
+    pub supply_spool_diameter: u16,
+    /// Steps per revolution of the payout motor.
+    #[cfg(feature = "payout")]
+    pub payout_steps_per_rev: u16,
+    /// Whether the door interlock is active. Turning this off overrides the
+    /// interlock.
+    #[cfg(feature = "door")]
+    pub door_interlock: bool,
+    /// Delay between steps for the slow pass of A homing, in microseconds.
+    #[cfg(feature = "a-index")]
+    pub a_home_slow_delay_us: u16,
+    /// Whether the second spindle turns opposite to the first.
+    #[cfg(feature = "spindle2")]
+    pub spindle2_mirrored: bool,
+    /// X position sensor reading at or below which X is at its left end of
+    /// travel, in ADC counts.
+    #[cfg(feature = "analog-limits")]
+    pub x_left_end_counts: u16,
+    /// X position sensor reading at or above which X is at its right end of
+    /// travel, in ADC counts.
+    #[cfg(feature = "analog-limits")]
+    pub x_right_end_counts: u16,
+    /// Distance that the X position sensor reading must move back from an
+    /// end of travel before X is taken to have left it, in ADC counts.
+    #[cfg(feature = "analog-limits")]
+    pub x_end_hysteresis_counts: u16,
+    /// Whether positions are captured on a rising edge of the capture
+    /// input, rather than a falling one.
+    #[cfg(feature = "capture")]
+    pub capture_rising_edge: bool,
+}
+impl Settings {
+    /// Returns the default settings.
+    pub const fn default() -> Self {
+        Self {
+            x_reversal_dwell_ms: 0,
+            bobbin_flange_x_microns: 0,
+            bobbin_origin_a_millidegrees: 0,
+            x_max_travel_microns: 0,
+            x_clip_moves: true,
+            #[cfg(not(any(
+                feature = "shared-limits",
+                feature = "analog-limits"
+            )))]
+            x_limit_switches_swapped: false,
+            #[cfg(not(any(
+                feature = "shared-limits",
+                feature = "analog-limits"
+            )))]
+            x_limit_switches_auto_swap: false,
+            report_units: ReportUnits::Millimetres,
+            permissive_gcode: false,
+            block_delete: false,
+            baud_rate: BaudRate::DEFAULT,
+            report_command_time: false,
+            crlf_line_endings: false,
+            quiet: false,
+            x_max_step_rate: 10_000,
+            a_max_step_rate: 10_000,
+            a_spindle_accel: 60,
+            slow_move_percent: 200,
+            a_index_positions: 4,
+            a_index_step_rate: 800,
+            default_relative: false,
+            default_inches: false,
+            x_accel: 0,
+            a_accel: 0,
+            x_profile: Profile::Trapezoid,
+            a_profile: Profile::Trapezoid,
+            lookahead: true,
+            x_left_step_trim: 0,
+            x_right_step_trim: 0,
+            a_neg_step_trim: 0,
+            a_pos_step_trim: 0,
+            end_park_x: false,
+            end_park_x_microns: 0,
+            end_index_a: false,
+            end_a_index: 0,
+            #[cfg(feature = "a-enable")]
+            end_release_a: false,
+            #[cfg(feature = "done-out")]
+            end_pulse_ms: 500,
+            #[cfg(feature = "tension")]
+            tension_setpoint: 512,
+            #[cfg(feature = "tension")]
+            tension_kp: 0,
+            #[cfg(feature = "tension")]
+            tension_ki: 0,
+            #[cfg(feature = "tension")]
+            tension_kd: 0,
+            #[cfg(feature = "tension")]
+            tension_period_steps: 100,
+            #[cfg(feature = "payout")]
+            coil_diameter: 0,
+            #[cfg(feature = "payout")]
+            supply_spool_diameter: 1000,
+            #[cfg(feature = "payout")]
+            payout_steps_per_rev: 6400,
+            #[cfg(feature = "door")]
+            door_interlock: true,
+            #[cfg(feature = "a-index")]
+            a_home_slow_delay_us: 2000,
+            #[cfg(feature = "spindle2")]
+            spindle2_mirrored: false,
+            #[cfg(feature = "analog-limits")]
+            x_left_end_counts: 50,
+            #[cfg(feature = "analog-limits")]
+            x_right_end_counts: 973,
+            #[cfg(feature = "analog-limits")]
+            x_end_hysteresis_counts: 10,
+            #[cfg(feature = "capture")]
+            capture_rising_edge: false,
+        }
+    }
+
+    /// Returns the tension PID gains.
+    #[cfg(feature = "tension")]
+    pub fn tension_gains(&self) -> Gains {
+        Gains {
+            kp: self.tension_kp as i32,
+            ki: self.tension_ki as i32,
+            kd: self.tension_kd as i32,
+        }
+    }
+
+    /// Returns the left and right ends of X travel, for analog
+    /// end-of-travel sensing.
+    #[cfg(feature = "analog-limits")]
+    pub fn analog_limits(&self) -> (Threshold, Threshold) {
+        let hysteresis = self.x_end_hysteresis_counts;
+        (
+            Threshold::below(self.x_left_end_counts, hysteresis),
+            Threshold::above(self.x_right_end_counts, hysteresis),
+        )
+    }
+
+    /// Returns the trims of the delays after X steps in each direction.
+    pub fn x_step_trim(&self) -> Trim {
+        Trim {
+            neg: self.x_left_step_trim,
+            pos: self.x_right_step_trim,
+        }
+    }
+
+    /// Returns the trims of the delays after A steps in each direction.
+    pub fn a_step_trim(&self) -> Trim {
+        Trim {
+            neg: self.a_neg_step_trim,
+            pos: self.a_pos_step_trim,
+        }
+    }
+
+    /// Returns the characters that end each line written to the UART.
+    pub fn line_ending(&self) -> &'static str {
+        if self.crlf_line_endings {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+
+    /// Returns the checksum of all settings, as reported by `$I`.
+    pub fn checksum(&self) -> u16 {
+        settings_checksum(
+            SETTINGS
+                .iter()
+                .map(|setting| (setting.id, (setting.get)(self))),
+        )
+    }
+
+    /// Set the value of a setting by number.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())`: If the setting was changed.
+    /// - `Err(Error)`: If the setting does not exist, or the value is out of
+    ///   its range.
+    pub fn set(&mut self, id: u8, value: u32) -> Result<(), Error> {
+        let setting = Setting::find(id).ok_or(Error::UnknownSetting)?;
+        if value > setting.max {
+            return Err(Error::ValueOutOfRange);
+        }
+        (setting.set)(self, value);
+        Ok(())
+    }
+}
+
+/// Description of a single numbered setting.
+pub struct Setting {
+    /// Number of the setting (`$<id>`).
+    pub id: u8,
+    /// Human-readable description, including the unit.
+    pub description: &'static str,
+    /// Largest allowed value.
+    pub max: u32,
+    /// Reads the setting.
+    pub get: fn(&Settings) -> u32,
+    /// Writes the setting. The value has already been range-checked.
+    set: fn(&mut Settings, u32),
+}
+impl Setting {
+    /// Find a setting by number.
+    pub fn find(id: u8) -> Option<&'static Setting> {
+        SETTINGS.iter().find(|setting| setting.id == id)
+    }
+}
+
+/// All numbered settings.
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        id: 1,
+        description: "X reversal dwell, ms",
+        max: u16::MAX as u32,
+        get: |s| s.x_reversal_dwell_ms as u32,
+        set: |s, v| s.x_reversal_dwell_ms = v as u16,
+    },
+    Setting {
+        id: 2,
+        description: "Bobbin left flange X, microns",
+        max: 1_000_000,
+        get: |s| s.bobbin_flange_x_microns,
+        set: |s, v| s.bobbin_flange_x_microns = v,
+    },
+    Setting {
+        id: 3,
+        description: "X maximum step rate, steps/s",
+        max: u16::MAX as u32,
+        get: |s| s.x_max_step_rate as u32,
+        set: |s, v| s.x_max_step_rate = (v as u16).max(1),
+    },
+    Setting {
+        id: 4,
+        description: "A maximum step rate, steps/s",
+        max: u16::MAX as u32,
+        get: |s| s.a_max_step_rate as u32,
+        set: |s, v| s.a_max_step_rate = (v as u16).max(1),
+    },
+    Setting {
+        id: 5,
+        description: "X maximum travel, microns (0 = measure at zero)",
+        max: 1_000_000,
+        get: |s| s.x_max_travel_microns,
+        set: |s, v| s.x_max_travel_microns = v,
+    },
+    Setting {
+        id: 6,
+        description: "X moves beyond soft limits (0 = reject, 1 = clip)",
+        max: 1,
+        get: |s| s.x_clip_moves as u32,
+        set: |s, v| s.x_clip_moves = v != 0,
+    },
+    Setting {
+        id: 7,
+        description: "Report units (0 = mm, 1 = steps, 2 = inches)",
+        max: 2,
+        get: |s| s.report_units as u32,
+        set: |s, v| {
+            s.report_units = match v {
+                0 => ReportUnits::Millimetres,
+                1 => ReportUnits::Steps,
+                _ => ReportUnits::Inches,
+            }
+        },
+    },
+    Setting {
+        id: 8,
+        description: "G-code parsing (0 = strict, 1 = permissive)",
+        max: 1,
+        get: |s| s.permissive_gcode as u32,
+        set: |s, v| s.permissive_gcode = v != 0,
+    },
+    Setting {
+        id: 9,
+        description: "Block delete (0 = run / lines, 1 = skip them)",
+        max: 1,
+        get: |s| s.block_delete as u32,
+        set: |s, v| s.block_delete = v != 0,
+    },
+    #[cfg(feature = "tension")]
+    Setting {
+        id: 10,
+        description: "Tension setpoint, ADC counts",
+        max: 1023,
+        get: |s| s.tension_setpoint as u32,
+        set: |s, v| s.tension_setpoint = v as u16,
+    },
+    #[cfg(feature = "tension")]
+    Setting {
+        id: 11,
+        description: "Tension Kp, thousandths",
+        max: u16::MAX as u32,
+        get: |s| s.tension_kp as u32,
+        set: |s, v| s.tension_kp = v as u16,
+    },
+    #[cfg(feature = "tension")]
+    Setting {
+        id: 12,
+        description: "Tension Ki, thousandths",
+        max: u16::MAX as u32,
+        get: |s| s.tension_ki as u32,
+        set: |s, v| s.tension_ki = v as u16,
+    },
+    #[cfg(feature = "tension")]
+    Setting {
+        id: 13,
+        description: "Tension Kd, thousandths",
+        max: u16::MAX as u32,
+        get: |s| s.tension_kd as u32,
+        set: |s, v| s.tension_kd = v as u16,
+    },
+    #[cfg(feature = "tension")]
+    Setting {
+        id: 14,
+        description: "Tension update period, steps",
+        max: u16::MAX as u32,
+        get: |s| s.tension_period_steps as u32,
+        set: |s, v| s.tension_period_steps = (v as u16).max(1),
+    },
+    Setting {
+        id: 15,
+        description: "A spindle acceleration, RPM/s (0 = immediate)",
+        max: u16::MAX as u32,
+        get: |s| s.a_spindle_accel as u32,
+        set: |s, v| s.a_spindle_accel = v as u16,
+    },
+    Setting {
+        id: 16,
+        description: "Slow move warning, % of planned time (0 = off)",
+        max: u16::MAX as u32,
+        get: |s| s.slow_move_percent as u32,
+        set: |s, v| s.slow_move_percent = v as u16,
+    },
+    Setting {
+        id: 17,
+        description: "A index positions per turn",
+        max: u16::MAX as u32,
+        get: |s| s.a_index_positions as u32,
+        set: |s, v| s.a_index_positions = (v as u16).max(1),
+    },
+    Setting {
+        id: 18,
+        description: "A indexing step rate, steps/s",
+        max: u16::MAX as u32,
+        get: |s| s.a_index_step_rate as u32,
+        set: |s, v| s.a_index_step_rate = (v as u16).max(1),
+    },
+    Setting {
+        id: 19,
+        description: "Default positioning (0 = G90, 1 = G91)",
+        max: 1,
+        get: |s| s.default_relative as u32,
+        set: |s, v| s.default_relative = v != 0,
+    },
+    #[cfg(feature = "payout")]
+    Setting {
+        id: 20,
+        description: "Coil diameter, 0.1 mm (0 disables payout)",
+        max: 10_000,
+        get: |s| s.coil_diameter as u32,
+        set: |s, v| s.coil_diameter = v as u16,
+    },
+    #[cfg(feature = "payout")]
+    Setting {
+        id: 21,
+        description: "Supply spool diameter, 0.1 mm",
+        max: 10_000,
+        get: |s| s.supply_spool_diameter as u32,
+        set: |s, v| s.supply_spool_diameter = (v as u16).max(1),
+    },
+    #[cfg(feature = "payout")]
+    Setting {
+        id: 22,
+        description: "Payout motor steps per revolution",
+        max: 6400,
+        get: |s| s.payout_steps_per_rev as u32,
+        set: |s, v| s.payout_steps_per_rev = v as u16,
+    },
+    Setting {
+        id: 23,
+        description: "Default X units (0 = G21 mm, 1 = G20 inches)",
+        max: 1,
+        get: |s| s.default_inches as u32,
+        set: |s, v| s.default_inches = v != 0,
+    },
+    Setting {
+        id: 24,
+        description: "X acceleration, steps/s^2 (0 = off)",
+        max: 1_000_000,
+        get: |s| s.x_accel,
+        set: |s, v| s.x_accel = v,
+    },
+    Setting {
+        id: 25,
+        description: "A acceleration, steps/s^2 (0 = off)",
+        max: 1_000_000,
+        get: |s| s.a_accel,
+        set: |s, v| s.a_accel = v,
+    },
+    Setting {
+        id: 26,
+        description: "X ramps (0 = trapezoid, 1 = S-curve)",
+        max: 1,
+        get: |s| s.x_profile as u32,
+        set: |s, v| s.x_profile = Profile::from_id(v),
+    },
+    Setting {
+        id: 27,
+        description: "A ramps (0 = trapezoid, 1 = S-curve)",
+        max: 1,
+        get: |s| s.a_profile as u32,
+        set: |s, v| s.a_profile = Profile::from_id(v),
+    },
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    Setting {
+        id: 28,
+        description: "X limit switches (0 = as wired, 1 = swapped)",
+        max: 1,
+        get: |s| s.x_limit_switches_swapped as u32,
+        set: |s, v| s.x_limit_switches_swapped = v != 0,
+    },
+    #[cfg(not(any(feature = "shared-limits", feature = "analog-limits")))]
+    Setting {
+        id: 29,
+        description: "Swap crossed X limit switches when zeroing (0/1)",
+        max: 1,
+        get: |s| s.x_limit_switches_auto_swap as u32,
+        set: |s, v| s.x_limit_switches_auto_swap = v != 0,
+    },
+    #[cfg(feature = "door")]
+    Setting {
+        id: 30,
+        description: "Door interlock (0 = overridden, 1 = active)",
+        max: 1,
+        get: |s| s.door_interlock as u32,
+        set: |s, v| s.door_interlock = v != 0,
+    },
+    Setting {
+        id: 31,
+        description: "X step delay added moving left, 0.1 %",
+        max: 1000,
+        get: |s| s.x_left_step_trim as u32,
+        set: |s, v| s.x_left_step_trim = v as u16,
+    },
+    Setting {
+        id: 32,
+        description: "X step delay added moving right, 0.1 %",
+        max: 1000,
+        get: |s| s.x_right_step_trim as u32,
+        set: |s, v| s.x_right_step_trim = v as u16,
+    },
+    Setting {
+        id: 33,
+        description: "A step delay added turning negative, 0.1 %",
+        max: 1000,
+        get: |s| s.a_neg_step_trim as u32,
+        set: |s, v| s.a_neg_step_trim = v as u16,
+    },
+    Setting {
+        id: 34,
+        description: "A step delay added turning positive, 0.1 %",
+        max: 1000,
+        get: |s| s.a_pos_step_trim as u32,
+        set: |s, v| s.a_pos_step_trim = v as u16,
+    },
+    Setting {
+        id: 35,
+        description: "Lookahead in repeated blocks and macros (0/1)",
+        max: 1,
+        get: |s| s.lookahead as u32,
+        set: |s, v| s.lookahead = v != 0,
+    },
+    Setting {
+        id: 36,
+        description: "Park X at the end of a job (0/1)",
+        max: 1,
+        get: |s| s.end_park_x as u32,
+        set: |s, v| s.end_park_x = v != 0,
+    },
+    Setting {
+        id: 37,
+        description: "End of job X park position, machine microns",
+        max: 1_000_000,
+        get: |s| s.end_park_x_microns,
+        set: |s, v| s.end_park_x_microns = v,
+    },
+    Setting {
+        id: 38,
+        description: "Turn A to an index at the end of a job (0/1)",
+        max: 1,
+        get: |s| s.end_index_a as u32,
+        set: |s, v| s.end_index_a = v != 0,
+    },
+    Setting {
+        id: 39,
+        description: "End of job A index",
+        max: u16::MAX as u32,
+        get: |s| s.end_a_index as u32,
+        set: |s, v| s.end_a_index = v as u16,
+    },
+    #[cfg(feature = "a-index")]
+    Setting {
+        id: 40,
+        description: "A homing slow pass step delay, us",
+        max: u16::MAX as u32,
+        get: |s| s.a_home_slow_delay_us as u32,
+        set: |s, v| s.a_home_slow_delay_us = v as u16,
+    },
+    #[cfg(feature = "a-enable")]
+    Setting {
+        id: 41,
+        description: "Release A at the end of a job (0/1)",
+        max: 1,
+        get: |s| s.end_release_a as u32,
+        set: |s, v| s.end_release_a = v != 0,
+    },
+    #[cfg(feature = "done-out")]
+    Setting {
+        id: 42,
+        description: "Job done pulse, ms (0 = off)",
+        max: u16::MAX as u32,
+        get: |s| s.end_pulse_ms as u32,
+        set: |s, v| s.end_pulse_ms = v as u16,
+    },
+    Setting {
+        id: 43,
+        description: "Bobbin A origin, millidegrees",
+        max: 359_999,
+        get: |s| s.bobbin_origin_a_millidegrees,
+        set: |s, v| s.bobbin_origin_a_millidegrees = v,
+    },
+    #[cfg(feature = "spindle2")]
+    Setting {
+        id: 45,
+        description: "Second spindle mirrored (0 = same, 1 = opposite)",
+        max: 1,
+        get: |s| s.spindle2_mirrored as u32,
+        set: |s, v| s.spindle2_mirrored = v != 0,
+    },
+    #[cfg(feature = "analog-limits")]
+    Setting {
+        id: 47,
+        description: "X left end of travel, ADC counts",
+        max: 1023,
+        get: |s| s.x_left_end_counts as u32,
+        set: |s, v| s.x_left_end_counts = v as u16,
+    },
+    #[cfg(feature = "analog-limits")]
+    Setting {
+        id: 48,
+        description: "X right end of travel, ADC counts",
+        max: 1023,
+        get: |s| s.x_right_end_counts as u32,
+        set: |s, v| s.x_right_end_counts = v as u16,
+    },
+    #[cfg(feature = "analog-limits")]
+    Setting {
+        id: 49,
+        description: "X end of travel hysteresis, ADC counts",
+        max: 1023,
+        get: |s| s.x_end_hysteresis_counts as u32,
+        set: |s, v| s.x_end_hysteresis_counts = v as u16,
+    },
+    Setting {
+        id: BAUD_RATE_ID,
+        description: concat!(
+            "Baud rate at restart (0 = 9600, 1 = 19200, 2 = 38400, ",
+            "3 = 57600, 4 = 115200, 5 = 250000)"
+        ),
+        max: 5,
+        get: |s| s.baud_rate as u32,
+        set: |s, v| {
+            s.baud_rate =
+                BaudRate::from_value(v as u8).unwrap_or(BaudRate::DEFAULT)
+        },
+    },
+    Setting {
+        id: 51,
+        description: "Command time in ok responses (0 = off, 1 = on)",
+        max: 1,
+        get: |s| s.report_command_time as u32,
+        set: |s, v| s.report_command_time = v != 0,
+    },
+    Setting {
+        id: 52,
+        description: "Line endings (0 = LF, 1 = CR LF)",
+        max: 1,
+        get: |s| s.crlf_line_endings as u32,
+        set: |s, v| s.crlf_line_endings = v != 0,
+    },
+    Setting {
+        id: 53,
+        description: "Log messages and startup line echo (0 = on, 1 = off)",
+        max: 1,
+        get: |s| s.quiet as u32,
+        set: |s, v| s.quiet = v != 0,
+    },
+    #[cfg(feature = "capture")]
+    Setting {
+        id: 55,
+        description: "Capture edge (0 = falling, 1 = rising)",
+        max: 1,
+        get: |s| s.capture_rising_edge as u32,
+        set: |s, v| s.capture_rising_edge = v != 0,
+    },
+];
+
+/// Number of the baud rate setting, which is stored when it changes.
+pub const BAUD_RATE_ID: u8 = 50;
+
+/// Units of positions in status reports. A is reported in degrees, except
+/// in steps.
+#[derive(Clone, Copy)]
+pub enum ReportUnits {
+    Millimetres = 0,
+    Steps = 1,
+    Inches = 2,
+}
+
+/// Shape of the speed ramps at the start and end of moves.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// Constant acceleration.
+    Trapezoid = 0,
+    /// Acceleration that eases in and out, for fine wire.
+    SCurve = 1,
+}
+impl Profile {
+    fn from_id(id: u32) -> Self {
+        match id {
+            0 => Profile::Trapezoid,
+            _ => Profile::SCurve,
+        }
+    }
+}
+
+/// Baud rate of the serial port.
+#[derive(Clone, Copy)]
+pub enum BaudRate {
+    B9600 = 0,
+    B19200 = 1,
+    B38400 = 2,
+    B57600 = 3,
+    B115200 = 4,
+    B250000 = 5,
+}
+impl BaudRate {
+    /// The baud rate used when none is stored.
+    pub const DEFAULT: BaudRate = BaudRate::B57600;
+
+    /// Return the baud rate with a setting value, if there is one.
+    pub fn from_value(value: u8) -> Option<BaudRate> {
+        match value {
+            0 => Some(BaudRate::B9600),
+            1 => Some(BaudRate::B19200),
+            2 => Some(BaudRate::B38400),
+            3 => Some(BaudRate::B57600),
+            4 => Some(BaudRate::B115200),
+            5 => Some(BaudRate::B250000),
+            _ => None,
+        }
+    }
+
+    /// Return the baud rate in bits per second.
+    pub fn bits_per_second(self) -> u32 {
+        match self {
+            BaudRate::B9600 => 9600,
+            BaudRate::B19200 => 19_200,
+            BaudRate::B38400 => 38_400,
+            BaudRate::B57600 => 57_600,
+            BaudRate::B115200 => 115_200,
+            BaudRate::B250000 => 250_000,
+        }
+    }
+}
+
+/// Errors that might occur when changing a setting.
+pub enum Error {
+    /// There is no setting with the given number.
+    UnknownSetting,
+    /// The value is outside the range of the setting.
+    ValueOutOfRange,
+}
@@ -0,0 +1,163 @@
+//! Sources of command lines.
+//!
+//! Commands come from the host over the UART, and from text stored on the
+//! device: startup lines, macros and repeated blocks. A [`CommandSource`]
+//! hides where a line came from, so that the controller runs lines from
+//! every source in the same way.
+//!
+//! Stored sources take priority over the UART: once started, they run to
+//! completion (or to their first error) before the next line is read from
+//! the host. Real-time bytes, such as a soft reset, are not lines. They
+//! are picked out of the UART input by the reader, and during moves, so
+//! they take effect whichever source is running.
+
+use core::convert::Infallible;
+
+use crate::{
+    line::LineBuffer,
+    repeat::Player,
+    serial::{self, readln, SerialIo},
+};
+
+/// A source of command lines.
+pub trait CommandSource {
+    /// Error from reading a line.
+    type Error;
+
+    /// Returns the next line.
+    ///
+    /// # Returns
+    ///
+    /// - `None`: If the source has no more lines.
+    /// - `Some(Ok(line))`: The next line.
+    /// - `Some(Err(error))`: If a line could not be read. The source may
+    ///   still have more lines.
+    fn next_line(&mut self) -> Option<Result<&str, Self::Error>>;
+}
+
+/// Lines of stored text, separated by a character (eg. `|` in macros).
+///
+/// Lines are trimmed, and blank lines are skipped.
+pub struct TextSource<'a> {
+    rest: &'a str,
+    separator: char,
+}
+impl<'a> TextSource<'a> {
+    pub fn new(text: &'a str, separator: char) -> Self {
+        Self {
+            rest: text,
+            separator,
+        }
+    }
+}
+impl CommandSource for TextSource<'_> {
+    type Error = Infallible;
+
+    fn next_line(&mut self) -> Option<Result<&str, Infallible>> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            let (line, rest) = self
+                .rest
+                .split_once(self.separator)
+                .unwrap_or((self.rest, ""));
+            self.rest = rest;
+            let line = line.trim();
+            if !line.is_empty() {
+                return Some(Ok(line));
+            }
+        }
+    }
+}
+
+impl CommandSource for Player<'_> {
+    type Error = Infallible;
+
+    fn next_line(&mut self) -> Option<Result<&str, Infallible>> {
+        self.next().map(Ok)
+    }
+}
+
+/// Lines received over a serial port.
+///
+/// The source never runs out: reading blocks until a line arrives.
+pub struct SerialSource<'a, S, const N: usize> {
+    serial: S,
+    buffer: &'a mut LineBuffer<N>,
+    /// Whether the buffer holds a complete line, which must be cleared
+    /// before the next one is read.
+    complete: bool,
+}
+impl<'a, S: SerialIo, const N: usize> SerialSource<'a, S, N> {
+    pub fn new(serial: S, buffer: &'a mut LineBuffer<N>) -> Self {
+        Self {
+            serial,
+            buffer,
+            complete: false,
+        }
+    }
+}
+impl<S: SerialIo, const N: usize> CommandSource for SerialSource<'_, S, N> {
+    type Error = serial::Error;
+
+    fn next_line(&mut self) -> Option<Result<&str, serial::Error>> {
+        if self.complete {
+            self.buffer.clear();
+        }
+        let result = readln(&mut self.serial, self.buffer);
+        self.complete = result.is_ok();
+        Some(result.map(|()| self.buffer.line()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockSerial<'a>(&'a [u8]);
+    impl SerialIo for MockSerial<'_> {
+        fn try_read(&mut self) -> Option<u8> {
+            let (byte, rest) = self.0.split_first().expect("out of input");
+            self.0 = rest;
+            Some(*byte)
+        }
+    }
+
+    #[test]
+    fn test_text_source() {
+        let mut source = TextSource::new(" G0 X1 || M31|G4 P1 ", '|');
+        assert_eq!(Some(Ok("G0 X1")), source.next_line());
+        assert_eq!(Some(Ok("M31")), source.next_line());
+        assert_eq!(Some(Ok("G4 P1")), source.next_line());
+        assert_eq!(None, source.next_line());
+    }
+
+    #[test]
+    fn test_empty_text_source() {
+        assert_eq!(None, TextSource::new("", '|').next_line());
+        assert_eq!(None, TextSource::new(" | ", '|').next_line());
+    }
+
+    #[test]
+    fn test_player_source() {
+        let mut source = Player::new("M808 L2\nG0 X1\nM808\n");
+        assert_eq!(Some(Ok("G0 X1")), source.next_line());
+        assert_eq!(Some(Ok("G0 X1")), source.next_line());
+        assert_eq!(None, source.next_line());
+    }
+
+    #[test]
+    fn test_serial_source() {
+        let mut buffer: LineBuffer<8> = LineBuffer::new();
+        let serial = MockSerial(b"G0 X1\nG0 X100000\nM31\n\x18");
+        let mut source = SerialSource::new(serial, &mut buffer);
+        assert_eq!(Some(Ok("G0 X1")), source.next_line());
+        assert_eq!(
+            Some(Err(serial::Error::BufferOverflow)),
+            source.next_line()
+        );
+        assert_eq!(Some(Ok("M31")), source.next_line());
+        assert_eq!(Some(Err(serial::Error::SoftReset)), source.next_line());
+    }
+}
@@ -0,0 +1,91 @@
+use arduino_hal::{
+    delay_us,
+    port::{mode::Output, Pin, A1, D7},
+    Peripherals, Pins,
+};
+
+use crate::{machine::MoveObserver, settings::Settings};
+
+/// Second spindle motor, for dual-bobbin machines.
+///
+/// The second spindle duplicates every step of the A axis while it is
+/// enabled, optionally in the opposite direction (for mirrored bobbins).
+/// It can be enabled and disabled between moves, eg. to stop winding one
+/// bobbin early.
+///
+/// Pins: D7 is the pulse output and A1 the direction output.
+pub struct Spindle2 {
+    pin_pulse: Pin<Output, D7>,
+    pin_direc: Pin<Output, A1>,
+    enabled: bool,
+    forward: bool,
+}
+impl Spindle2 {
+    const DELAY_DIREC_US: u32 = 10;
+    const DELAY_PULSE_US: u32 = 5;
+
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+
+        let mut spindle = Self {
+            pin_pulse: pins.d7.into_output(),
+            pin_direc: pins.a1.into_output(),
+            enabled: true,
+            forward: true,
+        };
+        spindle.pin_pulse.set_low();
+        spindle.pin_direc.set_high();
+        spindle
+    }
+
+    /// Enable or disable the second spindle.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Take a single step, forward or backward.
+    fn step(&mut self, forward: bool) {
+        if forward != self.forward {
+            delay_us(Self::DELAY_DIREC_US);
+            if forward {
+                self.pin_direc.set_high();
+            } else {
+                self.pin_direc.set_low();
+            }
+            self.forward = forward;
+            delay_us(Self::DELAY_DIREC_US);
+        }
+        self.pin_pulse.set_high();
+        delay_us(Self::DELAY_PULSE_US);
+        self.pin_pulse.set_low();
+        delay_us(Self::DELAY_PULSE_US);
+    }
+}
+
+/// Drives the second spindle from steps of the A axis.
+pub struct Spindle2Follower<'a> {
+    spindle: &'a mut Spindle2,
+    settings: &'a Settings,
+    last_a_pos: Option<i64>,
+}
+impl<'a> Spindle2Follower<'a> {
+    pub fn new(spindle: &'a mut Spindle2, settings: &'a Settings) -> Self {
+        Self {
+            spindle,
+            settings,
+            last_a_pos: None,
+        }
+    }
+}
+impl MoveObserver for Spindle2Follower<'_> {
+    fn after_step(&mut self, _x_pos: u32, a_pos: i64) {
+        let last_a_pos = self.last_a_pos.replace(a_pos).unwrap_or(a_pos);
+        if a_pos == last_a_pos || !self.spindle.enabled {
+            return;
+        }
+        let forward = a_pos > last_a_pos;
+        self.spindle
+            .step(forward != self.settings.spindle2_mirrored);
+    }
+}
@@ -0,0 +1,201 @@
+use core::cell::RefCell;
+
+use arduino_hal::{
+    delay_us,
+    pac::TC1,
+    port::{mode::Output, Pin, D10, D8},
+};
+use avr_device::interrupt::{self, Mutex};
+
+/// Timer prescaler: the 16 MHz system clock is divided by this.
+const PRESCALER: u32 = 8;
+/// Timer counts per microsecond.
+const COUNTS_PER_US: u32 = 16 / PRESCALER;
+/// Timer counts in a whole period, from one compare match to the next, with
+/// the compare register at its maximum.
+const PERIOD_COUNTS: u32 = 1 << 16;
+/// Timer counts before a pulse that is given at once.
+const IMMEDIATE_COUNTS: u16 = 2;
+/// Timer counts needed to set the compare register before the count
+/// reaches it. A pulse due sooner than this is given at once.
+const MARGIN_COUNTS: u32 = 4;
+/// Width of a step pulse, in microseconds.
+const PULSE_US: u32 = 5;
+
+/// Axis to give a step pulse.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pulse {
+    X,
+    A,
+}
+
+/// Step pulse outputs, and the pulse that is waiting for the timer.
+struct StepTimer {
+    x_pulse: Pin<Output, D8>,
+    a_pulse: Pin<Output, D10>,
+    /// Axis to pulse when the wait ends, or `None` for only a wait.
+    pulse: Option<Pulse>,
+    /// Whether a pulse (or a wait) has been scheduled, and not yet given.
+    armed: bool,
+    /// Whole timer periods left to wait after the next compare match.
+    periods: u16,
+    /// Whether the timer has run a whole period since the last pulse, so
+    /// that its count no longer measures the time since that pulse.
+    stale: bool,
+}
+
+/// The step timer, once it has been started.
+static TIMER: Mutex<RefCell<Option<StepTimer>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Start the step timer, which then owns the step pulse outputs.
+///
+/// Timer1 is run in CTC mode, so that its count restarts at each compare
+/// match: the match that gives a step pulse is then the time reference
+/// for the next one, with no drift however long the main loop takes in
+/// between (so long as it schedules the next pulse in time).
+///
+/// # Parameters
+///
+/// - `tc1`: Timer1, which is dedicated to step pulses.
+/// - `x_pulse`, `a_pulse`: Step pulse outputs of X and A.
+pub fn init(tc1: TC1, x_pulse: Pin<Output, D8>, a_pulse: Pin<Output, D10>) {
+    tc1.tccr1a().write(|w| w.wgm1().bits(0b00));
+    tc1.tccr1b()
+        .write(|w| w.wgm1().bits(0b01).cs1().prescale_8());
+    tc1.ocr1a().write(|w| w.set(u16::MAX));
+    tc1.timsk1().write(|w| w.ocie1a().set_bit());
+
+    interrupt::free(|cs| {
+        TIMER.borrow(cs).replace(Some(StepTimer {
+            x_pulse,
+            a_pulse,
+            pulse: None,
+            armed: false,
+            periods: 0,
+            stale: true,
+        }));
+    });
+}
+
+/// Schedule a step pulse, which the timer interrupt then gives.
+///
+/// A pulse that is already late is given at once. So is one that is
+/// scheduled more than a whole timer period (about 33 ms) after the last
+/// pulse, since the timer can no longer tell how long ago that was.
+///
+/// # Parameters
+///
+/// - `pulse`: Axis to pulse, or `None` to only wait, eg. for the delay
+///   after the last step of a move.
+/// - `delay_us`: Time after the last pulse, in microseconds, or `None` to
+///   give the pulse at once.
+pub fn schedule(pulse: Option<Pulse>, delay_us: Option<u32>) {
+    let tc1 = unsafe { &*TC1::ptr() };
+    interrupt::free(|cs| {
+        let mut timer = TIMER.borrow(cs).borrow_mut();
+        let Some(timer) = timer.as_mut() else {
+            return;
+        };
+        timer.pulse = pulse;
+        timer.armed = true;
+        timer.periods = 0;
+        let elapsed = tc1.tcnt1().read().bits() as u32;
+        let counts = delay_us.map(|delay| delay.saturating_mul(COUNTS_PER_US));
+        match counts {
+            Some(counts)
+                if !timer.stale && counts > elapsed + MARGIN_COUNTS =>
+            {
+                if counts <= PERIOD_COUNTS {
+                    // The count runs on from the last pulse.
+                    tc1.ocr1a().write(|w| w.set((counts - 1) as u16));
+                } else {
+                    // Wait out the rest in whole periods, from now.
+                    let remaining = counts - elapsed;
+                    let periods = (remaining - 1) / PERIOD_COUNTS;
+                    let first = remaining - periods * PERIOD_COUNTS;
+                    let first = (first as u16).max(IMMEDIATE_COUNTS);
+                    timer.periods = periods as u16;
+                    restart(tc1, first);
+                }
+            }
+            _ => restart(tc1, IMMEDIATE_COUNTS),
+        }
+    });
+}
+
+/// Return `true` once the pulse (or wait) from [`schedule`] is over.
+pub fn fired() -> bool {
+    interrupt::free(|cs| {
+        TIMER
+            .borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(true, |timer| !timer.armed)
+    })
+}
+
+/// Drop the pulse (or wait) from [`schedule`], if it has not been given
+/// yet, and return `true` if it was dropped.
+pub fn cancel() -> bool {
+    interrupt::free(|cs| {
+        let mut timer = TIMER.borrow(cs).borrow_mut();
+        let Some(timer) = timer.as_mut() else {
+            return false;
+        };
+        let armed = timer.armed;
+        timer.armed = false;
+        armed
+    })
+}
+
+/// Give a step pulse at once, and wait for it.
+pub fn pulse_now(pulse: Pulse) {
+    schedule(Some(pulse), None);
+    while !fired() {}
+}
+
+/// Restart the count, for a compare match after `counts`.
+fn restart(tc1: &TC1, counts: u16) {
+    tc1.ocr1a().write(|w| w.set(counts));
+    tc1.tcnt1().write(|w| w.set(0));
+    // Forget a compare match that happened before the restart.
+    tc1.tifr1().write(|w| w.ocf1a().set_bit());
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn TIMER1_COMPA() {
+    interrupt::free(|cs| {
+        let mut timer = TIMER.borrow(cs).borrow_mut();
+        let Some(timer) = timer.as_mut() else {
+            return;
+        };
+        let tc1 = unsafe { &*TC1::ptr() };
+        if !timer.armed {
+            timer.stale = true;
+            return;
+        }
+        if timer.periods > 0 {
+            timer.periods -= 1;
+            tc1.ocr1a().write(|w| w.set(u16::MAX));
+            return;
+        }
+        match timer.pulse {
+            Some(Pulse::X) => {
+                timer.x_pulse.set_high();
+                delay_us(PULSE_US);
+                timer.x_pulse.set_low();
+            }
+            Some(Pulse::A) => {
+                timer.a_pulse.set_high();
+                delay_us(PULSE_US);
+                timer.a_pulse.set_low();
+            }
+            None => {}
+        }
+        timer.armed = false;
+        timer.stale = false;
+        // Count on from this pulse, until the next one is scheduled.
+        tc1.ocr1a().write(|w| w.set(u16::MAX));
+    })
+}
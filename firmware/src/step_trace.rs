@@ -0,0 +1,55 @@
+use winderbot_lib::{
+    interpolate::{Dir, Step},
+    trace::StepTrace,
+};
+
+use crate::{clock, machine::MoveObserver};
+
+/// Number of recent steps kept in the step trace.
+pub const TRACE_STEPS: usize = 256;
+
+/// Records each step of a move in the step trace.
+///
+/// The step is worked out from the change in position, so a step that X
+/// did not take because it was at a soft limit is not recorded.
+pub struct TraceRecorder<'a> {
+    trace: &'a mut StepTrace<TRACE_STEPS>,
+    /// X and A positions after the last step, in steps.
+    x_pos: u32,
+    a_pos: i64,
+}
+impl<'a> TraceRecorder<'a> {
+    /// Create a recorder for a move that starts at `(x_pos, a_pos)`.
+    pub fn new(
+        trace: &'a mut StepTrace<TRACE_STEPS>,
+        (x_pos, a_pos): (u32, i64),
+    ) -> Self {
+        Self {
+            trace,
+            x_pos,
+            a_pos,
+        }
+    }
+}
+impl MoveObserver for TraceRecorder<'_> {
+    fn after_step(&mut self, x_pos: u32, a_pos: i64) {
+        let step = if x_pos != self.x_pos {
+            Step::X(dir(x_pos.wrapping_sub(self.x_pos)))
+        } else if a_pos != self.a_pos {
+            Step::A(dir(a_pos.wrapping_sub(self.a_pos) as u32))
+        } else {
+            return;
+        };
+        self.x_pos = x_pos;
+        self.a_pos = a_pos;
+        self.trace.record(step, clock::micros());
+    }
+}
+
+/// Return the direction of a one-step change in position.
+fn dir(delta: u32) -> Dir {
+    match delta {
+        1 => Dir::Pos,
+        _ => Dir::Neg,
+    }
+}
@@ -0,0 +1,183 @@
+use arduino_hal::{Eeprom, Peripherals};
+use heapless::String;
+
+use crate::settings::BaudRate;
+
+/// Offset of the stored baud rate.
+const BAUD_RATE_ADDR: u16 = 0x1FE;
+
+/// Number of startup lines.
+pub const STARTUP_LINES: u8 = 2;
+
+/// Size of a startup line slot, which is also the maximum line length.
+pub const STARTUP_LINE_SZ: usize = 64;
+
+/// Offset of the first startup line.
+const STARTUP_LINES_ADDR: u16 = 0x200;
+
+/// Number of macro slots.
+pub const MACROS: u8 = 4;
+
+/// Size of a macro slot, which is also the maximum macro length.
+pub const MACRO_SZ: usize = 96;
+
+/// Offset of the first macro slot.
+const MACROS_ADDR: u16 = 0x280;
+
+/// Persistent storage in the ATmega328P's EEPROM.
+///
+/// Layout (byte offsets):
+///
+/// - `0x1FE..0x200`: baud rate, as its setting value followed by the
+///   complement of the value. Any other contents select the default rate.
+/// - `0x200..0x280`: startup lines, [`STARTUP_LINE_SZ`] bytes each.
+/// - `0x280..0x400`: macros, [`MACRO_SZ`] bytes each. A macro is a few
+///   commands, separated by `|`.
+///
+/// Text is stored as ASCII, terminated by a NUL unless it fills its slot.
+/// Erased EEPROM reads as `0xFF`, which is treated as empty text.
+pub struct Storage {
+    eeprom: Eeprom,
+}
+impl Storage {
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        Self {
+            eeprom: Eeprom::new(peripherals.EEPROM),
+        }
+    }
+
+    /// Read the stored baud rate.
+    ///
+    /// # Returns
+    ///
+    /// - `None`: If no valid baud rate is stored.
+    /// - `Some(baud_rate)`: The stored baud rate.
+    pub fn read_baud_rate(&self) -> Option<BaudRate> {
+        let value = self.eeprom.read_byte(BAUD_RATE_ADDR);
+        let check = self.eeprom.read_byte(BAUD_RATE_ADDR + 1);
+        if check != !value {
+            return None;
+        }
+        BaudRate::from_value(value)
+    }
+
+    /// Store the baud rate.
+    pub fn write_baud_rate(&mut self, baud_rate: BaudRate) {
+        let value = baud_rate as u8;
+        self.eeprom.write_byte(BAUD_RATE_ADDR, value);
+        self.eeprom.write_byte(BAUD_RATE_ADDR + 1, !value);
+    }
+
+    /// Erase the stored baud rate, so that the default is used.
+    pub fn clear_baud_rate(&mut self) {
+        self.eeprom.write_byte(BAUD_RATE_ADDR, 0xFF);
+        self.eeprom.write_byte(BAUD_RATE_ADDR + 1, 0xFF);
+    }
+
+    /// Read a startup line.
+    ///
+    /// # Parameters
+    ///
+    /// - `index`: Index of the startup line, which must be less than
+    ///   `STARTUP_LINES`.
+    /// - `line`: Buffer that receives the line. It is empty if no line is
+    ///   stored.
+    pub fn read_startup_line(
+        &self,
+        index: u8,
+        line: &mut String<STARTUP_LINE_SZ>,
+    ) {
+        self.read_text(Self::startup_line_addr(index), line);
+    }
+
+    /// Store a startup line. An empty line clears the slot.
+    ///
+    /// # Parameters
+    ///
+    /// - `index`: Index of the startup line, which must be less than
+    ///   `STARTUP_LINES`.
+    /// - `line`: The line to store.
+    pub fn write_startup_line(
+        &mut self,
+        index: u8,
+        line: &str,
+    ) -> Result<(), Error> {
+        let addr = Self::startup_line_addr(index);
+        self.write_text::<STARTUP_LINE_SZ>(addr, line)
+            .map_err(|_| Error::InvalidStartupLine)
+    }
+
+    /// Read a macro.
+    ///
+    /// # Parameters
+    ///
+    /// - `index`: Index of the macro, which must be less than `MACROS`.
+    /// - `commands`: Buffer that receives the commands, separated by `|`.
+    ///   It is empty if no macro is stored.
+    pub fn read_macro(&self, index: u8, commands: &mut String<MACRO_SZ>) {
+        self.read_text(Self::macro_addr(index), commands);
+    }
+
+    /// Store a macro. An empty macro clears the slot.
+    ///
+    /// # Parameters
+    ///
+    /// - `index`: Index of the macro, which must be less than `MACROS`.
+    /// - `commands`: The commands to store, separated by `|`.
+    pub fn write_macro(
+        &mut self,
+        index: u8,
+        commands: &str,
+    ) -> Result<(), Error> {
+        self.write_text::<MACRO_SZ>(Self::macro_addr(index), commands)
+            .map_err(|_| Error::InvalidMacro)
+    }
+
+    /// Read text from a slot of `N` bytes.
+    fn read_text<const N: usize>(&self, addr: u16, text: &mut String<N>) {
+        text.clear();
+        for i in 0..N as u16 {
+            match self.eeprom.read_byte(addr + i) {
+                0 | 0xFF => break,
+                c if c.is_ascii() => text.push(c as char).unwrap(),
+                _ => {
+                    text.clear();
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Write text to a slot of `N` bytes.
+    fn write_text<const N: usize>(
+        &mut self,
+        addr: u16,
+        text: &str,
+    ) -> Result<(), ()> {
+        if text.len() > N || !text.is_ascii() {
+            return Err(());
+        }
+        self.eeprom.write(addr, text.as_bytes()).map_err(|_| ())?;
+        if text.len() < N {
+            self.eeprom.write_byte(addr + text.len() as u16, 0);
+        }
+        Ok(())
+    }
+
+    fn startup_line_addr(index: u8) -> u16 {
+        STARTUP_LINES_ADDR + index as u16 * STARTUP_LINE_SZ as u16
+    }
+
+    fn macro_addr(index: u8) -> u16 {
+        MACROS_ADDR + index as u16 * MACRO_SZ as u16
+    }
+}
+
+/// Errors that might occur when writing to storage.
+pub enum Error {
+    /// A startup line is too long, or is not ASCII.
+    InvalidStartupLine,
+    /// A macro is too long, or is not ASCII.
+    InvalidMacro,
+}
@@ -0,0 +1,44 @@
+use arduino_hal::{
+    delay_us,
+    port::{mode::Output, Pin, D12},
+    Peripherals, Pins,
+};
+
+use crate::machine::{Machine, MoveObserver};
+
+/// Sync output, which pulses once per revolution of the spindle, eg. to
+/// fire a strobe or to drive an external turn counter.
+///
+/// The revolutions are counted from the A steps: D12 pulses high whenever a
+/// step brings A to a whole number of turns from A zero, in either
+/// direction.
+pub struct SyncOutput {
+    pin: Pin<Output, D12>,
+    /// A position after the last step, in steps.
+    a_pos: i64,
+}
+impl SyncOutput {
+    const PULSE_US: u32 = 10;
+
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+        let mut pin = pins.d12.into_output();
+        pin.set_low();
+        Self { pin, a_pos: 0 }
+    }
+}
+impl MoveObserver for SyncOutput {
+    fn after_step(&mut self, _x_pos: u32, a_pos: i64) {
+        if a_pos == self.a_pos {
+            return;
+        }
+        self.a_pos = a_pos;
+        let turn_steps = Machine::A_STEPS_PER_REV as i64;
+        if a_pos.rem_euclid(turn_steps) == 0 {
+            self.pin.set_high();
+            delay_us(Self::PULSE_US);
+            self.pin.set_low();
+        }
+    }
+}
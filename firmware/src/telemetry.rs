@@ -0,0 +1,134 @@
+use arduino_hal::prelude::*;
+use nb::block;
+use ufmt::{uWrite, uwrite};
+use winderbot_lib::{
+    dro::DroStream, truncate::format_truncated, units::Thousandths,
+};
+
+use crate::{
+    clock,
+    machine::{Coordinates, Machine, MoveObserver},
+    settings::Settings,
+    uno::SharedSerial,
+};
+
+/// Size of the buffer for a DRO line.
+const DRO_LINE_SZ: usize = 40;
+
+/// Emits position samples over the UART while the machine is moving.
+///
+/// Every `interval` steps, a line `T:<ticks>,<x>,<a>` is written, where
+/// `ticks` counts steps since the start of the move and `x` / `a` are the
+/// positions in steps. Each step is followed by the same fixed delay, so a
+/// host can recover speed by differentiating against `ticks`.
+///
+/// Writing a sample blocks while the UART drains, so a short interval will
+/// visibly slow the move down; this is a debugging aid only.
+pub struct Telemetry<'a, 's> {
+    serial: &'a SharedSerial<'s>,
+    interval: u16,
+    line_ending: &'static str,
+    ticks: u32,
+}
+impl<'a, 's> Telemetry<'a, 's> {
+    pub fn new(
+        serial: &'a SharedSerial<'s>,
+        interval: u16,
+        line_ending: &'static str,
+    ) -> Self {
+        Self {
+            serial,
+            interval,
+            line_ending,
+            ticks: 0,
+        }
+    }
+}
+impl MoveObserver for Telemetry<'_, '_> {
+    fn after_step(&mut self, x_pos: u32, a_pos: i64) {
+        if self.interval == 0 {
+            return;
+        }
+        self.ticks += 1;
+        if self.ticks % self.interval as u32 == 0 {
+            let mut serial = self.serial.borrow_mut();
+            uwrite!(serial, "T:{},{},{}", self.ticks, x_pos, a_pos)
+                .unwrap_infallible();
+            serial.write_str(self.line_ending).unwrap_infallible();
+        }
+    }
+}
+
+/// Streams the position while the machine is moving, for a digital readout
+/// (DRO) on the host that follows the carriage as it jogs.
+///
+/// At the rate set by `M989`, a line `DRO X:<mm> A<turns>:<degrees>` is
+/// started, with X in bobbin coordinates. Unlike telemetry, it never holds up a
+/// step: a byte is only sent when the UART can take it at once, so a line
+/// is spread over the time until the steps that follow, and no new line is
+/// started until it has gone.
+pub struct DroReport<'a, 's> {
+    serial: &'a SharedSerial<'s>,
+    settings: &'a Settings,
+    stream: DroStream<DRO_LINE_SZ>,
+}
+impl<'a, 's> DroReport<'a, 's> {
+    pub fn new(
+        serial: &'a SharedSerial<'s>,
+        rate_hz: u16,
+        settings: &'a Settings,
+    ) -> Self {
+        Self {
+            serial,
+            settings,
+            stream: DroStream::new(rate_hz),
+        }
+    }
+
+    /// Send the next byte of the current line, if the UART can take it at
+    /// once.
+    fn send_byte(&mut self) {
+        if let Some(byte) = self.stream.next_byte() {
+            if self.serial.borrow_mut().write(byte).is_ok() {
+                self.stream.byte_sent();
+            }
+        }
+    }
+
+    /// Send the rest of the current line, so that it is not cut off by the
+    /// response to the move.
+    pub fn finish(&mut self) {
+        let mut serial = self.serial.borrow_mut();
+        while let Some(byte) = self.stream.next_byte() {
+            block!(serial.write(byte)).unwrap_infallible();
+            self.stream.byte_sent();
+        }
+    }
+}
+impl MoveObserver for DroReport<'_, '_> {
+    fn after_step(&mut self, x_pos: u32, a_pos: i64) {
+        if let Some(line) = self.stream.due(clock::millis()) {
+            let (x, a) = Machine::millis_of(
+                x_pos,
+                a_pos,
+                Coordinates::Bobbin,
+                self.settings,
+            );
+            format_truncated(
+                line,
+                format_args!(
+                    "DRO X:{} A{}:{}{}",
+                    Thousandths(x.into()),
+                    a.turns,
+                    Thousandths(a.angle.into()),
+                    self.settings.line_ending()
+                ),
+            );
+        }
+        self.send_byte();
+    }
+
+    fn while_waiting(&mut self) {
+        self.send_byte();
+    }
+}
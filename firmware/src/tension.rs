@@ -0,0 +1,89 @@
+use arduino_hal::{
+    hal::port::{PC0, PD3},
+    port::{
+        mode::{Analog, PwmOutput},
+        Pin,
+    },
+    simple_pwm::{IntoPwmPin, Prescaler, Timer2Pwm},
+    Adc, Peripherals, Pins,
+};
+use winderbot_lib::pid::Pid;
+
+use crate::{machine::MoveObserver, settings::Settings};
+
+/// Closed-loop wire tension control.
+///
+/// Tension is measured as an analog voltage on A0 (eg. from a dancer arm
+/// potentiometer or a load cell amplifier), and controlled by a PWM output
+/// on D3, which drives a magnetic brake or a motorized de-reeler. A PID loop
+/// sets the PWM duty so that the measurement holds at the setpoint.
+///
+/// A measurement *below* the setpoint increases the duty, so the sensor
+/// must be wired so that its reading rises with tension.
+pub struct Tension {
+    adc: Adc,
+    sensor: Pin<Analog, PC0>,
+    output: Pin<PwmOutput<Timer2Pwm>, PD3>,
+    pid: Pid,
+}
+impl Tension {
+    pub fn new() -> Self {
+        let peripherals: Peripherals = unsafe { Peripherals::steal() };
+        let pins: Pins = arduino_hal::pins!(peripherals);
+
+        let mut adc = Adc::new(peripherals.ADC, Default::default());
+        let sensor = pins.a0.into_analog_input(&mut adc);
+
+        let timer = Timer2Pwm::new(peripherals.TC2, Prescaler::Prescale64);
+        let mut output = pins.d3.into_output().into_pwm(&timer);
+        output.set_duty(0);
+        output.enable();
+
+        Self {
+            adc,
+            sensor,
+            output,
+            pid: Pid::new(0, u8::MAX as i32),
+        }
+    }
+
+    /// Run one iteration of the control loop.
+    pub fn update(&mut self, settings: &Settings) {
+        let measurement = self.sensor.analog_read(&mut self.adc) as i32;
+        let duty = self.pid.update(
+            &settings.tension_gains(),
+            settings.tension_setpoint as i32,
+            measurement,
+        );
+        self.output.set_duty(duty as u8);
+    }
+}
+
+/// Runs the tension control loop while the machine is moving.
+///
+/// Moves block the main loop, so this is where the control loop gets its
+/// periodic tick: it runs once every `$14` steps. Between moves, the output
+/// holds its last value.
+pub struct TensionLoop<'a> {
+    tension: &'a mut Tension,
+    settings: &'a Settings,
+    steps: u16,
+}
+impl<'a> TensionLoop<'a> {
+    pub fn new(tension: &'a mut Tension, settings: &'a Settings) -> Self {
+        Self {
+            tension,
+            settings,
+            steps: 0,
+        }
+    }
+}
+impl MoveObserver for TensionLoop<'_> {
+    fn after_step(&mut self, _x_pos: u32, _a_pos: i64) {
+        self.steps += 1;
+        if self.steps >= self.settings.tension_period_steps {
+            self.steps = 0;
+            self.tension.update(self.settings);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Thresholds on analog readings, with hysteresis.
+//!
+//! A noisy reading that sits near a threshold would flip a plain comparison
+//! back and forth. A [`Threshold`] turns on when the reading reaches the
+//! threshold, but only turns off again once the reading has moved back past
+//! it by the hysteresis (a Schmitt trigger).
+
+/// An on/off state derived from an analog reading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Threshold {
+    threshold: u16,
+    hysteresis: u16,
+    /// Whether the state is on at high readings, rather than low ones.
+    above: bool,
+    active: bool,
+}
+impl Threshold {
+    /// Creates a threshold that is on for readings of at least `threshold`.
+    pub const fn above(threshold: u16, hysteresis: u16) -> Self {
+        Self {
+            threshold,
+            hysteresis,
+            above: true,
+            active: false,
+        }
+    }
+
+    /// Creates a threshold that is on for readings of at most `threshold`.
+    pub const fn below(threshold: u16, hysteresis: u16) -> Self {
+        Self {
+            threshold,
+            hysteresis,
+            above: false,
+            active: false,
+        }
+    }
+
+    /// Returns `true` if the state is on.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Updates the state from a new reading, and returns it.
+    pub fn update(&mut self, reading: u16) -> bool {
+        self.active = if self.above {
+            if self.active {
+                reading > self.threshold.saturating_sub(self.hysteresis)
+            } else {
+                reading >= self.threshold
+            }
+        } else if self.active {
+            reading < self.threshold.saturating_add(self.hysteresis)
+        } else {
+            reading <= self.threshold
+        };
+        self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_above() {
+        let mut threshold = Threshold::above(900, 10);
+        assert!(!threshold.update(899));
+        assert!(threshold.update(900));
+        assert!(threshold.update(891));
+        assert!(!threshold.update(890));
+        assert!(!threshold.update(899));
+        assert!(threshold.update(905));
+        assert!(threshold.is_active());
+    }
+
+    #[test]
+    fn test_below() {
+        let mut threshold = Threshold::below(100, 10);
+        assert!(!threshold.update(101));
+        assert!(threshold.update(100));
+        assert!(threshold.update(109));
+        assert!(!threshold.update(110));
+        assert!(!threshold.update(101));
+    }
+
+    #[test]
+    fn test_no_hysteresis() {
+        let mut threshold = Threshold::above(500, 0);
+        assert!(threshold.update(500));
+        assert!(!threshold.update(499));
+    }
+
+    #[test]
+    fn test_saturation() {
+        let mut threshold = Threshold::above(5, 10);
+        assert!(threshold.update(5));
+        assert!(threshold.update(1));
+        assert!(!threshold.update(0));
+        let mut threshold = Threshold::below(u16::MAX - 5, 10);
+        assert!(threshold.update(0));
+        assert!(threshold.update(u16::MAX - 1));
+        assert!(!threshold.update(u16::MAX));
+    }
+}
@@ -0,0 +1,183 @@
+//! A flight recorder of the most recent steps.
+//!
+//! When a carriage "suddenly jerked", the steps that the firmware actually
+//! sent show whether it was asked to, or lost steps on its own. RAM is
+//! scarce, so each step is packed into two bytes: the axis and direction,
+//! and the time since the step before it.
+
+use heapless::HistoryBuffer;
+
+use crate::interpolate::{Dir, Step};
+
+/// Resolution of recorded times, in microseconds.
+pub const TICK_US: u32 = 4;
+
+/// Number of bits of the time between steps, in ticks.
+const DELTA_BITS: u32 = 14;
+/// Longest time between steps that can be recorded, in ticks. Longer gaps
+/// are recorded as this.
+const MAX_DELTA: u32 = (1 << DELTA_BITS) - 1;
+
+/// A recorded step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    pub step: Step,
+    /// Time of the step, in microseconds, at the resolution of
+    /// [`TICK_US`].
+    pub time_us: u32,
+}
+
+/// The last `N` steps.
+pub struct StepTrace<const N: usize> {
+    /// Steps, oldest first, packed by [`pack`].
+    events: HistoryBuffer<u16, N>,
+    /// Time of the newest step, in microseconds.
+    last_us: u32,
+}
+impl<const N: usize> StepTrace<N> {
+    /// Creates an empty trace.
+    pub const fn new() -> Self {
+        Self {
+            events: HistoryBuffer::new(),
+            last_us: 0,
+        }
+    }
+
+    /// Records a step, forgetting the oldest one if the trace is full.
+    ///
+    /// # Parameters
+    ///
+    /// - `step`: Axis and direction of the step.
+    /// - `time_us`: Time of the step, in microseconds. This may wrap.
+    pub fn record(&mut self, step: Step, time_us: u32) {
+        let delta = time_us.wrapping_sub(self.last_us) / TICK_US;
+        self.events.write(pack(step, delta.min(MAX_DELTA)));
+        self.last_us = time_us;
+    }
+
+    /// Returns the recorded steps, oldest first.
+    ///
+    /// Times are reconstructed back from the newest step, so a gap of more
+    /// than about 65 ms makes the steps before it look later than they
+    /// were.
+    pub fn events(&self) -> impl Iterator<Item = Event> + '_ {
+        let after_oldest: u32 = self
+            .events
+            .oldest_ordered()
+            .skip(1)
+            .map(|packed| unpack(*packed).1)
+            .sum();
+        let mut time_us = self
+            .last_us
+            .wrapping_sub(after_oldest.wrapping_mul(TICK_US));
+        self.events
+            .oldest_ordered()
+            .enumerate()
+            .map(move |(i, packed)| {
+                let (step, delta) = unpack(*packed);
+                if i > 0 {
+                    time_us = time_us.wrapping_add(delta * TICK_US);
+                }
+                Event { step, time_us }
+            })
+    }
+
+    /// Returns the number of recorded steps.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns `true` if no steps have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+impl<const N: usize> Default for StepTrace<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Packs a step and the ticks since the step before it into two bytes.
+fn pack(step: Step, delta: u32) -> u16 {
+    let bits = match step {
+        Step::X(Dir::Pos) => 0,
+        Step::X(Dir::Neg) => 1,
+        Step::A(Dir::Pos) => 2,
+        Step::A(Dir::Neg) => 3,
+    };
+    (bits << DELTA_BITS) | delta as u16
+}
+
+fn unpack(packed: u16) -> (Step, u32) {
+    let step = match packed >> DELTA_BITS {
+        0 => Step::X(Dir::Pos),
+        1 => Step::X(Dir::Neg),
+        2 => Step::A(Dir::Pos),
+        _ => Step::A(Dir::Neg),
+    };
+    (step, (packed as u32) & MAX_DELTA)
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut trace: StepTrace<4> = StepTrace::new();
+        assert!(trace.is_empty());
+        trace.record(Step::X(Dir::Pos), 1000);
+        trace.record(Step::A(Dir::Neg), 1100);
+        trace.record(Step::X(Dir::Neg), 1200);
+        let events: Vec<Event, 4> = trace.events().collect();
+        assert_eq!(
+            [
+                Event {
+                    step: Step::X(Dir::Pos),
+                    time_us: 1000
+                },
+                Event {
+                    step: Step::A(Dir::Neg),
+                    time_us: 1100
+                },
+                Event {
+                    step: Step::X(Dir::Neg),
+                    time_us: 1200
+                },
+            ],
+            events.as_slice()
+        );
+    }
+
+    #[test]
+    fn test_keeps_the_newest() {
+        let mut trace: StepTrace<2> = StepTrace::new();
+        trace.record(Step::X(Dir::Pos), 100);
+        trace.record(Step::A(Dir::Pos), 200);
+        trace.record(Step::A(Dir::Neg), 300);
+        assert_eq!(2, trace.len());
+        let times: Vec<u32, 2> = trace.events().map(|e| e.time_us).collect();
+        assert_eq!([200, 300], times.as_slice());
+    }
+
+    #[test]
+    fn test_long_gaps_are_clipped() {
+        let mut trace: StepTrace<2> = StepTrace::new();
+        trace.record(Step::X(Dir::Pos), 0);
+        trace.record(Step::X(Dir::Pos), 1_000_000);
+        let times: Vec<u32, 2> = trace.events().map(|e| e.time_us).collect();
+        assert_eq!([1_000_000 - MAX_DELTA * TICK_US, 1_000_000], times);
+    }
+
+    #[test]
+    fn test_wrapping_time() {
+        let mut trace: StepTrace<2> = StepTrace::new();
+        trace.record(Step::X(Dir::Pos), u32::MAX - 3);
+        trace.record(Step::X(Dir::Neg), 4);
+        let times: Vec<u32, 2> = trace.events().map(|e| e.time_us).collect();
+        assert_eq!([u32::MAX - 3, 4], times);
+    }
+}
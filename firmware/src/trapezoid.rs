@@ -0,0 +1,265 @@
+//! Trapezoidal speed profiles for moves.
+//!
+//! A move accelerates from rest to its cruise rate, cruises, and then
+//! decelerates to rest, at a constant acceleration. A move that is too
+//! short to reach its cruise rate decelerates as soon as it has covered
+//! half of its steps. A move that follows on from another, or runs on into
+//! the next, may instead start or end at a rate other than rest.
+//!
+//! Delays between steps follow David Austin's approximation ("Generate
+//! stepper-motor speed profiles in real time", 2005), which needs one
+//! division per step rather than a square root.
+
+/// Number of fractional bits in delays.
+const FRAC_BITS: u32 = 8;
+
+/// Yields the delay after each step of a move, in microseconds.
+pub struct Trapezoid {
+    /// Number of steps left.
+    remaining: u32,
+    /// Delay at cruise rate, with [`FRAC_BITS`] fractional bits.
+    cruise: u32,
+    /// Delay of the first step from rest, with [`FRAC_BITS`] fractional
+    /// bits.
+    first: u32,
+    /// Delay at the current point on the ramp, with [`FRAC_BITS`]
+    /// fractional bits.
+    delay: u32,
+    /// Number of steps of the ramp up to the current delay, or `None`
+    /// before the first step.
+    ramp: Option<u32>,
+    /// Number of steps of the ramp up to the rate at the start.
+    entry_ramp: u32,
+    /// Number of steps of the ramp up to the rate at the end.
+    exit_ramp: u32,
+}
+impl Trapezoid {
+    /// Creates a profile.
+    ///
+    /// # Parameters
+    ///
+    /// - `steps`: Number of steps in the move.
+    /// - `cruise_delay_us`: Delay after each step at the cruise rate, in
+    ///   microseconds.
+    /// - `accel`: Acceleration, in steps per second squared. Zero turns the
+    ///   ramps off, so that every step is at the cruise rate.
+    pub fn new(steps: u32, cruise_delay_us: u32, accel: u32) -> Self {
+        Self::between(steps, cruise_delay_us, accel, (0, 0))
+    }
+
+    /// Creates a profile that starts and ends at the given rates, rather
+    /// than at rest.
+    ///
+    /// # Parameters
+    ///
+    /// - `steps`, `cruise_delay_us`, `accel`: As for [`Trapezoid::new`].
+    /// - `entry_rate`, `exit_rate`: Rates at the start and the end, in
+    ///   steps per second, or zero for rest. They are limited to the cruise
+    ///   rate, and the move must be long enough to change between them at
+    ///   its acceleration (eg. as planned by a
+    ///   [`Lookahead`](crate::lookahead::Lookahead)).
+    pub fn between(
+        steps: u32,
+        cruise_delay_us: u32,
+        accel: u32,
+        (entry_rate, exit_rate): (u32, u32),
+    ) -> Self {
+        let cruise = cruise_delay_us.saturating_mul(1 << FRAC_BITS);
+        let first = match accel {
+            0 => cruise,
+            accel => {
+                let first = start_delay_us(accel) << FRAC_BITS;
+                (first.min(u32::MAX as u64) as u32).max(cruise)
+            }
+        };
+        let cruise_rate = 1_000_000 / cruise_delay_us.max(1);
+        // Steps of the ramp from rest to a rate, which is rate^2 / 2a.
+        let ramp = |rate: u32| match accel {
+            0 => 0,
+            accel => {
+                let rate = rate.min(cruise_rate) as u64;
+                (rate * rate / (2 * accel as u64)) as u32
+            }
+        };
+        let entry_ramp = ramp(entry_rate);
+        let delay = match entry_ramp {
+            0 => first,
+            _ => {
+                let rate = entry_rate.min(cruise_rate);
+                ((1_000_000 << FRAC_BITS) / rate as u64) as u32
+            }
+        };
+        Self {
+            remaining: steps,
+            cruise,
+            first,
+            delay: delay.clamp(cruise, first),
+            ramp: None,
+            entry_ramp,
+            exit_ramp: ramp(exit_rate),
+        }
+    }
+}
+impl Iterator for Trapezoid {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        // Steps after this one, plus the ramp at the end: the longest ramp
+        // that can still come back down to the rate at the end.
+        let after = self.remaining.saturating_add(self.exit_ramp);
+        self.ramp = Some(match self.ramp {
+            None => self.entry_ramp,
+            Some(ramp) if ramp > after => {
+                // Decelerate: undo one step of the ramp.
+                let ramp = ramp - 1;
+                self.delay = match ramp {
+                    0 => self.first,
+                    _ => self.delay + 2 * self.delay / (4 * ramp + 3),
+                };
+                ramp
+            }
+            Some(ramp) if ramp < after && self.delay > self.cruise => {
+                // Accelerate.
+                let ramp = ramp + 1;
+                self.delay -= 2 * self.delay / (4 * ramp + 1);
+                ramp
+            }
+            Some(ramp) => ramp,
+        });
+        Some(self.delay.max(self.cruise) >> FRAC_BITS)
+    }
+}
+
+/// Returns the delay after the first step of a move from rest, in
+/// microseconds, for an acceleration in steps per second squared.
+///
+/// This is 0.676 * sqrt(2 / accel) seconds. The factor of 0.676 corrects
+/// Austin's approximation for the first step.
+pub(crate) fn start_delay_us(accel: u32) -> u64 {
+    let root = isqrt(2_000_000_000_000 / accel.max(1) as u64);
+    root * 676 / 1000
+}
+
+/// Returns the integer square root of a value, rounded down.
+pub(crate) fn isqrt(value: u64) -> u64 {
+    let mut root = 0;
+    let mut bit = 1 << 62;
+    let mut rest = value;
+    while bit > value {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+
+    #[test]
+    fn test_isqrt() {
+        assert_eq!(0, isqrt(0));
+        assert_eq!(1, isqrt(3));
+        assert_eq!(2, isqrt(4));
+        assert_eq!(1_414_213, isqrt(2_000_000_000_000));
+        assert_eq!(u32::MAX as u64, isqrt(u64::MAX));
+    }
+
+    #[test]
+    fn test_no_acceleration() {
+        let delays: Vec<u32, 4> = Trapezoid::new(4, 100, 0).collect();
+        assert_eq!([100, 100, 100, 100], delays.as_slice());
+    }
+
+    #[test]
+    fn test_first_step() {
+        // 0.676 * sqrt(2 / 10_000) s, rounded down.
+        assert_eq!(Some(9559), Trapezoid::new(1, 100, 10_000).next());
+        // Acceleration so fast that the first step is at the cruise rate.
+        assert_eq!(Some(100), Trapezoid::new(1, 100, u32::MAX).next());
+    }
+
+    #[test]
+    fn test_trapezoid() {
+        let delays: Vec<u32, 2000> =
+            Trapezoid::new(2000, 100, 100_000).collect();
+        assert_eq!(2000, delays.len());
+        // Ramp up, cruise, and ramp down the same way.
+        assert_eq!(3023, delays[0]);
+        assert!(delays[..50].windows(2).all(|d| d[0] >= d[1]));
+        assert_eq!(100, delays[1000]);
+        assert!(delays[1950..].windows(2).all(|d| d[0] <= d[1]));
+        for (up, down) in delays[..60].iter().zip(delays.iter().rev()) {
+            assert!(up.abs_diff(*down) <= 1, "{} {}", up, down);
+        }
+        // 10_000 steps/s from rest at 100_000 steps/s^2 takes 500 steps.
+        let ramp = delays.iter().take_while(|d| **d > 100).count();
+        assert!((480..=520).contains(&ramp), "{}", ramp);
+    }
+
+    #[test]
+    fn test_between() {
+        // Already at the cruise rate, and staying there.
+        let delays: Vec<u32, 10> =
+            Trapezoid::between(10, 100, 100_000, (10_000, 10_000)).collect();
+        assert_eq!([100; 10], delays.as_slice());
+
+        // Half the cruise rate at the start, and rest at the end.
+        let delays: Vec<u32, 2000> =
+            Trapezoid::between(2000, 100, 100_000, (5000, 0)).collect();
+        assert_eq!(200, delays[0]);
+        assert!(delays[..100].windows(2).all(|d| d[0] >= d[1]));
+        assert_eq!(100, delays[1000]);
+        assert_eq!(
+            Trapezoid::new(1, 100, 100_000).next(),
+            delays.last().copied()
+        );
+        // 5000 to 10_000 steps/s at 100_000 steps/s^2 takes 375 steps.
+        let ramp = delays.iter().take_while(|d| **d > 100).count();
+        assert!((360..=390).contains(&ramp), "{}", ramp);
+    }
+
+    #[test]
+    fn test_runs_on() {
+        // A move that ends at 2000 steps/s runs on into one that starts
+        // there, without a jump in the delay.
+        let first: Vec<u32, 100> =
+            Trapezoid::between(100, 100, 100_000, (0, 2000)).collect();
+        let second: Vec<u32, 100> =
+            Trapezoid::between(100, 100, 100_000, (2000, 0)).collect();
+        let (end, start) = (first[99], second[0]);
+        assert!(end.abs_diff(500) <= 25, "{}", end);
+        assert!(start.abs_diff(500) <= 25, "{}", start);
+        // Both are long enough to speed up beyond it in between.
+        assert!(first.iter().all(|d| *d >= 100) && first[50] < 400);
+        assert!(second[40] < 400);
+        assert_eq!(
+            Trapezoid::new(1, 100, 100_000).next(),
+            second.last().copied()
+        );
+    }
+
+    #[test]
+    fn test_triangle() {
+        let delays: Vec<u32, 101> = Trapezoid::new(101, 10, 1000).collect();
+        assert_eq!(delays[0], delays[100]);
+        assert_eq!(delays[10], delays[90]);
+        let fastest = delays.iter().min().unwrap();
+        assert_eq!(fastest, &delays[50]);
+        assert!(*fastest > 10);
+    }
+}
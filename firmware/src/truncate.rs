@@ -0,0 +1,118 @@
+//! Formatting into fixed-size strings without failing on overflow.
+//!
+//! `write!` into a `heapless::String` fails once the string is full, and
+//! unwrapping that error would halt the firmware. Output written through a
+//! [`TruncatingWriter`] is cut off at the capacity of the string instead.
+
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+/// A writer that appends to a `heapless::String`, dropping whatever does
+/// not fit.
+///
+/// Text is only ever cut at a character boundary. Once anything has been
+/// dropped, all further writes are dropped too, so the output is always a
+/// prefix of the full text.
+pub struct TruncatingWriter<'a, const N: usize> {
+    buffer: &'a mut String<N>,
+    truncated: bool,
+}
+impl<'a, const N: usize> TruncatingWriter<'a, N> {
+    /// Creates a writer that appends to `buffer`.
+    pub fn new(buffer: &'a mut String<N>) -> Self {
+        Self {
+            buffer,
+            truncated: false,
+        }
+    }
+
+    /// Returns `true` if any output was dropped.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+impl<const N: usize> Write for TruncatingWriter<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+        for c in s.chars() {
+            if self.buffer.push(c).is_err() {
+                self.truncated = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Clears `buffer` and formats `args` into it, truncating the output if it
+/// does not fit.
+///
+/// # Returns
+///
+/// `true` if the output was truncated.
+pub fn format_truncated<const N: usize>(
+    buffer: &mut String<N>,
+    args: fmt::Arguments,
+) -> bool {
+    buffer.clear();
+    let mut writer = TruncatingWriter::new(buffer);
+    // The writer itself never fails, so an error can only come from a
+    // `Display` impl; whatever was written up to that point is kept.
+    let _ = writer.write_fmt(args);
+    writer.truncated()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fits() {
+        let mut buffer: String<8> = String::new();
+        assert!(!format_truncated(&mut buffer, format_args!("ab{}", 12)));
+        assert_eq!("ab12", buffer.as_str());
+    }
+
+    #[test]
+    fn test_exactly_full() {
+        let mut buffer: String<4> = String::new();
+        assert!(!format_truncated(&mut buffer, format_args!("abcd")));
+        assert_eq!("abcd", buffer.as_str());
+    }
+
+    #[test]
+    fn test_truncates_at_capacity() {
+        let mut buffer: String<4> = String::new();
+        assert!(format_truncated(&mut buffer, format_args!("ab{}", 1234)));
+        assert_eq!("ab12", buffer.as_str());
+    }
+
+    #[test]
+    fn test_truncates_at_char_boundary() {
+        // "é" is two bytes, and only one byte is left for it.
+        let mut buffer: String<4> = String::new();
+        assert!(format_truncated(&mut buffer, format_args!("abcé")));
+        assert_eq!("abc", buffer.as_str());
+    }
+
+    #[test]
+    fn test_drops_writes_after_truncation() {
+        let mut buffer: String<4> = String::new();
+        let mut writer = TruncatingWriter::new(&mut buffer);
+        writer.write_str("abcé").unwrap();
+        writer.write_str("d").unwrap();
+        assert!(writer.truncated());
+        assert_eq!("abc", buffer.as_str());
+    }
+
+    #[test]
+    fn test_clears_buffer() {
+        let mut buffer: String<8> = String::new();
+        buffer.push_str("old").unwrap();
+        format_truncated(&mut buffer, format_args!("new"));
+        assert_eq!("new", buffer.as_str());
+    }
+}
@@ -0,0 +1,54 @@
+//! Fixed-point quantities.
+//!
+//! Lengths and angles are carried as integers in thousandths of their unit
+//! (microns and millidegrees), which avoids floating point on the AVR.
+
+use core::fmt::{self, Display, Formatter};
+
+/// A value in thousandths, displayed as a decimal number with three places
+/// (eg. `1500` is displayed as `1.500`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thousandths(pub i64);
+impl Display for Thousandths {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let value = self.0;
+        if value < 0 {
+            write!(f, "-")?;
+        }
+        let value = value.unsigned_abs();
+        write!(f, "{}.{:03}", value / 1000, value % 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use heapless::String;
+
+    fn format(value: i64) -> String<24> {
+        let mut s = String::new();
+        write!(s, "{}", Thousandths(value)).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_positive() {
+        assert_eq!("0.000", format(0).as_str());
+        assert_eq!("1.500", format(1500).as_str());
+        assert_eq!("12.034", format(12_034).as_str());
+    }
+
+    #[test]
+    fn test_negative() {
+        assert_eq!("-0.001", format(-1).as_str());
+        assert_eq!("-3.142", format(-3142).as_str());
+    }
+
+    #[test]
+    fn test_extremes() {
+        assert_eq!("2147483.647", format(i32::MAX.into()).as_str());
+        assert_eq!("-2147483.648", format(i32::MIN.into()).as_str());
+        assert_eq!("-9223372036854775.808", format(i64::MIN).as_str());
+    }
+}
@@ -1,3 +1,5 @@
+use core::cell::RefCell;
+
 use arduino_hal::{
     hal::port::{PD0, PD1},
     pac::USART0,
@@ -5,7 +7,21 @@ use arduino_hal::{
         mode::{Input, Output},
         Pin,
     },
+    prelude::*,
     Usart,
 };
+use winderbot_lib::serial::SerialIo;
 
 pub type UnoSerial = Usart<USART0, Pin<Input, PD0>, Pin<Output, PD1>>;
+
+/// The UART, shared between several users during a move (eg. real-time
+/// command polling and telemetry).
+pub type SharedSerial<'s> = RefCell<&'s mut UnoSerial>;
+
+/// The UART, as a [`SerialIo`] to read lines from.
+pub struct UnoSerialIo<'a>(pub &'a mut UnoSerial);
+impl SerialIo for UnoSerialIo<'_> {
+    fn try_read(&mut self) -> Option<u8> {
+        self.0.read().ok()
+    }
+}
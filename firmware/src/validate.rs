@@ -0,0 +1,256 @@
+//! Checks of whether the machine may run a command in its current state,
+//! without running it.
+//!
+//! The firmware checks each command with [`validate`] before it runs it.
+//! Host tools (eg. a simulator) can check a job offline with the same
+//! function, so that they accept exactly the commands that the firmware
+//! does.
+
+/// A command, as far as its checks are concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Zero the machine (`Z`).
+    Zero,
+    /// Home A to the spindle index.
+    HomeA,
+    /// Move X and A (`G0`, `G2`, `G3`, `G53`).
+    Move,
+    /// Turn A to an index angle (`M19 P<index>`).
+    IndexA { index: u16 },
+    /// Start the spindle, or change its speed (`M3`).
+    SpindleOn,
+    /// Change the speed of a running spindle (`S<rpm>`).
+    SpindleSpeed,
+    /// Pause (`G4`).
+    Dwell,
+}
+
+/// The parts of the machine's state that decide whether a command may run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MachineState {
+    /// Whether the machine has been zeroed.
+    pub zeroed: bool,
+    /// Whether A is turning in spindle mode.
+    pub spindle_running: bool,
+    /// Whether the door is open, with its interlock active.
+    pub door_open: bool,
+    /// Whether a hard limit switch is open.
+    pub hard_limit_open: bool,
+    /// Whether motion is locked after a hard limit alarm.
+    pub locked: bool,
+    /// Whether a job (a macro or a repeated block) is running.
+    pub job_active: bool,
+    /// Number of A index positions per turn.
+    pub a_index_positions: u16,
+}
+
+/// Why a command may not run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The machine must be zeroed first.
+    NotZeroed,
+    /// The machine must be still, but A is turning in spindle mode.
+    SpindleRunning,
+    /// The spindle speed was changed while the spindle was stopped.
+    SpindleStopped,
+    /// The A index is beyond the number of index positions.
+    InvalidIndex,
+    /// The door is open.
+    DoorOpen,
+    /// A hard limit switch is open.
+    HardLimit,
+    /// Motion is locked after a hard limit alarm.
+    Locked,
+    /// The command is only for an idle machine, and a job is running.
+    JobActive,
+}
+
+/// Checks whether `command` may run in `state`.
+///
+/// When several checks fail, the error is the first of them in the order
+/// that the firmware has always reported them.
+pub fn validate(
+    command: Command,
+    state: &MachineState,
+) -> Result<(), ValidationError> {
+    match command {
+        Command::Zero => {
+            check_spindle_stopped(state)?;
+            check_interlocks(state)
+        }
+        Command::HomeA | Command::Move => {
+            check_spindle_stopped(state)?;
+            check_interlocks(state)?;
+            check_zeroed(state)
+        }
+        Command::IndexA { index } => {
+            if state.job_active {
+                return Err(ValidationError::JobActive);
+            }
+            if index >= state.a_index_positions {
+                return Err(ValidationError::InvalidIndex);
+            }
+            check_spindle_stopped(state)?;
+            check_interlocks(state)?;
+            check_zeroed(state)
+        }
+        Command::SpindleOn => {
+            check_interlocks(state)?;
+            check_zeroed(state)
+        }
+        Command::SpindleSpeed => {
+            if !state.spindle_running {
+                return Err(ValidationError::SpindleStopped);
+            }
+            check_interlocks(state)?;
+            check_zeroed(state)
+        }
+        Command::Dwell => check_spindle_stopped(state),
+    }
+}
+
+/// Checks whether the door interlock and the hard limits allow motion.
+///
+/// This is also checked during continuous motion, such as spindle mode.
+pub fn check_interlocks(state: &MachineState) -> Result<(), ValidationError> {
+    if state.door_open {
+        return Err(ValidationError::DoorOpen);
+    }
+    if state.hard_limit_open {
+        return Err(ValidationError::HardLimit);
+    }
+    if state.locked {
+        return Err(ValidationError::Locked);
+    }
+    Ok(())
+}
+
+fn check_spindle_stopped(state: &MachineState) -> Result<(), ValidationError> {
+    match state.spindle_running {
+        false => Ok(()),
+        true => Err(ValidationError::SpindleRunning),
+    }
+}
+
+fn check_zeroed(state: &MachineState) -> Result<(), ValidationError> {
+    match state.zeroed {
+        true => Ok(()),
+        false => Err(ValidationError::NotZeroed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE: MachineState = MachineState {
+        zeroed: true,
+        spindle_running: false,
+        door_open: false,
+        hard_limit_open: false,
+        locked: false,
+        job_active: false,
+        a_index_positions: 4,
+    };
+
+    #[test]
+    fn test_idle_machine_accepts_everything() {
+        for command in [
+            Command::Zero,
+            Command::HomeA,
+            Command::Move,
+            Command::IndexA { index: 3 },
+            Command::SpindleOn,
+            Command::Dwell,
+        ] {
+            assert_eq!(Ok(()), validate(command, &IDLE));
+        }
+    }
+
+    #[test]
+    fn test_not_zeroed() {
+        let state = MachineState {
+            zeroed: false,
+            ..IDLE
+        };
+        assert_eq!(Ok(()), validate(Command::Zero, &state));
+        assert_eq!(
+            Err(ValidationError::NotZeroed),
+            validate(Command::Move, &state)
+        );
+        assert_eq!(
+            Err(ValidationError::NotZeroed),
+            validate(Command::SpindleOn, &state)
+        );
+    }
+
+    #[test]
+    fn test_spindle() {
+        let running = MachineState {
+            spindle_running: true,
+            ..IDLE
+        };
+        assert_eq!(
+            Err(ValidationError::SpindleRunning),
+            validate(Command::Move, &running)
+        );
+        assert_eq!(
+            Err(ValidationError::SpindleRunning),
+            validate(Command::Dwell, &running)
+        );
+        assert_eq!(Ok(()), validate(Command::SpindleSpeed, &running));
+        assert_eq!(
+            Err(ValidationError::SpindleStopped),
+            validate(Command::SpindleSpeed, &IDLE)
+        );
+    }
+
+    #[test]
+    fn test_interlocks() {
+        let state = MachineState {
+            door_open: true,
+            locked: true,
+            ..IDLE
+        };
+        assert_eq!(
+            Err(ValidationError::DoorOpen),
+            validate(Command::Zero, &state)
+        );
+        let state = MachineState {
+            door_open: false,
+            ..state
+        };
+        assert_eq!(
+            Err(ValidationError::Locked),
+            validate(Command::SpindleOn, &state)
+        );
+        assert_eq!(Ok(()), validate(Command::Dwell, &state));
+    }
+
+    #[test]
+    fn test_index_a() {
+        assert_eq!(
+            Err(ValidationError::InvalidIndex),
+            validate(Command::IndexA { index: 4 }, &IDLE)
+        );
+        let state = MachineState {
+            job_active: true,
+            spindle_running: true,
+            ..IDLE
+        };
+        assert_eq!(
+            Err(ValidationError::JobActive),
+            validate(Command::IndexA { index: 0 }, &state)
+        );
+        assert_eq!(
+            Ok(()),
+            validate(
+                Command::Zero,
+                &MachineState {
+                    job_active: true,
+                    ..IDLE
+                }
+            )
+        );
+    }
+}
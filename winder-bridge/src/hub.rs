@@ -0,0 +1,108 @@
+//! Fan-out of the winder's output to the connected clients.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, SyncSender},
+    Arc, Mutex,
+};
+
+/// Number of lines that a client may fall behind by before it is
+/// disconnected.
+pub const CLIENT_BACKLOG: usize = 1024;
+
+/// Shares the lines written by the winder between the clients, and makes
+/// sure that only one client controls the winder at a time.
+#[derive(Default)]
+pub struct Hub {
+    subscribers: Mutex<Vec<SyncSender<Arc<str>>>>,
+    controlled: AtomicBool,
+}
+impl Hub {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Returns a receiver for every line written from now on. The receiver
+    /// is disconnected if it falls more than [`CLIENT_BACKLOG`] lines
+    /// behind.
+    pub fn subscribe(&self) -> Receiver<Arc<str>> {
+        let (sender, receiver) = mpsc::sync_channel(CLIENT_BACKLOG);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends a line to every subscriber, and forgets the subscribers that
+    /// have gone or fallen behind.
+    pub fn broadcast(&self, line: &str) {
+        let line: Arc<str> = line.into();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.try_send(line.clone()).is_ok());
+    }
+
+    /// Takes control of the winder, or returns `None` if another client
+    /// has it. Control is released when the guard is dropped.
+    pub fn take_control(self: &Arc<Self>) -> Option<ControlGuard> {
+        self.controlled
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| ControlGuard { hub: self.clone() })
+    }
+}
+
+/// Control of the winder, held by one client.
+pub struct ControlGuard {
+    hub: Arc<Hub>,
+}
+impl Drop for ControlGuard {
+    fn drop(&mut self) {
+        self.hub.controlled.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast() {
+        let hub = Hub::new();
+        let first = hub.subscribe();
+        hub.broadcast("ok");
+        let second = hub.subscribe();
+        hub.broadcast("error:1");
+        assert_eq!("ok", &*first.recv().unwrap());
+        assert_eq!("error:1", &*first.recv().unwrap());
+        assert_eq!("error:1", &*second.recv().unwrap());
+    }
+
+    #[test]
+    fn test_gone_subscribers_are_dropped() {
+        let hub = Hub::new();
+        drop(hub.subscribe());
+        hub.broadcast("ok");
+        assert!(hub.subscribers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_slow_subscribers_are_dropped() {
+        let hub = Hub::new();
+        let slow = hub.subscribe();
+        for _ in 0..=CLIENT_BACKLOG {
+            hub.broadcast("ok");
+        }
+        assert!(hub.subscribers.lock().unwrap().is_empty());
+        assert_eq!(CLIENT_BACKLOG, slow.try_iter().count());
+    }
+
+    #[test]
+    fn test_one_controller() {
+        let hub = Hub::new();
+        let control = hub.take_control();
+        assert!(control.is_some());
+        assert!(hub.take_control().is_none());
+        drop(control);
+        assert!(hub.take_control().is_some());
+    }
+}
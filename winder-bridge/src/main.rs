@@ -0,0 +1,201 @@
+//! Bridges the winder's serial port to TCP and WebSocket clients.
+//!
+//! One controller may send lines to the winder, and any number of
+//! read-only observers follow what it writes. See the README for usage.
+
+mod hub;
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    process::ExitCode,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    thread,
+};
+
+use tungstenite::Message;
+
+use hub::Hub;
+
+/// Default address of the controller port.
+const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:7878";
+
+const USAGE: &str = "usage: winder-bridge <serial-port> [--control <addr>] \
+                     [--observe <addr>] [--ws <addr>]";
+
+/// Command-line options.
+struct Options {
+    serial_port: String,
+    control_addr: String,
+    observe_addr: Option<String>,
+    ws_addr: Option<String>,
+}
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Options> {
+        let mut options = Options {
+            serial_port: args.next()?,
+            control_addr: DEFAULT_CONTROL_ADDR.to_string(),
+            observe_addr: None,
+            ws_addr: None,
+        };
+        while let Some(flag) = args.next() {
+            let value = args.next()?;
+            match flag.as_str() {
+                "--control" => options.control_addr = value,
+                "--observe" => options.observe_addr = Some(value),
+                "--ws" => options.ws_addr = Some(value),
+                _ => return None,
+            }
+        }
+        Some(options)
+    }
+}
+
+fn main() -> ExitCode {
+    let Some(options) = Options::parse(std::env::args().skip(1)) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("winder-bridge: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: Options) -> io::Result<()> {
+    let serial = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&options.serial_port)?;
+    let serial_reader = BufReader::new(serial.try_clone()?);
+    let serial = Arc::new(Mutex::new(serial));
+    let hub = Hub::new();
+
+    let control = TcpListener::bind(&options.control_addr)?;
+    eprintln!("Controller port on {}.", control.local_addr()?);
+    spawn_listener(control, hub.clone(), {
+        let serial = serial.clone();
+        move |stream, hub| serve_controller(stream, &hub, &serial)
+    });
+    if let Some(addr) = &options.observe_addr {
+        let observe = TcpListener::bind(addr)?;
+        eprintln!("Observer port on {}.", observe.local_addr()?);
+        spawn_listener(observe, hub.clone(), |stream, hub| {
+            let lines = hub.subscribe();
+            serve_observer(stream, lines)
+        });
+    }
+    if let Some(addr) = &options.ws_addr {
+        let ws = TcpListener::bind(addr)?;
+        eprintln!("WebSocket observer port on {}.", ws.local_addr()?);
+        spawn_listener(ws, hub.clone(), |stream, hub| {
+            let lines = hub.subscribe();
+            serve_ws_observer(stream, lines)
+        });
+    }
+
+    // Forward the winder's output until the serial port closes.
+    for line in serial_reader.lines() {
+        let line = line?;
+        hub.broadcast(line.trim_end_matches('\r'));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "serial port closed",
+    ))
+}
+
+/// Accepts connections on a listener, and serves each on its own thread.
+fn spawn_listener<F>(listener: TcpListener, hub: Arc<Hub>, serve: F)
+where
+    F: Fn(TcpStream, Arc<Hub>) -> io::Result<()> + Clone + Send + 'static,
+{
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let peer = stream.peer_addr().ok();
+            let (serve, hub) = (serve.clone(), hub.clone());
+            thread::spawn(move || {
+                if let Err(error) = serve(stream, hub) {
+                    eprintln!("Client {:?}: {}", peer, error);
+                }
+            });
+        }
+    });
+}
+
+/// Serves the controller: its lines go to the winder, and it receives
+/// every line from the winder.
+fn serve_controller(
+    mut stream: TcpStream,
+    hub: &Arc<Hub>,
+    serial: &Mutex<File>,
+) -> io::Result<()> {
+    let Some(_control) = hub.take_control() else {
+        writeln!(stream, "[BRIDGE: another controller is connected]")?;
+        return Ok(());
+    };
+    let lines = hub.subscribe();
+    let output = stream.try_clone()?;
+    thread::spawn(move || serve_observer(output, lines));
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let mut serial = serial.lock().unwrap();
+        writeln!(serial, "{}", line.trim_end_matches('\r'))?;
+        serial.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes each line from the winder to a TCP observer.
+fn serve_observer(
+    mut stream: TcpStream,
+    lines: Receiver<Arc<str>>,
+) -> io::Result<()> {
+    for line in lines {
+        writeln!(stream, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Sends each line from the winder to a WebSocket observer, as a text
+/// message.
+fn serve_ws_observer(
+    stream: TcpStream,
+    lines: Receiver<Arc<str>>,
+) -> io::Result<()> {
+    let mut ws = tungstenite::accept(stream).map_err(io::Error::other)?;
+    for line in lines {
+        ws.send(Message::text(&*line)).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Option<Options> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse(&["/dev/ttyACM0", "--ws", "0.0.0.0:80"]).unwrap();
+        assert_eq!("/dev/ttyACM0", options.serial_port);
+        assert_eq!(DEFAULT_CONTROL_ADDR, options.control_addr);
+        assert_eq!(None, options.observe_addr);
+        assert_eq!(Some("0.0.0.0:80".to_string()), options.ws_addr);
+    }
+
+    #[test]
+    fn test_bad_options() {
+        assert!(parse(&[]).is_none());
+        assert!(parse(&["/dev/ttyACM0", "--ws"]).is_none());
+        assert!(parse(&["/dev/ttyACM0", "--baud", "9600"]).is_none());
+    }
+}
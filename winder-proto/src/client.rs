@@ -0,0 +1,313 @@
+//! A blocking client for the serial protocol.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+use crate::{
+    request::Request,
+    response::{
+        Info, Level, Line, MachineState, Response, Setting, Version,
+        PROTOCOL_VERSION,
+    },
+};
+
+/// A blocking client for the firmware.
+///
+/// The client sends one line at a time, and waits for its reply, as the
+/// protocol requires. It works over any port that implements
+/// [`Read`] and [`Write`]; set a read timeout on the port so that a board
+/// that stops answering fails with [`Error::Io`] rather than blocking
+/// forever.
+pub struct Client<P> {
+    port: BufReader<P>,
+}
+
+/// The reply to a line: the lines written before its terminating response,
+/// and the response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reply {
+    pub lines: Vec<Line>,
+    pub response: Response,
+}
+impl Reply {
+    /// Returns how long the line took to execute, in milliseconds, if the
+    /// firmware reported it (with `$51=1`).
+    pub fn elapsed_ms(&self) -> Option<u32> {
+        match self.response {
+            Response::OkTimed(ms) => Some(ms),
+            _ => None,
+        }
+    }
+}
+
+/// An error from the client.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the port failed.
+    Io(io::Error),
+    /// The port was closed.
+    Closed,
+    /// The firmware rejected the line with an error code. `alarm` is the
+    /// alarm that it raised, if any, and `message` is its error message.
+    Command {
+        code: u8,
+        alarm: Option<u8>,
+        message: Option<String>,
+    },
+    /// The firmware speaks a protocol version that this client does not
+    /// support.
+    UnsupportedProtocol(Version),
+    /// The reply did not contain the expected report.
+    MissingReport,
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {}", error),
+            Error::Closed => write!(f, "Port closed."),
+            Error::Command { code, message, .. } => match message {
+                Some(message) => write!(f, "error:{} ({})", code, message),
+                None => write!(f, "error:{}", code),
+            },
+            Error::UnsupportedProtocol(version) => write!(
+                f,
+                "Firmware speaks protocol {}, which does not support {}.",
+                version, PROTOCOL_VERSION
+            ),
+            Error::MissingReport => write!(f, "Expected report not found."),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl<P: Read + Write> Client<P> {
+    /// Creates a client on an open port, without checking the firmware.
+    pub fn new(port: P) -> Self {
+        Self {
+            port: BufReader::new(port),
+        }
+    }
+
+    /// Creates a client on an open port, and checks that the firmware
+    /// supports [`PROTOCOL_VERSION`].
+    pub fn connect(port: P) -> Result<Self, Error> {
+        let mut client = Self::new(port);
+        let version = client.protocol_version()?;
+        if !version.supports(PROTOCOL_VERSION) {
+            return Err(Error::UnsupportedProtocol(version));
+        }
+        Ok(client)
+    }
+
+    /// Returns the port.
+    pub fn into_inner(self) -> P {
+        self.port.into_inner()
+    }
+
+    /// Sends a request, and returns its reply.
+    ///
+    /// Returns [`Error::Command`] if the firmware rejects the request.
+    pub fn send(&mut self, request: &Request) -> Result<Reply, Error> {
+        let port = self.port.get_mut();
+        writeln!(port, "{}", request)?;
+        port.flush()?;
+        let mut lines = Vec::new();
+        let mut alarm = None;
+        loop {
+            match self.read_line()? {
+                Line::Response(Response::Alarm(code)) => alarm = Some(code),
+                Line::Response(Response::Error(code)) => {
+                    let message =
+                        lines.iter().rev().find_map(|line| match line {
+                            Line::Log(log) if log.level == Level::Error => {
+                                Some(log.message.clone())
+                            }
+                            _ => None,
+                        });
+                    return Err(Error::Command {
+                        code,
+                        alarm,
+                        message,
+                    });
+                }
+                Line::Response(response) => {
+                    return Ok(Reply { lines, response })
+                }
+                line => lines.push(line),
+            }
+        }
+    }
+
+    /// Returns the protocol version of the firmware, from `$I`.
+    pub fn protocol_version(&mut self) -> Result<Version, Error> {
+        self.send(&Request::Info)?
+            .lines
+            .into_iter()
+            .find_map(|line| match line {
+                Line::Info(Info::Protocol(version)) => Some(version),
+                _ => None,
+            })
+            .ok_or(Error::MissingReport)
+    }
+
+    /// Returns the settings checksum of the firmware, from `$I`.
+    pub fn settings_checksum(&mut self) -> Result<u16, Error> {
+        self.send(&Request::Info)?
+            .lines
+            .into_iter()
+            .find_map(|line| match line {
+                Line::Info(Info::SettingsChecksum(checksum)) => Some(checksum),
+                _ => None,
+            })
+            .ok_or(Error::MissingReport)
+    }
+
+    /// Returns the machine state, from `M996`.
+    pub fn state(&mut self) -> Result<MachineState, Error> {
+        self.send(&Request::State)?
+            .lines
+            .into_iter()
+            .find_map(|line| match line {
+                Line::State(state) => Some(state),
+                _ => None,
+            })
+            .ok_or(Error::MissingReport)
+    }
+
+    /// Returns the settings, from `$$`.
+    pub fn settings(&mut self) -> Result<Vec<Setting>, Error> {
+        let reply = self.send(&Request::ListSettings)?;
+        Ok(reply
+            .lines
+            .into_iter()
+            .filter_map(|line| match line {
+                Line::Setting(setting) => Some(setting),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Changes a setting.
+    pub fn set_setting(&mut self, id: u8, value: u32) -> Result<(), Error> {
+        self.send(&Request::SetSetting { id, value }).map(|_| ())
+    }
+
+    fn read_line(&mut self) -> Result<Line, Error> {
+        let mut line = String::new();
+        if self.port.read_line(&mut line)? == 0 {
+            return Err(Error::Closed);
+        }
+        Ok(Line::parse(&line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::State;
+
+    /// A port that answers from a script, and records what was written.
+    struct ScriptedPort {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+    impl ScriptedPort {
+        fn new(script: &str) -> Self {
+            Self {
+                input: io::Cursor::new(script.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+    impl Read for ScriptedPort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+    impl Write for ScriptedPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_send() {
+        let port =
+            ScriptedPort::new("[10] INFO: Starting to zero.\nok t=5ms\n");
+        let mut client = Client::new(port);
+        let reply = client.send(&Request::Zero).unwrap();
+        assert_eq!(1, reply.lines.len());
+        assert_eq!(Some(5), reply.elapsed_ms());
+        assert_eq!(b"Z\n", client.into_inner().output.as_slice());
+    }
+
+    #[test]
+    fn test_command_error() {
+        let port = ScriptedPort::new(
+            "[20] ERROR: Reset while in motion.\nALARM:1\nerror:7\n",
+        );
+        let mut client = Client::new(port);
+        match client.send(&Request::Zero) {
+            Err(Error::Command {
+                code: 7,
+                alarm: Some(1),
+                message: Some(message),
+            }) => assert_eq!("Reset while in motion.", message),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_connect() {
+        let port = ScriptedPort::new(&format!(
+            "WINDERBOT! {0}\n[VER:0.1.0:WinderBot]\n[PROTOCOL:{0}]\n\
+             [SETTINGS:10758]\nok\n",
+            PROTOCOL_VERSION
+        ));
+        assert!(Client::connect(port).is_ok());
+        let port = ScriptedPort::new("[PROTOCOL:1.2]\nok\n");
+        assert!(matches!(
+            Client::connect(port),
+            Err(Error::UnsupportedProtocol(Version { major: 1, minor: 2 }))
+        ));
+    }
+
+    #[test]
+    fn test_state() {
+        let port = ScriptedPort::new(
+            "State:NotZeroed Units:mm Feed:100 Queue:0\nok\n",
+        );
+        let mut client = Client::new(port);
+        assert_eq!(State::NotZeroed, client.state().unwrap().state);
+        assert!(matches!(client.state(), Err(Error::Closed)));
+    }
+
+    #[test]
+    fn test_settings() {
+        let port = ScriptedPort::new(
+            "$1=0 (X reversal dwell, ms)\n$7=1 (Report units)\nok\n",
+        );
+        let mut client = Client::new(port);
+        let settings = client.settings().unwrap();
+        assert_eq!(2, settings.len());
+        assert_eq!((7, 1), (settings[1].id, settings[1].value));
+    }
+}
@@ -0,0 +1,30 @@
+//! Host-side API for the WinderBot serial protocol.
+//!
+//! [`Request`]s are written as the command lines that the firmware reads,
+//! and each line that the firmware writes back is parsed into a [`Line`].
+//! [`Client`] puts the two together over a serial port: it sends one
+//! request at a time, and collects the lines written before the terminating
+//! `ok` or `error:<code>` response.
+//!
+//! Positions are in thousandths of their unit throughout (microns for X,
+//! millidegrees for A), as in the firmware, so that values round-trip
+//! exactly. [`Microns`] and [`MilliDegrees`] convert from floating point
+//! millimetres and degrees.
+//!
+//! With the `serde` feature, positions, moves and settings can be
+//! serialized, eg. to save jobs and machine configurations.
+
+mod client;
+mod request;
+mod response;
+mod settings;
+mod units;
+
+pub use client::{Client, Error, Reply};
+pub use request::{Move, Request};
+pub use response::{
+    settings_checksum, Info, JobDone, Level, Line, LogLine, MachineState,
+    Response, Setting, State, Version, BANNER, PROTOCOL_VERSION,
+};
+pub use settings::MachineSettings;
+pub use units::{Microns, MilliDegrees, RangeError};
@@ -0,0 +1,203 @@
+//! Commands sent to the firmware.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::units::{Microns, MilliDegrees, RangeError};
+
+/// A command line for the firmware. Its [`Display`] form is the line to
+/// send, without the line terminator.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    /// `Z`: zero the machine.
+    Zero,
+    /// `ZA`: home A to the spindle index sensor.
+    HomeA,
+    /// `G90`: absolute positioning.
+    AbsolutePositioning,
+    /// `G91`: relative positioning.
+    RelativePositioning,
+    /// `G0`: move in bobbin coordinates.
+    Move(Move),
+    /// `G53 G0`: move in machine coordinates.
+    MachineMove(Move),
+    /// `G4 P<ms>`: dwell.
+    Dwell { ms: u32 },
+    /// `G10 L2 P1 X<mm>`: put the origin of bobbin coordinates at X, in
+    /// machine coordinates.
+    SetOrigin { x_microns: i32 },
+    /// `G10 L20 P1 X<mm>`: make the current position X, in bobbin
+    /// coordinates.
+    SetCurrentX { x_microns: i32 },
+    /// `G10 L2 P1 A<degrees>`: put the A origin of bobbin coordinates at A,
+    /// in machine coordinates.
+    SetOriginA { a_millidegrees: i32 },
+    /// `G10 L20 P1 A<degrees>`: make the current angle A, in bobbin
+    /// coordinates.
+    SetCurrentA { a_millidegrees: i32 },
+    /// `$$`: list the settings.
+    ListSettings,
+    /// `$<id>=<value>`: change a setting.
+    SetSetting { id: u8, value: u32 },
+    /// `$I`: report the firmware and protocol versions, and the settings
+    /// checksum.
+    Info,
+    /// `$X`: unlock motion after a hard limit alarm.
+    Unlock,
+    /// `M810` to `M813`: run a stored macro.
+    RunMacro(u8),
+    /// `M31`: report the uptime.
+    Uptime,
+    /// `M114`: report the position.
+    Position,
+    /// `M115`: report the firmware and its capabilities.
+    Firmware,
+    /// `M996`: report the machine state on one line.
+    State,
+    /// Any other line, sent as it is.
+    Raw(String),
+}
+impl Display for Request {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Request::Zero => write!(f, "Z"),
+            Request::HomeA => write!(f, "ZA"),
+            Request::AbsolutePositioning => write!(f, "G90"),
+            Request::RelativePositioning => write!(f, "G91"),
+            Request::Move(mv) => write!(f, "G0{}", mv),
+            Request::MachineMove(mv) => write!(f, "G53 G0{}", mv),
+            Request::Dwell { ms } => write!(f, "G4 P{}", ms),
+            Request::SetOrigin { x_microns } => {
+                write!(f, "G10 L2 P1 X{}", Thousandths(*x_microns))
+            }
+            Request::SetCurrentX { x_microns } => {
+                write!(f, "G10 L20 P1 X{}", Thousandths(*x_microns))
+            }
+            Request::SetOriginA { a_millidegrees } => {
+                write!(f, "G10 L2 P1 A{}", Thousandths(*a_millidegrees))
+            }
+            Request::SetCurrentA { a_millidegrees } => {
+                write!(f, "G10 L20 P1 A{}", Thousandths(*a_millidegrees))
+            }
+            Request::ListSettings => write!(f, "$$"),
+            Request::SetSetting { id, value } => write!(f, "${}={}", id, value),
+            Request::Info => write!(f, "$I"),
+            Request::Unlock => write!(f, "$X"),
+            Request::RunMacro(index) => write!(f, "M81{}", index),
+            Request::Uptime => write!(f, "M31"),
+            Request::Position => write!(f, "M114"),
+            Request::Firmware => write!(f, "M115"),
+            Request::State => write!(f, "M996"),
+            Request::Raw(line) => write!(f, "{}", line),
+        }
+    }
+}
+
+/// The axis words of a move. Axes that are `None` do not move.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Move {
+    pub x_microns: Option<i32>,
+    pub a_millidegrees: Option<i32>,
+}
+impl Move {
+    /// Returns a move to X in millimetres and A in degrees, rounded to
+    /// the nearest thousandth.
+    pub fn from_mm_deg(
+        x_mm: Option<f64>,
+        a_deg: Option<f64>,
+    ) -> Result<Self, RangeError> {
+        Ok(Self {
+            x_microns: x_mm.map(Microns::from_mm_f64).transpose()?.map(|x| x.0),
+            a_millidegrees: a_deg
+                .map(MilliDegrees::from_deg_f64)
+                .transpose()?
+                .map(|a| a.0),
+        })
+    }
+}
+impl Display for Move {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(x) = self.x_microns {
+            write!(f, " X{}", Thousandths(x))?;
+        }
+        if let Some(a) = self.a_millidegrees {
+            write!(f, " A{}", Thousandths(a))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a value in thousandths as a decimal, eg. `-1.250`.
+struct Thousandths(i32);
+impl Display for Thousandths {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let value = self.0 as i64;
+        if value < 0 {
+            write!(f, "-")?;
+        }
+        let value = value.abs();
+        write!(f, "{}.{:03}", value / 1000, value % 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move() {
+        let mv = Move {
+            x_microns: Some(1500),
+            a_millidegrees: Some(-250),
+        };
+        assert_eq!("G0 X1.500 A-0.250", Request::Move(mv).to_string());
+        let mv = Move {
+            a_millidegrees: Some(i32::MIN),
+            ..Move::default()
+        };
+        assert_eq!(
+            "G53 G0 A-2147483.648",
+            Request::MachineMove(mv).to_string()
+        );
+    }
+
+    #[test]
+    fn test_move_from_mm_deg() {
+        let mv = Move::from_mm_deg(Some(12.3456), None).unwrap();
+        assert_eq!("G0 X12.346", Request::Move(mv).to_string());
+        let mv = Move::from_mm_deg(None, Some(-0.1)).unwrap();
+        assert_eq!("G0 A-0.100", Request::Move(mv).to_string());
+        assert!(Move::from_mm_deg(Some(1.0), Some(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        assert_eq!("$$", Request::ListSettings.to_string());
+        let set = Request::SetSetting { id: 51, value: 1 };
+        assert_eq!("$51=1", set.to_string());
+    }
+
+    #[test]
+    fn test_offsets() {
+        let origin = Request::SetOrigin { x_microns: 12_000 };
+        assert_eq!("G10 L2 P1 X12.000", origin.to_string());
+        let current = Request::SetCurrentX { x_microns: 5 };
+        assert_eq!("G10 L20 P1 X0.005", current.to_string());
+        let origin = Request::SetOriginA {
+            a_millidegrees: 90_000,
+        };
+        assert_eq!("G10 L2 P1 A90.000", origin.to_string());
+        let current = Request::SetCurrentA {
+            a_millidegrees: -500,
+        };
+        assert_eq!("G10 L20 P1 A-0.500", current.to_string());
+    }
+
+    #[test]
+    fn test_other_commands() {
+        assert_eq!("G4 P250", Request::Dwell { ms: 250 }.to_string());
+        assert_eq!("M812", Request::RunMacro(2).to_string());
+        assert_eq!("M996", Request::State.to_string());
+        assert_eq!("M105", Request::Raw("M105".into()).to_string());
+    }
+}
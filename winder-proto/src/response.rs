@@ -0,0 +1,469 @@
+//! Lines written by the firmware.
+//!
+//! These mirror the formats written by the firmware (see
+//! `firmware/src/protocol.rs`). Lines that this crate does not know are
+//! parsed as [`Line::Other`] rather than rejected, since newer firmware may
+//! add reports within the same major protocol version.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Line written at startup and after a soft reset, before the protocol
+/// version.
+pub const BANNER: &str = "WINDERBOT!";
+
+/// Version of the protocol spoken by this crate.
+pub const PROTOCOL_VERSION: Version = Version { major: 2, minor: 9 };
+
+/// A line written by the firmware.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    /// A response to the line that was sent.
+    Response(Response),
+    /// The banner, with the protocol version of the firmware.
+    Banner(Version),
+    /// A log message.
+    Log(LogLine),
+    /// A setting, from `$$`.
+    Setting(Setting),
+    /// The machine state, from `M996`.
+    State(MachineState),
+    /// A line of the `$I` report.
+    Info(Info),
+    /// The totals of a job, at its end.
+    JobDone(JobDone),
+    /// Any other line, eg. other reports.
+    Other(String),
+}
+impl Line {
+    /// Parses a line, without its line terminator.
+    pub fn parse(line: &str) -> Line {
+        let line = line.trim_end_matches(['\r', '\n']);
+        Response::parse(line)
+            .map(Line::Response)
+            .or_else(|| parse_banner(line).map(Line::Banner))
+            .or_else(|| LogLine::parse(line).map(Line::Log))
+            .or_else(|| Setting::parse(line).map(Line::Setting))
+            .or_else(|| MachineState::parse(line).map(Line::State))
+            .or_else(|| Info::parse(line).map(Line::Info))
+            .or_else(|| JobDone::parse(line).map(Line::JobDone))
+            .unwrap_or_else(|| Line::Other(line.to_string()))
+    }
+}
+
+/// A response line. `ok` and `error:<code>` end the reply to a line;
+/// `ALARM:<code>` is written before the `error:<code>` of an error that
+/// left the machine needing attention.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response {
+    /// The line was executed successfully.
+    Ok,
+    /// The line was executed successfully, taking the given number of
+    /// milliseconds (with `$51=1`).
+    OkTimed(u32),
+    /// The line failed with the given error code.
+    Error(u8),
+    /// An alarm was raised with the given alarm code.
+    Alarm(u8),
+}
+impl Response {
+    /// Parses a response line.
+    pub fn parse(line: &str) -> Option<Response> {
+        if line == "ok" {
+            Some(Response::Ok)
+        } else if let Some(ms) = line.strip_prefix("ok t=") {
+            ms.strip_suffix("ms")?.parse().ok().map(Response::OkTimed)
+        } else if let Some(code) = line.strip_prefix("error:") {
+            code.parse().ok().map(Response::Error)
+        } else if let Some(code) = line.strip_prefix("ALARM:") {
+            code.parse().ok().map(Response::Alarm)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if this response ends the reply to a line.
+    pub fn is_terminating(&self) -> bool {
+        !matches!(self, Response::Alarm(_))
+    }
+}
+
+/// A protocol version, written as `<major>.<minor>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+impl Version {
+    /// Parses a version, eg. `1.2`.
+    pub fn parse(s: &str) -> Option<Version> {
+        let (major, minor) = s.split_once('.')?;
+        Some(Version {
+            major: major.parse().ok()?,
+            minor: minor.parse().ok()?,
+        })
+    }
+
+    /// Returns `true` if a host written for the `required` version can use
+    /// firmware that speaks this version: the major versions must match,
+    /// and this minor version must be at least the required one.
+    pub fn supports(&self, required: Version) -> bool {
+        self.major == required.major && self.minor >= required.minor
+    }
+}
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+fn parse_banner(line: &str) -> Option<Version> {
+    let version = line.strip_prefix(BANNER)?.strip_prefix(' ')?;
+    Version::parse(version)
+}
+
+/// Level of a log message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// A log message: `[<ms>] <LEVEL>: <message>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLine {
+    /// Uptime when the message was written, in milliseconds.
+    pub time_ms: u32,
+    pub level: Level,
+    pub message: String,
+}
+impl LogLine {
+    fn parse(line: &str) -> Option<LogLine> {
+        let (time_ms, rest) = line.strip_prefix('[')?.split_once("] ")?;
+        let (level, message) = rest.split_once(": ")?;
+        let level = match level {
+            "ERROR" => Level::Error,
+            "WARN" => Level::Warn,
+            "INFO" => Level::Info,
+            "DEBUG" => Level::Debug,
+            _ => return None,
+        };
+        Some(LogLine {
+            time_ms: time_ms.parse().ok()?,
+            level,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// A setting: `$<id>=<value> (<description>)`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Setting {
+    pub id: u8,
+    pub value: u32,
+    pub description: String,
+}
+impl Setting {
+    fn parse(line: &str) -> Option<Setting> {
+        let (id, rest) = line.strip_prefix('$')?.split_once('=')?;
+        let (value, description) = rest.split_once(" (")?;
+        Some(Setting {
+            id: id.parse().ok()?,
+            value: value.parse().ok()?,
+            description: description.strip_suffix(')')?.to_string(),
+        })
+    }
+}
+
+/// Computes the checksum of a set of settings, given as `(id, value)`
+/// pairs in the order that `$$` lists them, as reported by `$I`.
+///
+/// The checksum is a Fletcher-16 checksum of the id byte and the four
+/// little-endian value bytes of each setting.
+pub fn settings_checksum(settings: impl IntoIterator<Item = (u8, u32)>) -> u16 {
+    let (mut sum1, mut sum2) = (0u16, 0u16);
+    for (id, value) in settings {
+        for byte in [id].into_iter().chain(value.to_le_bytes()) {
+            sum1 = (sum1 + byte as u16) % 255;
+            sum2 = (sum2 + sum1) % 255;
+        }
+    }
+    (sum2 << 8) | sum1
+}
+
+/// A line of the `$I` report.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Info {
+    /// `[VER:<version>:WinderBot]`: the firmware version.
+    FirmwareVersion(String),
+    /// `[PROTOCOL:<version>]`: the protocol version.
+    Protocol(Version),
+    /// `[SETTINGS:<checksum>]`: the checksum of the settings.
+    SettingsChecksum(u16),
+}
+impl Info {
+    fn parse(line: &str) -> Option<Info> {
+        let (key, value) =
+            line.strip_prefix('[')?.strip_suffix(']')?.split_once(':')?;
+        match key {
+            "VER" => value
+                .strip_suffix(":WinderBot")
+                .map(|version| Info::FirmwareVersion(version.to_string())),
+            "PROTOCOL" => Version::parse(value).map(Info::Protocol),
+            "SETTINGS" => value.parse().ok().map(Info::SettingsChecksum),
+            _ => None,
+        }
+    }
+}
+
+/// State of the machine, in an `M996` report.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// The machine must be zeroed before it can move.
+    NotZeroed,
+    /// The machine is zeroed and waiting for commands.
+    Idle,
+    /// A hard limit has tripped, and motion is locked until `$X`.
+    Alarm,
+}
+
+/// A machine state report, from `M996`. Positions and soft limits are in
+/// machine steps, and are only reported once the machine is zeroed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MachineState {
+    pub state: State,
+    pub x_steps: Option<u32>,
+    /// A's whole step count, which is negative left of zero.
+    pub a_steps: Option<i64>,
+    pub x_min_steps: Option<i32>,
+    pub x_max_steps: Option<i32>,
+    /// Units of position reports (`mm`, `steps` or `in`).
+    pub units: String,
+    /// Whether moves are absolute (`G90`) rather than relative (`G91`).
+    pub absolute: Option<bool>,
+    /// Feed override, in percent.
+    pub feed_override_percent: u32,
+    /// Number of queued commands.
+    pub queue_depth: u32,
+}
+impl MachineState {
+    /// Parses a state report. Unknown fields are skipped.
+    fn parse(line: &str) -> Option<MachineState> {
+        let mut fields = line.split(' ').map(|field| field.split_once(':'));
+        let state = match fields.next()?? {
+            ("State", "NotZeroed") => State::NotZeroed,
+            ("State", "Idle") => State::Idle,
+            ("State", "Alarm") => State::Alarm,
+            _ => return None,
+        };
+        let mut report = MachineState {
+            state,
+            x_steps: None,
+            a_steps: None,
+            x_min_steps: None,
+            x_max_steps: None,
+            units: String::new(),
+            absolute: None,
+            feed_override_percent: 100,
+            queue_depth: 0,
+        };
+        for field in fields {
+            let (key, value) = field?;
+            match key {
+                "X" => report.x_steps = Some(value.parse().ok()?),
+                "A" => report.a_steps = Some(value.parse().ok()?),
+                "XMin" => report.x_min_steps = Some(value.parse().ok()?),
+                "XMax" => report.x_max_steps = Some(value.parse().ok()?),
+                "Units" => report.units = value.to_string(),
+                "Mode" => report.absolute = Some(value == "G90"),
+                "Feed" => report.feed_override_percent = value.parse().ok()?,
+                "Queue" => report.queue_depth = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        Some(report)
+    }
+}
+
+/// Totals of a job (a macro or a repeated block), from the `JOB-DONE`
+/// report written at its end.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct JobDone {
+    /// Number of lines run.
+    pub lines: u32,
+    /// Number of moves, and of those that were slow.
+    pub moves: u32,
+    pub slow_moves: u32,
+    /// Whole turns of A, negative for turns backwards.
+    pub turns: i64,
+    /// Time the job took, in milliseconds.
+    pub time_ms: u32,
+}
+impl JobDone {
+    /// Parses a job done report. Unknown fields are skipped.
+    fn parse(line: &str) -> Option<JobDone> {
+        let fields = line.strip_prefix("JOB-DONE ")?;
+        let mut report = JobDone::default();
+        for field in fields.split(' ') {
+            let (key, value) = field.split_once(':')?;
+            match key {
+                "Lines" => report.lines = value.parse().ok()?,
+                "Moves" => report.moves = value.parse().ok()?,
+                "SlowMoves" => report.slow_moves = value.parse().ok()?,
+                "Turns" => report.turns = value.parse().ok()?,
+                "Time" => report.time_ms = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        Some(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responses() {
+        assert_eq!(Line::Response(Response::Ok), Line::parse("ok\r\n"));
+        assert_eq!(
+            Some(Response::OkTimed(1234)),
+            Response::parse("ok t=1234ms")
+        );
+        assert_eq!(Some(Response::Error(3)), Response::parse("error:3"));
+        assert_eq!(Some(Response::Alarm(12)), Response::parse("ALARM:12"));
+        assert_eq!(None, Response::parse("error:"));
+        assert!(!Response::Alarm(1).is_terminating());
+        assert!(Response::Error(1).is_terminating());
+    }
+
+    #[test]
+    fn test_banner() {
+        let version = Version { major: 1, minor: 2 };
+        assert_eq!(Line::Banner(version), Line::parse("WINDERBOT! 1.2"));
+        assert_eq!("1.2", version.to_string());
+    }
+
+    #[test]
+    fn test_version_supports() {
+        let version = Version { major: 1, minor: 1 };
+        assert!(version.supports(Version { major: 1, minor: 0 }));
+        assert!(version.supports(Version { major: 1, minor: 1 }));
+        assert!(!version.supports(Version { major: 1, minor: 2 }));
+        assert!(!version.supports(Version { major: 2, minor: 0 }));
+    }
+
+    #[test]
+    fn test_log() {
+        let line = LogLine {
+            time_ms: 1500,
+            level: Level::Warn,
+            message: "X clipped by 3 steps at the soft limits.".into(),
+        };
+        assert_eq!(
+            Line::Log(line),
+            Line::parse(
+                "[1500] WARN: X clipped by 3 steps at the soft limits."
+            )
+        );
+        assert_eq!(
+            Line::Other("[1500] NOTE: x".into()),
+            Line::parse("[1500] NOTE: x")
+        );
+    }
+
+    #[test]
+    fn test_setting() {
+        let setting = Setting {
+            id: 7,
+            value: 2,
+            description: "Report units (0 = mm, 1 = steps, 2 = inches)".into(),
+        };
+        assert_eq!(
+            Line::Setting(setting),
+            Line::parse("$7=2 (Report units (0 = mm, 1 = steps, 2 = inches))")
+        );
+        assert_eq!(Line::Other("$N0=G90".into()), Line::parse("$N0=G90"));
+    }
+
+    #[test]
+    fn test_settings_checksum() {
+        assert_eq!(0, settings_checksum([]));
+        // Matches the firmware's test vector.
+        assert_eq!(0x2A06, settings_checksum([(1, 2), (2, 1)]));
+    }
+
+    #[test]
+    fn test_info() {
+        assert_eq!(
+            Line::Info(Info::FirmwareVersion("0.1.0".into())),
+            Line::parse("[VER:0.1.0:WinderBot]")
+        );
+        assert_eq!(
+            Line::Info(Info::Protocol(Version { major: 1, minor: 2 })),
+            Line::parse("[PROTOCOL:1.2]")
+        );
+        assert_eq!(
+            Line::Info(Info::SettingsChecksum(10758)),
+            Line::parse("[SETTINGS:10758]")
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let line = "State:Idle X:120 A:6400 XMin:0 XMax:15999 Units:mm \
+                    Mode:G91 Feed:100 Queue:0";
+        let Line::State(state) = Line::parse(line) else {
+            panic!("not a state report");
+        };
+        assert_eq!(State::Idle, state.state);
+        assert_eq!(Some(120), state.x_steps);
+        assert_eq!(Some(6400), state.a_steps);
+        assert_eq!(Some(0), state.x_min_steps);
+        assert_eq!(Some(15999), state.x_max_steps);
+        assert_eq!("mm", state.units);
+        assert_eq!(Some(false), state.absolute);
+        assert_eq!(100, state.feed_override_percent);
+        assert_eq!(0, state.queue_depth);
+
+        let line = "State:Idle X:0 A:-8589934592 Units:steps";
+        let Line::State(state) = Line::parse(line) else {
+            panic!("not a state report");
+        };
+        assert_eq!(Some(-8_589_934_592), state.a_steps);
+    }
+
+    #[test]
+    fn test_job_done() {
+        let line = "JOB-DONE Lines:12 Moves:10 SlowMoves:1 Turns:-250 \
+                    Time:93500 New:1";
+        assert_eq!(
+            Line::JobDone(JobDone {
+                lines: 12,
+                moves: 10,
+                slow_moves: 1,
+                turns: -250,
+                time_ms: 93500,
+            }),
+            Line::parse(line)
+        );
+        assert_eq!(
+            Line::Other("JOB-DONE Lines:x".into()),
+            Line::parse("JOB-DONE Lines:x")
+        );
+    }
+
+    #[test]
+    fn test_state_not_zeroed() {
+        let line = "State:NotZeroed Units:in Feed:100 Queue:0 New:1";
+        let Line::State(state) = Line::parse(line) else {
+            panic!("not a state report");
+        };
+        assert_eq!(State::NotZeroed, state.state);
+        assert_eq!(None, state.x_steps);
+        assert_eq!(None, state.absolute);
+        assert_eq!("in", state.units);
+    }
+}
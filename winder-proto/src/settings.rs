@@ -0,0 +1,98 @@
+//! A machine's settings as a whole, for saving and restoring them.
+
+use crate::{
+    request::Request,
+    response::{settings_checksum, Setting},
+};
+
+/// All the settings of a machine, in the order that `$$` lists them, eg.
+/// read with [`Client::settings`](crate::Client::settings) to be saved,
+/// and restored later.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachineSettings {
+    pub settings: Vec<Setting>,
+}
+impl MachineSettings {
+    /// Returns the value of a setting, if there is one with that number.
+    pub fn get(&self, id: u8) -> Option<u32> {
+        self.settings
+            .iter()
+            .find(|setting| setting.id == id)
+            .map(|setting| setting.value)
+    }
+
+    /// Returns the checksum that `$I` reports for a machine with these
+    /// settings.
+    pub fn checksum(&self) -> u16 {
+        settings_checksum(
+            self.settings
+                .iter()
+                .map(|setting| (setting.id, setting.value)),
+        )
+    }
+
+    /// Returns the requests that change a machine's settings to these.
+    ///
+    /// This includes the baud rate (`$50`), which only takes effect when
+    /// the board restarts.
+    pub fn requests(&self) -> impl Iterator<Item = Request> + '_ {
+        self.settings.iter().map(|setting| Request::SetSetting {
+            id: setting.id,
+            value: setting.value,
+        })
+    }
+}
+impl From<Vec<Setting>> for MachineSettings {
+    fn from(settings: Vec<Setting>) -> Self {
+        Self { settings }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> MachineSettings {
+        vec![
+            Setting {
+                id: 1,
+                value: 250,
+                description: "X reversal dwell, ms".into(),
+            },
+            Setting {
+                id: 4,
+                value: 8000,
+                description: "A maximum step rate, steps/s".into(),
+            },
+        ]
+        .into()
+    }
+
+    #[test]
+    fn test_requests() {
+        let settings = settings();
+        let lines: Vec<String> =
+            settings.requests().map(|r| r.to_string()).collect();
+        assert_eq!(vec!["$1=250", "$4=8000"], lines);
+        assert_eq!(Some(8000), settings.get(4));
+        assert_eq!(None, settings.get(2));
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(
+            settings_checksum([(1, 250), (4, 8000)]),
+            settings().checksum()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let json = serde_json::to_string(&settings()).unwrap();
+        assert!(json.starts_with(r#"{"settings":[{"id":1,"value":250,"#));
+        let loaded: MachineSettings = serde_json::from_str(&json).unwrap();
+        assert_eq!(settings(), loaded);
+    }
+}
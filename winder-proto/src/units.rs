@@ -0,0 +1,100 @@
+//! Lengths and angles in the thousandths that the protocol uses, made from
+//! the floating point millimetres and degrees that host code works in.
+//!
+//! The firmware has no floating point, so values are converted here, once,
+//! with rounding and range checks.
+
+use std::fmt::{self, Display, Formatter};
+
+/// A length in microns, eg. an X position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Microns(pub i32);
+impl Microns {
+    /// Converts a length in millimetres, rounding to the nearest micron.
+    ///
+    /// Returns an error if the length is not finite, or is out of the range
+    /// of the firmware.
+    pub fn from_mm_f64(mm: f64) -> Result<Self, RangeError> {
+        thousandths(mm).map(Microns)
+    }
+}
+
+/// An angle in millidegrees, eg. an A position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct MilliDegrees(pub i32);
+impl MilliDegrees {
+    /// Converts an angle in degrees, rounding to the nearest millidegree.
+    ///
+    /// Returns an error if the angle is not finite, or is out of the range
+    /// of the firmware.
+    pub fn from_deg_f64(deg: f64) -> Result<Self, RangeError> {
+        thousandths(deg).map(MilliDegrees)
+    }
+}
+
+/// A value that cannot be converted: it is not finite, or its thousandths
+/// do not fit in an `i32`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RangeError(pub f64);
+impl Display for RangeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} is out of range.", self.0)
+    }
+}
+impl std::error::Error for RangeError {}
+
+/// Converts a value to thousandths of its unit, rounding half away from
+/// zero.
+fn thousandths(value: f64) -> Result<i32, RangeError> {
+    let scaled = (value * 1000.0).round();
+    if scaled >= i32::MIN as f64 && scaled <= i32::MAX as f64 {
+        Ok(scaled as i32)
+    } else {
+        Err(RangeError(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(Ok(Microns(1500)), Microns::from_mm_f64(1.5));
+        assert_eq!(Ok(Microns(100)), Microns::from_mm_f64(0.1));
+        assert_eq!(Ok(Microns(-1)), Microns::from_mm_f64(-0.0005));
+        assert_eq!(Ok(Microns(0)), Microns::from_mm_f64(0.0004));
+        assert_eq!(
+            Ok(MilliDegrees(359_999)),
+            MilliDegrees::from_deg_f64(359.9994)
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(Ok(Microns(i32::MAX)), Microns::from_mm_f64(2147483.647));
+        assert_eq!(Ok(Microns(i32::MIN)), Microns::from_mm_f64(-2147483.648));
+        assert!(Microns::from_mm_f64(2147483.648).is_err());
+        assert!(MilliDegrees::from_deg_f64(-2147483.649).is_err());
+        assert!(MilliDegrees::from_deg_f64(f64::NAN).is_err());
+        assert!(Microns::from_mm_f64(f64::INFINITY).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        assert_eq!("1500", serde_json::to_string(&Microns(1500)).unwrap());
+        let angle: MilliDegrees = serde_json::from_str("-250").unwrap();
+        assert_eq!(MilliDegrees(-250), angle);
+    }
+}